@@ -0,0 +1,582 @@
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+use unwalled_client::{
+    AuctionTrigger, Bid, Client, Error, ErrorCode, EvmWallet, IdentityTier, IdentityTierUpdate, IssuerRegistryUpdate, Keystore,
+    LocalWallet, MultisigAction, MultisigUpdate, PublicKey, SessionKeyUpdate, Signature, SignatureScheme, Signer,
+    VerifiableCredential, DEFAULT_CHAIN_ID, DEFAULT_FEE,
+};
+use uuid::Uuid;
+
+/// Wallet and operations tool for an Unwalled node.
+///
+/// Keys are kept in password-encrypted keystore files. The password is read
+/// from `--password-file`, or else the `UNWALLED_PASSWORD` environment variable.
+/// Every command prints its result as JSON on stdout. Failures print
+/// `{"error": ..., "code": ...}` on stderr, with the node's error code when
+/// the node reported one, and exit with status 1.
+#[derive(Debug, Parser)]
+#[command(name = "unwalled", version)]
+struct Cli {
+    /// The node's HTTP RPC endpoint.
+    #[arg(long, global = true, env = "UNWALLED_RPC", default_value = "http://127.0.0.1:8080")]
+    rpc: String,
+    /// The keystore transactions are signed with.
+    #[arg(long, global = true, env = "UNWALLED_KEY")]
+    key: Option<PathBuf>,
+    /// A file whose first line is the keystore password.
+    #[arg(long, global = true)]
+    password_file: Option<PathBuf>,
+    /// The fee attached to each transaction, in the smallest unit of KUSD.
+    #[arg(long, global = true, default_value_t = DEFAULT_FEE)]
+    fee: u64,
+    /// The network transactions are signed for; it must match the node's.
+    #[arg(long, global = true, env = "UNWALLED_CHAIN_ID", default_value = DEFAULT_CHAIN_ID)]
+    chain_id: String,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Generates a new key and encrypts it into the keystore `out`.
+    Keygen {
+        out: PathBuf,
+        /// Generate a secp256k1 key that signs EIP-712 typed data, like an
+        /// Ethereum account, instead of an ed25519 key.
+        #[arg(long)]
+        evm: bool,
+    },
+    /// Encrypts a hex-encoded secret key into the keystore `out`.
+    Import {
+        #[arg(long)]
+        secret_key: String,
+        out: PathBuf,
+        /// The key is a secp256k1 (Ethereum) private key rather than ed25519.
+        #[arg(long)]
+        evm: bool,
+    },
+    /// Prints the address and secret key of `--key`.
+    Export,
+    /// Prints the address of `--key`.
+    Address,
+    /// Shows an account's balance, by default the one for `--key`.
+    Balance { address: Option<String> },
+    /// Shows an account's next nonce, by default the one for `--key`.
+    Nonce { address: Option<String> },
+    /// Places, cancels and lists bids.
+    #[command(subcommand)]
+    Bid(BidCommand),
+    /// Triggers an auction and waits for its outcome.
+    Trigger {
+        /// The lowest acceptable price, in the smallest unit of KUSD.
+        #[arg(long)]
+        floor: u64,
+        /// Comma-separated `key=value` attributes of the impression.
+        #[arg(long, value_delimiter = ',')]
+        attributes: Vec<String>,
+        /// How long to wait for the trigger to commit.
+        #[arg(long, default_value_t = 500)]
+        wait_ms: u64,
+        /// The auction id, random by default. A presentation must be made
+        /// with `presentation_challenge` of the chain, the publisher's
+        /// address and this id as its challenge.
+        #[arg(long)]
+        auction_id: Option<Uuid>,
+        /// A JSON file with the user's Verifiable Presentation.
+        #[arg(long)]
+        presentation: Option<PathBuf>,
+        /// Only accept bids from advertisers with at least this tier:
+        /// anonymous, verified or kyc.
+        #[arg(long, default_value_t = IdentityTier::Anonymous)]
+        min_advertiser_tier: IdentityTier,
+    },
+    /// Shows the receipt of a transaction.
+    Receipt { tx_hash: String },
+    /// Shows a committed block by height or hex-encoded hash.
+    Block { id: String },
+    /// Shows the latest committed block.
+    Head,
+    /// Claims a settlement-layer deposit into the `--key` account.
+    Deposit {
+        amount: u64,
+        /// The settlement-layer transaction that locked the funds.
+        #[arg(long)]
+        settlement_ref: String,
+        /// Credits this account, such as a multisig account, instead.
+        #[arg(long)]
+        account: Option<String>,
+        /// A JSON file with the settlement attesters' proof that the deposit
+        /// is final.
+        #[arg(long)]
+        proof: PathBuf,
+    },
+    /// Withdraws from the `--key` account to the settlement layer.
+    Withdraw {
+        amount: u64,
+        /// The settlement-layer address that receives the funds.
+        #[arg(long)]
+        recipient: String,
+    },
+    /// Manages the trusted credential issuer registry.
+    #[command(subcommand)]
+    Issuer(IssuerCommand),
+    /// Shows and sets identity tiers.
+    #[command(subcommand)]
+    Identity(IdentityCommand),
+    /// Delegates signing for the `--key` account to session keys.
+    #[command(subcommand)]
+    Session(SessionCommand),
+    /// Creates multisig accounts and proposes and approves their actions.
+    #[command(subcommand)]
+    Multisig(MultisigCommand),
+}
+
+#[derive(Debug, Subcommand)]
+enum MultisigCommand {
+    /// Creates an account run by `members`, DIDs, of which `--threshold`
+    /// must approve each action.
+    Create {
+        #[arg(required = true)]
+        members: Vec<String>,
+        #[arg(long)]
+        threshold: u32,
+    },
+    /// Proposes an action for `account`, given as JSON, e.g.
+    /// `{"type":"withdraw","amount":100,"recipient":"..."}`. Proposing counts
+    /// as the `--key` member's approval.
+    Propose { account: String, action: String },
+    /// Approves one of `account`'s open proposals as the `--key` member.
+    Approve { account: String, proposal_id: u64 },
+    /// Shows a multisig account's members and threshold.
+    Get { account: String },
+    /// Shows a proposal and its approvals.
+    Proposal { account: String, proposal_id: u64 },
+}
+
+#[derive(Debug, Subcommand)]
+enum SessionCommand {
+    /// Lets `session_key`, a DID, sign the given actions for the `--key`
+    /// account.
+    Authorize {
+        session_key: String,
+        /// Comma-separated action types, e.g. `PlaceBid,CancelBid`.
+        #[arg(long, value_delimiter = ',', required = true)]
+        actions: Vec<String>,
+        /// The most the session may spend, in the smallest unit of KUSD.
+        #[arg(long)]
+        spend_limit: Option<u64>,
+        #[arg(long)]
+        expires_at_height: Option<u64>,
+    },
+    /// Revokes one of the `--key` account's session keys.
+    Revoke { session_key: String },
+    /// Shows a session key's grant.
+    Get { session_key: String },
+}
+
+#[derive(Debug, Subcommand)]
+enum IdentityCommand {
+    /// Sets `subject`'s tier, signed by a trusted issuer scoped for `tier`.
+    Attest { subject: String, tier: IdentityTier },
+    /// Sets the `--key` account's tier from a JSON file with a credential
+    /// carrying a `tier` claim about it.
+    Claim { credential: PathBuf },
+    /// Shows a DID's identity tier.
+    Get { did: String },
+}
+
+#[derive(Debug, Subcommand)]
+enum IssuerCommand {
+    /// Trusts `issuer` for the given attribute namespaces. Governors only.
+    Add {
+        issuer: String,
+        /// Comma-separated attribute keys the issuer may attest, e.g. `geo,age`.
+        #[arg(long, value_delimiter = ',')]
+        namespaces: Vec<String>,
+    },
+    /// Stops trusting `issuer`. Governors only.
+    Remove { issuer: String },
+    /// Replaces the namespaces `issuer` is trusted for. Governors only.
+    Scope {
+        issuer: String,
+        #[arg(long, value_delimiter = ',')]
+        namespaces: Vec<String>,
+    },
+    /// Moves `issuer` to a new signing key, given as a DID.
+    Rotate {
+        issuer: String,
+        #[arg(long)]
+        new_key: String,
+    },
+    /// Revokes credentials `issuer` has issued, by credential id.
+    Revoke {
+        issuer: String,
+        #[arg(required = true)]
+        credential_ids: Vec<String>,
+    },
+    /// Shows a trusted issuer.
+    Get { issuer: String },
+}
+
+#[derive(Debug, Subcommand)]
+enum BidCommand {
+    /// Places a bid from the `--key` account.
+    Place {
+        /// The price per impression, in the smallest unit of KUSD.
+        #[arg(long)]
+        price: u64,
+        /// Comma-separated `key=value` attributes the impression must have.
+        #[arg(long, value_delimiter = ',')]
+        targeting: Vec<String>,
+        /// The ad markup served when the bid wins.
+        #[arg(long)]
+        adm: String,
+        #[arg(long)]
+        expires_at_height: Option<u64>,
+        /// Only match impressions whose targeting attributes are backed by
+        /// trusted credentials.
+        #[arg(long)]
+        requires_verified_targeting: bool,
+        /// Only match users presenting at least this tier: anonymous,
+        /// verified or kyc.
+        #[arg(long, default_value_t = IdentityTier::Anonymous)]
+        min_user_tier: IdentityTier,
+        /// The id to sign the bid with, random by default. The bid is stored
+        /// under an id derived from it and the advertiser's address.
+        #[arg(long)]
+        id: Option<Uuid>,
+    },
+    /// Cancels one of the `--key` account's open bids.
+    Cancel { id: Uuid },
+    /// Shows a bid and its status.
+    Get { id: Uuid },
+    /// Lists an advertiser's bids, by default the `--key` account's.
+    List { address: Option<String> },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    env_logger::init();
+    match run(Cli::parse()).await {
+        Ok(output) => {
+            println!("{}", serde_json::to_string_pretty(&output).unwrap_or_else(|_| output.to_string()));
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            let code = e.downcast_ref::<Error>().and_then(Error::code).map(ErrorCode::code);
+            eprintln!("{}", json!({ "error": format!("{:#}", e), "code": code }));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<Value> {
+    match &cli.command {
+        Command::Keygen { out, evm } => {
+            let wallet = if *evm { Wallet::Evm(EvmWallet::new()) } else { Wallet::Ed25519(LocalWallet::new()) };
+            wallet.save(out, &cli.password()?)?;
+            Ok(json!({ "address": wallet.address(), "keystore": out }))
+        }
+        Command::Import { secret_key, out, evm } => {
+            let secret_key = hex::decode(secret_key.trim_start_matches("0x"))?;
+            let wallet = if *evm {
+                Wallet::Evm(EvmWallet::from_secret_key(&secret_key)?)
+            } else {
+                Wallet::Ed25519(LocalWallet::from_secret_key(&secret_key)?)
+            };
+            wallet.save(out, &cli.password()?)?;
+            Ok(json!({ "address": wallet.address(), "keystore": out }))
+        }
+        Command::Export => {
+            let wallet = cli.wallet()?;
+            let mut output = wallet.describe();
+            output["secret_key"] = json!(hex::encode(wallet.secret_key()));
+            Ok(output)
+        }
+        Command::Address => Ok(cli.wallet()?.describe()),
+        Command::Balance { address } => {
+            let account = cli.client().get_account(&cli.address(address)?).await?;
+            Ok(serde_json::to_value(found(account, "Account")?)?)
+        }
+        Command::Nonce { address } => {
+            let account = found(cli.client().get_account(&cli.address(address)?).await?, "Account")?;
+            Ok(json!({ "address": account.address, "nonce": account.nonce }))
+        }
+        Command::Bid(BidCommand::Place {
+            price,
+            targeting,
+            adm,
+            expires_at_height,
+            requires_verified_targeting,
+            min_user_tier,
+            id,
+        }) => {
+            let bid = Bid {
+                id: id.unwrap_or_else(Uuid::new_v4),
+                price: *price,
+                targeting: targeting.clone(),
+                adm: adm.clone(),
+                expires_at_height: *expires_at_height,
+                requires_verified_targeting: *requires_verified_targeting,
+                min_user_tier: *min_user_tier,
+            };
+            let client = cli.signing_client()?;
+            let tx_hash = client.place_bid(&bid).await?;
+            Ok(json!({ "bid_id": client.bid_id(&bid), "tx_hash": tx_hash }))
+        }
+        Command::Bid(BidCommand::Cancel { id }) => {
+            let tx_hash = cli.signing_client()?.cancel_bid(id).await?;
+            Ok(json!({ "bid_id": id, "tx_hash": tx_hash }))
+        }
+        Command::Bid(BidCommand::Get { id }) => Ok(serde_json::to_value(found(cli.client().get_bid(id).await?, "Bid")?)?),
+        Command::Bid(BidCommand::List { address }) => {
+            let bids = cli.client().get_bids_by_advertiser(&cli.address(address)?).await?;
+            Ok(serde_json::to_value(bids)?)
+        }
+        Command::Trigger { floor, attributes, wait_ms, auction_id, presentation, min_advertiser_tier } => {
+            let presentation = match presentation {
+                Some(path) => Some(serde_json::from_slice(
+                    &std::fs::read(path).with_context(|| format!("Failed to read presentation {}", path.display()))?,
+                )?),
+                None => None,
+            };
+            let auction = AuctionTrigger {
+                id: auction_id.unwrap_or_else(Uuid::new_v4),
+                bid_floor: *floor,
+                attributes: attributes.clone(),
+                presentation,
+                min_advertiser_tier: *min_advertiser_tier,
+            };
+            let result = cli
+                .signing_client()?
+                .trigger_auction_with_deadline(&auction, Duration::from_millis(*wait_ms))
+                .await?;
+            Ok(json!({ "auction_id": auction.id, "result": result }))
+        }
+        Command::Receipt { tx_hash } => {
+            Ok(serde_json::to_value(found(cli.client().get_receipt(tx_hash).await?, "Receipt")?)?)
+        }
+        Command::Block { id } => {
+            let client = cli.client();
+            let block = match id.parse::<u64>() {
+                Ok(height) => client.get_block_by_height(height).await?,
+                Err(_) => client.get_block_by_hash(id).await?,
+            };
+            Ok(serde_json::to_value(found(block, "Block")?)?)
+        }
+        Command::Head => Ok(serde_json::to_value(cli.client().get_head().await?)?),
+        Command::Deposit { amount, settlement_ref, account, proof } => {
+            let proof = serde_json::from_slice(
+                &std::fs::read(proof).with_context(|| format!("Failed to read deposit proof {}", proof.display()))?,
+            )?;
+            let tx_hash = cli.signing_client()?.deposit(*amount, settlement_ref, account.as_deref(), proof).await?;
+            Ok(json!({ "tx_hash": tx_hash }))
+        }
+        Command::Withdraw { amount, recipient } => {
+            let tx_hash = cli.signing_client()?.withdraw(*amount, recipient).await?;
+            Ok(json!({ "tx_hash": tx_hash }))
+        }
+        Command::Identity(IdentityCommand::Get { did }) => {
+            Ok(serde_json::to_value(found(cli.client().get_identity(did).await?, "Identity")?)?)
+        }
+        Command::Identity(IdentityCommand::Attest { subject, tier }) => {
+            let update = IdentityTierUpdate::Attest { subject: subject.clone(), tier: *tier };
+            let tx_hash = cli.signing_client()?.update_identity_tier(&update).await?;
+            Ok(json!({ "tx_hash": tx_hash }))
+        }
+        Command::Identity(IdentityCommand::Claim { credential }) => {
+            let credential: VerifiableCredential = serde_json::from_slice(
+                &std::fs::read(credential).with_context(|| format!("Failed to read credential {}", credential.display()))?,
+            )?;
+            let tx_hash = cli.signing_client()?.update_identity_tier(&IdentityTierUpdate::Claim { credential: Box::new(credential) }).await?;
+            Ok(json!({ "tx_hash": tx_hash }))
+        }
+        Command::Session(SessionCommand::Authorize { session_key, actions, spend_limit, expires_at_height }) => {
+            let update = SessionKeyUpdate::Authorize {
+                session_key: session_key.clone(),
+                actions: actions.clone(),
+                spend_limit: *spend_limit,
+                expires_at_height: *expires_at_height,
+            };
+            let tx_hash = cli.signing_client()?.update_session_key(&update).await?;
+            Ok(json!({ "tx_hash": tx_hash }))
+        }
+        Command::Session(SessionCommand::Revoke { session_key }) => {
+            let update = SessionKeyUpdate::Revoke { session_key: session_key.clone() };
+            let tx_hash = cli.signing_client()?.update_session_key(&update).await?;
+            Ok(json!({ "tx_hash": tx_hash }))
+        }
+        Command::Session(SessionCommand::Get { session_key }) => {
+            Ok(serde_json::to_value(found(cli.client().get_session_key(session_key).await?, "Session key")?)?)
+        }
+        Command::Multisig(MultisigCommand::Create { members, threshold }) => {
+            let (address, tx_hash) = cli.signing_client()?.create_multisig(members, *threshold).await?;
+            Ok(json!({ "address": address, "tx_hash": tx_hash }))
+        }
+        Command::Multisig(MultisigCommand::Propose { account, action }) => {
+            let proposal: MultisigAction = serde_json::from_str(action).context("Invalid multisig action")?;
+            let update = MultisigUpdate::Propose { account: account.clone(), proposal };
+            let tx_hash = cli.signing_client()?.update_multisig(&update).await?;
+            Ok(json!({ "tx_hash": tx_hash }))
+        }
+        Command::Multisig(MultisigCommand::Approve { account, proposal_id }) => {
+            let update = MultisigUpdate::Approve { account: account.clone(), proposal_id: *proposal_id };
+            let tx_hash = cli.signing_client()?.update_multisig(&update).await?;
+            Ok(json!({ "tx_hash": tx_hash }))
+        }
+        Command::Multisig(MultisigCommand::Get { account }) => {
+            Ok(serde_json::to_value(found(cli.client().get_multisig(account).await?, "Multisig account")?)?)
+        }
+        Command::Multisig(MultisigCommand::Proposal { account, proposal_id }) => {
+            let proposal = cli.client().get_multisig_proposal(account, *proposal_id).await?;
+            Ok(serde_json::to_value(found(proposal, "Proposal")?)?)
+        }
+        Command::Issuer(IssuerCommand::Get { issuer }) => {
+            Ok(serde_json::to_value(found(cli.client().get_issuer(issuer).await?, "Issuer")?)?)
+        }
+        Command::Issuer(command) => {
+            let update = match command {
+                IssuerCommand::Add { issuer, namespaces } => {
+                    IssuerRegistryUpdate::AddIssuer { issuer: issuer.clone(), namespaces: namespaces.clone() }
+                }
+                IssuerCommand::Remove { issuer } => IssuerRegistryUpdate::RemoveIssuer { issuer: issuer.clone() },
+                IssuerCommand::Scope { issuer, namespaces } => {
+                    IssuerRegistryUpdate::SetIssuerScope { issuer: issuer.clone(), namespaces: namespaces.clone() }
+                }
+                IssuerCommand::Rotate { issuer, new_key } => {
+                    IssuerRegistryUpdate::RotateIssuerKey { issuer: issuer.clone(), new_key: new_key.clone() }
+                }
+                IssuerCommand::Revoke { issuer, credential_ids } => IssuerRegistryUpdate::RevokeCredentials {
+                    issuer: issuer.clone(),
+                    credential_ids: credential_ids.clone(),
+                },
+                IssuerCommand::Get { .. } => unreachable!("handled above"),
+            };
+            let tx_hash = cli.signing_client()?.update_issuer_registry(&update).await?;
+            Ok(json!({ "tx_hash": tx_hash }))
+        }
+    }
+}
+
+/// A keystore's key: ed25519, or secp256k1 signing EIP-712 typed data.
+#[derive(Debug)]
+enum Wallet {
+    Ed25519(LocalWallet),
+    Evm(EvmWallet),
+}
+
+impl Wallet {
+    /// Decrypts the keystore at `path`, telling the key type from the DID
+    /// it is stored under.
+    fn load(path: &Path, password: &str) -> Result<Self> {
+        let keystore = Keystore::load(path)?;
+        if PublicKey::from_did(&keystore.address)?.is_secp256k1() {
+            Ok(Wallet::Evm(EvmWallet::load(path, password)?))
+        } else {
+            Ok(Wallet::Ed25519(LocalWallet::load(path, password)?))
+        }
+    }
+
+    fn save(&self, path: &Path, password: &str) -> Result<()> {
+        match self {
+            Wallet::Ed25519(wallet) => wallet.save(path, password),
+            Wallet::Evm(wallet) => wallet.save(path, password),
+        }
+    }
+
+    fn secret_key(&self) -> [u8; 32] {
+        match self {
+            Wallet::Ed25519(wallet) => wallet.secret_key(),
+            Wallet::Evm(wallet) => wallet.secret_key(),
+        }
+    }
+
+    /// The address, plus the Ethereum address for EVM keys.
+    fn describe(&self) -> Value {
+        match self {
+            Wallet::Ed25519(wallet) => json!({ "address": wallet.address() }),
+            Wallet::Evm(wallet) => json!({ "address": wallet.address(), "eth_address": wallet.eth_address() }),
+        }
+    }
+}
+
+impl Signer for Wallet {
+    fn sign(&self, data: &[u8]) -> Result<Signature> {
+        match self {
+            Wallet::Ed25519(wallet) => wallet.sign(data),
+            Wallet::Evm(wallet) => wallet.sign(data),
+        }
+    }
+
+    fn public_key(&self) -> PublicKey {
+        match self {
+            Wallet::Ed25519(wallet) => wallet.public_key(),
+            Wallet::Evm(wallet) => wallet.public_key(),
+        }
+    }
+
+    fn address(&self) -> String {
+        match self {
+            Wallet::Ed25519(wallet) => wallet.address(),
+            Wallet::Evm(wallet) => wallet.address(),
+        }
+    }
+
+    fn scheme(&self) -> SignatureScheme {
+        match self {
+            Wallet::Ed25519(wallet) => wallet.scheme(),
+            Wallet::Evm(wallet) => wallet.scheme(),
+        }
+    }
+}
+
+impl Cli {
+    /// The wallet in the `--key` keystore.
+    fn wallet(&self) -> Result<Wallet> {
+        match &self.key {
+            Some(path) => Wallet::load(path, &self.password()?),
+            None => Err(anyhow::anyhow!("No keystore given; pass --key or set UNWALLED_KEY")),
+        }
+    }
+
+    fn password(&self) -> Result<String> {
+        match &self.password_file {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read password file {}", path.display()))?;
+                Ok(contents.lines().next().unwrap_or_default().to_string())
+            }
+            None => std::env::var("UNWALLED_PASSWORD")
+                .map_err(|_| anyhow::anyhow!("No password given; pass --password-file or set UNWALLED_PASSWORD")),
+        }
+    }
+
+    /// `address`, or else the address of `--key`.
+    fn address(&self, address: &Option<String>) -> Result<String> {
+        match address {
+            Some(address) => Ok(address.clone()),
+            None => Ok(self.wallet()?.address()),
+        }
+    }
+
+    /// A client signing with `--key`, for commands that submit transactions.
+    fn signing_client(&self) -> Result<Client<Wallet>> {
+        Ok(Client::new(self.rpc.clone(), self.wallet()?).with_fee(self.fee).with_chain_id(self.chain_id.clone()))
+    }
+
+    /// A client for queries, which sign nothing and so need no key.
+    fn client(&self) -> Client<LocalWallet> {
+        Client::new(self.rpc.clone(), LocalWallet::new())
+    }
+}
+
+/// Turns a missing query result into the error the node would report for it.
+fn found<T>(value: Option<T>, what: &str) -> Result<T> {
+    value.ok_or_else(|| {
+        Error::Rpc { status: 404, code: ErrorCode::NotFound, message: format!("{} not found", what) }.into()
+    })
+}
@@ -0,0 +1,93 @@
+use anyhow::Result;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use uuid::Uuid;
+
+fn unwalled(args: &[&str]) -> Result<(bool, Value)> {
+    let output = Command::new(env!("CARGO_BIN_EXE_unwalled"))
+        .args(args)
+        .env_remove("UNWALLED_KEY")
+        .env("UNWALLED_PASSWORD", "correct horse")
+        .output()?;
+    let stream = if output.status.success() { &output.stdout } else { &output.stderr };
+    Ok((output.status.success(), serde_json::from_slice(stream)?))
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("unwalled-cli-test-{}-{}.json", name, Uuid::new_v4()))
+}
+
+fn path_str(path: &Path) -> &str {
+    path.to_str().expect("utf-8 temp path")
+}
+
+#[test]
+fn exported_key_imports_to_the_same_address() -> Result<()> {
+    let key = temp_path("key");
+    let (ok, generated) = unwalled(&["keygen", path_str(&key)])?;
+    assert!(ok);
+
+    let (ok, exported) = unwalled(&["export", "--key", path_str(&key)])?;
+    assert!(ok);
+    assert_eq!(exported["address"], generated["address"]);
+
+    let imported = temp_path("imported");
+    let secret_key = exported["secret_key"].as_str().expect("secret key");
+    let (ok, result) = unwalled(&["import", "--secret-key", secret_key, path_str(&imported)])?;
+    assert!(ok);
+    assert_eq!(result["address"], generated["address"]);
+
+    let (ok, address) = unwalled(&["address", "--key", path_str(&imported)])?;
+    assert!(ok);
+    assert_eq!(address["address"], generated["address"]);
+    Ok(())
+}
+
+#[test]
+fn keygen_never_overwrites_a_key() -> Result<()> {
+    let key = temp_path("key");
+    assert!(unwalled(&["keygen", path_str(&key)])?.0);
+    let before = std::fs::read(&key)?;
+
+    let (ok, error) = unwalled(&["keygen", path_str(&key)])?;
+    assert!(!ok);
+    assert!(error["error"].as_str().expect("error message").contains("Failed to create keystore"));
+    assert_eq!(std::fs::read(&key)?, before);
+    Ok(())
+}
+
+#[test]
+fn transactions_require_a_key() -> Result<()> {
+    let (ok, error) = unwalled(&["bid", "cancel", &Uuid::new_v4().to_string()])?;
+    assert!(!ok);
+    assert_eq!(error["code"], Value::Null);
+    Ok(())
+}
+
+#[test]
+fn wrong_password_does_not_unlock_the_keystore() -> Result<()> {
+    let key = temp_path("key");
+    assert!(unwalled(&["keygen", path_str(&key)])?.0);
+    let password_file = temp_path("password");
+    std::fs::write(&password_file, "battery staple\n")?;
+
+    let (ok, error) = unwalled(&["export", "--key", path_str(&key), "--password-file", path_str(&password_file)])?;
+    assert!(!ok);
+    assert!(error["error"].as_str().expect("error message").contains("Wrong password"));
+    Ok(())
+}
+
+#[test]
+fn ethereum_keys_import_with_their_eth_address() -> Result<()> {
+    let key = temp_path("evm");
+    let secret_key = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+    let (ok, imported) = unwalled(&["import", "--evm", "--secret-key", secret_key, path_str(&key)])?;
+    assert!(ok);
+
+    let (ok, address) = unwalled(&["address", "--key", path_str(&key)])?;
+    assert!(ok);
+    assert_eq!(address["address"], imported["address"]);
+    assert_eq!(address["eth_address"], "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23");
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use std::fmt;
+pub use unwalled_node::error::ErrorCode;
+use unwalled_node::primitives::ErrorResponse;
+
+/// Why a `Client` call failed.
+#[derive(Debug)]
+pub enum Error {
+    /// The node refused the request, e.g. for an invalid signature.
+    Rpc { status: u16, code: ErrorCode, message: String },
+    /// The transaction committed but was rejected, e.g. for a bad nonce or
+    /// insufficient funds.
+    Rejected { tx_hash: String, code: ErrorCode, reason: String },
+    /// An auction trigger did not commit before the deadline. It may still
+    /// commit; its receipt can be fetched by `tx_hash`.
+    Timeout { tx_hash: String },
+    /// No usable answer: connection, TLS, signing or decoding failures.
+    Transport(anyhow::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// The node's error code, for failures the node reported.
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            Error::Rpc { code, .. } | Error::Rejected { code, .. } => Some(*code),
+            Error::Timeout { .. } | Error::Transport(_) => None,
+        }
+    }
+
+    /// Decodes an RPC error body, keeping the status if the body is not an
+    /// `ErrorResponse`.
+    pub(crate) fn from_response(status: u16, body: &[u8]) -> Self {
+        match serde_json::from_slice::<ErrorResponse>(body) {
+            Ok(response) => Error::Rpc { status, code: response.code, message: response.error },
+            Err(_) => Error::Rpc {
+                status,
+                code: ErrorCode::Unknown,
+                message: String::from_utf8_lossy(body).into_owned(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Rpc { status, code, message } => {
+                write!(f, "Node returned {} (code {}): {}", status, code.code(), message)
+            }
+            Error::Rejected { tx_hash, code, reason } => {
+                write!(f, "Transaction {} rejected (code {}): {}", tx_hash, code.code(), reason)
+            }
+            Error::Timeout { tx_hash } => write!(f, "Transaction {} did not commit in time", tx_hash),
+            Error::Transport(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Transport(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        Error::Transport(e)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Transport(e.into())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Transport(e.into())
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        Error::Transport(e.into())
+    }
+}
@@ -0,0 +1,429 @@
+use anyhow::Result;
+use base64::Engine;
+use quiche::h3::NameValue;
+use ring::rand::SecureRandom;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, oneshot, Mutex};
+
+const MAX_DATAGRAM_SIZE: usize = 1350;
+
+/// How long to wait for the QUIC handshake before falling back to HTTP/1.1.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Where and how to reach a node's HTTP/3 RPC listener.
+#[derive(Debug, Clone)]
+pub struct Http3Config {
+    /// The node's `rpc_listen_address`.
+    pub server_addr: SocketAddr,
+    /// The TLS server name, e.g. `localhost` for a node's self-signed certificate.
+    pub server_name: String,
+    /// SHA-256 of the node's DER certificate. When set, the connection is
+    /// accepted only if the node presents exactly this certificate, which is
+    /// how a self-signed node certificate is trusted.
+    pub pinned_cert_sha256: Option<[u8; 32]>,
+    /// PEM CA bundle used to verify the node's certificate when it is not pinned.
+    pub ca_file: Option<PathBuf>,
+}
+
+/// Computes the pin for `Http3Config::pinned_cert_sha256` from a PEM
+/// certificate such as a node's `rpc-cert.pem`.
+pub fn cert_fingerprint(pem: &str) -> Result<[u8; 32]> {
+    let base64: String = pem
+        .lines()
+        .skip_while(|line| !line.starts_with("-----BEGIN CERTIFICATE-----"))
+        .skip(1)
+        .take_while(|line| !line.starts_with("-----END CERTIFICATE-----"))
+        .collect();
+    if base64.is_empty() {
+        return Err(anyhow::anyhow!("No certificate found in PEM"));
+    }
+    let der = base64::engine::general_purpose::STANDARD.decode(base64)?;
+    let digest = ring::digest::digest(&ring::digest::SHA256, &der);
+    let mut fingerprint = [0u8; 32];
+    fingerprint.copy_from_slice(digest.as_ref());
+    Ok(fingerprint)
+}
+
+/// The status code and body of an HTTP/3 response.
+pub(crate) type H3Response = (u16, Vec<u8>);
+
+struct H3Request {
+    method: String,
+    path: String,
+    /// Sent as both `content-type` and `accept`.
+    content_type: &'static str,
+    body: Vec<u8>,
+    respond: oneshot::Sender<Result<H3Response>>,
+}
+
+/// A reusable HTTP/3 connection to one node.
+///
+/// A background task owns the QUIC connection and multiplexes every request
+/// onto it as a separate stream. If the connection closes, the next request
+/// reconnects, resuming the previous TLS session so it can go out as 0-RTT.
+pub(crate) struct H3Transport {
+    config: Http3Config,
+    requests: Mutex<mpsc::Sender<H3Request>>,
+    /// The TLS session from the most recent connection, for resumption.
+    session: std::sync::Arc<std::sync::Mutex<Option<Vec<u8>>>>,
+}
+
+impl H3Transport {
+    /// Connects and completes the handshake, so an unreachable node or a
+    /// certificate mismatch is reported here rather than on first use.
+    pub(crate) async fn connect(config: Http3Config) -> Result<Self> {
+        let session = std::sync::Arc::new(std::sync::Mutex::new(None));
+        let requests = spawn_connection(&config, session.clone()).await?;
+        Ok(Self { config, requests: Mutex::new(requests), session })
+    }
+
+    pub(crate) async fn request(
+        &self,
+        method: &str,
+        path: &str,
+        content_type: &'static str,
+        body: Vec<u8>,
+    ) -> Result<H3Response> {
+        let (respond, response) = oneshot::channel();
+        let mut request =
+            H3Request { method: method.to_string(), path: path.to_string(), content_type, body, respond };
+
+        let mut requests = self.requests.lock().await;
+        if let Err(mpsc::error::SendError(returned)) = requests.send(request).await {
+            log::debug!("HTTP/3 connection to {} closed, reconnecting", self.config.server_addr);
+            *requests = spawn_connection(&self.config, self.session.clone()).await?;
+            request = returned;
+            requests
+                .send(request)
+                .await
+                .map_err(|_| anyhow::anyhow!("HTTP/3 connection closed"))?;
+        }
+        drop(requests);
+
+        response.await.map_err(|_| anyhow::anyhow!("HTTP/3 connection closed before responding"))?
+    }
+}
+
+/// Opens a QUIC connection, waits for the handshake (or 0-RTT readiness) and
+/// hands it to a background task. Returns the channel requests are sent on.
+async fn spawn_connection(
+    config: &Http3Config,
+    session: std::sync::Arc<std::sync::Mutex<Option<Vec<u8>>>>,
+) -> Result<mpsc::Sender<H3Request>> {
+    let socket = UdpSocket::bind(if config.server_addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }).await?;
+    let local = socket.local_addr()?;
+
+    let mut quic_config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    match (&config.pinned_cert_sha256, &config.ca_file) {
+        // The pin is checked against the peer certificate after the handshake.
+        (Some(_), _) => quic_config.verify_peer(false),
+        (None, Some(ca_file)) => {
+            quic_config.verify_peer(true);
+            let ca_file = ca_file
+                .to_str()
+                .ok_or_else(|| anyhow::anyhow!("Path is not valid UTF-8: {}", ca_file.display()))?;
+            quic_config.load_verify_locations_from_file(ca_file)?;
+        }
+        (None, None) => quic_config.verify_peer(true),
+    }
+    quic_config.set_application_protos(quiche::h3::APPLICATION_PROTOCOL)?;
+    quic_config.set_max_idle_timeout(30_000);
+    quic_config.set_max_recv_udp_payload_size(MAX_DATAGRAM_SIZE);
+    quic_config.set_max_send_udp_payload_size(MAX_DATAGRAM_SIZE);
+    quic_config.set_initial_max_data(10_000_000);
+    quic_config.set_initial_max_stream_data_bidi_local(1_000_000);
+    quic_config.set_initial_max_stream_data_bidi_remote(1_000_000);
+    quic_config.set_initial_max_stream_data_uni(1_000_000);
+    quic_config.set_initial_max_streams_bidi(100);
+    quic_config.set_initial_max_streams_uni(100);
+    quic_config.enable_early_data();
+
+    let mut scid = [0; quiche::MAX_CONN_ID_LEN];
+    ring::rand::SystemRandom::new()
+        .fill(&mut scid)
+        .map_err(|_| anyhow::anyhow!("Failed to generate connection id"))?;
+    let scid = quiche::ConnectionId::from_ref(&scid);
+    let mut conn = quiche::connect(Some(&config.server_name), &scid, local, config.server_addr, &mut quic_config)?;
+    let resumed = session.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+    if let Some(previous) = resumed {
+        if let Err(e) = conn.set_session(&previous) {
+            log::debug!("Could not resume TLS session: {}", e);
+        }
+    }
+
+    let mut connection = Connection {
+        socket,
+        local,
+        conn,
+        h3_config: quiche::h3::Config::new()?,
+        h3_conn: None,
+        streams: HashMap::new(),
+        pinned_cert_sha256: config.pinned_cert_sha256,
+        session,
+    };
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, connection.handshake())
+        .await
+        .map_err(|_| anyhow::anyhow!("HTTP/3 handshake with {} timed out", config.server_addr))??;
+
+    let (tx, rx) = mpsc::channel(64);
+    tokio::spawn(connection.run(rx));
+    Ok(tx)
+}
+
+/// A request in flight on one stream.
+struct InFlight {
+    /// Request body bytes not yet accepted by flow control.
+    outgoing: Vec<u8>,
+    status: Option<u16>,
+    body: Vec<u8>,
+    respond: oneshot::Sender<Result<H3Response>>,
+}
+
+/// What woke the connection task.
+enum Event {
+    Request(Option<H3Request>),
+    Packet(std::io::Result<(usize, SocketAddr)>),
+    Timeout,
+}
+
+struct Connection {
+    socket: UdpSocket,
+    local: SocketAddr,
+    conn: quiche::Connection,
+    h3_config: quiche::h3::Config,
+    h3_conn: Option<quiche::h3::Connection>,
+    streams: HashMap<u64, InFlight>,
+    pinned_cert_sha256: Option<[u8; 32]>,
+    session: std::sync::Arc<std::sync::Mutex<Option<Vec<u8>>>>,
+}
+
+impl Connection {
+    async fn handshake(&mut self) -> Result<()> {
+        // On the heap: buffers held across awaits bloat every caller's future.
+        let mut buf = vec![0; 65535];
+        loop {
+            self.flush().await?;
+            if self.conn.is_in_early_data() || self.conn.is_established() {
+                break;
+            }
+            if self.conn.is_closed() {
+                return Err(anyhow::anyhow!("HTTP/3 handshake failed: {:?}", self.conn.peer_error()));
+            }
+            self.recv(&mut buf).await?;
+        }
+        self.check_pin()?;
+        self.h3_conn = Some(quiche::h3::Connection::with_transport(&mut self.conn, &self.h3_config)?);
+        Ok(())
+    }
+
+    /// Rejects the connection if the node's certificate is not the pinned one.
+    /// During 0-RTT the certificate is not yet known; it is checked again once
+    /// the handshake completes.
+    fn check_pin(&mut self) -> Result<()> {
+        let Some(pinned) = self.pinned_cert_sha256 else {
+            return Ok(());
+        };
+        let Some(cert) = self.conn.peer_cert() else {
+            if self.conn.is_established() {
+                return Err(anyhow::anyhow!("Node presented no certificate"));
+            }
+            return Ok(());
+        };
+        if ring::digest::digest(&ring::digest::SHA256, cert).as_ref() != pinned {
+            self.conn.close(false, 0x100, b"certificate mismatch").ok();
+            return Err(anyhow::anyhow!("Node certificate does not match the pinned fingerprint"));
+        }
+        // Only checked once; after this the pin is satisfied.
+        self.pinned_cert_sha256 = None;
+        Ok(())
+    }
+
+    async fn run(mut self, mut requests: mpsc::Receiver<H3Request>) {
+        let mut buf = vec![0; 65535];
+        let mut accepting = true;
+
+        loop {
+            if let Err(e) = self.flush().await {
+                log::debug!("HTTP/3 send failed: {}", e);
+                break;
+            }
+            if self.conn.is_closed() || (!accepting && self.streams.is_empty()) {
+                break;
+            }
+
+            let timeout = self.conn.timeout().unwrap_or(Duration::from_secs(30));
+            let event = tokio::select! {
+                request = requests.recv(), if accepting => Event::Request(request),
+                recv = self.socket.recv_from(&mut buf) => Event::Packet(recv),
+                _ = tokio::time::sleep(timeout) => Event::Timeout,
+            };
+            match event {
+                Event::Request(Some(request)) => self.start(request),
+                // The transport was dropped; finish what is in flight.
+                Event::Request(None) => accepting = false,
+                Event::Packet(Ok((len, from))) => {
+                    let recv_info = quiche::RecvInfo { from, to: self.local };
+                    if let Err(e) = self.conn.recv(&mut buf[..len], recv_info) {
+                        log::debug!("QUIC recv failed: {}", e);
+                    }
+                }
+                Event::Packet(Err(e)) => {
+                    log::debug!("UDP recv failed: {}", e);
+                    break;
+                }
+                Event::Timeout => self.conn.on_timeout(),
+            }
+
+            if let Err(e) = self.check_pin() {
+                log::warn!("{}", e);
+                break;
+            }
+            self.write_pending();
+            self.poll();
+        }
+
+        if let Some(session) = self.conn.session() {
+            *self.session.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(session.to_vec());
+        }
+        for (_, stream) in self.streams.drain() {
+            let _ = stream.respond.send(Err(anyhow::anyhow!("HTTP/3 connection closed")));
+        }
+    }
+
+    fn start(&mut self, request: H3Request) {
+        let Some(h3_conn) = self.h3_conn.as_mut() else {
+            let _ = request.respond.send(Err(anyhow::anyhow!("HTTP/3 connection not ready")));
+            return;
+        };
+        let headers = [
+            quiche::h3::Header::new(b":method", request.method.as_bytes()),
+            quiche::h3::Header::new(b":scheme", b"https"),
+            quiche::h3::Header::new(b":authority", b"unwalled-node"),
+            quiche::h3::Header::new(b":path", request.path.as_bytes()),
+            quiche::h3::Header::new(b"content-type", request.content_type.as_bytes()),
+            quiche::h3::Header::new(b"accept", request.content_type.as_bytes()),
+        ];
+        let fin = request.body.is_empty();
+        match h3_conn.send_request(&mut self.conn, &headers, fin) {
+            Ok(stream_id) => {
+                self.streams.insert(stream_id, InFlight {
+                    outgoing: request.body,
+                    status: None,
+                    body: Vec::new(),
+                    respond: request.respond,
+                });
+                self.write_body(stream_id);
+            }
+            Err(e) => {
+                let _ = request.respond.send(Err(anyhow::anyhow!("Failed to send HTTP/3 request: {}", e)));
+            }
+        }
+    }
+
+    /// Continues request bodies that did not fit in the stream's window.
+    fn write_pending(&mut self) {
+        let pending: Vec<u64> = self
+            .streams
+            .iter()
+            .filter(|(_, stream)| !stream.outgoing.is_empty())
+            .map(|(stream_id, _)| *stream_id)
+            .collect();
+        for stream_id in pending {
+            self.write_body(stream_id);
+        }
+    }
+
+    fn write_body(&mut self, stream_id: u64) {
+        let (Some(h3_conn), Some(stream)) = (self.h3_conn.as_mut(), self.streams.get_mut(&stream_id)) else {
+            return;
+        };
+        if stream.outgoing.is_empty() {
+            return;
+        }
+        match h3_conn.send_body(&mut self.conn, stream_id, &stream.outgoing, true) {
+            Ok(written) => {
+                stream.outgoing.drain(..written);
+            }
+            Err(quiche::h3::Error::Done) => {}
+            Err(e) => {
+                let stream = self.streams.remove(&stream_id).expect("stream was just looked up");
+                let _ = stream.respond.send(Err(anyhow::anyhow!("Failed to send HTTP/3 body: {}", e)));
+            }
+        }
+    }
+
+    fn poll(&mut self) {
+        let Some(h3_conn) = self.h3_conn.as_mut() else {
+            return;
+        };
+        let mut buf = [0; 65535];
+        loop {
+            match h3_conn.poll(&mut self.conn) {
+                Ok((stream_id, quiche::h3::Event::Headers { list, .. })) => {
+                    if let Some(stream) = self.streams.get_mut(&stream_id) {
+                        stream.status = list
+                            .iter()
+                            .find(|header| header.name() == b":status")
+                            .and_then(|header| std::str::from_utf8(header.value()).ok()?.parse().ok());
+                    }
+                }
+                Ok((stream_id, quiche::h3::Event::Data)) => {
+                    while let Ok(read) = h3_conn.recv_body(&mut self.conn, stream_id, &mut buf) {
+                        if let Some(stream) = self.streams.get_mut(&stream_id) {
+                            stream.body.extend_from_slice(&buf[..read]);
+                        }
+                    }
+                }
+                Ok((stream_id, quiche::h3::Event::Finished)) => {
+                    if let Some(stream) = self.streams.remove(&stream_id) {
+                        let response = match stream.status {
+                            Some(status) => Ok((status, stream.body)),
+                            None => Err(anyhow::anyhow!("HTTP/3 response had no status")),
+                        };
+                        let _ = stream.respond.send(response);
+                    }
+                }
+                Ok((stream_id, quiche::h3::Event::Reset(code))) => {
+                    if let Some(stream) = self.streams.remove(&stream_id) {
+                        let _ = stream.respond.send(Err(anyhow::anyhow!("HTTP/3 stream reset with code {}", code)));
+                    }
+                }
+                Ok(_) => {}
+                Err(quiche::h3::Error::Done) => break,
+                Err(e) => {
+                    log::debug!("HTTP/3 error: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn recv(&mut self, buf: &mut [u8]) -> Result<()> {
+        let timeout = self.conn.timeout().unwrap_or(HANDSHAKE_TIMEOUT);
+        match tokio::time::timeout(timeout, self.socket.recv_from(buf)).await {
+            Ok(recv) => {
+                let (len, from) = recv?;
+                self.conn.recv(&mut buf[..len], quiche::RecvInfo { from, to: self.local })?;
+            }
+            Err(_) => self.conn.on_timeout(),
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        let mut out = [0; MAX_DATAGRAM_SIZE];
+        loop {
+            match self.conn.send(&mut out) {
+                Ok((write, send_info)) => {
+                    self.socket.send_to(&out[..write], send_info.to).await?;
+                }
+                Err(quiche::Error::Done) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
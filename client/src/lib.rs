@@ -1,56 +1,414 @@
-use anyhow::Result;
-use log;
+mod error;
+mod h3;
+
+pub use error::{Error, ErrorCode, Result};
+pub use h3::{cert_fingerprint, Http3Config};
 
 // Re-exporting primitives for convenience from the node crate.
-pub use unwalled_node::primitives::{AuctionTrigger, Bid, Match};
+pub use unwalled_node::credentials::{presentation_challenge, VerifiableCredential, VerifiablePresentation};
+pub use unwalled_node::identity::{
+    multisig_address, Address, DidDocument, EvmWallet, LocalWallet, PublicKey, Signature, SignatureScheme, Signer,
+};
+pub use unwalled_node::keystore::Keystore;
+pub use unwalled_node::settlement::{Attestation, DepositProof};
+pub use unwalled_node::wire::WireFormat;
+pub use unwalled_node::primitives::{
+    AccountInfo, Action, AuctionOutcome, AuctionResult, AuctionTrigger, Bid, BidInfo, BidStatus, BlockInfo, CancelBid, ChainEvent, ChainHead, Deposit,
+    EventType, IdentityInfo, IdentityTier, IdentityTierUpdate, IssuerRegistryUpdate, Match, MultisigAccount, MultisigAction,
+    MultisigProposal, MultisigUpdate, ProposalStatus, Receipt, ReceiptStatus, SessionKeyInfo, SessionKeyUpdate, Signed,
+    SubmitResponse, SubscriptionFilter, TrustedIssuer, Withdraw, bid_id, DEFAULT_CHAIN_ID, DELEGABLE_ACTIONS,
+};
+use futures_util::{SinkExt, Stream, StreamExt};
+use h3::H3Transport;
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio_tungstenite::tungstenite::Message;
+use uuid::Uuid;
+
+/// How long `trigger_auction` waits for its trigger to commit.
+pub const DEFAULT_TRIGGER_WAIT: Duration = Duration::from_millis(500);
+
+/// The fee attached to each transaction unless overridden with `with_fee`,
+/// in the smallest unit of KUSD.
+pub const DEFAULT_FEE: u64 = 10;
 
 /// A client for interacting with an Unwalled node.
-pub struct Client {
+///
+/// Requests go over HTTP/1.1 to `rpc_endpoint`, or over a single reused
+/// HTTP/3 connection when built with `connect_http3`.
+/// Transactions are signed with `signer`. Nonces come from a local cache,
+/// seeded from the node on first use, so submissions can be pipelined without
+/// waiting for each one to commit. Each submission holds the cache until the
+/// node answers, so a nonce is only used again if the node refused it.
+pub struct Client<S> {
     rpc_endpoint: String,
     http_client: reqwest::Client,
+    http3: Option<H3Transport>,
+    signer: S,
+    fee: u64,
+    /// The network transactions are signed for.
+    chain_id: String,
+    /// How request and response bodies are encoded. Errors are always JSON.
+    wire_format: WireFormat,
+    /// The nonce the next transaction will use, once known.
+    next_nonce: Mutex<Option<u64>>,
 }
 
-impl Client {
-    pub fn new(rpc_endpoint: String) -> Self {
+impl<S: Signer> Client<S> {
+    /// Builds a client that talks HTTP/1.1 to `rpc_endpoint`.
+    pub fn new(rpc_endpoint: String, signer: S) -> Self {
         Self {
             rpc_endpoint,
             http_client: reqwest::Client::new(),
+            http3: None,
+            signer,
+            fee: DEFAULT_FEE,
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
+            wire_format: WireFormat::Json,
+            next_nonce: Mutex::new(None),
         }
     }
 
-    /// Placeholder for placing a bid via RPC.
-    pub async fn place_bid(&self, bid: &Bid) -> Result<()> {
-        log::info!("Sending place_bid request for bid ID: {}", bid.id);
-        // In a real implementation, this would make an HTTP/3 RPC call.
-        // Using a standard POST request as a placeholder.
-        let response = self.http_client
-            .post(&format!("{}/rpc/place_bid", self.rpc_endpoint))
-            .json(bid)
-            .send()
-            .await?;
-
-        if response.status().is_success() {
-            log::info!("Successfully placed bid.");
-            Ok(())
-        } else {
-            Err(anyhow::anyhow!("Failed to place bid: {}", response.status()))
+    /// Builds a client that talks HTTP/3 to the node described by `http3`,
+    /// falling back to HTTP/1.1 on `rpc_endpoint` if the QUIC handshake fails
+    /// or the node's certificate does not match the pin. Subscriptions always
+    /// use a WebSocket on `rpc_endpoint`.
+    pub async fn connect_http3(rpc_endpoint: String, signer: S, http3: Http3Config) -> Self {
+        let mut client = Self::new(rpc_endpoint, signer);
+        match H3Transport::connect(http3.clone()).await {
+            Ok(transport) => client.http3 = Some(transport),
+            Err(e) => log::warn!(
+                "HTTP/3 to {} unavailable, falling back to HTTP/1.1 on {}: {}",
+                http3.server_addr,
+                client.rpc_endpoint,
+                e
+            ),
         }
+        client
+    }
+
+    /// Whether requests are going over HTTP/3.
+    pub fn is_http3(&self) -> bool {
+        self.http3.is_some()
+    }
+
+    /// Sets the fee attached to every transaction.
+    pub fn with_fee(mut self, fee: u64) -> Self {
+        self.fee = fee;
+        self
+    }
+
+    /// Signs transactions for the network `chain_id` rather than
+    /// `DEFAULT_CHAIN_ID`. It must match the node's.
+    pub fn with_chain_id(mut self, chain_id: String) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    /// Sends requests and asks for responses in `wire_format`. MessagePack
+    /// is more compact; JSON is easier to debug.
+    pub fn with_wire_format(mut self, wire_format: WireFormat) -> Self {
+        self.wire_format = wire_format;
+        self
+    }
+
+    /// The address transactions are signed from.
+    pub fn address(&self) -> Address {
+        self.signer.address()
+    }
+
+    /// The id `bid` is stored under once this client places it, for fetching
+    /// or cancelling it later. Bids placed through a session key are stored
+    /// under the principal's address instead; see `primitives::bid_id`.
+    pub fn bid_id(&self, bid: &Bid) -> Uuid {
+        bid_id(&self.address(), &bid.id)
+    }
+
+    /// Signs and submits a bid, returning its transaction hash.
+    pub async fn place_bid(&self, bid: &Bid) -> Result<String> {
+        log::info!("Sending place_bid request for bid ID: {}", bid.id);
+        let body = self.submit("place_bid", bid.clone()).await?;
+        Ok(self.decode::<SubmitResponse>(&body)?.tx_hash)
+    }
+
+    /// Signs and submits a cancellation of one of our bids, returning its
+    /// transaction hash.
+    pub async fn cancel_bid(&self, bid_id: &Uuid) -> Result<String> {
+        log::info!("Sending cancel_bid request for bid ID: {}", bid_id);
+        let body = self.submit("cancel_bid", CancelBid { bid_id: *bid_id }).await?;
+        Ok(self.decode::<SubmitResponse>(&body)?.tx_hash)
     }
 
-    /// Placeholder for triggering an auction via RPC.
+    /// Claims a settlement-layer deposit, identified by `settlement_ref`, into
+    /// our account or into `account`, returning its transaction hash. The
+    /// claim is rejected unless `proof` carries enough settlement attesters'
+    /// signatures.
+    pub async fn deposit(&self, amount: u64, settlement_ref: &str, account: Option<&str>, proof: DepositProof) -> Result<String> {
+        log::info!("Sending deposit request for {}", settlement_ref);
+        let deposit =
+            Deposit { amount, settlement_ref: settlement_ref.to_string(), account: account.map(str::to_string), proof };
+        let body = self.submit("deposit", deposit).await?;
+        Ok(self.decode::<SubmitResponse>(&body)?.tx_hash)
+    }
+
+    /// Withdraws `amount` from our account to `recipient` on the settlement
+    /// layer, returning its transaction hash.
+    pub async fn withdraw(&self, amount: u64, recipient: &str) -> Result<String> {
+        log::info!("Sending withdraw request to {}", recipient);
+        let withdraw = Withdraw { amount, recipient: recipient.to_string() };
+        let body = self.submit("withdraw", withdraw).await?;
+        Ok(self.decode::<SubmitResponse>(&body)?.tx_hash)
+    }
+
+    /// Submits a change to the trusted credential issuer registry, returning
+    /// its transaction hash. Most changes must be signed by a governor.
+    pub async fn update_issuer_registry(&self, update: &IssuerRegistryUpdate) -> Result<String> {
+        log::info!("Sending issuer registry update: {:?}", update);
+        let body = self.submit("issuer_registry", update.clone()).await?;
+        Ok(self.decode::<SubmitResponse>(&body)?.tx_hash)
+    }
+
+    /// Attests or claims an identity tier, returning its transaction hash.
+    pub async fn update_identity_tier(&self, update: &IdentityTierUpdate) -> Result<String> {
+        log::info!("Sending identity tier update: {:?}", update);
+        let body = self.submit("identity_tier", update.clone()).await?;
+        Ok(self.decode::<SubmitResponse>(&body)?.tx_hash)
+    }
+
+    /// Authorises or revokes a session key for this client's account,
+    /// returning the transaction hash. A client built with the session key
+    /// as its signer then acts for this account.
+    pub async fn update_session_key(&self, update: &SessionKeyUpdate) -> Result<String> {
+        log::info!("Sending session key update: {:?}", update);
+        let body = self.submit("session_key", update.clone()).await?;
+        Ok(self.decode::<SubmitResponse>(&body)?.tx_hash)
+    }
+
+    /// Creates a `threshold`-of-`members` multisig account, returning its
+    /// address and the transaction hash. The address is derived from this
+    /// client's address and the nonce the transaction is signed with.
+    pub async fn create_multisig(&self, members: &[Address], threshold: u32) -> Result<(Address, String)> {
+        log::info!("Sending create multisig request for {} members", members.len());
+        let update = MultisigUpdate::Create { members: members.to_vec(), threshold };
+        let mut next_nonce = self.next_nonce.lock().await;
+        let (nonce, body) = self.submit_locked(&mut next_nonce, "multisig", update).await?;
+        let tx_hash = self.decode::<SubmitResponse>(&body)?.tx_hash;
+        Ok((multisig_address(&self.address(), nonce), tx_hash))
+    }
+
+    /// Proposes or approves an action on a multisig account this client's
+    /// signer is a member of, returning the transaction hash. The action runs
+    /// in the transaction that brings it to the threshold.
+    pub async fn update_multisig(&self, update: &MultisigUpdate) -> Result<String> {
+        log::info!("Sending multisig update: {:?}", update);
+        let body = self.submit("multisig", update.clone()).await?;
+        Ok(self.decode::<SubmitResponse>(&body)?.tx_hash)
+    }
+
+    /// Triggers an auction and waits up to `DEFAULT_TRIGGER_WAIT` for the
+    /// result. Returns `None` on a definitive no-fill, and an error if the
+    /// trigger was rejected or did not commit in time.
     pub async fn trigger_auction(&self, auction: &AuctionTrigger) -> Result<Option<Match>> {
+        let result = self.trigger_auction_with_deadline(auction, DEFAULT_TRIGGER_WAIT).await?;
+        match result.outcome {
+            AuctionOutcome::Matched { auction_match } => Ok(Some(auction_match)),
+            AuctionOutcome::NoFill => Ok(None),
+            AuctionOutcome::Rejected { code, reason } => Err(Error::Rejected { tx_hash: result.tx_hash, code, reason }),
+            AuctionOutcome::Timeout => Err(Error::Timeout { tx_hash: result.tx_hash }),
+        }
+    }
+
+    /// Signs an auction trigger and holds the request until its block commits
+    /// or `deadline` passes. The node caps the deadline at a few seconds.
+    pub async fn trigger_auction_with_deadline(
+        &self,
+        auction: &AuctionTrigger,
+        deadline: Duration,
+    ) -> Result<AuctionResult> {
         log::info!("Sending trigger_auction request for auction ID: {}", auction.id);
-        let response = self.http_client
-            .post(&format!("{}/rpc/trigger_auction", self.rpc_endpoint))
-            .json(auction)
-            .send()
-            .await?;
-
-        if response.status().is_success() {
-            let auction_match = response.json::<Option<Match>>().await?;
-            Ok(auction_match)
+        let path = format!("trigger_auction?wait_ms={}", deadline.as_millis());
+        let mut next_nonce = self.next_nonce.lock().await;
+        let (_, body) = self.submit_locked(&mut next_nonce, &path, auction.clone()).await?;
+        let result: AuctionResult = self.decode(&body)?;
+        if let AuctionOutcome::Rejected { .. } = result.outcome {
+            // Whether the rejected trigger used its nonce depends on why it
+            // was rejected, and the node answered only once it committed.
+            *next_nonce = None;
+        }
+        Ok(result)
+    }
+
+    /// Drops the cached nonce so the next submission refetches it from the
+    /// node. Call this after learning from a receipt that a transaction was
+    /// rejected, once none of this client's transactions are pending.
+    pub async fn resync_nonce(&self) {
+        *self.next_nonce.lock().await = None;
+    }
+
+    /// Signs `data` with the next nonce and the configured fee and POSTs it,
+    /// returning the response body.
+    async fn submit<T: Action>(&self, path: &str, data: T) -> Result<Vec<u8>> {
+        let mut next_nonce = self.next_nonce.lock().await;
+        Ok(self.submit_locked(&mut next_nonce, path, data).await?.1)
+    }
+
+    /// Signs `data` with the cached nonce and submits it, returning the nonce
+    /// and the response body. The cache advances only if the node accepted
+    /// the transaction, and is dropped if the node could not be reached or
+    /// the nonce was stale.
+    async fn submit_locked<T: Action>(&self, next_nonce: &mut Option<u64>, path: &str, data: T) -> Result<(u64, Vec<u8>)> {
+        let nonce = match *next_nonce {
+            Some(nonce) => nonce,
+            None => self.get_account(&self.address()).await?.map_or(0, |account| account.nonce),
+        };
+        let signed = Signed::sign_for_chain(&self.chain_id, data, nonce, self.fee, &self.signer)?;
+
+        let body = self.wire_format.encode(&signed).map_err(Error::Transport)?;
+        match self.send("POST", &format!("/rpc/{}", path), body).await {
+            Ok((status, body)) if (200..300).contains(&status) => {
+                *next_nonce = Some(nonce + 1);
+                Ok((nonce, body))
+            }
+            Ok((status, body)) => {
+                let error = Error::from_response(status, &body);
+                // A refused transaction leaves its nonce unused, unless the
+                // nonce itself was stale.
+                *next_nonce = (error.code() != Some(ErrorCode::BadNonce)).then_some(nonce);
+                Err(error)
+            }
+            Err(e) => {
+                *next_nonce = None;
+                Err(e)
+            }
+        }
+    }
+
+    /// Sends a request over HTTP/3 when connected, otherwise HTTP/1.1.
+    async fn send(&self, method: &str, path: &str, body: Vec<u8>) -> Result<(u16, Vec<u8>)> {
+        if let Some(http3) = &self.http3 {
+            return Ok(http3.request(method, path, self.wire_format.content_type(), body).await?);
+        }
+
+        let url = format!("{}{}", self.rpc_endpoint, path);
+        let content_type = self.wire_format.content_type();
+        let request = match method {
+            "POST" => self.http_client.post(&url).header("content-type", content_type).body(body),
+            _ => self.http_client.get(&url),
+        }
+        .header("accept", content_type);
+        let response = request.send().await?;
+        let status = response.status().as_u16();
+        Ok((status, response.bytes().await?.to_vec()))
+    }
+
+    /// Fetches an account's balance and next nonce.
+    pub async fn get_account(&self, address: &str) -> Result<Option<AccountInfo>> {
+        self.query(&format!("account/{}", address)).await
+    }
+
+    /// Resolves a `did:key` DID to its DID document.
+    pub async fn resolve_did(&self, did: &str) -> Result<DidDocument> {
+        let (status, body) = self.send("GET", &format!("/rpc/did/{}", did), Vec::new()).await?;
+        if !(200..300).contains(&status) {
+            return Err(Error::from_response(status, &body));
+        }
+        self.decode(&body)
+    }
+
+    /// Fetches a trusted credential issuer by its registry id.
+    pub async fn get_issuer(&self, issuer: &str) -> Result<Option<TrustedIssuer>> {
+        self.query(&format!("issuer/{}", issuer)).await
+    }
+
+    /// Fetches a DID's recorded identity tier. `None` means anonymous.
+    pub async fn get_identity(&self, did: &str) -> Result<Option<IdentityInfo>> {
+        self.query(&format!("identity/{}", did)).await
+    }
+
+    /// Fetches a session key's grant by the key's DID.
+    pub async fn get_session_key(&self, session_key: &str) -> Result<Option<SessionKeyInfo>> {
+        self.query(&format!("session_key/{}", session_key)).await
+    }
+
+    /// Fetches a multisig account's members and threshold by address.
+    pub async fn get_multisig(&self, address: &str) -> Result<Option<MultisigAccount>> {
+        self.query(&format!("multisig/{}", address)).await
+    }
+
+    /// Fetches one of a multisig account's proposals, with its approvals and
+    /// status.
+    pub async fn get_multisig_proposal(&self, address: &str, id: u64) -> Result<Option<MultisigProposal>> {
+        self.query(&format!("multisig/{}/proposal/{}", address, id)).await
+    }
+
+    /// Fetches a bid and its status by id.
+    pub async fn get_bid(&self, id: &Uuid) -> Result<Option<BidInfo>> {
+        self.query(&format!("bid/{}", id)).await
+    }
+
+    /// Lists every bid placed by an advertiser.
+    pub async fn get_bids_by_advertiser(&self, address: &str) -> Result<Vec<BidInfo>> {
+        Ok(self.query(&format!("advertiser/{}/bids", address)).await?.unwrap_or_default())
+    }
+
+    /// Fetches the receipt, including any match, for a hex-encoded transaction hash.
+    pub async fn get_receipt(&self, tx_hash: &str) -> Result<Option<Receipt>> {
+        self.query(&format!("receipt/{}", tx_hash)).await
+    }
+
+    pub async fn get_block_by_height(&self, height: u64) -> Result<Option<BlockInfo>> {
+        self.query(&format!("block/{}", height)).await
+    }
+
+    /// Fetches a block by its hex-encoded hash.
+    pub async fn get_block_by_hash(&self, hash: &str) -> Result<Option<BlockInfo>> {
+        self.query(&format!("block/hash/{}", hash)).await
+    }
+
+    /// Fetches the node's latest committed block height, hash and state root.
+    pub async fn get_head(&self) -> Result<ChainHead> {
+        self.query("head")
+            .await?
+            .ok_or_else(|| Error::Transport(anyhow::anyhow!("Node returned no chain head")))
+    }
+
+    /// Subscribes to committed blocks, matches, expiries and cancellations
+    /// selected by `filter`. The stream ends if the node closes the
+    /// subscription, for example because the subscriber fell too far behind.
+    pub async fn subscribe(
+        &self,
+        filter: &SubscriptionFilter,
+    ) -> Result<impl Stream<Item = Result<ChainEvent>>> {
+        let url = format!("{}/rpc/subscribe", self.rpc_endpoint).replacen("http", "ws", 1);
+        log::info!("Subscribing to chain events at {}", url);
+        let (mut ws, _) = tokio_tungstenite::connect_async(url).await?;
+        ws.send(Message::Text(serde_json::to_string(filter)?)).await?;
+
+        Ok(ws.filter_map(|message| async move {
+            match message {
+                Ok(Message::Text(text)) => Some(serde_json::from_str::<ChainEvent>(&text).map_err(Into::into)),
+                Ok(_) => None,
+                Err(e) => Some(Err(e.into())),
+            }
+        }))
+    }
+
+    /// Decodes a successful response body, which is in our wire format.
+    fn decode<T: DeserializeOwned>(&self, body: &[u8]) -> Result<T> {
+        self.wire_format.decode(body).map_err(Error::Transport)
+    }
+
+    /// Issues a GET against a query path, mapping 404 to `None`.
+    async fn query<T: DeserializeOwned>(&self, path: &str) -> Result<Option<T>> {
+        let (status, body) = self.send("GET", &format!("/rpc/{}", path), Vec::new()).await?;
+
+        if status == 404 {
+            Ok(None)
+        } else if (200..300).contains(&status) {
+            Ok(Some(self.decode::<T>(&body)?))
         } else {
-            Err(anyhow::anyhow!("Failed to trigger auction: {}", response.status()))
+            Err(Error::from_response(status, &body))
         }
     }
 }
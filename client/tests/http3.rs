@@ -0,0 +1,67 @@
+use anyhow::Result;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use unwalled_client::{cert_fingerprint, Bid, Client, Http3Config, IdentityTier, LocalWallet, Signer};
+use unwalled_node::consensus::{ReceiptWaiters, Transaction};
+use unwalled_node::rpc::{self, RpcHandler, TlsConfig};
+use unwalled_node::state::StateManager;
+use uuid::Uuid;
+
+/// Starts the HTTP/3 RPC server and returns its address and certificate pin.
+async fn start_server() -> Result<(SocketAddr, [u8; 32], mpsc::Receiver<Transaction>, StateManager)> {
+    let addr = std::net::UdpSocket::bind("127.0.0.1:0")?.local_addr()?;
+    let tls = TlsConfig::self_signed(std::env::temp_dir().join(format!("unwalled-client-h3-{}", Uuid::new_v4())))?;
+    let pin = cert_fingerprint(&std::fs::read_to_string(&tls.cert_path)?)?;
+    let state_manager = StateManager::new(std::env::temp_dir().join(format!("unwalled-client-h3-db-{}", Uuid::new_v4())))?;
+    let (tx, rx) = mpsc::channel(16);
+    let handler = RpcHandler::new(tx, state_manager.clone(), broadcast::channel(16).0, ReceiptWaiters::default());
+    tokio::spawn(rpc::run_server(addr, tls, handler));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    Ok((addr, pin, rx, state_manager))
+}
+
+fn http3_config(server_addr: SocketAddr, pin: [u8; 32]) -> Http3Config {
+    Http3Config {
+        server_addr,
+        server_name: "localhost".to_string(),
+        pinned_cert_sha256: Some(pin),
+        ca_file: None,
+    }
+}
+
+#[tokio::test]
+async fn pinned_http3_connection_is_reused() -> Result<()> {
+    let (addr, pin, mut rx, state_manager) = start_server().await?;
+    let wallet = LocalWallet::new();
+    state_manager.set_balance(&wallet.address(), 100)?;
+    let client = Client::connect_http3("http://127.0.0.1:1".to_string(), wallet, http3_config(addr, pin)).await;
+    assert!(client.is_http3());
+
+    for _ in 0..3 {
+        let bid = Bid {
+            id: Uuid::new_v4(),
+            price: 150,
+            targeting: vec!["sports".to_string()],
+            adm: "<VAST version='4.2'>...</VAST>".to_string(),
+            expires_at_height: None,
+            requires_verified_targeting: false,
+            min_user_tier: IdentityTier::Anonymous,
+        };
+        let tx_hash = client.place_bid(&bid).await?;
+        let transaction = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+            .await?
+            .expect("transaction forwarded");
+        assert_eq!(tx_hash, hex::encode(transaction.hash()?));
+    }
+    assert_eq!(client.get_head().await?.height, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn wrong_pin_falls_back_to_http1() -> Result<()> {
+    let (addr, _, _rx, _) = start_server().await?;
+    let client = Client::connect_http3("http://127.0.0.1:1".to_string(), LocalWallet::new(), http3_config(addr, [0; 32])).await;
+    assert!(!client.is_http3());
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use anyhow::Result;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use unwalled_client::{Bid, Client, Error, ErrorCode, IdentityTier, LocalWallet, Signer, WireFormat};
+use unwalled_node::consensus::{ReceiptWaiters, Transaction};
+use unwalled_node::http;
+use unwalled_node::rpc::RpcHandler;
+use unwalled_node::state::StateManager;
+use uuid::Uuid;
+
+fn sample_bid() -> Bid {
+    Bid {
+        id: Uuid::new_v4(),
+        price: 150,
+        targeting: vec!["sports".to_string()],
+        adm: "<VAST version='4.2'>...</VAST>".to_string(),
+        expires_at_height: None,
+        requires_verified_targeting: false,
+        min_user_tier: IdentityTier::Anonymous,
+    }
+}
+
+#[tokio::test]
+async fn submissions_are_signed_with_pipelined_nonces() -> Result<()> {
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let state_manager = StateManager::new(std::env::temp_dir().join(format!("unwalled-client-test-db-{}", Uuid::new_v4())))?;
+    let (tx, mut rx) = mpsc::channel(16);
+    let handler = RpcHandler::new(tx, state_manager.clone(), broadcast::channel(16).0, ReceiptWaiters::default());
+    tokio::spawn(http::run_server(addr, handler));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let wallet = LocalWallet::new();
+    for _ in 0..3 {
+        state_manager.increment_nonce(&wallet.address())?;
+    }
+    state_manager.set_balance(&wallet.address(), 100)?;
+    let client = Client::new(format!("http://{}", addr), wallet).with_fee(25);
+
+    // The first submission seeds the cache from the node; later ones do not wait.
+    let first = client.place_bid(&sample_bid()).await?;
+    let second = client.cancel_bid(&Uuid::new_v4()).await?;
+    assert_ne!(first, second);
+
+    let mut nonces = Vec::new();
+    for _ in 0..2 {
+        let transaction = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+            .await?
+            .expect("transaction forwarded");
+        match transaction {
+            Transaction::PlaceBid(signed) => {
+                assert!(signed.verify()?);
+                assert_eq!(signed.fee, 25);
+                nonces.push(signed.nonce);
+            }
+            Transaction::CancelBid(signed) => {
+                assert!(signed.verify()?);
+                nonces.push(signed.nonce);
+            }
+            other => panic!("Unexpected transaction {:?}", other),
+        }
+    }
+    assert_eq!(nonces, vec![3, 4]);
+
+    // Neither transaction committed, so a resync goes back to the node's nonce.
+    client.resync_nonce().await;
+    client.place_bid(&sample_bid()).await?;
+    match rx.recv().await.expect("transaction forwarded") {
+        Transaction::PlaceBid(signed) => assert_eq!(signed.nonce, 3),
+        other => panic!("Unexpected transaction {:?}", other),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn node_errors_carry_their_code() -> Result<()> {
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let state_manager = StateManager::new(std::env::temp_dir().join(format!("unwalled-client-test-db-{}", Uuid::new_v4())))?;
+    // With consensus gone the node cannot accept transactions.
+    let (tx, _) = mpsc::channel(16);
+    let handler = RpcHandler::new(tx, state_manager.clone(), broadcast::channel(16).0, ReceiptWaiters::default());
+    tokio::spawn(http::run_server(addr, handler));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let wallet = LocalWallet::new();
+    state_manager.set_balance(&wallet.address(), 100)?;
+    let client = Client::new(format!("http://{}", addr), wallet);
+    match client.place_bid(&sample_bid()).await {
+        Err(Error::Rpc { status, code, .. }) => {
+            assert_eq!(status, 503);
+            assert_eq!(code, ErrorCode::Unavailable);
+        }
+        other => panic!("Unexpected result {:?}", other),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn msgpack_clients_submit_and_query() -> Result<()> {
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let state_manager = StateManager::new(std::env::temp_dir().join(format!("unwalled-client-test-db-{}", Uuid::new_v4())))?;
+    let (tx, mut rx) = mpsc::channel(16);
+    let handler = RpcHandler::new(tx, state_manager.clone(), broadcast::channel(16).0, ReceiptWaiters::default());
+    tokio::spawn(http::run_server(addr, handler));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let wallet = LocalWallet::new();
+    state_manager.set_balance(&wallet.address(), 500)?;
+    let client = Client::new(format!("http://{}", addr), wallet).with_wire_format(WireFormat::MessagePack);
+
+    let account = client.get_account(&client.address()).await?.expect("account");
+    assert_eq!(account.balance, 500);
+    let bid = sample_bid();
+    client.place_bid(&bid).await?;
+    match rx.recv().await.expect("transaction forwarded") {
+        Transaction::PlaceBid(signed) => {
+            assert!(signed.verify()?);
+            assert_eq!(signed.data.id, bid.id);
+        }
+        other => panic!("Unexpected transaction {:?}", other),
+    }
+
+    // Errors stay JSON, so they still carry their code.
+    assert!(client.get_bid(&Uuid::new_v4()).await?.is_none());
+    Ok(())
+}
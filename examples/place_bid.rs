@@ -1,25 +1,27 @@
 use anyhow::Result;
-use unwalled_client::{Client, Bid};
+use unwalled_client::{Bid, Client, IdentityTier, LocalWallet};
 use uuid::Uuid;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::init();
 
-    let client = Client::new("http://127.0.0.1:8080".to_string());
+    let client = Client::new("http://127.0.0.1:8080".to_string(), LocalWallet::new());
 
     let bid = Bid {
         id: Uuid::new_v4(),
-        advertiser_id: "my-advertiser".to_string(),
-        price: 150, // e.g., 1.5 cents in smallest units
-        targeting: vec!["sports".to_string(), "news".to_string()],
+        price: 150, // e.g., 0.00015 KUSD in smallest units
+        targeting: vec!["interest=sports".to_string(), "geo=usa".to_string()],
         adm: "<VAST version='4.2'>...</VAST>".to_string(),
+        expires_at_height: None,
+        requires_verified_targeting: false,
+        min_user_tier: IdentityTier::Anonymous,
     };
 
-    log::info!("Placing a sample bid: {:?}", bid);
+    log::info!("Placing a sample bid from {}: {:?}", client.address(), bid);
 
     match client.place_bid(&bid).await {
-        Ok(_) => log::info!("Bid placed successfully!"),
+        Ok(tx_hash) => log::info!("Bid placed in transaction {}", tx_hash),
         Err(e) => log::error!("Failed to place bid: {}", e),
     }
 
@@ -1,21 +1,22 @@
 use anyhow::Result;
-use unwalled_client::{Client, AuctionTrigger};
+use unwalled_client::{AuctionTrigger, Client, IdentityTier, LocalWallet};
 use uuid::Uuid;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::init();
 
-    let client = Client::new("http://127.0.0.1:8080".to_string());
+    let client = Client::new("http://127.0.0.1:8080".to_string(), LocalWallet::new());
 
     let auction = AuctionTrigger {
         id: Uuid::new_v4(),
-        publisher_id: "my-publisher".to_string(),
-        bid_floor: 100, // 1 cent floor
-        attributes: vec!["sports".to_string(), "news".to_string(), "premium".to_string()],
+        bid_floor: 100, // 0.0001 KUSD floor
+        attributes: vec!["interest=sports".to_string(), "geo=usa".to_string(), "device=mobile".to_string()],
+        presentation: None,
+        min_advertiser_tier: IdentityTier::Anonymous,
     };
 
-    log::info!("Triggering an auction: {:?}", auction);
+    log::info!("Triggering an auction from {}: {:?}", client.address(), auction);
 
     match client.trigger_auction(&auction).await {
         Ok(Some(match_result)) => {
@@ -0,0 +1,62 @@
+//! Compares JSON and MessagePack for gossiped and submitted transactions:
+//! the encoded size of each, printed once, and encode and decode times.
+//!
+//! Run with `cargo bench -p unwalled-node --bench wire`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use unwalled_node::consensus::Transaction;
+use unwalled_node::identity::LocalWallet;
+use unwalled_node::primitives::{AuctionTrigger, Bid, IdentityTier, Signed};
+use unwalled_node::wire::WireFormat;
+use uuid::Uuid;
+
+const FORMATS: [(&str, WireFormat); 2] = [("json", WireFormat::Json), ("msgpack", WireFormat::MessagePack)];
+
+/// A bid and an auction trigger with typical targeting, as an exchange
+/// would see them.
+fn transactions() -> Vec<(&'static str, Transaction)> {
+    let wallet = LocalWallet::new();
+    let targeting: Vec<String> =
+        ["geo=de", "device=mobile", "os=ios", "interest=sports", "segment=acme:42"].iter().map(|s| s.to_string()).collect();
+    let bid = Bid {
+        id: Uuid::new_v4(),
+        price: 1_250,
+        targeting: targeting.clone(),
+        adm: "<VAST version='4.2'><Ad id='1'><InLine><AdTitle>ad</AdTitle></InLine></Ad></VAST>".to_string(),
+        expires_at_height: Some(100_000),
+        requires_verified_targeting: false,
+        min_user_tier: IdentityTier::Anonymous,
+    };
+    let auction = AuctionTrigger {
+        id: Uuid::new_v4(),
+        bid_floor: 500,
+        attributes: targeting,
+        presentation: None,
+        min_advertiser_tier: IdentityTier::Anonymous,
+    };
+    vec![
+        ("place_bid", Transaction::PlaceBid(Signed::sign(bid, 7, 10, &wallet).expect("sign bid"))),
+        ("trigger_auction", Transaction::TriggerAuction(Signed::sign(auction, 8, 10, &wallet).expect("sign trigger"))),
+    ]
+}
+
+fn wire_formats(c: &mut Criterion) {
+    for (name, transaction) in transactions() {
+        let mut group = c.benchmark_group(name);
+        for (format_name, format) in FORMATS {
+            let encoded = format.encode(&transaction).expect("encode");
+            println!("{}/{}: {} bytes", name, format_name, encoded.len());
+
+            group.bench_with_input(BenchmarkId::new("encode", format_name), &transaction, |b, transaction| {
+                b.iter(|| format.encode(black_box(transaction)).expect("encode"))
+            });
+            group.bench_with_input(BenchmarkId::new("decode", format_name), &encoded, |b, encoded| {
+                b.iter(|| format.decode::<Transaction>(black_box(encoded)).expect("decode"))
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, wire_formats);
+criterion_main!(benches);
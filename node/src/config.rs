@@ -1,10 +1,52 @@
+use crate::primitives::DEFAULT_CHAIN_ID;
+use anyhow::{Context, Result};
 use serde::Deserialize;
 use std::net::SocketAddr;
+use std::path::Path;
 
+/// The node configuration. Fields missing from a configuration file keep
+/// their defaults.
 #[derive(Debug, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub rpc_listen_address: SocketAddr,
+    /// TCP address for the HTTP/1.1 and HTTP/2 fallback RPC endpoint.
+    pub http_listen_address: SocketAddr,
+    /// PEM certificate chain and private key for the HTTP/3 RPC server. When
+    /// either is unset a self-signed certificate for `localhost` is generated.
+    pub rpc_cert_path: Option<String>,
+    pub rpc_key_path: Option<String>,
     pub db_path: String,
+    /// The validator set's ed25519 public keys as `did:key` DIDs (or hex),
+    /// used to verify commit certificates during block sync. When empty, the
+    /// node runs a single-validator development chain with its own key.
+    pub validators: Vec<String>,
+    /// `did:key` DIDs allowed to add, remove and re-scope trusted credential issuers.
+    pub governors: Vec<String>,
+    /// The network transactions must be signed for.
+    pub chain_id: String,
+    /// Directory where state snapshots are written and served from.
+    pub snapshot_dir: String,
+    /// Create a snapshot every this many blocks; 0 disables periodic snapshots.
+    pub snapshot_interval: u64,
+    /// Anchor a state commitment on the settlement layer every this many
+    /// blocks; 0 disables anchoring.
+    pub commitment_interval: u64,
+    /// `did:key` DIDs of the attesters whose signatures confirm deposits, and
+    /// how many must sign each one. With no attesters, deposits and
+    /// withdrawals are disabled.
+    pub settlement_attesters: Vec<String>,
+    pub settlement_attestation_threshold: usize,
+    /// Start an empty node from a peer's snapshot anchored at this trusted
+    /// block, as `<height>:<block hash hex>`, instead of replaying from
+    /// genesis. Commit certificates do not cover state roots, so fast sync
+    /// is off unless a checkpoint is given.
+    pub fast_sync_checkpoint: Option<String>,
+    /// Encrypted keystores for the validator signing key and the libp2p
+    /// identity, unlocked with `KEYSTORE_PASSWORD_ENV`. Each is generated on
+    /// first start; when unset an ephemeral key is used.
+    pub validator_key_path: Option<String>,
+    pub p2p_key_path: Option<String>,
     // Add other configuration fields as needed
 }
 
@@ -12,13 +54,45 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             rpc_listen_address: "127.0.0.1:8080".parse().unwrap(),
+            http_listen_address: "127.0.0.1:8080".parse().unwrap(),
+            rpc_cert_path: None,
+            rpc_key_path: None,
             db_path: "/tmp/unwalled-node-db".to_string(),
+            validators: Vec::new(),
+            governors: Vec::new(),
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
+            snapshot_dir: "/tmp/unwalled-node-snapshots".to_string(),
+            snapshot_interval: 10_000,
+            commitment_interval: 1_000,
+            settlement_attesters: Vec::new(),
+            settlement_attestation_threshold: 1,
+            fast_sync_checkpoint: None,
+            validator_key_path: None,
+            p2p_key_path: None,
         }
     }
 }
 
-/// Loads the node configuration.
-/// For now, it just returns the default configuration.
-pub fn load_config() -> anyhow::Result<Config> {
-    Ok(Config::default())
+impl Config {
+    /// Reads a JSON configuration file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).with_context(|| format!("Failed to read config file {}", path.display()))?;
+        serde_json::from_slice(&bytes).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+}
+
+/// The environment variable holding the password for the node's keystores.
+pub const KEYSTORE_PASSWORD_ENV: &str = "UNWALLED_KEYSTORE_PASSWORD";
+
+/// The environment variable naming the node's JSON configuration file.
+pub const CONFIG_PATH_ENV: &str = "UNWALLED_CONFIG";
+
+/// Loads the node configuration from the file named by `CONFIG_PATH_ENV`,
+/// or the defaults when it is unset.
+pub fn load_config() -> Result<Config> {
+    match std::env::var_os(CONFIG_PATH_ENV) {
+        Some(path) => Config::from_file(path),
+        None => Ok(Config::default()),
+    }
 }
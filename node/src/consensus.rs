@@ -1,66 +1,1465 @@
-use crate::primitives::{AuctionTrigger, Bid, Signed};
-use crate::state::StateManager;
+use crate::credentials::presentation_challenge;
+use crate::error::{self, ErrorCode, NodeError};
+use crate::identity::{self, Address, LocalWallet, PublicKey};
+use crate::mempool::Mempool;
+use crate::network::ConsensusNetwork;
+use crate::primitives::{
+    self, AuctionTrigger, Bid, CancelBid, ChainEvent, Deposit, Hash, IdentityTier, IdentityTierUpdate, IssuerRegistryUpdate,
+    Action, Match, MultisigAccount, MultisigAction, MultisigProposal, MultisigUpdate, PendingWithdrawal, ProposalStatus, Receipt, ReceiptStatus,
+    SessionKeyInfo, SessionKeyUpdate, Signed, Withdraw, DELEGABLE_ACTIONS, IDENTITY_TIER_NAMESPACE,
+};
+use crate::settlement::SettlementAttesters;
+use crate::snapshot::SnapshotStore;
+use crate::state::{ConsensusStore, StateManager};
+use crate::wire::WireFormat;
 use anyhow::Result;
-use hotstuff_rs::app::App;
+use borsh::{BorshDeserialize, BorshSerialize};
+use hotstuff_rs::app::{App, ProduceBlockRequest, ProduceBlockResponse, ValidateBlockRequest, ValidateBlockResponse};
+use hotstuff_rs::block_tree::accessors::app::AppBlockTreeView;
+use hotstuff_rs::block_tree::accessors::public::BlockTreeSnapshot;
+use hotstuff_rs::block_tree::pluggables::{KVGet, KVStore};
+use hotstuff_rs::hotstuff::types::{Phase, PhaseCertificate};
+use hotstuff_rs::types::block::Block as ConsensusBlock;
+use hotstuff_rs::replica::{Configuration, Replica, ReplicaSpec};
+use hotstuff_rs::types::data_types::{BlockHeight, BufferSize, ChainID, CryptoHash, Data, DataLen, Datum, EpochLength, Power};
+use hotstuff_rs::types::update_sets::AppStateUpdates;
+use hotstuff_rs::types::validator_set::ValidatorSetState;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
 
 // The transactions that our state machine can process.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Transaction {
     PlaceBid(Signed<Bid>),
     TriggerAuction(Signed<AuctionTrigger>),
+    CancelBid(Signed<CancelBid>),
+    Deposit(Signed<Deposit>),
+    Withdraw(Signed<Withdraw>),
+    UpdateIssuerRegistry(Signed<IssuerRegistryUpdate>),
+    UpdateIdentityTier(Signed<IdentityTierUpdate>),
+    UpdateSessionKey(Signed<SessionKeyUpdate>),
+    UpdateMultisig(Signed<MultisigUpdate>),
+}
+
+impl Transaction {
+    /// The transaction id: `Signed::hash` of the wrapped action. It does not
+    /// depend on the wire format the transaction arrived in, so mempools,
+    /// gossip, receipts and RPC lookups all agree on it.
+    pub fn hash(&self) -> Result<Hash> {
+        match self {
+            Transaction::PlaceBid(signed) => signed.hash(),
+            Transaction::TriggerAuction(signed) => signed.hash(),
+            Transaction::CancelBid(signed) => signed.hash(),
+            Transaction::Deposit(signed) => signed.hash(),
+            Transaction::Withdraw(signed) => signed.hash(),
+            Transaction::UpdateIssuerRegistry(signed) => signed.hash(),
+            Transaction::UpdateIdentityTier(signed) => signed.hash(),
+            Transaction::UpdateSessionKey(signed) => signed.hash(),
+            Transaction::UpdateMultisig(signed) => signed.hash(),
+        }
+    }
+
+    /// Checks the signature of the wrapped action.
+    pub fn verify(&self) -> Result<bool> {
+        match self {
+            Transaction::PlaceBid(signed) => signed.verify(),
+            Transaction::TriggerAuction(signed) => signed.verify(),
+            Transaction::CancelBid(signed) => signed.verify(),
+            Transaction::Deposit(signed) => signed.verify(),
+            Transaction::Withdraw(signed) => signed.verify(),
+            Transaction::UpdateIssuerRegistry(signed) => signed.verify(),
+            Transaction::UpdateIdentityTier(signed) => signed.verify(),
+            Transaction::UpdateSessionKey(signed) => signed.verify(),
+            Transaction::UpdateMultisig(signed) => signed.verify(),
+        }
+    }
+
+    /// The signer, nonce and fee of the wrapped action.
+    fn envelope(&self) -> (&PublicKey, u64, u64) {
+        match self {
+            Transaction::PlaceBid(signed) => (&signed.signer, signed.nonce, signed.fee),
+            Transaction::TriggerAuction(signed) => (&signed.signer, signed.nonce, signed.fee),
+            Transaction::CancelBid(signed) => (&signed.signer, signed.nonce, signed.fee),
+            Transaction::Deposit(signed) => (&signed.signer, signed.nonce, signed.fee),
+            Transaction::Withdraw(signed) => (&signed.signer, signed.nonce, signed.fee),
+            Transaction::UpdateIssuerRegistry(signed) => (&signed.signer, signed.nonce, signed.fee),
+            Transaction::UpdateIdentityTier(signed) => (&signed.signer, signed.nonce, signed.fee),
+            Transaction::UpdateSessionKey(signed) => (&signed.signer, signed.nonce, signed.fee),
+            Transaction::UpdateMultisig(signed) => (&signed.signer, signed.nonce, signed.fee),
+        }
+    }
+
+    /// Checks a transaction against committed state before it is admitted to
+    /// the mempool: its nonce is not already used, and whoever pays its fee
+    /// can currently afford it. Delivery checks both again exactly; this
+    /// only keeps transactions that cannot succeed out of blocks.
+    pub fn precheck(&self, state_manager: &StateManager) -> Result<()> {
+        let (signer, nonce, fee) = self.envelope();
+        let signer = identity::address_of(signer);
+        let expected = state_manager.get_nonce(&signer)?;
+        if nonce < expected {
+            let message = format!("Nonce {} for {} is already used, expected at least {}", nonce, signer, expected);
+            return Err(NodeError::new(ErrorCode::BadNonce, message).into());
+        }
+        let payer = match state_manager.get_session_key(&signer)? {
+            Some(session) => session.principal,
+            None => signer,
+        };
+        if state_manager.get_balance(&payer)? < fee {
+            let message = format!("{} cannot pay a fee of {}", payer, fee);
+            return Err(NodeError::new(ErrorCode::InsufficientFunds, message).into());
+        }
+        Ok(())
+    }
+}
+
+/// The most transactions proposed in one block.
+pub const MAX_BLOCK_TRANSACTIONS: usize = 1_000;
+
+/// The most encoded transaction bytes proposed in one block, keeping
+/// proposals well within the gossip message size limit.
+pub const MAX_BLOCK_BYTES: usize = 1024 * 1024;
+
+/// The hash a consensus block commits to its transactions with: the hash
+/// of their concatenated transaction ids.
+pub fn transactions_hash(transactions: &[Transaction]) -> Result<Hash> {
+    let ids = transactions.iter().map(Transaction::hash).collect::<Result<Vec<_>>>()?;
+    Ok(hash_ids(&ids))
+}
+
+fn hash_ids(ids: &[Hash]) -> Hash {
+    primitives::sha256(&ids.concat())
+}
+
+/// The consensus engine's numeric chain id for the network `chain_id`, so
+/// votes cast for one network do not count on another.
+pub fn consensus_chain_id(chain_id: &str) -> ChainID {
+    let digest = primitives::sha256(chain_id.as_bytes());
+    ChainID::new(u64::from_be_bytes(digest[..8].try_into().expect("8 bytes")))
+}
+
+/// A block of ordered transactions produced by consensus.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    pub height: u64,
+    /// The hash of the block at `height - 1`, or all zeroes for the first block.
+    pub parent_hash: Hash,
+    pub transactions: Vec<Transaction>,
+    /// The `StateManager::state_root` after applying this block's transactions.
+    pub state_root: Hash,
+}
+
+impl Block {
+    /// Computes the hash that identifies this block in the node's history.
+    pub fn hash(&self) -> Result<Hash> {
+        Ok(primitives::sha256(&serde_json::to_vec(self)?))
+    }
+}
+
+/// Proof that consensus committed a block: the consensus engine's phase
+/// certificate over the consensus block that carried the block's
+/// transactions, at consensus height `height - 1`, and the certified
+/// descendants needed to show it was committed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CommitCertificate {
+    /// The borsh-encoded phase certificate the consensus block extends. The
+    /// consensus block hash covers it.
+    #[serde(with = "crate::identity::raw_bytes")]
+    pub justify: Vec<u8>,
+    /// The borsh-encoded phase certificate over the consensus block.
+    #[serde(with = "crate::identity::raw_bytes")]
+    pub phase_certificate: Vec<u8>,
+    /// The consensus block's descendants in height order, each extending the
+    /// one before it. Empty when `phase_certificate` proves the commit alone.
+    #[serde(default)]
+    pub descendants: Vec<CertifiedDescendant>,
+}
+
+/// A descendant of a certified consensus block. Its justify is the phase
+/// certificate over its parent, so its hash follows from its data hash.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CertifiedDescendant {
+    pub data_hash: Hash,
+    /// The borsh-encoded phase certificate over the descendant.
+    #[serde(with = "crate::identity::raw_bytes")]
+    pub phase_certificate: Vec<u8>,
+}
+
+/// A block together with the certificate that finalised it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommittedBlock {
+    pub block: Block,
+    pub certificate: CommitCertificate,
+}
+
+/// The set of validators whose signatures count towards a commit quorum.
+/// Every validator has one vote.
+#[derive(Debug, Clone)]
+pub struct ValidatorSet {
+    /// Ordered by key bytes, as the consensus engine orders signatures.
+    validators: Vec<PublicKey>,
+    /// The consensus engine's chain id, see `consensus_chain_id`.
+    chain_id: u64,
+}
+
+impl ValidatorSet {
+    /// The validators of the network `chain_id`.
+    pub fn new(chain_id: &str, mut validators: Vec<PublicKey>) -> Self {
+        validators.sort_by(|a, b| a.0.cmp(&b.0));
+        validators.dedup();
+        Self { validators, chain_id: consensus_chain_id(chain_id).int() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.validators.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+
+    /// The number of distinct validator signatures required to commit a block.
+    pub fn quorum(&self) -> usize {
+        self.validators.len() * 2 / 3 + 1
+    }
+
+    /// The set as the consensus engine's genesis validator set.
+    pub fn to_consensus(&self) -> Result<hotstuff_rs::types::validator_set::ValidatorSet> {
+        let mut validator_set = hotstuff_rs::types::validator_set::ValidatorSet::new();
+        for validator in &self.validators {
+            validator_set.put(&verifying_key(validator)?, Power::new(1));
+        }
+        Ok(validator_set)
+    }
+
+    /// Checks that a committed block is certified by a quorum of this
+    /// validator set, and that its certificates prove consensus committed it
+    /// rather than merely voted for it.
+    pub fn verify(&self, committed: &CommittedBlock) -> Result<()> {
+        let height = committed.block.height;
+        let certificate = &committed.certificate;
+        let justify = PhaseCertificate::try_from_slice(&certificate.justify)?;
+        let consensus_height = height
+            .checked_sub(1)
+            .ok_or_else(|| anyhow::anyhow!("Block height 0 cannot be certified"))?;
+        let data_hash = CryptoHash::new(transactions_hash(&committed.block.transactions)?);
+        let mut block_hash = ConsensusBlock::hash(BlockHeight::new(consensus_height), &justify, &data_hash);
+
+        let mut phase_certificate = PhaseCertificate::try_from_slice(&certificate.phase_certificate)?;
+        let mut phase_certificates = Vec::with_capacity(certificate.descendants.len() + 1);
+        for (offset, descendant) in (1..).zip(&certificate.descendants) {
+            self.verify_phase_certificate(&phase_certificate, &block_hash, height)?;
+            let data_hash = CryptoHash::new(descendant.data_hash);
+            block_hash = ConsensusBlock::hash(BlockHeight::new(consensus_height + offset), &phase_certificate, &data_hash);
+            let next = PhaseCertificate::try_from_slice(&descendant.phase_certificate)?;
+            phase_certificates.push(std::mem::replace(&mut phase_certificate, next));
+        }
+        self.verify_phase_certificate(&phase_certificate, &block_hash, height)?;
+        phase_certificates.push(phase_certificate);
+
+        if !proves_commit(&phase_certificates) {
+            return Err(anyhow::anyhow!("Certificate for block at height {} does not prove it was committed", height));
+        }
+        Ok(())
+    }
+
+    /// Checks that `phase_certificate` is over `block_hash` and signed by a
+    /// quorum.
+    fn verify_phase_certificate(&self, phase_certificate: &PhaseCertificate, block_hash: &CryptoHash, height: u64) -> Result<()> {
+        if phase_certificate.block != *block_hash || phase_certificate.chain_id.int() != self.chain_id {
+            return Err(anyhow::anyhow!("Certificate does not match block at height {}", height));
+        }
+
+        let message = (phase_certificate.chain_id, phase_certificate.view, phase_certificate.block, phase_certificate.phase)
+            .try_to_vec()?;
+        let signatures = &phase_certificate.signatures;
+        let signers = if signatures.len() == self.validators.len() {
+            self.validators
+                .iter()
+                .zip(signatures.iter())
+                .filter(|(validator, signature)| match signature {
+                    Some(signature) => {
+                        let signature = identity::Signature(signature.bytes().to_vec());
+                        identity::verify_signature(validator, &message, &signature).unwrap_or(false)
+                    }
+                    None => false,
+                })
+                .count()
+        } else {
+            0
+        };
+
+        if self.validators.is_empty() || signers < self.quorum() {
+            return Err(anyhow::anyhow!(
+                "Block at height {} has {} valid signatures, {} required",
+                height,
+                signers,
+                self.quorum()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Whether a chain of phase certificates, each over the child of the block
+/// the one before certifies, commits the first certified block under the
+/// consensus engine's commit rule. A Commit or Decide certificate commits
+/// its block and that block's ancestors; so do Generic certificates from
+/// three consecutive views for the first of their blocks. Prepare and
+/// Precommit certificates commit nothing.
+fn proves_commit(phase_certificates: &[PhaseCertificate]) -> bool {
+    phase_certificates
+        .iter()
+        .any(|certificate| matches!(certificate.phase, Phase::Commit | Phase::Decide))
+        || phase_certificates.windows(3).any(|chain| {
+            chain.iter().all(|certificate| certificate.phase.is_generic())
+                && chain[1].view == chain[0].view + 1
+                && chain[2].view == chain[1].view + 1
+        })
+}
+
+/// The ed25519 key of a validator, as the consensus engine identifies it.
+pub fn verifying_key(validator: &PublicKey) -> Result<ed25519_dalek::VerifyingKey> {
+    let bytes: &[u8; 32] = validator
+        .0
+        .as_slice()
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Validator keys must be ed25519 keys"))?;
+    Ok(ed25519_dalek::VerifyingKey::from_bytes(bytes)?)
+}
+
+/// Reads the block committed at consensus height `height - 1` out of the
+/// consensus engine's block tree, with certificates proving it committed.
+///
+/// Returns `None` while no block is committed at that height yet, or while
+/// the block tree does not hold the certificates to prove it.
+pub fn committed_transactions<S: KVGet>(
+    block_tree: &BlockTreeSnapshot<S>,
+    height: u64,
+) -> Result<Option<(Vec<Transaction>, CommitCertificate)>> {
+    let Some(consensus_height) = height.checked_sub(1) else {
+        return Ok(None);
+    };
+    let Some(block_hash) = block_tree.block_at_height(BlockHeight::new(consensus_height)).map_err(block_tree_error)? else {
+        return Ok(None);
+    };
+    let Some(block) = block_tree.block(&block_hash).map_err(block_tree_error)? else {
+        return Ok(None);
+    };
+
+    // Follow the block's descendants until their certificates satisfy the
+    // commit rule.
+    let mut phase_certificates = Vec::new();
+    let mut data_hashes = Vec::new();
+    let mut current = (block_hash, BlockHeight::new(consensus_height));
+    loop {
+        let Some((child, phase_certificate)) = certified_child(block_tree, &current.0, current.1)? else {
+            return Ok(None);
+        };
+        phase_certificates.push(phase_certificate);
+        if proves_commit(&phase_certificates) {
+            break;
+        }
+        let Some(child) = child else {
+            return Ok(None);
+        };
+        let data_hash = block_tree
+            .block_data_hash(&child)
+            .map_err(block_tree_error)?
+            .ok_or_else(|| anyhow::anyhow!("Stored block has no data hash"))?;
+        data_hashes.push(data_hash.bytes());
+        current = (child, current.1 + 1);
+    }
+
+    let data_len = block_tree.block_data_len(&block_hash).map_err(block_tree_error)?;
+    let transactions = stored_transactions(data_len, |index| block_tree.block_datum(&block_hash, index))?;
+    let mut phase_certificates = phase_certificates.iter().map(|certificate| certificate.try_to_vec());
+    let certificate = CommitCertificate {
+        justify: block.justify.try_to_vec()?,
+        phase_certificate: phase_certificates.next().expect("one certificate per block")?,
+        descendants: data_hashes
+            .into_iter()
+            .zip(phase_certificates)
+            .map(|(data_hash, phase_certificate)| Ok(CertifiedDescendant { data_hash, phase_certificate: phase_certificate? }))
+            .collect::<Result<_>>()?,
+    };
+    Ok(Some((transactions, certificate)))
+}
+
+/// A child of `block`, preferring the committed one, with the phase
+/// certificate over `block` it carries as its justify. While `block` has no
+/// child yet, the highest certificate stands in if it is over `block`.
+fn certified_child<S: KVGet>(
+    block_tree: &BlockTreeSnapshot<S>,
+    block: &CryptoHash,
+    height: BlockHeight,
+) -> Result<Option<(Option<CryptoHash>, PhaseCertificate)>> {
+    // The block tree stores no children list for a block without children.
+    let children = block_tree.children(block).map(|children| children.vec().clone()).unwrap_or_default();
+    let committed = block_tree.block_at_height(height + 1).map_err(block_tree_error)?;
+    let child = committed
+        .filter(|committed| children.contains(committed))
+        .or_else(|| children.first().copied());
+    if let Some(child) = child {
+        let justify = block_tree.block_justify(&child).map_err(block_tree_error)?;
+        if justify.block == *block {
+            return Ok(Some((Some(child), justify)));
+        }
+    }
+    let highest = block_tree.highest_pc().map_err(block_tree_error)?;
+    Ok((highest.block == *block).then_some((None, highest)))
+}
+
+fn block_tree_error(e: hotstuff_rs::block_tree::accessors::internal::BlockTreeError) -> anyhow::Error {
+    anyhow::anyhow!("Block tree error: {:?}", e)
+}
+
+/// Decodes a consensus block's data: one MessagePack-encoded transaction per datum.
+fn decode_data(data: &Data) -> Result<Vec<Transaction>> {
+    data.iter().map(|datum| WireFormat::MessagePack.decode(datum.bytes())).collect()
+}
+
+/// Decodes the data of a block stored in the block tree, one datum at a
+/// time. The block tree's `block` and `block_data` accessors return stored
+/// blocks with their data dropped in hotstuff_rs 0.4, so they cannot be used
+/// for this.
+fn stored_transactions(data_len: Option<DataLen>, datum: impl Fn(u32) -> Option<Datum>) -> Result<Vec<Transaction>> {
+    let Some(data_len) = data_len else {
+        return Err(anyhow::anyhow!("Stored block has no data length"));
+    };
+    (0..data_len.int())
+        .map(|index| {
+            let datum = datum(index).ok_or_else(|| anyhow::anyhow!("Stored block is missing datum {}", index))?;
+            WireFormat::MessagePack.decode(datum.bytes())
+        })
+        .collect()
+}
+
+/// Starts the consensus engine, which proposes blocks through
+/// `block_producer` and signals `commits` whenever it commits one.
+pub fn start_replica(
+    chain_id: &str,
+    state_manager: &StateManager,
+    validator_set: &ValidatorSet,
+    validator_wallet: &LocalWallet,
+    block_producer: BlockProducer,
+    consensus_network: ConsensusNetwork,
+    commits: mpsc::UnboundedSender<()>,
+) -> Result<Replica<ConsensusStore>> {
+    let kv_store = state_manager.consensus_store();
+    if kv_store.committed_validator_set().is_err() {
+        let validators = validator_set.to_consensus()?;
+        Replica::initialize(
+            kv_store.clone(),
+            AppStateUpdates::new(),
+            ValidatorSetState::new(validators.clone(), validators, None, true),
+        );
+    }
+
+    let configuration = Configuration::builder()
+        .me(validator_wallet.signing_key().clone())
+        .chain_id(consensus_chain_id(chain_id))
+        .block_sync_request_limit(10)
+        .block_sync_server_advertise_time(Duration::from_secs(10))
+        .block_sync_response_timeout(Duration::from_secs(3))
+        .block_sync_blacklist_expiry_time(Duration::from_secs(10))
+        .block_sync_trigger_min_view_difference(2)
+        .block_sync_trigger_timeout(Duration::from_secs(60))
+        .progress_msg_buffer_capacity(BufferSize::new(1024 * 1024 * 1024))
+        .epoch_length(EpochLength::new(50))
+        .max_view_time(Duration::from_secs(2))
+        .log_events(false)
+        .build();
+
+    Ok(ReplicaSpec::builder()
+        .app(block_producer)
+        .network(consensus_network)
+        .kv_store(kv_store)
+        .configuration(configuration)
+        .on_commit_block(move |_| {
+            let _ = commits.send(());
+        })
+        .build()
+        .start())
+}
+
+/// Executes every block the consensus engine has committed beyond the
+/// local tip, and drops their transactions from the mempool. Blocks the
+/// block syncer already applied are skipped.
+pub fn apply_committed_blocks(
+    replica: &Replica<ConsensusStore>,
+    app: &mut ConsensusApp,
+    state_manager: &StateManager,
+    mempool: &Mutex<Mempool>,
+) -> Result<()> {
+    let block_tree = replica.block_tree_camera().snapshot();
+    loop {
+        let height = state_manager.latest_height()? + 1;
+        let Some((transactions, certificate)) = committed_transactions(&block_tree, height)? else {
+            return Ok(());
+        };
+        let committed = app.commit_block(transactions, certificate)?;
+        let mut mempool = mempool.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for transaction in &committed.block.transactions {
+            mempool.remove(&transaction.hash()?);
+        }
+    }
+}
+
+/// Proposes and checks consensus blocks for the consensus engine.
+///
+/// Blocks carry pending transactions from the mempool, one per datum, and
+/// commit to them with `transactions_hash`. Transactions are executed only
+/// once their block is committed, by `ConsensusApp::commit_block`, so
+/// proposing and voting never touch application state.
+pub struct BlockProducer {
+    mempool: Arc<Mutex<Mempool>>,
+    /// Committed state, for the nonce each signer uses next.
+    state_manager: StateManager,
+    /// When set, a block may only carry deposits with enough attestations.
+    settlement: Option<SettlementAttesters>,
+}
+
+impl BlockProducer {
+    pub fn new(mempool: Arc<Mutex<Mempool>>, state_manager: StateManager) -> Self {
+        Self { mempool, state_manager, settlement: None }
+    }
+
+    /// Only proposes and accepts deposits attested by `attesters`.
+    pub fn with_settlement(mut self, attesters: SettlementAttesters) -> Self {
+        self.settlement = Some(attesters);
+        self
+    }
+
+    /// Checks what can be decided about `transaction` from the transaction
+    /// alone: its signature, and a deposit's attestations.
+    fn admissible(&self, transaction: &Transaction) -> Result<()> {
+        if !transaction.verify().unwrap_or(false) {
+            return Err(anyhow::anyhow!("Invalid signature"));
+        }
+        if let (Transaction::Deposit(signed_deposit), Some(settlement)) = (transaction, &self.settlement) {
+            settlement.verify(&signed_deposit.data)?;
+        }
+        Ok(())
+    }
+
+    /// Picks pending transactions not already proposed in an uncommitted
+    /// ancestor of the new block, oldest first, returning their ids and
+    /// encodings. Each signer's transactions are proposed in nonce order
+    /// from the signer's next nonce, so a transaction that arrives ahead of
+    /// its predecessor is held until the gap fills. Transactions that would
+    /// make the block invalid, or whose nonce is already used, are dropped
+    /// from the mempool.
+    fn propose<K: KVStore>(&self, block_tree: &AppBlockTreeView<K>, parent: Option<CryptoHash>) -> Result<Vec<(Hash, Vec<u8>)>> {
+        let (proposed, mut next_nonces) = uncommitted_transactions(block_tree, parent)?;
+        let mut mempool = self.mempool.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut transactions = Vec::new();
+        let mut dropped = Vec::new();
+        let mut held: HashMap<Address, BTreeMap<u64, (Hash, &Transaction)>> = HashMap::new();
+        let mut bytes = 0;
+        'pending: for (hash, transaction) in mempool.iter() {
+            if proposed.contains(hash) {
+                continue;
+            }
+            if let Err(e) = self.admissible(transaction) {
+                log::debug!("Dropping transaction {}: {}", hex::encode(hash), e);
+                dropped.push(*hash);
+                continue;
+            }
+            let (signer, nonce, _) = transaction.envelope();
+            let signer = identity::address_of(signer);
+            let committed = self.state_manager.get_nonce(&signer)?;
+            if nonce < committed {
+                log::debug!("Dropping transaction {}: nonce {} is already used", hex::encode(hash), nonce);
+                dropped.push(*hash);
+                continue;
+            }
+            let next_nonce = next_nonces.entry(signer.clone()).or_insert(committed);
+            if nonce != *next_nonce {
+                // Ahead of a missing nonce, or conflicting with one already
+                // proposed: wait for the gap to fill or the other to commit.
+                held.entry(signer).or_default().entry(nonce).or_insert((*hash, transaction));
+                continue;
+            }
+            let mut ready = Some((*hash, transaction));
+            while let Some((hash, transaction)) = ready {
+                let encoded = WireFormat::MessagePack.encode(transaction)?;
+                if transactions.len() == MAX_BLOCK_TRANSACTIONS || bytes + encoded.len() > MAX_BLOCK_BYTES {
+                    break 'pending;
+                }
+                bytes += encoded.len();
+                transactions.push((hash, encoded));
+                *next_nonce += 1;
+                ready = held.get_mut(&signer).and_then(|held| held.remove(&*next_nonce));
+            }
+        }
+        for hash in dropped {
+            mempool.remove(&hash);
+        }
+        Ok(transactions)
+    }
+
+    /// Checks that a proposed block's data decodes to admissible transactions
+    /// within the block limits, and matches its data hash.
+    fn check(&self, block: &ConsensusBlock) -> Result<()> {
+        let bytes: usize = block.data.iter().map(|datum| datum.bytes().len()).sum();
+        if block.data.iter().count() > MAX_BLOCK_TRANSACTIONS || bytes > MAX_BLOCK_BYTES {
+            return Err(anyhow::anyhow!("Block exceeds the block size limits"));
+        }
+        let transactions = decode_data(&block.data)?;
+        if block.data_hash.bytes() != transactions_hash(&transactions)? {
+            return Err(anyhow::anyhow!("Block data does not match its data hash"));
+        }
+        for (index, transaction) in transactions.iter().enumerate() {
+            self.admissible(transaction).map_err(|e| anyhow::anyhow!("Transaction {}: {:#}", index, e))?;
+        }
+        Ok(())
+    }
+
+    fn validate<K: KVStore>(&self, request: ValidateBlockRequest<K>) -> ValidateBlockResponse {
+        match self.check(request.proposed_block()) {
+            Ok(()) => ValidateBlockResponse::Valid { app_state_updates: None, validator_set_updates: None },
+            Err(e) => {
+                log::warn!("Rejecting proposed block: {:#}", e);
+                ValidateBlockResponse::Invalid
+            }
+        }
+    }
+}
+
+impl<K: KVStore> App<K> for BlockProducer {
+    fn produce_block(&mut self, request: ProduceBlockRequest<K>) -> ProduceBlockResponse {
+        let transactions = self.propose(request.block_tree(), request.parent_block()).unwrap_or_else(|e| {
+            log::error!("Failed to select transactions, proposing an empty block: {:#}", e);
+            Vec::new()
+        });
+        log::debug!("Proposing a block of {} transactions", transactions.len());
+        let (ids, data): (Vec<Hash>, Vec<Datum>) =
+            transactions.into_iter().map(|(hash, encoded)| (hash, Datum::new(encoded))).unzip();
+        ProduceBlockResponse {
+            data_hash: CryptoHash::new(hash_ids(&ids)),
+            data: Data::new(data),
+            app_state_updates: None,
+            validator_set_updates: None,
+        }
+    }
+
+    fn validate_block(&mut self, request: ValidateBlockRequest<K>) -> ValidateBlockResponse {
+        self.validate(request)
+    }
+
+    fn validate_block_for_sync(&mut self, request: ValidateBlockRequest<K>) -> ValidateBlockResponse {
+        self.validate(request)
+    }
+}
+
+/// The ids of the transactions in `block` and its ancestors back to the
+/// last committed block, and the nonce each of their signers uses next.
+fn uncommitted_transactions<K: KVStore>(
+    block_tree: &AppBlockTreeView<K>,
+    block: Option<CryptoHash>,
+) -> Result<(HashSet<Hash>, HashMap<Address, u64>)> {
+    let mut proposed = HashSet::new();
+    let mut next_nonces: HashMap<Address, u64> = HashMap::new();
+    let mut cursor = block;
+    while let Some(hash) = cursor {
+        let Some(block) = block_tree.block(&hash).map_err(block_tree_error)? else {
+            break;
+        };
+        if block_tree.block_at_height(block.height).map_err(block_tree_error)? == Some(hash) {
+            break;
+        }
+        let data_len = block_tree.block_data_len(&hash).map_err(block_tree_error)?;
+        for transaction in stored_transactions(data_len, |index| block_tree.block_datum(&hash, index))? {
+            proposed.insert(transaction.hash()?);
+            let (signer, nonce, _) = transaction.envelope();
+            let next_nonce = next_nonces.entry(identity::address_of(signer)).or_default();
+            *next_nonce = (*next_nonce).max(nonce + 1);
+        }
+        cursor = (!block.justify.is_genesis_pc()).then_some(block.justify.block);
+    }
+    Ok((proposed, next_nonces))
+}
+
+/// Hands receipts to RPC callers waiting on a specific transaction.
+///
+/// A waiter must be registered before its transaction is submitted, so the
+/// receipt cannot be delivered in between.
+#[derive(Debug, Clone, Default)]
+pub struct ReceiptWaiters {
+    waiters: Arc<Mutex<HashMap<Hash, Vec<oneshot::Sender<Receipt>>>>>,
+}
+
+impl ReceiptWaiters {
+    /// Returns a receiver that resolves when the receipt for `tx_hash` is written.
+    pub fn register(&self, tx_hash: Hash) -> oneshot::Receiver<Receipt> {
+        let (sender, receiver) = oneshot::channel();
+        self.lock().entry(tx_hash).or_default().push(sender);
+        receiver
+    }
+
+    /// Drops waiters for `tx_hash` whose callers have given up.
+    pub fn forget(&self, tx_hash: &Hash) {
+        let mut waiters = self.lock();
+        if let Some(senders) = waiters.get_mut(tx_hash) {
+            senders.retain(|sender| !sender.is_closed());
+            if senders.is_empty() {
+                waiters.remove(tx_hash);
+            }
+        }
+    }
+
+    fn notify(&self, tx_hash: &Hash, receipt: &Receipt) {
+        for sender in self.lock().remove(tx_hash).unwrap_or_default() {
+            let _ = sender.send(receipt.clone());
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Hash, Vec<oneshot::Sender<Receipt>>>> {
+        // The map stays consistent even if a holder panicked.
+        self.waiters.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
 }
 
 // Our application state machine.
 #[derive(Debug)]
 pub struct ConsensusApp {
     state_manager: StateManager,
+    /// Where periodic snapshots are written, and every how many blocks.
+    snapshots: Option<(SnapshotStore, u64)>,
+    /// Where state changes are published for RPC subscribers.
+    events: Option<broadcast::Sender<ChainEvent>>,
+    /// Callers waiting on the receipt of a specific transaction.
+    receipt_waiters: Option<ReceiptWaiters>,
+    /// Checks deposit attestations. Without it deposits and withdrawals are
+    /// rejected.
+    settlement: Option<SettlementAttesters>,
+    /// Addresses allowed to add, remove and re-scope trusted credential issuers.
+    governors: Vec<Address>,
+    /// The network transactions must be signed for.
+    chain_id: String,
+    /// The height of the block currently being delivered.
+    height: u64,
+    /// The id of the transaction currently being delivered.
+    transaction: Hash,
 }
 
 impl ConsensusApp {
     pub fn new(state_manager: StateManager) -> Self {
-        Self { state_manager }
+        Self {
+            state_manager,
+            snapshots: None,
+            events: None,
+            receipt_waiters: None,
+            settlement: None,
+            governors: Vec::new(),
+            chain_id: primitives::DEFAULT_CHAIN_ID.to_string(),
+            height: 0,
+            transaction: [0u8; 32],
+        }
+    }
+
+    /// Publishes committed blocks, matches, expiries and cancellations to `events`.
+    pub fn with_events(mut self, events: broadcast::Sender<ChainEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Hands each receipt to any caller waiting on its transaction.
+    pub fn with_receipt_waiters(mut self, receipt_waiters: ReceiptWaiters) -> Self {
+        self.receipt_waiters = Some(receipt_waiters);
+        self
+    }
+
+    /// Accepts deposits attested by `attesters`, and queues withdrawals for
+    /// the relayer.
+    pub fn with_settlement(mut self, attesters: SettlementAttesters) -> Self {
+        self.settlement = Some(attesters);
+        self
+    }
+
+    /// Lets `governors` manage the trusted credential issuer registry.
+    pub fn with_governors(mut self, governors: Vec<Address>) -> Self {
+        self.governors = governors;
+        self
+    }
+
+    /// Only accepts transactions signed for `chain_id`.
+    pub fn with_chain_id(mut self, chain_id: String) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    /// Enables a state snapshot every `interval` committed blocks.
+    pub fn with_snapshots(mut self, store: SnapshotStore, interval: u64) -> Self {
+        if interval > 0 {
+            self.snapshots = Some((store, interval));
+        }
+        self
+    }
+
+    /// Replays a committed block from a peer and records it as the new tip.
+    ///
+    /// The certificate covers the block's transactions but not its state
+    /// root, so the block is recorded with the root the replay produces. A
+    /// peer claiming a different root is reported as an error once the
+    /// replayed block is recorded, so the tip stays in step with state.
+    pub fn apply_block(&mut self, committed: CommittedBlock) -> Result<()> {
+        let expected_height = self.state_manager.latest_height()? + 1;
+        if committed.block.height != expected_height {
+            return Err(anyhow::anyhow!(
+                "Expected block at height {}, got {}",
+                expected_height,
+                committed.block.height
+            ));
+        }
+        let parent_hash = match self.state_manager.get_block(expected_height - 1)? {
+            Some(parent) => parent.block.hash()?,
+            None => [0u8; 32],
+        };
+        if committed.block.parent_hash != parent_hash {
+            return Err(anyhow::anyhow!("Block at height {} does not extend our chain", expected_height));
+        }
+
+        let CommittedBlock { block, certificate } = committed;
+        let replayed = self.commit_block(block.transactions, certificate)?;
+        if replayed.block.state_root != block.state_root {
+            return Err(anyhow::anyhow!(
+                "Block at height {} claims state root {}, but replaying it gives {}",
+                block.height,
+                hex::encode(block.state_root),
+                hex::encode(replayed.block.state_root)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Executes the transactions the consensus engine committed as the next
+    /// block, recording the block with the state root they produce.
+    pub fn commit_block(&mut self, transactions: Vec<Transaction>, certificate: CommitCertificate) -> Result<CommittedBlock> {
+        let height = self.state_manager.latest_height()? + 1;
+        let parent_hash = match self.state_manager.get_block(height - 1)? {
+            Some(parent) => parent.block.hash()?,
+            None => [0u8; 32],
+        };
+
+        self.deliver_block(height, &transactions)?;
+        let block = Block { height, parent_hash, transactions, state_root: self.state_manager.state_root()? };
+        let committed = CommittedBlock { block, certificate };
+        self.record_block(&committed)?;
+        Ok(committed)
+    }
+
+    /// Expires bids due at `height`, then delivers each transaction in order.
+    fn deliver_block(&mut self, height: u64, transactions: &[Transaction]) -> Result<()> {
+        self.height = height;
+        for info in self.state_manager.expire_bids(self.height)? {
+            log::info!("Bid {:?} expired at height {}", info.bid.id, self.height);
+            self.emit(ChainEvent::BidExpired { bid_id: info.bid.id, advertiser: info.advertiser });
+        }
+        for tx in transactions.iter().cloned() {
+            self.deliver(tx);
+        }
+        Ok(())
     }
-}
 
-impl App for ConsensusApp {
-    type Transaction = Transaction;
+    /// Stores an executed block as the new tip, then snapshots state if it is
+    /// due. Anchoring and payouts are left to the `Relayer`, so replaying a
+    /// block never repeats them.
+    fn record_block(&mut self, committed: &CommittedBlock) -> Result<()> {
+        self.state_manager.put_block(committed)?;
+        log::info!("Applied block at height {}", committed.block.height);
+        self.emit(ChainEvent::BlockCommitted {
+            height: committed.block.height,
+            hash: hex::encode(committed.block.hash()?),
+        });
 
-    fn deliver(&mut self, tx: Self::Transaction) {
-        log::info!("Consensus engine delivering transaction to the app state.");
+        if let Some((store, interval)) = &self.snapshots {
+            if committed.block.height.is_multiple_of(*interval) {
+                if let Err(e) = store.create(&self.state_manager) {
+                    log::error!("Failed to create snapshot at height {}: {}", committed.block.height, e);
+                }
+            }
+        }
+        Ok(())
+    }
 
-        let result: Result<()> = match tx {
+    /// Applies a transaction to state, returning the match for auction triggers.
+    fn execute(&mut self, tx: Transaction) -> Result<Option<Match>> {
+        match tx {
             Transaction::PlaceBid(signed_bid) => {
-                if signed_bid.verify().unwrap_or(false) {
-                    // TODO: Convert PublicKey to Address for fee application.
-                    // let address = ...;
-                    // self.state_manager.apply_fees(&address, signed_bid.fee)?;
-                    
-                    log::info!("Applying bid to state: {:?}", signed_bid.data.id);
-                    self.state_manager.place_bid(&signed_bid.data)
-                } else {
-                    log::warn!("Invalid signature for bid {:?}", signed_bid.data.id);
-                    Ok(())
+                if !signed_bid.verify().unwrap_or(false) {
+                    return Err(NodeError::new(ErrorCode::InvalidSignature, format!("Invalid signature for bid {:?}", signed_bid.data.id)).into());
                 }
+                let address = self.authorize(&signed_bid)?;
+
+                log::info!("Applying bid to state: {:?}", signed_bid.data.id);
+                let session_key = session_key_of(&signed_bid, &address);
+                let bid_id = self.state_manager.place_bid_as(&signed_bid.data, &address, session_key.as_ref(), self.height)?;
+                log::info!("Bid {:?} placed as {:?}", signed_bid.data.id, bid_id);
+                Ok(None)
             }
             Transaction::TriggerAuction(signed_auction) => {
-                if signed_auction.verify().unwrap_or(false) {
-                    // TODO: Convert PublicKey to Address for fee application.
-                    // let address = ...;
-                    // self.state_manager.apply_fees(&address, signed_auction.fee)?;
+                if !signed_auction.verify().unwrap_or(false) {
+                    return Err(NodeError::new(ErrorCode::InvalidSignature, format!("Invalid signature for auction {:?}", signed_auction.data.id)).into());
+                }
+                let address = self.authorize(&signed_auction)?;
+
+                let auction = &signed_auction.data;
+                if self.state_manager.is_auction_triggered(&address, &auction.id)? {
+                    let message = format!("Auction {} has already been triggered", auction.id);
+                    return Err(NodeError::new(ErrorCode::AuctionAlreadyExists, message).into());
+                }
+                let (verified, user_tier) = match &auction.presentation {
+                    Some(presentation) => {
+                        let challenge = presentation_challenge(&self.chain_id, &address, &auction.id);
+                        let verified = presentation.verified_attributes(&challenge, |credential, attribute| {
+                            self.state_manager.trusts_credential(credential, attribute)
+                        })?;
+                        let user_tier = self.user_tier(&presentation.holder, &verified)?;
+                        (verified, user_tier)
+                    }
+                    None => (Vec::new(), IdentityTier::Anonymous),
+                };
+
+                log::info!("Matching auction in state: {:?}", auction.id);
+                let auction_match = self.state_manager.find_match(auction, &verified, user_tier, &address, self.height)?;
+                if let Some(auction_match) = &auction_match {
+                    self.state_manager.settle_match(auction_match, self.height)?;
+                }
+                self.state_manager.record_auction(&address, &auction.id, self.height)?;
+                Ok(auction_match)
+            }
+            Transaction::CancelBid(signed_cancel) => {
+                if !signed_cancel.verify().unwrap_or(false) {
+                    let message = format!("Invalid signature for cancel of bid {:?}", signed_cancel.data.bid_id);
+                    return Err(NodeError::new(ErrorCode::InvalidSignature, message).into());
+                }
+                let address = self.authorize(&signed_cancel)?;
+
+                log::info!("Cancelling bid: {:?}", signed_cancel.data.bid_id);
+                let info = self.state_manager.cancel_bid(&signed_cancel.data.bid_id, &address)?;
+                self.emit(ChainEvent::BidCancelled { bid_id: info.bid.id, advertiser: info.advertiser });
+                Ok(None)
+            }
+            Transaction::Deposit(signed_deposit) => {
+                if !signed_deposit.verify().unwrap_or(false) {
+                    let message = format!("Invalid signature for deposit {}", signed_deposit.data.settlement_ref);
+                    return Err(NodeError::new(ErrorCode::InvalidSignature, message).into());
+                }
+                let settlement = self.settlement()?;
+                let address = self.authorize(&signed_deposit)?;
+
+                let Deposit { amount, settlement_ref, account, proof } = &signed_deposit.data;
+                if self.state_manager.is_deposit_claimed(settlement_ref)? {
+                    let message = format!("Deposit {} has already been claimed", settlement_ref);
+                    return Err(NodeError::new(ErrorCode::DepositAlreadyClaimed, message).into());
+                }
+                settlement.verify(&signed_deposit.data)?;
+                if proof.recipient != address {
+                    let message = format!("Deposit {} was made out to {}", settlement_ref, proof.recipient);
+                    return Err(NodeError::new(ErrorCode::DepositNotConfirmed, message).into());
+                }
+                if let Some(account) = account {
+                    if require_did(account).is_err() && self.state_manager.get_multisig_account(account)?.is_none() {
+                        let message = format!("Cannot credit unknown account {}", account);
+                        return Err(NodeError::new(ErrorCode::InvalidRequest, message).into());
+                    }
+                }
+                let account = account.as_ref().unwrap_or(&address);
+                log::info!("Crediting deposit {} of {} to {}", settlement_ref, amount, account);
+                self.state_manager.credit_deposit(account, *amount, settlement_ref)?;
+                Ok(None)
+            }
+            Transaction::Withdraw(signed_withdraw) => {
+                if !signed_withdraw.verify().unwrap_or(false) {
+                    let message = format!("Invalid signature for withdrawal to {}", signed_withdraw.data.recipient);
+                    return Err(NodeError::new(ErrorCode::InvalidSignature, message).into());
+                }
+                // Checked before charging, so chains without settlement refuse
+                // withdrawals without taking a fee.
+                self.settlement()?;
+                let address = self.authorize(&signed_withdraw)?;
+
+                let session_key = session_key_of(&signed_withdraw, &address);
+                self.withdraw(&address, session_key.as_ref(), &signed_withdraw.data)?;
+                Ok(None)
+            }
+            Transaction::UpdateIssuerRegistry(signed_update) => {
+                if !signed_update.verify().unwrap_or(false) {
+                    let message = "Invalid signature for issuer registry update";
+                    return Err(NodeError::new(ErrorCode::InvalidSignature, message).into());
+                }
+                let address = self.authorize(&signed_update)?;
+
+                log::info!("Updating issuer registry: {:?}", signed_update.data);
+                self.update_issuer_registry(&signed_update.data, &address)?;
+                Ok(None)
+            }
+            Transaction::UpdateIdentityTier(signed_update) => {
+                if !signed_update.verify().unwrap_or(false) {
+                    let message = "Invalid signature for identity tier update";
+                    return Err(NodeError::new(ErrorCode::InvalidSignature, message).into());
+                }
+                let address = self.authorize(&signed_update)?;
+
+                self.update_identity_tier(&signed_update.data, &address)?;
+                Ok(None)
+            }
+            Transaction::UpdateSessionKey(signed_update) => {
+                if !signed_update.verify().unwrap_or(false) {
+                    let message = "Invalid signature for session key update";
+                    return Err(NodeError::new(ErrorCode::InvalidSignature, message).into());
+                }
+                let address = self.authorize(&signed_update)?;
+
+                self.update_session_key(&signed_update.data, &address)?;
+                Ok(None)
+            }
+            Transaction::UpdateMultisig(signed_update) => {
+                if !signed_update.verify().unwrap_or(false) {
+                    let message = "Invalid signature for multisig update";
+                    return Err(NodeError::new(ErrorCode::InvalidSignature, message).into());
+                }
+                let address = self.authorize(&signed_update)?;
+
+                self.update_multisig(&signed_update.data, &address, signed_update.nonce)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Creates a multisig account, or records a member's proposal or
+    /// approval and runs the proposal once enough members approve.
+    fn update_multisig(&self, update: &MultisigUpdate, member: &Address, nonce: u64) -> Result<()> {
+        match update {
+            MultisigUpdate::Create { members, threshold } => {
+                validate_members(members, *threshold)?;
+                let address = identity::multisig_address(member, nonce);
+                if self.state_manager.get_multisig_account(&address)?.is_some() {
+                    let message = format!("Multisig account {} already exists", address);
+                    return Err(NodeError::new(ErrorCode::InvalidRequest, message).into());
+                }
+                log::info!("Creating {}-of-{} multisig account {}", threshold, members.len(), address);
+                self.state_manager.put_multisig_account(&MultisigAccount {
+                    address,
+                    members: members.clone(),
+                    threshold: *threshold,
+                    created_at_height: self.height,
+                    proposal_count: 0,
+                })
+            }
+            MultisigUpdate::Propose { account, proposal } => {
+                let mut multisig = self.require_multisig_member(account, member)?;
+                if let MultisigAction::SetMembers { members, threshold } = proposal {
+                    validate_members(members, *threshold)?;
+                }
+                let proposal = MultisigProposal {
+                    id: multisig.proposal_count,
+                    account: account.clone(),
+                    action: proposal.clone(),
+                    proposer: member.clone(),
+                    approvals: vec![member.clone()],
+                    status: ProposalStatus::Open,
+                    created_at_height: self.height,
+                };
+                multisig.proposal_count += 1;
+                self.state_manager.put_multisig_account(&multisig)?;
+                log::info!("{} proposes {:?} for {}", member, proposal.action, account);
+                self.run_if_approved(&multisig, proposal)
+            }
+            MultisigUpdate::Approve { account, proposal_id } => {
+                let multisig = self.require_multisig_member(account, member)?;
+                let mut proposal = self.state_manager.get_multisig_proposal(account, *proposal_id)?.ok_or_else(|| {
+                    NodeError::new(ErrorCode::ProposalNotFound, format!("Proposal {} of {} not found", proposal_id, account))
+                })?;
+                if proposal.status != ProposalStatus::Open {
+                    let message = format!("Proposal {} of {} is no longer open", proposal_id, account);
+                    return Err(NodeError::new(ErrorCode::ProposalNotOpen, message).into());
+                }
+                if proposal.approvals.contains(member) {
+                    let message = format!("{} has already approved proposal {}", member, proposal_id);
+                    return Err(NodeError::new(ErrorCode::InvalidRequest, message).into());
+                }
+                proposal.approvals.push(member.clone());
+                self.run_if_approved(&multisig, proposal)
+            }
+        }
+    }
+
+    /// Stores `proposal`, first running its action if current members'
+    /// approvals reach the threshold. A failed action is recorded on the
+    /// proposal and also fails the transaction that triggered it.
+    fn run_if_approved(&self, multisig: &MultisigAccount, mut proposal: MultisigProposal) -> Result<()> {
+        let approvals = proposal.approvals.iter().filter(|approver| multisig.members.contains(approver)).count();
+        if approvals < multisig.threshold as usize {
+            return self.state_manager.put_multisig_proposal(&proposal);
+        }
+
+        log::info!("Running proposal {} of {} with {} approvals", proposal.id, multisig.address, approvals);
+        let result = self.run_multisig_action(multisig, &proposal.action);
+        proposal.status = match &result {
+            Ok(()) => ProposalStatus::Executed { height: self.height },
+            Err(e) => {
+                let error = error::classify(e);
+                ProposalStatus::Failed { code: error.code, reason: error.message }
+            }
+        };
+        self.state_manager.put_multisig_proposal(&proposal)?;
+        result
+    }
+
+    fn run_multisig_action(&self, multisig: &MultisigAccount, action: &MultisigAction) -> Result<()> {
+        let address = &multisig.address;
+        match action {
+            MultisigAction::Withdraw(withdraw) => self.withdraw(address, None, withdraw),
+            MultisigAction::PlaceBid(bid) => self.state_manager.place_bid(bid, address, self.height).map(|_| ()),
+            MultisigAction::CancelBid(cancel) => {
+                let info = self.state_manager.cancel_bid(&cancel.bid_id, address)?;
+                self.emit(ChainEvent::BidCancelled { bid_id: info.bid.id, advertiser: info.advertiser });
+                Ok(())
+            }
+            MultisigAction::SetMembers { members, threshold } => {
+                // Validated again, as the proposal was checked against the
+                // rules at the time it was made.
+                validate_members(members, *threshold)?;
+                self.state_manager.put_multisig_account(&MultisigAccount {
+                    members: members.clone(),
+                    threshold: *threshold,
+                    ..self.state_manager.get_multisig_account(address)?.unwrap_or_else(|| multisig.clone())
+                })
+            }
+        }
+    }
+
+    /// The multisig account at `address`, if `member` is one of its members.
+    fn require_multisig_member(&self, address: &Address, member: &Address) -> Result<MultisigAccount> {
+        let multisig = self
+            .state_manager
+            .get_multisig_account(address)?
+            .ok_or_else(|| NodeError::new(ErrorCode::NotFound, format!("Multisig account {} not found", address)))?;
+        if !multisig.members.contains(member) {
+            return Err(NodeError::new(ErrorCode::Unauthorized, format!("{} is not a member of {}", member, address)).into());
+        }
+        Ok(multisig)
+    }
+
+    /// Debits `withdraw.amount` from `address` and queues it for the relayer
+    /// to pay out on the settlement layer.
+    fn withdraw(&self, address: &Address, session_key: Option<&Address>, withdraw: &Withdraw) -> Result<()> {
+        self.settlement()?;
+        let Withdraw { amount, recipient } = withdraw;
+        if let Some(session_key) = session_key {
+            self.state_manager.check_session_spend(session_key, *amount)?;
+        }
+        self.state_manager.queue_withdrawal(&PendingWithdrawal {
+            id: self.transaction,
+            height: self.height,
+            address: address.clone(),
+            amount: *amount,
+            recipient: recipient.clone(),
+        })?;
+        log::info!("Queued withdrawal of {} from {} to {}", amount, address, recipient);
+        if let Some(session_key) = session_key {
+            self.state_manager.record_session_spend(session_key, *amount)?;
+        }
+        Ok(())
+    }
+
+    /// Resolves the account a verified transaction acts for and charges it.
+    ///
+    /// A transaction signed by a session key acts for the session's
+    /// principal, if the session has not expired and is authorised for the
+    /// action. The nonce is the session key's own, so the principal and its
+    /// sessions can sign concurrently, while the fee is paid by the principal
+    /// and counts against the session's spend limit.
+    ///
+    /// Transactions signed for another network are rejected first, before
+    /// any fee is taken.
+    fn authorize<T: Action>(&self, signed: &Signed<T>) -> Result<Address> {
+        if signed.chain_id != self.chain_id {
+            let message = format!("Signed for chain {}, but this is {}", signed.chain_id, self.chain_id);
+            return Err(NodeError::new(ErrorCode::WrongChain, message).into());
+        }
+        let signer = identity::address_of(&signed.signer);
+        let Some(session) = self.state_manager.get_session_key(&signer)? else {
+            self.charge(&signer, &signer, signed.nonce, signed.fee)?;
+            return Ok(signer);
+        };
+        if session.is_expired(self.height) {
+            return Err(NodeError::new(ErrorCode::Unauthorized, format!("Session key {} has expired", signer)).into());
+        }
+        if !session.actions.iter().any(|action| action == T::TYPE) {
+            let message = format!("Session key {} is not authorised for {}", signer, T::TYPE);
+            return Err(NodeError::new(ErrorCode::Unauthorized, message).into());
+        }
+        self.state_manager.check_session_spend(&signer, signed.fee)?;
+        self.charge(&signer, &session.principal, signed.nonce, signed.fee)?;
+        self.state_manager.record_session_spend(&signer, signed.fee)?;
+        Ok(session.principal)
+    }
+
+    /// Authorises or revokes one of `principal`'s session keys.
+    fn update_session_key(&self, update: &SessionKeyUpdate, principal: &Address) -> Result<()> {
+        match update {
+            SessionKeyUpdate::Authorize { session_key, actions, spend_limit, expires_at_height } => {
+                require_did(session_key)?;
+                if session_key == principal {
+                    return Err(NodeError::new(ErrorCode::InvalidRequest, "An account cannot be its own session key").into());
+                }
+                if let Some(action) = actions.iter().find(|action| !DELEGABLE_ACTIONS.contains(&action.as_str())) {
+                    let message = format!("{} cannot be delegated; expected one of {}", action, DELEGABLE_ACTIONS.join(", "));
+                    return Err(NodeError::new(ErrorCode::InvalidRequest, message).into());
+                }
+                if let Some(existing) = self.state_manager.get_session_key(session_key)? {
+                    if &existing.principal != principal {
+                        let message = format!("Session key {} belongs to another account", session_key);
+                        return Err(NodeError::new(ErrorCode::Unauthorized, message).into());
+                    }
+                }
+                log::info!("{} authorises session key {} for {:?}", principal, session_key, actions);
+                self.state_manager.put_session_key(&SessionKeyInfo {
+                    session_key: session_key.clone(),
+                    principal: principal.clone(),
+                    actions: actions.clone(),
+                    spend_limit: *spend_limit,
+                    spent: 0,
+                    expires_at_height: *expires_at_height,
+                    authorized_at_height: self.height,
+                })
+            }
+            SessionKeyUpdate::Revoke { session_key } => {
+                log::info!("{} revokes session key {}", principal, session_key);
+                for info in self.state_manager.revoke_session_key(session_key, principal)? {
+                    self.emit(ChainEvent::BidCancelled { bid_id: info.bid.id, advertiser: info.advertiser });
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// The tier of a user whose presentation was verified: the holder's
+    /// recorded tier, at least `Verified` if any attribute was backed, and
+    /// raised by any `tier` claim among the backed attributes.
+    fn user_tier(&self, holder: &str, verified: &[String]) -> Result<IdentityTier> {
+        let mut tier = self.state_manager.get_identity_tier(holder)?;
+        if !verified.is_empty() {
+            tier = tier.max(IdentityTier::Verified);
+        }
+        for attribute in verified {
+            if let Some(Ok(claimed)) = attribute
+                .strip_prefix(IDENTITY_TIER_NAMESPACE)
+                .and_then(|rest| rest.strip_prefix('='))
+                .map(str::parse::<IdentityTier>)
+            {
+                tier = tier.max(claimed);
+            }
+        }
+        Ok(tier)
+    }
 
-                    log::info!("Matching auction in state: {:?}", signed_auction.data.id);
-                    let _ = self.state_manager.find_match(&signed_auction.data);
-                    Ok(())
-                } else {
-                    log::warn!("Invalid signature for auction {:?}", signed_auction.data.id);
-                    Ok(())
+    /// Records a tier attested by a trusted issuer, or claimed by `signer`
+    /// with a trusted credential.
+    fn update_identity_tier(&self, update: &IdentityTierUpdate, signer: &Address) -> Result<()> {
+        match update {
+            IdentityTierUpdate::Attest { subject, tier } => {
+                let attestor = self
+                    .state_manager
+                    .issuer_for_key(signer)?
+                    .filter(|issuer| issuer.namespaces.iter().any(|scope| scope == IDENTITY_TIER_NAMESPACE))
+                    .ok_or_else(|| NodeError::new(ErrorCode::Unauthorized, format!("{} may not attest identity tiers", signer)))?;
+                require_did(subject)?;
+                log::info!("{} attests tier {} for {}", attestor.id, tier, subject);
+                self.state_manager.set_identity_tier(subject, *tier, &attestor.id, self.height)
+            }
+            IdentityTierUpdate::Claim { credential } => {
+                if credential.credential_subject.id != *signer || !credential.verify().unwrap_or(false) {
+                    let message = format!("Credential does not verify for {}", signer);
+                    return Err(NodeError::new(ErrorCode::UntrustedCredential, message).into());
                 }
+                let claimed = credential
+                    .credential_subject
+                    .claims
+                    .get(IDENTITY_TIER_NAMESPACE)
+                    .ok_or_else(|| NodeError::new(ErrorCode::InvalidRequest, "Credential has no tier claim"))?;
+                let tier = claimed
+                    .parse::<IdentityTier>()
+                    .map_err(|e| NodeError::new(ErrorCode::InvalidRequest, e.to_string()))?;
+                let attribute = format!("{}={}", IDENTITY_TIER_NAMESPACE, claimed);
+                let issuer = match self.state_manager.issuer_for_key(&credential.issuer)? {
+                    Some(issuer) if self.state_manager.trusts_credential(credential, &attribute)? => issuer,
+                    _ => {
+                        let message = format!("Issuer {} is not trusted for identity tiers", credential.issuer);
+                        return Err(NodeError::new(ErrorCode::UntrustedCredential, message).into());
+                    }
+                };
+                log::info!("{} claims tier {} from {}", signer, tier, issuer.id);
+                self.state_manager.set_identity_tier(signer, tier, &issuer.id, self.height)
+            }
+        }
+    }
+
+    /// Applies a registry update after checking that `signer` may make it.
+    fn update_issuer_registry(&self, update: &IssuerRegistryUpdate, signer: &Address) -> Result<()> {
+        let is_governor = self.governors.contains(signer);
+        let require_governor = || -> Result<()> {
+            if is_governor {
+                Ok(())
+            } else {
+                Err(NodeError::new(ErrorCode::Unauthorized, format!("{} is not a governor", signer)).into())
+            }
+        };
+        // Governors, or the issuer acting with its current key.
+        let require_governor_or_issuer = |issuer: &str| -> Result<()> {
+            match self.state_manager.get_trusted_issuer(issuer)? {
+                Some(record) if is_governor || &record.key == signer => Ok(()),
+                Some(_) => Err(NodeError::new(ErrorCode::Unauthorized, format!("{} may not act for issuer {}", signer, issuer)).into()),
+                None => Err(NodeError::new(ErrorCode::IssuerNotFound, format!("Issuer {} is not trusted", issuer)).into()),
             }
         };
+        match update {
+            IssuerRegistryUpdate::AddIssuer { issuer, namespaces } => {
+                require_governor()?;
+                require_did(issuer)?;
+                self.state_manager.add_trusted_issuer(issuer, namespaces, self.height)?;
+            }
+            IssuerRegistryUpdate::RemoveIssuer { issuer } => {
+                require_governor()?;
+                self.state_manager.remove_trusted_issuer(issuer)?;
+            }
+            IssuerRegistryUpdate::SetIssuerScope { issuer, namespaces } => {
+                require_governor()?;
+                self.state_manager.set_issuer_scope(issuer, namespaces)?;
+            }
+            IssuerRegistryUpdate::RotateIssuerKey { issuer, new_key } => {
+                require_governor_or_issuer(issuer)?;
+                require_did(new_key)?;
+                self.state_manager.rotate_issuer_key(issuer, new_key)?;
+            }
+            IssuerRegistryUpdate::RevokeCredentials { issuer, credential_ids } => {
+                require_governor_or_issuer(issuer)?;
+                for credential_id in credential_ids {
+                    self.state_manager.revoke_credential(issuer, credential_id)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The settlement attesters, or an error for chains running without
+    /// settlement.
+    fn settlement(&self) -> Result<&SettlementAttesters> {
+        self.settlement
+            .as_ref()
+            .ok_or_else(|| NodeError::new(ErrorCode::Unavailable, "Settlement is not enabled on this node").into())
+    }
 
-        if let Err(e) = result {
-            log::error!("Failed to apply transaction to state: {}", e);
+    /// Checks the signer's nonce, takes the fee from `payer` and consumes the
+    /// nonce.
+    ///
+    /// Runs before the transaction's action, so a transaction whose action
+    /// fails still pays its fee and uses up its nonce. A transaction rejected
+    /// here leaves the account untouched.
+    fn charge(&self, signer: &Address, payer: &Address, nonce: u64, fee: u64) -> Result<()> {
+        let expected = self.state_manager.get_nonce(signer)?;
+        if nonce != expected {
+            let message = format!("Bad nonce {} for {}, expected {}", nonce, signer, expected);
+            return Err(NodeError::new(ErrorCode::BadNonce, message).into());
+        }
+        self.state_manager.apply_fees(payer, fee)?;
+        self.state_manager.increment_nonce(signer)
+    }
+
+    fn emit(&self, event: ChainEvent) {
+        if let Some(events) = &self.events {
+            // Sending only fails when nobody is subscribed.
+            let _ = events.send(event);
+        }
+    }
+}
+
+/// The session key that signed `signed` for `account`, if it was not signed
+/// by the account's own key.
+fn session_key_of<T>(signed: &Signed<T>, account: &Address) -> Option<Address> {
+    let signer = identity::address_of(&signed.signer);
+    (&signer != account).then_some(signer)
+}
+
+/// Checks a multisig member list: distinct `did:key` DIDs, with a threshold
+/// between one and the number of members.
+fn validate_members(members: &[Address], threshold: u32) -> Result<()> {
+    for (index, member) in members.iter().enumerate() {
+        require_did(member)?;
+        if members[..index].contains(member) {
+            return Err(NodeError::new(ErrorCode::InvalidRequest, format!("{} is listed twice", member)).into());
+        }
+    }
+    if threshold == 0 || threshold as usize > members.len() {
+        let message = format!("Threshold {} must be between 1 and the {} members", threshold, members.len());
+        return Err(NodeError::new(ErrorCode::InvalidRequest, message).into());
+    }
+    Ok(())
+}
+
+/// Rejects anything but a well-formed `did:key` DID.
+fn require_did(did: &str) -> Result<()> {
+    PublicKey::from_did(did)
+        .map(|_| ())
+        .map_err(|e| NodeError::new(ErrorCode::InvalidRequest, e.to_string()).into())
+}
+
+impl ConsensusApp {
+    /// Executes one transaction of the block at the current height and
+    /// stores its receipt.
+    pub fn deliver(&mut self, tx: Transaction) {
+        log::info!("Delivering transaction to the app state.");
+
+        let hash = match tx.hash() {
+            Ok(hash) => hash,
+            Err(e) => {
+                log::error!("Failed to hash transaction: {}", e);
+                return;
+            }
+        };
+        // A transaction delivered twice would fail its nonce check the second
+        // time; skipping it keeps that rejection from replacing its receipt.
+        match self.state_manager.get_receipt(&hash) {
+            Ok(Some(_)) => {
+                log::info!("Skipping already delivered transaction {}", hex::encode(hash));
+                return;
+            }
+            Ok(None) => {}
+            Err(e) => log::error!("Failed to look up receipt: {}", e),
+        }
+
+        self.transaction = hash;
+        let (status, auction_match) = match self.execute(tx) {
+            Ok(auction_match) => (ReceiptStatus::Applied, auction_match),
+            Err(e) => {
+                log::warn!("Failed to apply transaction to state: {}", e);
+                let error = error::classify(&e);
+                (ReceiptStatus::Rejected { code: error.code, reason: error.message }, None)
+            }
+        };
+
+        let receipt = Receipt {
+            tx_hash: hex::encode(hash),
+            height: self.height,
+            status,
+            auction_match,
+        };
+        if let Err(e) = self.state_manager.put_receipt(&receipt) {
+            log::error!("Failed to store receipt: {}", e);
+        }
+        if let Some(receipt_waiters) = &self.receipt_waiters {
+            receipt_waiters.notify(&hash, &receipt);
+        }
+        if let Some(auction_match) = receipt.auction_match {
+            self.emit(ChainEvent::Matched { tx_hash: receipt.tx_hash, auction_match });
         }
     }
 }
@@ -0,0 +1,220 @@
+use crate::error::{ErrorCode, NodeError};
+use crate::identity::{PublicKey, Signature, SignatureScheme, Signer};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+const CREDENTIALS_CONTEXT: &str = "https://www.w3.org/2018/credentials/v1";
+/// Our own proof type for ed25519 DIDs. The signature scheme is ed25519, but
+/// the payload is plain JSON rather than the RDF canonicalization
+/// `Ed25519Signature2020` specifies, so claiming that suite would mislead
+/// other verifiers.
+const ED25519_PROOF_TYPE: &str = "UnwalledEd25519Signature2024";
+/// The proof type for secp256k1 DIDs: an `r || s || v` signature over the
+/// keccak256 of the same payload, as `EvmWallet` signs.
+const SECP256K1_PROOF_TYPE: &str = "UnwalledSecp256k1Signature2024";
+const ASSERTION_METHOD: &str = "assertionMethod";
+const AUTHENTICATION: &str = "authentication";
+/// The multibase prefix for base58btc.
+const MULTIBASE_BASE58BTC: char = 'z';
+
+/// A W3C Verifiable Credential in which `issuer` attests claims about a subject.
+///
+/// Each claim `key: value` backs the targeting attribute `key=value`, e.g. a
+/// `geo: de` claim backs `geo=de`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifiableCredential {
+    #[serde(rename = "@context")]
+    pub context: Vec<String>,
+    /// Names the credential in its issuer's revocation list. Credentials
+    /// without an id cannot be revoked, so they are never trusted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub types: Vec<String>,
+    /// The DID of the issuer's signing key.
+    pub issuer: String,
+    pub credential_subject: CredentialSubject,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof: Option<Proof>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CredentialSubject {
+    /// The holder's DID.
+    pub id: String,
+    #[serde(flatten)]
+    pub claims: BTreeMap<String, String>,
+}
+
+/// A W3C Verifiable Presentation: credentials the holder presents, signed by
+/// the holder over a verifier-chosen challenge so it cannot be replayed. For
+/// auction triggers the challenge is `presentation_challenge`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifiablePresentation {
+    #[serde(rename = "@context")]
+    pub context: Vec<String>,
+    #[serde(rename = "type")]
+    pub types: Vec<String>,
+    /// The holder's DID.
+    pub holder: String,
+    pub verifiable_credential: Vec<VerifiableCredential>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof: Option<Proof>,
+}
+
+/// An `UnwalledEd25519Signature2024` or `UnwalledSecp256k1Signature2024`
+/// proof, by the signing DID's key type. The signature covers the JSON
+/// encoding of the document without its proof, followed by the JSON encoding
+/// of the proof without `proof_value`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Proof {
+    #[serde(rename = "type")]
+    pub proof_type: String,
+    /// A DID URL naming the signing key, e.g. `did:key:z6Mk...#z6Mk...`.
+    pub verification_method: String,
+    pub proof_purpose: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub challenge: Option<String>,
+    /// The base58btc multibase-encoded signature.
+    #[serde(default)]
+    pub proof_value: String,
+}
+
+/// The challenge a presentation attached to an auction trigger must be made
+/// for: it names the chain, the publisher the trigger acts for and the
+/// trigger's id, so the presentation is good for that one auction only.
+pub fn presentation_challenge(chain_id: &str, publisher: &str, auction_id: &Uuid) -> String {
+    format!("unwalled-auction:{}:{}:{}", chain_id, publisher, auction_id)
+}
+
+impl VerifiableCredential {
+    /// Issues a credential attesting `claims` about `subject`.
+    pub fn issue<S: Signer>(issuer: &S, subject: &str, claims: BTreeMap<String, String>) -> Result<Self> {
+        let mut credential = Self {
+            context: vec![CREDENTIALS_CONTEXT.to_string()],
+            id: Some(format!("urn:uuid:{}", Uuid::new_v4())),
+            types: vec!["VerifiableCredential".to_string()],
+            issuer: issuer.did(),
+            credential_subject: CredentialSubject { id: subject.to_string(), claims },
+            proof: None,
+        };
+        credential.proof = Some(sign_proof(&credential, issuer, ASSERTION_METHOD, None)?);
+        Ok(credential)
+    }
+
+    /// Checks the issuer's proof over the credential.
+    pub fn verify(&self) -> Result<bool> {
+        match &self.proof {
+            Some(proof) => verify_proof(&Self { proof: None, ..self.clone() }, proof, &self.issuer, ASSERTION_METHOD),
+            None => Ok(false),
+        }
+    }
+
+    /// The `key=value` attributes this credential backs. The key is the
+    /// attribute's namespace, which the issuer must be trusted for.
+    pub fn attributes(&self) -> impl Iterator<Item = String> + '_ {
+        self.credential_subject.claims.iter().map(|(key, value)| format!("{}={}", key, value))
+    }
+}
+
+impl VerifiablePresentation {
+    /// Presents `credentials` as `holder`, bound to `challenge`.
+    pub fn present<S: Signer>(holder: &S, credentials: Vec<VerifiableCredential>, challenge: &str) -> Result<Self> {
+        let mut presentation = Self {
+            context: vec![CREDENTIALS_CONTEXT.to_string()],
+            types: vec!["VerifiablePresentation".to_string()],
+            holder: holder.did(),
+            verifiable_credential: credentials,
+            proof: None,
+        };
+        presentation.proof = Some(sign_proof(&presentation, holder, AUTHENTICATION, Some(challenge))?);
+        Ok(presentation)
+    }
+
+    /// Verifies the holder's proof over `challenge` and returns the attributes
+    /// backed by credentials about the holder from issuers `is_trusted`
+    /// accepts for that attribute. Credentials that are untrusted or fail
+    /// verification are skipped; a bad holder proof fails the whole
+    /// presentation.
+    pub fn verified_attributes<F>(&self, challenge: &str, mut is_trusted: F) -> Result<Vec<String>>
+    where
+        F: FnMut(&VerifiableCredential, &str) -> Result<bool>,
+    {
+        let proof = self
+            .proof
+            .as_ref()
+            .ok_or_else(|| NodeError::new(ErrorCode::InvalidPresentation, "Presentation has no proof"))?;
+        if proof.challenge.as_deref() != Some(challenge) {
+            return Err(NodeError::new(ErrorCode::InvalidPresentation, "Presentation is bound to another challenge").into());
+        }
+        if !verify_proof(&Self { proof: None, ..self.clone() }, proof, &self.holder, AUTHENTICATION).unwrap_or(false) {
+            return Err(NodeError::new(ErrorCode::InvalidPresentation, "Invalid presentation proof").into());
+        }
+
+        let mut attributes = Vec::new();
+        for credential in &self.verifiable_credential {
+            if credential.credential_subject.id != self.holder || !credential.verify().unwrap_or(false) {
+                log::debug!("Skipping unverifiable credential from {}", credential.issuer);
+                continue;
+            }
+            for attribute in credential.attributes() {
+                if is_trusted(credential, &attribute)? {
+                    attributes.push(attribute);
+                }
+            }
+        }
+        attributes.sort();
+        attributes.dedup();
+        Ok(attributes)
+    }
+}
+
+fn sign_proof<T: Serialize, S: Signer>(document: &T, signer: &S, purpose: &str, challenge: Option<&str>) -> Result<Proof> {
+    let did = signer.did();
+    let mut proof = Proof {
+        proof_type: proof_suite(&signer.public_key()).0.to_string(),
+        verification_method: format!("{}#{}", did, &did["did:key:".len()..]),
+        proof_purpose: purpose.to_string(),
+        challenge: challenge.map(str::to_string),
+        proof_value: String::new(),
+    };
+    let signature = signer.sign(&proof_payload(document, &proof)?)?;
+    proof.proof_value = format!("{}{}", MULTIBASE_BASE58BTC, bs58::encode(signature.0).into_string());
+    Ok(proof)
+}
+
+/// Checks that `proof` is a valid signature over `document` by `expected_did`
+/// for `purpose`.
+fn verify_proof<T: Serialize>(document: &T, proof: &Proof, expected_did: &str, purpose: &str) -> Result<bool> {
+    let public_key = PublicKey::from_did(&proof.verification_method)?;
+    let (proof_type, scheme) = proof_suite(&public_key);
+    if proof.proof_type != proof_type || proof.proof_purpose != purpose || public_key.to_did() != expected_did {
+        return Ok(false);
+    }
+    let encoded = proof
+        .proof_value
+        .strip_prefix(MULTIBASE_BASE58BTC)
+        .ok_or_else(|| anyhow::anyhow!("Unsupported multibase encoding in proof"))?;
+    let signature = Signature(bs58::decode(encoded).into_vec()?);
+    scheme.verify(&public_key, &proof_payload(document, proof)?, &signature)
+}
+
+/// The proof type a key signs proofs under, and how they are verified.
+fn proof_suite(public_key: &PublicKey) -> (&'static str, SignatureScheme) {
+    if public_key.is_secp256k1() {
+        (SECP256K1_PROOF_TYPE, SignatureScheme::Eip712Secp256k1)
+    } else {
+        (ED25519_PROOF_TYPE, SignatureScheme::Ed25519)
+    }
+}
+
+fn proof_payload<T: Serialize>(document: &T, proof: &Proof) -> Result<Vec<u8>> {
+    let mut payload = serde_json::to_vec(document)?;
+    payload.extend_from_slice(&serde_json::to_vec(&Proof { proof_value: String::new(), ..proof.clone() })?);
+    Ok(payload)
+}
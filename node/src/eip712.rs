@@ -0,0 +1,88 @@
+use crate::encoding;
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::json;
+use sha3::{Digest, Keccak256};
+
+/// The EIP-712 domain every Unwalled action is signed in.
+pub const DOMAIN_NAME: &str = "Unwalled";
+pub const DOMAIN_VERSION: &str = "3";
+
+const DOMAIN_TYPE: &str = "EIP712Domain(string name,string version)";
+/// Actions are signed as the keccak256 of their canonical encoding, the same
+/// bytes ed25519 signatures cover, so the signature does not depend on how a
+/// JSON serializer orders or spaces fields. The chain goes in the message
+/// rather than the domain's `chainId`, which wallets require to match the
+/// EVM network they are connected to.
+const ACTION_TYPE: &str = "Action(string chain,string action,bytes32 data,uint64 nonce,uint64 fee)";
+
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    Keccak256::digest(data).into()
+}
+
+/// The bytes whose keccak256 digest an EIP-712 signature covers:
+/// `0x19 0x01 || domainSeparator || hashStruct(Action)`.
+pub fn encode<T: Serialize>(chain_id: &str, action: &str, data: &T, nonce: u64, fee: u64) -> Result<Vec<u8>> {
+    let mut encoded = vec![0x19, 0x01];
+    encoded.extend_from_slice(&domain_separator());
+    encoded.extend_from_slice(&hash_action(chain_id, action, &data_hash(data)?, nonce, fee));
+    Ok(encoded)
+}
+
+/// The same message as `encode`, in the JSON form `eth_signTypedData_v4`
+/// takes, for signing with an external Ethereum wallet.
+pub fn typed_data<T: Serialize>(chain_id: &str, action: &str, data: &T, nonce: u64, fee: u64) -> Result<serde_json::Value> {
+    Ok(json!({
+        "types": {
+            "EIP712Domain": [
+                { "name": "name", "type": "string" },
+                { "name": "version", "type": "string" },
+            ],
+            "Action": [
+                { "name": "chain", "type": "string" },
+                { "name": "action", "type": "string" },
+                { "name": "data", "type": "bytes32" },
+                { "name": "nonce", "type": "uint64" },
+                { "name": "fee", "type": "uint64" },
+            ],
+        },
+        "primaryType": "Action",
+        "domain": { "name": DOMAIN_NAME, "version": DOMAIN_VERSION },
+        "message": {
+            "chain": chain_id,
+            "action": action,
+            "data": format!("0x{}", hex::encode(data_hash(data)?)),
+            "nonce": nonce,
+            "fee": fee,
+        },
+    }))
+}
+
+/// The `data` member of the message: keccak256 of `encoding::canonical(data)`.
+pub fn data_hash<T: Serialize>(data: &T) -> Result<[u8; 32]> {
+    Ok(keccak256(&encoding::canonical(data)?))
+}
+
+fn domain_separator() -> [u8; 32] {
+    let mut encoded = keccak256(DOMAIN_TYPE.as_bytes()).to_vec();
+    encoded.extend_from_slice(&keccak256(DOMAIN_NAME.as_bytes()));
+    encoded.extend_from_slice(&keccak256(DOMAIN_VERSION.as_bytes()));
+    keccak256(&encoded)
+}
+
+fn hash_action(chain_id: &str, action: &str, data_hash: &[u8; 32], nonce: u64, fee: u64) -> [u8; 32] {
+    let mut encoded = keccak256(ACTION_TYPE.as_bytes()).to_vec();
+    encoded.extend_from_slice(&keccak256(chain_id.as_bytes()));
+    encoded.extend_from_slice(&keccak256(action.as_bytes()));
+    encoded.extend_from_slice(data_hash);
+    encoded.extend_from_slice(&uint256(nonce));
+    encoded.extend_from_slice(&uint256(fee));
+    keccak256(&encoded)
+}
+
+/// ABI-encodes an unsigned integer as a 32-byte big-endian word.
+fn uint256(value: u64) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[24..].copy_from_slice(&value.to_be_bytes());
+    word
+}
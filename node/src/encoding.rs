@@ -0,0 +1,171 @@
+use crate::primitives::{sha256, Hash};
+use anyhow::{bail, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+/// The version of the signing format below. Bumped whenever the bytes an
+/// action is signed over change shape.
+pub const SIGNING_VERSION: u8 = 1;
+
+/// Prefixes every signed payload, so Unwalled signatures can never be
+/// mistaken for signatures over anything else the key signs.
+const SIGNING_MAGIC: &[u8] = b"unwalled-sig";
+
+/// Prefixes the bytes a transaction id is hashed over, keeping ids apart
+/// from signing payloads and any other digest.
+const TX_HASH_MAGIC: &[u8] = b"unwalled-tx";
+
+/// The bytes an ed25519 action signature covers:
+///
+/// `"unwalled-sig" || version || domain(chain_id, action) || canonical(data) || nonce || fee`
+///
+/// with the nonce and fee as big-endian u64s. The domain separator pins the
+/// signature to one network and one action type.
+pub fn signing_bytes<T: Serialize>(chain_id: &str, action: &str, data: &T, nonce: u64, fee: u64) -> Result<Vec<u8>> {
+    let mut bytes = SIGNING_MAGIC.to_vec();
+    bytes.push(SIGNING_VERSION);
+    bytes.extend_from_slice(&domain_separator(chain_id, action));
+    bytes.extend_from_slice(&canonical(data)?);
+    bytes.extend_from_slice(&nonce.to_be_bytes());
+    bytes.extend_from_slice(&fee.to_be_bytes());
+    Ok(bytes)
+}
+
+/// The id of a signed transaction:
+///
+/// `sha256("unwalled-tx" || version || domain(chain_id, action) || canonical(signed))`
+///
+/// `signed` is the whole `Signed` wrapper, signature included, so the id
+/// is the same however the transaction travelled (JSON or MessagePack, any
+/// field order) and changes if any signed or unsigned part of it does.
+pub fn tx_hash<T: Serialize>(chain_id: &str, action: &str, signed: &T) -> Result<Hash> {
+    let mut bytes = TX_HASH_MAGIC.to_vec();
+    bytes.push(SIGNING_VERSION);
+    bytes.extend_from_slice(&domain_separator(chain_id, action));
+    bytes.extend_from_slice(&canonical(signed)?);
+    Ok(sha256(&bytes))
+}
+
+/// `sha256(len(chain_id) || chain_id || len(action) || action)`, with the
+/// lengths as big-endian u32s.
+pub fn domain_separator(chain_id: &str, action: &str) -> [u8; 32] {
+    let mut encoded = Vec::new();
+    for part in [chain_id, action] {
+        encoded.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        encoded.extend_from_slice(part.as_bytes());
+    }
+    sha256(&encoded)
+}
+
+/// Encodes `value` as canonical MessagePack, from the same serde
+/// representation it has in JSON:
+///
+/// - map keys are sorted by their UTF-8 bytes, and entries whose value is
+///   null are left out, so an absent optional field and an explicit null
+///   encode the same;
+/// - integers and lengths use the shortest MessagePack form that holds them;
+/// - floats are rejected, as no action carries one.
+pub fn canonical<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    let mut encoded = Vec::new();
+    write_value(&mut encoded, &serde_json::to_value(value)?)?;
+    Ok(encoded)
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value) -> Result<()> {
+    match value {
+        Value::Null => out.push(0xc0),
+        Value::Bool(false) => out.push(0xc2),
+        Value::Bool(true) => out.push(0xc3),
+        Value::Number(number) => {
+            if let Some(n) = number.as_u64() {
+                write_uint(out, n);
+            } else if let Some(n) = number.as_i64() {
+                write_int(out, n);
+            } else {
+                bail!("Floats have no canonical encoding: {}", number);
+            }
+        }
+        Value::String(string) => write_str(out, string),
+        Value::Array(items) => {
+            write_len(out, items.len(), 0x90, 0xdc, 0xdd);
+            for item in items {
+                write_value(out, item)?;
+            }
+        }
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().filter(|(_, value)| !value.is_null()).collect();
+            entries.sort_by(|(a, _), (b, _)| a.as_bytes().cmp(b.as_bytes()));
+            write_len(out, entries.len(), 0x80, 0xde, 0xdf);
+            for (key, value) in entries {
+                write_str(out, key);
+                write_value(out, value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_uint(out: &mut Vec<u8>, n: u64) {
+    if n < 0x80 {
+        out.push(n as u8);
+    } else if n <= u8::MAX as u64 {
+        out.extend_from_slice(&[0xcc, n as u8]);
+    } else if n <= u16::MAX as u64 {
+        out.push(0xcd);
+        out.extend_from_slice(&(n as u16).to_be_bytes());
+    } else if n <= u32::MAX as u64 {
+        out.push(0xce);
+        out.extend_from_slice(&(n as u32).to_be_bytes());
+    } else {
+        out.push(0xcf);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+/// Writes a negative integer; non-negative ones go through `write_uint`.
+fn write_int(out: &mut Vec<u8>, n: i64) {
+    if n >= -32 {
+        out.push(n as i8 as u8);
+    } else if n >= i8::MIN as i64 {
+        out.extend_from_slice(&[0xd0, n as i8 as u8]);
+    } else if n >= i16::MIN as i64 {
+        out.push(0xd1);
+        out.extend_from_slice(&(n as i16).to_be_bytes());
+    } else if n >= i32::MIN as i64 {
+        out.push(0xd2);
+        out.extend_from_slice(&(n as i32).to_be_bytes());
+    } else {
+        out.push(0xd3);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+fn write_str(out: &mut Vec<u8>, string: &str) {
+    let len = string.len();
+    if len < 32 {
+        out.push(0xa0 | len as u8);
+    } else if len <= u8::MAX as usize {
+        out.extend_from_slice(&[0xd9, len as u8]);
+    } else if len <= u16::MAX as usize {
+        out.push(0xda);
+        out.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        out.push(0xdb);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+    out.extend_from_slice(string.as_bytes());
+}
+
+/// Writes an array or map header: the fix form for up to 15 entries, then
+/// the 16- and 32-bit forms.
+fn write_len(out: &mut Vec<u8>, len: usize, fix: u8, marker16: u8, marker32: u8) {
+    if len < 16 {
+        out.push(fix | len as u8);
+    } else if len <= u16::MAX as usize {
+        out.push(marker16);
+        out.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        out.push(marker32);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+}
@@ -0,0 +1,189 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A stable numeric code identifying why a request or transaction failed.
+///
+/// Codes are part of the RPC contract: existing values never change meaning,
+/// and new failures get new codes. `1xxx` are malformed or refused requests,
+/// `2xxx` are transactions the current state does not allow, and `5xxx` are
+/// node-side failures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "u16", into = "u16")]
+pub enum ErrorCode {
+    /// A code this build does not know, e.g. from a newer node.
+    Unknown,
+    InvalidRequest,
+    InvalidSignature,
+    NotFound,
+    MethodNotAllowed,
+    PayloadTooLarge,
+    UpgradeRequired,
+    RateLimited,
+    BadNonce,
+    InsufficientFunds,
+    BelowFloor,
+    BidNotFound,
+    BidAlreadyExists,
+    BidExpired,
+    NotBidOwner,
+    BidNotOpen,
+    DepositAlreadyClaimed,
+    DepositNotConfirmed,
+    InvalidPresentation,
+    Unauthorized,
+    IssuerNotFound,
+    IssuerAlreadyExists,
+    UntrustedCredential,
+    SpendLimitExceeded,
+    ProposalNotFound,
+    ProposalNotOpen,
+    WrongChain,
+    BalanceOverflow,
+    AuctionAlreadyExists,
+    Internal,
+    Unavailable,
+}
+
+impl ErrorCode {
+    pub fn code(self) -> u16 {
+        match self {
+            ErrorCode::Unknown => 0,
+            ErrorCode::InvalidRequest => 1000,
+            ErrorCode::InvalidSignature => 1001,
+            ErrorCode::NotFound => 1002,
+            ErrorCode::MethodNotAllowed => 1003,
+            ErrorCode::PayloadTooLarge => 1004,
+            ErrorCode::UpgradeRequired => 1005,
+            ErrorCode::RateLimited => 1006,
+            ErrorCode::BadNonce => 2000,
+            ErrorCode::InsufficientFunds => 2001,
+            ErrorCode::BelowFloor => 2002,
+            ErrorCode::BidNotFound => 2003,
+            ErrorCode::BidAlreadyExists => 2004,
+            ErrorCode::BidExpired => 2005,
+            ErrorCode::NotBidOwner => 2006,
+            ErrorCode::BidNotOpen => 2007,
+            ErrorCode::DepositAlreadyClaimed => 2008,
+            ErrorCode::DepositNotConfirmed => 2009,
+            ErrorCode::InvalidPresentation => 2010,
+            ErrorCode::Unauthorized => 2011,
+            ErrorCode::IssuerNotFound => 2012,
+            ErrorCode::IssuerAlreadyExists => 2013,
+            ErrorCode::UntrustedCredential => 2014,
+            ErrorCode::SpendLimitExceeded => 2015,
+            ErrorCode::ProposalNotFound => 2016,
+            ErrorCode::ProposalNotOpen => 2017,
+            ErrorCode::WrongChain => 2018,
+            ErrorCode::BalanceOverflow => 2019,
+            ErrorCode::AuctionAlreadyExists => 2020,
+            ErrorCode::Internal => 5000,
+            ErrorCode::Unavailable => 5001,
+        }
+    }
+
+    /// The HTTP status an RPC error with this code is answered with.
+    pub fn http_status(self) -> u16 {
+        match self {
+            ErrorCode::InvalidRequest | ErrorCode::InvalidSignature | ErrorCode::WrongChain => 400,
+            ErrorCode::InsufficientFunds | ErrorCode::SpendLimitExceeded => 402,
+            ErrorCode::NotBidOwner | ErrorCode::Unauthorized => 403,
+            ErrorCode::NotFound | ErrorCode::BidNotFound | ErrorCode::IssuerNotFound | ErrorCode::ProposalNotFound => 404,
+            ErrorCode::MethodNotAllowed => 405,
+            ErrorCode::BadNonce
+            | ErrorCode::BidAlreadyExists
+            | ErrorCode::BidNotOpen
+            | ErrorCode::DepositAlreadyClaimed
+            | ErrorCode::IssuerAlreadyExists
+            | ErrorCode::AuctionAlreadyExists
+            | ErrorCode::ProposalNotOpen => 409,
+            ErrorCode::PayloadTooLarge => 413,
+            ErrorCode::BelowFloor
+            | ErrorCode::BidExpired
+            | ErrorCode::DepositNotConfirmed
+            | ErrorCode::InvalidPresentation
+            | ErrorCode::UntrustedCredential
+            | ErrorCode::BalanceOverflow => 422,
+            ErrorCode::UpgradeRequired => 426,
+            ErrorCode::RateLimited => 429,
+            ErrorCode::Unknown | ErrorCode::Internal => 500,
+            ErrorCode::Unavailable => 503,
+        }
+    }
+}
+
+impl From<ErrorCode> for u16 {
+    fn from(code: ErrorCode) -> Self {
+        code.code()
+    }
+}
+
+impl From<u16> for ErrorCode {
+    fn from(code: u16) -> Self {
+        match code {
+            1000 => ErrorCode::InvalidRequest,
+            1001 => ErrorCode::InvalidSignature,
+            1002 => ErrorCode::NotFound,
+            1003 => ErrorCode::MethodNotAllowed,
+            1004 => ErrorCode::PayloadTooLarge,
+            1005 => ErrorCode::UpgradeRequired,
+            1006 => ErrorCode::RateLimited,
+            2000 => ErrorCode::BadNonce,
+            2001 => ErrorCode::InsufficientFunds,
+            2002 => ErrorCode::BelowFloor,
+            2003 => ErrorCode::BidNotFound,
+            2004 => ErrorCode::BidAlreadyExists,
+            2005 => ErrorCode::BidExpired,
+            2006 => ErrorCode::NotBidOwner,
+            2007 => ErrorCode::BidNotOpen,
+            2008 => ErrorCode::DepositAlreadyClaimed,
+            2009 => ErrorCode::DepositNotConfirmed,
+            2010 => ErrorCode::InvalidPresentation,
+            2011 => ErrorCode::Unauthorized,
+            2012 => ErrorCode::IssuerNotFound,
+            2013 => ErrorCode::IssuerAlreadyExists,
+            2014 => ErrorCode::UntrustedCredential,
+            2015 => ErrorCode::SpendLimitExceeded,
+            2016 => ErrorCode::ProposalNotFound,
+            2017 => ErrorCode::ProposalNotOpen,
+            2018 => ErrorCode::WrongChain,
+            2019 => ErrorCode::BalanceOverflow,
+            2020 => ErrorCode::AuctionAlreadyExists,
+            5000 => ErrorCode::Internal,
+            5001 => ErrorCode::Unavailable,
+            _ => ErrorCode::Unknown,
+        }
+    }
+}
+
+/// A failure with a code callers can branch on.
+///
+/// State and consensus code return it inside `anyhow::Error`; `classify`
+/// recovers it at the RPC and receipt boundaries.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl NodeError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+impl fmt::Display for NodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for NodeError {}
+
+/// Returns the `NodeError` carried by `error`, or an `Internal` one for
+/// failures that were not given a code.
+pub fn classify(error: &anyhow::Error) -> NodeError {
+    match error.downcast_ref::<NodeError>() {
+        Some(node_error) => NodeError::new(node_error.code, error.to_string()),
+        None => NodeError::new(ErrorCode::Internal, error.to_string()),
+    }
+}
@@ -0,0 +1,158 @@
+use crate::error::ErrorCode;
+use crate::primitives::{ChainEvent, SubscriptionFilter};
+use crate::rpc::{RpcHandler, RpcResponse, MAX_BODY_SIZE};
+use crate::wire::Negotiated;
+use anyhow::Result;
+use bytes::Bytes;
+use futures_util::{SinkExt, StreamExt};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::Incoming;
+use hyper::upgrade::Upgraded;
+use hyper::{Request, Response};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+/// The WebSocket endpoint for event subscriptions. The client's first message
+/// is a JSON `SubscriptionFilter`; every matching `ChainEvent` is then pushed
+/// as a JSON text message.
+pub const SUBSCRIBE_PATH: &str = "/rpc/subscribe";
+
+/// Runs the TCP fallback RPC server for clients that cannot speak HTTP/3.
+///
+/// Each connection is served as HTTP/1.1 or HTTP/2 (prior knowledge) depending
+/// on what the client sends, and every request goes through the same
+/// `RpcHandler` as the quiche listener. Event subscriptions are served from
+/// `SUBSCRIBE_PATH` over an HTTP/1.1 WebSocket upgrade.
+pub async fn run_server(listen_address: SocketAddr, handler: RpcHandler) -> Result<()> {
+    let listener = TcpListener::bind(listen_address).await?;
+    log::info!("RPC server listening on {} with HTTP/1.1 and HTTP/2", listener.local_addr()?);
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let handler = handler.clone();
+
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |request| {
+                let handler = handler.clone();
+                async move { Ok::<_, Infallible>(serve(&handler, request).await) }
+            });
+
+            if let Err(e) = auto::Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await
+            {
+                log::debug!("HTTP connection from {} ended with error: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn serve(handler: &RpcHandler, request: Request<Incoming>) -> Response<Full<Bytes>> {
+    if request.uri().path() == SUBSCRIBE_PATH {
+        return subscribe(handler, request);
+    }
+
+    let method = request.method().as_str().to_owned();
+    let formats = Negotiated::from_headers(
+        header(&request, hyper::header::CONTENT_TYPE),
+        header(&request, hyper::header::ACCEPT),
+    );
+    let path = request
+        .uri()
+        .path_and_query()
+        .map_or_else(|| request.uri().path().to_owned(), |path| path.as_str().to_owned());
+
+    let response = match Limited::new(request.into_body(), MAX_BODY_SIZE).collect().await {
+        Ok(body) => handler.handle_as(&method, &path, &body.to_bytes(), formats).await,
+        Err(_) => RpcResponse::error(ErrorCode::PayloadTooLarge, "Request body too large"),
+    };
+    to_http(response)
+}
+
+/// Accepts a WebSocket upgrade and streams events on the upgraded connection.
+fn subscribe(handler: &RpcHandler, mut request: Request<Incoming>) -> Response<Full<Bytes>> {
+    let is_upgrade = request
+        .headers()
+        .get(hyper::header::UPGRADE)
+        .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"websocket"));
+    let accept_key = match request.headers().get(hyper::header::SEC_WEBSOCKET_KEY) {
+        Some(key) if is_upgrade => derive_accept_key(key.as_bytes()),
+        _ => return to_http(RpcResponse::error(ErrorCode::UpgradeRequired, "Expected a WebSocket upgrade")),
+    };
+
+    // Subscribe before answering so no event between the two is missed.
+    let events = handler.subscribe();
+    let upgrade = hyper::upgrade::on(&mut request);
+    tokio::spawn(async move {
+        match upgrade.await {
+            Ok(upgraded) => {
+                let ws = WebSocketStream::from_raw_socket(TokioIo::new(upgraded), Role::Server, None).await;
+                if let Err(e) = stream_events(ws, events).await {
+                    log::debug!("Subscription ended with error: {}", e);
+                }
+            }
+            Err(e) => log::debug!("WebSocket upgrade failed: {}", e),
+        }
+    });
+
+    Response::builder()
+        .status(101)
+        .header(hyper::header::CONNECTION, "upgrade")
+        .header(hyper::header::UPGRADE, "websocket")
+        .header(hyper::header::SEC_WEBSOCKET_ACCEPT, accept_key)
+        .body(Full::new(Bytes::new()))
+        .expect("status and headers are valid")
+}
+
+async fn stream_events(
+    mut ws: WebSocketStream<TokioIo<Upgraded>>,
+    mut events: broadcast::Receiver<ChainEvent>,
+) -> Result<()> {
+    let filter: SubscriptionFilter = match ws.next().await {
+        Some(Ok(Message::Text(text))) => serde_json::from_str(&text)?,
+        _ => return Ok(()),
+    };
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    if filter.matches(&event) {
+                        ws.send(Message::Text(serde_json::to_string(&event)?)).await?;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    // Close rather than silently drop events; the client resubscribes.
+                    ws.close(None).await.ok();
+                    return Err(anyhow::anyhow!("Subscriber lagged behind by {} events", skipped));
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+            message = ws.next() => match message {
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Err(e)) => return Err(e.into()),
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+fn header(request: &Request<Incoming>, name: hyper::header::HeaderName) -> Option<&str> {
+    request.headers().get(name).and_then(|value| value.to_str().ok())
+}
+
+fn to_http(response: RpcResponse) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(response.status)
+        .header("content-type", response.content_type)
+        .body(Full::new(Bytes::from(response.body)))
+        .expect("status and headers are valid")
+}
@@ -1,17 +1,199 @@
+use crate::eip712;
+use crate::keystore::Keystore;
 use serde::{Deserialize, Serialize};
 use anyhow::Result;
+use std::path::Path;
 
 // A wrapper for a public key.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub struct PublicKey(pub Vec<u8>);
+pub struct PublicKey(#[serde(with = "raw_bytes")] pub Vec<u8>);
 
 // A wrapper for a cryptographic signature.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct Signature(pub Vec<u8>);
+pub struct Signature(#[serde(with = "raw_bytes")] pub Vec<u8>);
 
-// A unique identifier for a wallet or account.
+/// Key and signature bytes are a number array in JSON, and raw bytes in
+/// binary formats such as MessagePack. Either form is accepted when decoding.
+pub(crate) mod raw_bytes {
+    use serde::de::{SeqAccess, Visitor};
+    use serde::{Deserializer, Serialize, Serializer};
+    use std::fmt;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            bytes.serialize(serializer)
+        } else {
+            serializer.serialize_bytes(bytes)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        deserializer.deserialize_any(BytesVisitor)
+    }
+
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("bytes or an array of bytes")
+        }
+
+        fn visit_bytes<E: serde::de::Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(bytes.to_vec())
+        }
+
+        fn visit_byte_buf<E: serde::de::Error>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(bytes)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(byte) = seq.next_element()? {
+                bytes.push(byte);
+            }
+            Ok(bytes)
+        }
+    }
+}
+
+// A unique identifier for a wallet or account: the `did:key` DID of its public key.
 pub type Address = String;
 
+/// The multicodec code for an ed25519 public key (`ed25519-pub`, 0xed), varint-encoded.
+const ED25519_PUB_MULTICODEC: [u8; 2] = [0xed, 0x01];
+/// The multicodec code for a compressed secp256k1 public key (`secp256k1-pub`, 0xe7), varint-encoded.
+const SECP256K1_PUB_MULTICODEC: [u8; 2] = [0xe7, 0x01];
+const ED25519_KEY_LEN: usize = 32;
+const SECP256K1_KEY_LEN: usize = 33;
+const DID_KEY_PREFIX: &str = "did:key:";
+/// The multibase prefix for base58btc.
+const MULTIBASE_BASE58BTC: char = 'z';
+
+impl PublicKey {
+    /// The key's `did:key` identifier: the multicodec-tagged key, base58btc
+    /// multibase-encoded, e.g. `did:key:z6Mk...`.
+    pub fn to_did(&self) -> String {
+        format!("{}{}", DID_KEY_PREFIX, self.to_multibase())
+    }
+
+    /// Parses an ed25519 or secp256k1 `did:key` identifier.
+    pub fn from_did(did: &str) -> Result<Self> {
+        let multibase = did
+            .strip_prefix(DID_KEY_PREFIX)
+            .ok_or_else(|| anyhow::anyhow!("Not a did:key identifier: {}", did))?;
+        // A DID URL may carry a fragment naming the verification method.
+        let multibase = multibase.split('#').next().unwrap_or_default();
+        let encoded = multibase
+            .strip_prefix(MULTIBASE_BASE58BTC)
+            .ok_or_else(|| anyhow::anyhow!("Unsupported multibase encoding in {}", did))?;
+        let bytes = bs58::decode(encoded).into_vec()?;
+        let (key, expected_len) = if let Some(key) = bytes.strip_prefix(&ED25519_PUB_MULTICODEC[..]) {
+            (key, ED25519_KEY_LEN)
+        } else if let Some(key) = bytes.strip_prefix(&SECP256K1_PUB_MULTICODEC[..]) {
+            (key, SECP256K1_KEY_LEN)
+        } else {
+            return Err(anyhow::anyhow!("{} is not an ed25519 or secp256k1 key", did));
+        };
+        if key.len() != expected_len {
+            return Err(anyhow::anyhow!("{} has a {}-byte key, expected {}", did, key.len(), expected_len));
+        }
+        Ok(PublicKey(key.to_vec()))
+    }
+
+    /// Whether this is a compressed secp256k1 key rather than an ed25519 one.
+    /// The two are told apart by length.
+    pub fn is_secp256k1(&self) -> bool {
+        self.0.len() == SECP256K1_KEY_LEN
+    }
+
+    /// The multicodec-tagged key in base58btc multibase, as used in
+    /// `publicKeyMultibase`.
+    fn to_multibase(&self) -> String {
+        let mut bytes = if self.is_secp256k1() { SECP256K1_PUB_MULTICODEC } else { ED25519_PUB_MULTICODEC }.to_vec();
+        bytes.extend_from_slice(&self.0);
+        format!("{}{}", MULTIBASE_BASE58BTC, bs58::encode(bytes).into_string())
+    }
+}
+
+/// How a `Signed` payload is encoded and signed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureScheme {
+    /// ed25519 over `encoding::signing_bytes`: the canonical MessagePack
+    /// encoding of the action, domain-separated by chain and action type.
+    #[default]
+    Ed25519,
+    /// secp256k1 ECDSA over an EIP-712 typed-data digest, as produced by
+    /// Ethereum wallets. Signatures are 65 bytes, `r || s || v`.
+    Eip712Secp256k1,
+}
+
+impl SignatureScheme {
+    /// Verifies `signature` over `data` under this scheme. For EIP-712,
+    /// `data` is the encoded typed-data message whose digest was signed.
+    pub fn verify(self, public_key: &PublicKey, data: &[u8], signature: &Signature) -> Result<bool> {
+        match self {
+            SignatureScheme::Ed25519 => verify_signature(public_key, data, signature),
+            SignatureScheme::Eip712Secp256k1 => verify_secp256k1_signature(public_key, data, signature),
+        }
+    }
+}
+
+/// A W3C DID document, as resolved from a `did:key` identifier.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidDocument {
+    #[serde(rename = "@context")]
+    pub context: Vec<String>,
+    pub id: String,
+    pub verification_method: Vec<VerificationMethod>,
+    /// Verification method ids, by the relationships the key is trusted for.
+    pub authentication: Vec<String>,
+    pub assertion_method: Vec<String>,
+    pub capability_invocation: Vec<String>,
+    pub capability_delegation: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerificationMethod {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub method_type: String,
+    pub controller: String,
+    pub public_key_multibase: String,
+}
+
+/// Resolves a `did:key` identifier to its DID document. The document is
+/// derived from the key itself, so resolution needs no network or state.
+pub fn resolve_did(did: &str) -> Result<DidDocument> {
+    let public_key = PublicKey::from_did(did)?;
+    let did = public_key.to_did();
+    let multibase = public_key.to_multibase();
+    let method_id = format!("{}#{}", did, multibase);
+    let (suite, method_type) = if public_key.is_secp256k1() {
+        ("https://w3id.org/security/suites/secp256k1-2019/v1", "EcdsaSecp256k1VerificationKey2019")
+    } else {
+        ("https://w3id.org/security/suites/ed25519-2020/v1", "Ed25519VerificationKey2020")
+    };
+    Ok(DidDocument {
+        context: vec!["https://www.w3.org/ns/did/v1".to_string(), suite.to_string()],
+        id: did.clone(),
+        verification_method: vec![VerificationMethod {
+            id: method_id.clone(),
+            method_type: method_type.to_string(),
+            controller: did,
+            public_key_multibase: multibase,
+        }],
+        authentication: vec![method_id.clone()],
+        assertion_method: vec![method_id.clone()],
+        capability_invocation: vec![method_id.clone()],
+        capability_delegation: vec![method_id],
+    })
+}
+
 /// Represents an entity that can sign data.
 /// This trait can be implemented for different kinds of wallets
 /// (e.g., local keypairs, hardware wallets, EVM wallets via EIP-712).
@@ -19,36 +201,218 @@ pub trait Signer {
     fn sign(&self, data: &[u8]) -> Result<Signature>;
     fn public_key(&self) -> PublicKey;
     fn address(&self) -> Address;
+
+    /// The scheme `Signed` payloads are encoded for before `sign` is called.
+    fn scheme(&self) -> SignatureScheme {
+        SignatureScheme::Ed25519
+    }
+
+    /// The signer's `did:key` identifier.
+    fn did(&self) -> String {
+        self.public_key().to_did()
+    }
+}
+
+/// Derives the account address for a public key. Advertisers, publishers
+/// and validators are all identified by their `did:key` DID.
+pub fn address_of(public_key: &PublicKey) -> Address {
+    public_key.to_did()
+}
+
+/// The prefix of multisig account addresses.
+pub const MULTISIG_ADDRESS_PREFIX: &str = "multisig:";
+
+/// Derives the address of the multisig account `creator` creates with the
+/// transaction at `nonce`. Multisig accounts have no key of their own, so
+/// their address is not a DID and they can never sign directly.
+pub fn multisig_address(creator: &Address, nonce: u64) -> Address {
+    let mut preimage = creator.as_bytes().to_vec();
+    preimage.extend_from_slice(&nonce.to_le_bytes());
+    let digest = ring::digest::digest(&ring::digest::SHA256, &preimage);
+    format!("{}{}", MULTISIG_ADDRESS_PREFIX, hex::encode(&digest.as_ref()[..20]))
+}
+
+/// Verifies an ed25519 signature over `data` for the given public key.
+pub fn verify_signature(public_key: &PublicKey, data: &[u8], signature: &Signature) -> Result<bool> {
+    use ed25519_dalek::Verifier;
+    let Ok(public_key) = <&[u8; 32]>::try_from(public_key.0.as_slice()) else {
+        return Ok(false);
+    };
+    let Ok(signature) = <&[u8; 64]>::try_from(signature.0.as_slice()) else {
+        return Ok(false);
+    };
+    let public_key = ed25519_dalek::VerifyingKey::from_bytes(public_key)?;
+    let signature = ed25519_dalek::Signature::from_bytes(signature);
+    Ok(public_key.verify(data, &signature).is_ok())
+}
+
+/// Verifies an EIP-712 secp256k1 signature: `r || s || v` over the keccak256
+/// digest of `data`. `v` may be 0/1 or Ethereum's 27/28. High-`s` signatures
+/// are rejected, so each message has a single valid signature per key.
+fn verify_secp256k1_signature(public_key: &PublicKey, data: &[u8], signature: &Signature) -> Result<bool> {
+    use k256::ecdsa::{RecoveryId, Signature as EcdsaSignature, VerifyingKey};
+    let expected = VerifyingKey::from_sec1_bytes(&public_key.0)?;
+    if signature.0.len() != 65 {
+        return Ok(false);
+    }
+    let ecdsa_signature = EcdsaSignature::from_slice(&signature.0[..64])?;
+    if ecdsa_signature.normalize_s().is_some() {
+        return Ok(false);
+    }
+    let v = signature.0[64];
+    let Some(recovery_id) = RecoveryId::from_byte(if v >= 27 { v - 27 } else { v }) else {
+        return Ok(false);
+    };
+    match VerifyingKey::recover_from_prehash(&eip712::keccak256(data), &ecdsa_signature, recovery_id) {
+        Ok(recovered) => Ok(recovered == expected),
+        Err(_) => Ok(false),
+    }
 }
 
 /// Represents a simple, local wallet for testing.
 #[derive(Debug)]
 pub struct LocalWallet {
-    keypair: ed25519_dalek::Keypair,
+    signing_key: ed25519_dalek::SigningKey,
+}
+
+impl Default for LocalWallet {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl LocalWallet {
     /// Creates a new random wallet.
     pub fn new() -> Self {
-        let mut csprng = rand::rngs::OsRng{};
-        let keypair: ed25519_dalek::Keypair = ed25519_dalek::Keypair::generate(&mut csprng);
-        Self { keypair }
+        let mut secret_key = [0u8; 32];
+        rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut secret_key);
+        Self { signing_key: ed25519_dalek::SigningKey::from_bytes(&secret_key) }
+    }
+
+    /// Restores a wallet from its 32-byte ed25519 secret key.
+    pub fn from_secret_key(secret_key: &[u8]) -> Result<Self> {
+        let secret_key: &[u8; 32] = secret_key
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Expected a 32-byte ed25519 secret key, got {} bytes", secret_key.len()))?;
+        Ok(Self { signing_key: ed25519_dalek::SigningKey::from_bytes(secret_key) })
+    }
+
+    /// The wallet's 32-byte ed25519 secret key, for backup or import elsewhere.
+    pub fn secret_key(&self) -> [u8; 32] {
+        self.signing_key.to_bytes()
+    }
+
+    /// The wallet's key, for a validator to sign consensus messages with.
+    pub fn signing_key(&self) -> &ed25519_dalek::SigningKey {
+        &self.signing_key
+    }
+
+    /// Decrypts a wallet from the keystore file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P, password: &str) -> Result<Self> {
+        let keystore = Keystore::load(path)?;
+        let wallet = Self::from_secret_key(&keystore.decrypt(password)?)?;
+        if wallet.address() != keystore.address {
+            return Err(anyhow::anyhow!("Keystore address {} does not match its key", keystore.address));
+        }
+        Ok(wallet)
+    }
+
+    /// Encrypts the wallet under `password` into a new keystore file at `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P, password: &str) -> Result<()> {
+        Keystore::encrypt(&self.secret_key(), self.address(), password)?.save(path)
     }
 }
 
 impl Signer for LocalWallet {
     fn sign(&self, data: &[u8]) -> Result<Signature> {
         use ed25519_dalek::Signer;
-        let signature = self.keypair.sign(data);
+        let signature = self.signing_key.sign(data);
         Ok(Signature(signature.to_bytes().to_vec()))
     }
 
     fn public_key(&self) -> PublicKey {
-        PublicKey(self.keypair.public.to_bytes().to_vec())
+        PublicKey(self.signing_key.verifying_key().to_bytes().to_vec())
     }
 
     fn address(&self) -> Address {
-        // For simplicity, we'll use the hex-encoded public key as the address.
-        hex::encode(self.public_key().0)
+        address_of(&self.public_key())
+    }
+}
+
+/// A secp256k1 wallet that signs like an Ethereum account: transactions are
+/// signed as EIP-712 typed data, so the same key can be used from MetaMask
+/// or any other wallet supporting `eth_signTypedData_v4`.
+#[derive(Debug)]
+pub struct EvmWallet {
+    signing_key: k256::ecdsa::SigningKey,
+}
+
+impl Default for EvmWallet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EvmWallet {
+    /// Creates a new random wallet.
+    pub fn new() -> Self {
+        Self { signing_key: k256::ecdsa::SigningKey::random(&mut rand::rngs::OsRng) }
+    }
+
+    /// Restores a wallet from its 32-byte secp256k1 secret key, e.g. an
+    /// Ethereum private key.
+    pub fn from_secret_key(secret_key: &[u8]) -> Result<Self> {
+        Ok(Self { signing_key: k256::ecdsa::SigningKey::from_slice(secret_key)? })
+    }
+
+    /// The wallet's 32-byte secp256k1 secret key.
+    pub fn secret_key(&self) -> [u8; 32] {
+        self.signing_key.to_bytes().into()
+    }
+
+    /// The `0x`-prefixed Ethereum address of the key, for display; accounts
+    /// are still identified by their DID.
+    pub fn eth_address(&self) -> String {
+        let point = self.signing_key.verifying_key().to_encoded_point(false);
+        let hash = eip712::keccak256(&point.as_bytes()[1..]);
+        format!("0x{}", hex::encode(&hash[12..]))
+    }
+
+    /// Decrypts a wallet from the keystore file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P, password: &str) -> Result<Self> {
+        let keystore = Keystore::load(path)?;
+        let wallet = Self::from_secret_key(&keystore.decrypt(password)?)?;
+        if wallet.address() != keystore.address {
+            return Err(anyhow::anyhow!("Keystore address {} does not match its key", keystore.address));
+        }
+        Ok(wallet)
+    }
+
+    /// Encrypts the wallet under `password` into a new keystore file at `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P, password: &str) -> Result<()> {
+        Keystore::encrypt(&self.secret_key(), self.address(), password)?.save(path)
+    }
+}
+
+impl Signer for EvmWallet {
+    /// Signs the keccak256 digest of `data`, returning `r || s || v` with
+    /// Ethereum's `v` of 27 or 28.
+    fn sign(&self, data: &[u8]) -> Result<Signature> {
+        let (signature, recovery_id) = self.signing_key.sign_prehash_recoverable(&eip712::keccak256(data))?;
+        let mut bytes = signature.to_bytes().to_vec();
+        bytes.push(27 + recovery_id.to_byte());
+        Ok(Signature(bytes))
+    }
+
+    fn public_key(&self) -> PublicKey {
+        PublicKey(self.signing_key.verifying_key().to_encoded_point(true).as_bytes().to_vec())
+    }
+
+    fn address(&self) -> Address {
+        address_of(&self.public_key())
+    }
+
+    fn scheme(&self) -> SignatureScheme {
+        SignatureScheme::Eip712Secp256k1
     }
 }
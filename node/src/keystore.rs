@@ -0,0 +1,165 @@
+use anyhow::{Context, Result};
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::num::NonZeroU32;
+use std::path::Path;
+use uuid::Uuid;
+
+/// The keystore layout version written by this build.
+pub const KEYSTORE_VERSION: u32 = 1;
+
+/// PBKDF2 rounds used by `Keystore::encrypt`, matching the Ethereum clients'
+/// default for PBKDF2 keystores.
+pub const DEFAULT_ITERATIONS: u32 = 262_144;
+
+const CIPHER: &str = "aes-256-gcm";
+const KDF: &str = "pbkdf2";
+const PRF: &str = "hmac-sha256";
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 32;
+
+/// A secret key encrypted under a password, stored as JSON.
+///
+/// The layout follows Ethereum's v3 keystore: a PBKDF2 key derived from the
+/// password encrypts the secret. The cipher is AES-256-GCM rather than
+/// AES-128-CTR, so the authentication tag appended to `ciphertext` takes the
+/// place of the v3 `mac`. The address is authenticated too, so a file whose
+/// address was edited fails to decrypt.
+///
+/// Used for advertiser and publisher wallets, the validator key and the
+/// libp2p identity key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Keystore {
+    pub version: u32,
+    pub id: Uuid,
+    /// Who the key belongs to: an account address, or a peer id for libp2p keys.
+    pub address: String,
+    pub crypto: KeystoreCrypto,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeystoreCrypto {
+    pub cipher: String,
+    pub cipherparams: CipherParams,
+    /// Hex-encoded encrypted secret followed by the GCM tag.
+    pub ciphertext: String,
+    pub kdf: String,
+    pub kdfparams: KdfParams,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CipherParams {
+    /// Hex-encoded 12-byte nonce.
+    pub iv: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub c: u32,
+    pub dklen: usize,
+    pub prf: String,
+    /// Hex-encoded salt.
+    pub salt: String,
+}
+
+impl Keystore {
+    /// Encrypts `secret` under `password` with `DEFAULT_ITERATIONS` rounds.
+    pub fn encrypt(secret: &[u8], address: impl Into<String>, password: &str) -> Result<Self> {
+        Self::encrypt_with_iterations(secret, address, password, DEFAULT_ITERATIONS)
+    }
+
+    /// Encrypts `secret` under `password`, deriving the key with `iterations`
+    /// PBKDF2 rounds.
+    pub fn encrypt_with_iterations(
+        secret: &[u8],
+        address: impl Into<String>,
+        password: &str,
+        iterations: u32,
+    ) -> Result<Self> {
+        let rng = SystemRandom::new();
+        let mut salt = [0u8; SALT_LEN];
+        let mut iv = [0u8; aead::NONCE_LEN];
+        rng.fill(&mut salt).map_err(|_| anyhow::anyhow!("Failed to generate salt"))?;
+        rng.fill(&mut iv).map_err(|_| anyhow::anyhow!("Failed to generate iv"))?;
+
+        let address = address.into();
+        let key = derive_key(password, &salt, iterations)?;
+        let mut ciphertext = secret.to_vec();
+        key.seal_in_place_append_tag(Nonce::assume_unique_for_key(iv), Aad::from(address.as_bytes()), &mut ciphertext)
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt keystore"))?;
+
+        Ok(Self {
+            version: KEYSTORE_VERSION,
+            id: Uuid::new_v4(),
+            address,
+            crypto: KeystoreCrypto {
+                cipher: CIPHER.to_string(),
+                cipherparams: CipherParams { iv: hex::encode(iv) },
+                ciphertext: hex::encode(ciphertext),
+                kdf: KDF.to_string(),
+                kdfparams: KdfParams { c: iterations, dklen: KEY_LEN, prf: PRF.to_string(), salt: hex::encode(salt) },
+            },
+        })
+    }
+
+    /// Recovers the secret, failing on a wrong password or a tampered file.
+    pub fn decrypt(&self, password: &str) -> Result<Vec<u8>> {
+        let crypto = &self.crypto;
+        if self.version != KEYSTORE_VERSION {
+            return Err(anyhow::anyhow!("Unsupported keystore version {}", self.version));
+        }
+        if crypto.cipher != CIPHER || crypto.kdf != KDF || crypto.kdfparams.prf != PRF || crypto.kdfparams.dklen != KEY_LEN {
+            return Err(anyhow::anyhow!(
+                "Unsupported keystore scheme {}/{}/{}",
+                crypto.cipher,
+                crypto.kdf,
+                crypto.kdfparams.prf
+            ));
+        }
+
+        let key = derive_key(password, &hex::decode(&crypto.kdfparams.salt)?, crypto.kdfparams.c)?;
+        let iv: [u8; aead::NONCE_LEN] = hex::decode(&crypto.cipherparams.iv)?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Keystore iv must be {} bytes", aead::NONCE_LEN))?;
+        let mut ciphertext = hex::decode(&crypto.ciphertext)?;
+        let secret = key
+            .open_in_place(Nonce::assume_unique_for_key(iv), Aad::from(self.address.as_bytes()), &mut ciphertext)
+            .map_err(|_| anyhow::anyhow!("Wrong password or corrupted keystore"))?;
+        Ok(secret.to_vec())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).with_context(|| format!("Failed to read keystore {}", path.display()))?;
+        serde_json::from_slice(&bytes).with_context(|| format!("Invalid keystore {}", path.display()))
+    }
+
+    /// Writes the keystore to a new file readable by its owner only. An
+    /// existing file is never overwritten.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options
+            .open(path)
+            .with_context(|| format!("Failed to create keystore {}", path.display()))?;
+        file.write_all(&serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+fn derive_key(password: &str, salt: &[u8], iterations: u32) -> Result<LessSafeKey> {
+    let iterations = NonZeroU32::new(iterations).ok_or_else(|| anyhow::anyhow!("Keystore iterations must be non-zero"))?;
+    let mut derived = [0u8; KEY_LEN];
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, salt, password.as_bytes(), &mut derived);
+    let key = UnboundKey::new(&aead::AES_256_GCM, &derived).map_err(|_| anyhow::anyhow!("Invalid keystore key"))?;
+    Ok(LessSafeKey::new(key))
+}
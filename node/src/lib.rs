@@ -0,0 +1,21 @@
+pub mod config;
+pub mod consensus;
+pub mod credentials;
+pub mod eip712;
+pub mod encoding;
+pub mod error;
+pub mod http;
+pub mod identity;
+pub mod keystore;
+pub mod mempool;
+pub mod network;
+pub mod openrtb;
+pub mod prebid;
+pub mod primitives;
+pub mod relayer;
+pub mod rpc;
+pub mod settlement;
+pub mod snapshot;
+pub mod state;
+pub mod sync;
+pub mod wire;
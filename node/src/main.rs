@@ -1,16 +1,21 @@
-mod primitives;
-mod config;
-mod state;
-mod network;
-mod rpc;
-mod consensus;
-mod settlement; // <-- new module
-
 use anyhow::Result;
-use tokio::sync::mpsc;
-use crate::consensus::{ConsensusApp, Transaction};
-use crate::state::StateManager;
-use crate::settlement::SettlementManager; // <-- new import
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use unwalled_node::{config, consensus, http, network, rpc};
+use unwalled_node::consensus::{BlockProducer, ConsensusApp, ReceiptWaiters, Transaction, ValidatorSet};
+use unwalled_node::identity::{LocalWallet, PublicKey, Signer};
+use unwalled_node::mempool::Mempool;
+use unwalled_node::primitives::ChainEvent;
+use unwalled_node::relayer::Relayer;
+use unwalled_node::rpc::{RpcHandler, TlsConfig};
+use unwalled_node::state::StateManager;
+use unwalled_node::settlement::{self, SettlementAttesters};
+use unwalled_node::snapshot::SnapshotStore;
+use unwalled_node::sync::{BlockSyncer, SyncedBlock};
+
+/// How often the relayer looks for newly committed blocks to settle.
+const RELAYER_INTERVAL: Duration = Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -20,36 +25,183 @@ async fn main() -> Result<()> {
     let config = config::load_config()?;
     log::info!("Configuration loaded: {:?}", config);
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        return run_command(&config, &args);
+    }
+
     // --- Component Initialization ---
     let state_manager = StateManager::new(&config.db_path)?;
-    let settlement_manager = SettlementManager::new();
-    let app = ConsensusApp::new(state_manager);
-    let consensus_engine = consensus::Consensus::new();
+    // No settlement adapter ships yet, so the relayer cannot pay out or anchor
+    // anything; queued withdrawals wait for one.
+    let relayer = Relayer::new(state_manager.clone(), settlement::Disconnected, &config.chain_id)
+        .with_commitment_interval(config.commitment_interval);
+    let settlement_attesters = match config.settlement_attesters.as_slice() {
+        [] => None,
+        attesters => Some(SettlementAttesters::new(
+            &config.chain_id,
+            attesters.iter().map(|did| PublicKey::from_did(did)).collect::<Result<Vec<_>>>()?,
+            config.settlement_attestation_threshold,
+        )?),
+    };
+    let validator_wallet = load_validator_wallet(&config)?;
+    log::info!("Validator DID: {}", validator_wallet.did());
+    let p2p_key = match &config.p2p_key_path {
+        Some(path) => network::load_or_create_key(path, &keystore_password()?)?,
+        None => libp2p::identity::Keypair::generate_ed25519(),
+    };
+    let validators = match config.validators.as_slice() {
+        [] => {
+            log::warn!("No validators configured; running a single-validator development chain");
+            vec![validator_wallet.public_key()]
+        }
+        validators => validators
+            .iter()
+            .map(|key| PublicKey::from_did(key).or_else(|_| Ok(PublicKey(hex::decode(key)?))))
+            .collect::<Result<Vec<_>>>()?,
+    };
+    let snapshots = SnapshotStore::new(&config.snapshot_dir);
+    let (events, _) = broadcast::channel::<ChainEvent>(1024);
+    let receipt_waiters = ReceiptWaiters::default();
+    let mempool = Arc::new(Mutex::new(Mempool::new()));
+    let mut app = ConsensusApp::new(state_manager.clone())
+        .with_snapshots(snapshots.clone(), config.snapshot_interval)
+        .with_events(events.clone())
+        .with_receipt_waiters(receipt_waiters.clone())
+        .with_governors(config.governors.clone())
+        .with_chain_id(config.chain_id.clone());
+    let mut block_producer = BlockProducer::new(mempool.clone(), state_manager.clone());
+    if let Some(attesters) = settlement_attesters {
+        app = app.with_settlement(attesters.clone());
+        block_producer = block_producer.with_settlement(attesters);
+    }
+    let validator_set = ValidatorSet::new(&config.chain_id, validators);
 
     let (tx_to_consensus, mut rx_from_components) = mpsc::channel::<Transaction>(100);
+    let (tx_synced_blocks, mut rx_synced_blocks) = mpsc::channel::<SyncedBlock>(100);
+    let (tx_outbound, rx_outbound) = mpsc::unbounded_channel();
+    let (tx_commits, mut rx_commits) = mpsc::unbounded_channel::<()>();
 
-    let mut network_manager = network::NetworkManager::new(tx_to_consensus.clone()).await?;
-    let rpc_server = rpc::run_server(config.rpc_listen_address, tx_to_consensus);
+    let (consensus_network, consensus_inbox) =
+        network::consensus_network(validator_wallet.signing_key().clone(), tx_outbound.clone());
+    let syncer = BlockSyncer::new(
+        state_manager.clone(),
+        validator_set.clone(),
+        snapshots,
+        config.fast_sync_checkpoint.as_deref().map(str::parse).transpose()?,
+        tx_synced_blocks,
+    )?;
+    let replica = consensus::start_replica(&config.chain_id, &state_manager, &validator_set, &validator_wallet, block_producer, consensus_network, tx_commits)?;
+    let network_manager =
+        network::NetworkManager::new(p2p_key, tx_to_consensus.clone(), syncer, consensus_inbox, rx_outbound).await?;
+    let network_task = tokio::spawn(network::event_loop(network_manager));
+    tokio::pin!(network_task);
+    let tls = match (&config.rpc_cert_path, &config.rpc_key_path) {
+        (Some(cert_path), Some(key_path)) => TlsConfig {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+        },
+        _ => TlsConfig::self_signed(std::env::temp_dir().join("unwalled-node-tls"))?,
+    };
+    let rpc_handler = RpcHandler::new(tx_to_consensus, state_manager.clone(), events, receipt_waiters);
+    let rpc_server = rpc::run_server(config.rpc_listen_address, tls, rpc_handler.clone());
+    let http_server = http::run_server(config.http_listen_address, rpc_handler);
+    tokio::pin!(rpc_server);
+    tokio::pin!(http_server);
     
+    let mut relayer_interval = tokio::time::interval(RELAYER_INTERVAL);
+
     log::info!("All components initialized. Starting main event loop...");
 
     // --- Main Event Loop ---
     loop {
         tokio::select! {
-            _ = rpc_server => {
-                log::error!("RPC server task unexpectedly finished.");
+            result = &mut rpc_server => {
+                log::error!("RPC server task unexpectedly finished: {:?}", result);
                 break;
             },
-            event = network::event_loop(network_manager) => {
-                log::error!("Network event loop finished: {:?}", event);
+            result = &mut http_server => {
+                log::error!("HTTP RPC server task unexpectedly finished: {:?}", result);
+                break;
+            },
+            result = &mut network_task => {
+                log::error!("Network event loop finished: {:?}", result);
                 break;
             },
             Some(transaction) = rx_from_components.recv() => {
-                log::info!("Received transaction for consensus: {:?}", transaction);
-                // The consensus engine would process this transaction.
+                let mut mempool = mempool.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                match mempool.admit(transaction.clone(), &state_manager) {
+                    Ok(Some(hash)) => {
+                        log::info!("Added transaction {} to the mempool ({} pending)", hex::encode(hash), mempool.len());
+                        // Only fails once the network task has stopped.
+                        let _ = tx_outbound.send(network::Outbound::Transaction(Box::new(transaction)));
+                    }
+                    Ok(None) => log::debug!("Dropping duplicate or committed transaction"),
+                    Err(e) => log::debug!("Dropping transaction: {}", e),
+                }
+            },
+            Some(()) = rx_commits.recv() => {
+                if let Err(e) = consensus::apply_committed_blocks(&replica, &mut app, &state_manager, &mempool) {
+                    log::error!("Failed to apply committed block: {}", e);
+                }
+            },
+            Some(synced) = rx_synced_blocks.recv() => {
+                // The syncer logs failures and resumes from the state's height.
+                let _ = synced.applied.send(app.apply_block(synced.block));
+            },
+            _ = relayer_interval.tick() => {
+                if let Err(e) = relayer.run() {
+                    log::debug!("Relayer stopped short: {}", e);
+                }
             },
         }
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Loads the validator's signing key, generating and saving it on first start.
+fn load_validator_wallet(config: &config::Config) -> Result<LocalWallet> {
+    let path = match &config.validator_key_path {
+        Some(path) => std::path::Path::new(path),
+        None => return Ok(LocalWallet::new()),
+    };
+    let password = keystore_password()?;
+    if path.exists() {
+        return LocalWallet::load(path, &password);
+    }
+    let wallet = LocalWallet::new();
+    wallet.save(path, &password)?;
+    log::info!("Generated validator key in {}", path.display());
+    Ok(wallet)
+}
+
+fn keystore_password() -> Result<String> {
+    std::env::var(config::KEYSTORE_PASSWORD_ENV)
+        .map_err(|_| anyhow::anyhow!("Set {} to unlock the node's keystores", config::KEYSTORE_PASSWORD_ENV))
+}
+
+/// Runs an offline maintenance command against the node's database.
+///
+///   snapshot create            Snapshot the current state into `snapshot_dir`.
+///   snapshot restore <height>  Restore the snapshot at `height` into an empty database.
+fn run_command(config: &config::Config, args: &[String]) -> Result<()> {
+    let snapshots = SnapshotStore::new(&config.snapshot_dir);
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["snapshot", "create"] => {
+            let state_manager = StateManager::new(&config.db_path)?;
+            let manifest = snapshots.create(&state_manager)?;
+            println!("Created snapshot at height {} in {}", manifest.height(), config.snapshot_dir);
+            Ok(())
+        }
+        ["snapshot", "restore", height] => {
+            let state_manager = StateManager::new(&config.db_path)?;
+            let manifest = snapshots.restore(&state_manager, height.parse()?)?;
+            println!("Restored snapshot at height {} into {}", manifest.height(), config.db_path);
+            Ok(())
+        }
+        _ => Err(anyhow::anyhow!(
+            "Usage: unwalled-node [snapshot create | snapshot restore <height>]"
+        )),
+    }
+}
@@ -0,0 +1,102 @@
+use crate::consensus::Transaction;
+use crate::error::{ErrorCode, NodeError};
+use crate::primitives::Hash;
+use crate::state::StateManager;
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+
+/// The most transactions held at once by default.
+pub const DEFAULT_MEMPOOL_CAPACITY: usize = 10_000;
+
+/// Transactions waiting to be proposed, keyed by transaction id so the same
+/// transaction arriving over RPC and gossip, or from several peers, is held
+/// once.
+#[derive(Debug)]
+pub struct Mempool {
+    /// Each transaction with its arrival sequence number.
+    transactions: HashMap<Hash, (u64, Transaction)>,
+    /// Ids by arrival sequence number, for proposing oldest first.
+    order: BTreeMap<u64, Hash>,
+    next_sequence: u64,
+    capacity: usize,
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_MEMPOOL_CAPACITY)
+    }
+}
+
+impl Mempool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A mempool that refuses new transactions once it holds `capacity`.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            transactions: HashMap::new(),
+            order: BTreeMap::new(),
+            next_sequence: 0,
+            capacity,
+        }
+    }
+
+    /// Adds `transaction`, returning its id, or `None` if it is already
+    /// pending. Fails with `Unavailable` while the mempool is full.
+    pub fn insert(&mut self, transaction: Transaction) -> Result<Option<Hash>> {
+        let hash = transaction.hash()?;
+        if self.transactions.contains_key(&hash) {
+            return Ok(None);
+        }
+        if self.transactions.len() >= self.capacity {
+            return Err(NodeError::new(ErrorCode::Unavailable, "Mempool is full").into());
+        }
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.transactions.insert(hash, (sequence, transaction));
+        self.order.insert(sequence, hash);
+        Ok(Some(hash))
+    }
+
+    /// Admits a transaction from RPC or gossip. Already committed
+    /// transactions are ignored like pending ones; the signature, nonce and
+    /// fee are checked before it is inserted.
+    pub fn admit(&mut self, transaction: Transaction, state_manager: &StateManager) -> Result<Option<Hash>> {
+        let hash = transaction.hash()?;
+        if state_manager.get_receipt(&hash)?.is_some() {
+            return Ok(None);
+        }
+        if !transaction.verify().unwrap_or(false) {
+            return Err(NodeError::new(ErrorCode::InvalidSignature, "Invalid signature").into());
+        }
+        transaction.precheck(state_manager)?;
+        self.insert(transaction)
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.transactions.contains_key(hash)
+    }
+
+    /// Drops a transaction, e.g. once it has been committed.
+    pub fn remove(&mut self, hash: &Hash) -> Option<Transaction> {
+        let (sequence, transaction) = self.transactions.remove(hash)?;
+        self.order.remove(&sequence);
+        Some(transaction)
+    }
+
+    /// The pending transactions with their ids, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = (&Hash, &Transaction)> {
+        self.order
+            .values()
+            .filter_map(|hash| self.transactions.get_key_value(hash).map(|(hash, (_, transaction))| (hash, transaction)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+}
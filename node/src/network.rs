@@ -1,67 +1,289 @@
 use crate::consensus::Transaction;
+use crate::identity::{self, PublicKey, Signature};
+use crate::primitives;
+use crate::sync::{BlockSyncer, SyncRequest, SyncResponse, SYNC_PROTOCOL};
+use crate::keystore::Keystore;
+use crate::wire::WireFormat;
 use anyhow::Result;
+use borsh::{BorshDeserialize, BorshSerialize};
+use ed25519_dalek::{Signer as _, SigningKey, VerifyingKey};
+use hotstuff_rs::networking::messages::Message;
+use hotstuff_rs::networking::network::Network;
+use hotstuff_rs::types::update_sets::ValidatorSetUpdates;
+use hotstuff_rs::types::validator_set::ValidatorSet;
+use libp2p::futures::StreamExt;
 use libp2p::{
     gossipsub,
-    identity,
     mdns,
     noise,
+    request_response::{self, ProtocolSupport},
     swarm::{NetworkBehaviour, SwarmEvent},
     tcp,
     yamux,
+    StreamProtocol,
     Swarm,
     SwarmBuilder,
 };
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::{mpsc as std_mpsc, Arc, Mutex};
+use std::time::Duration;
 use tokio::sync::mpsc;
 
+/// How often connected peers are polled for their chain height.
+const SYNC_STATUS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Gossip topics for transactions, one per wire format and version, so the
+/// encoding can change without peers misreading each other's messages.
+/// Transactions are published as MessagePack; the JSON topic is read too,
+/// for debugging tools that publish by hand.
+pub const TRANSACTION_TOPICS: &[(&str, WireFormat)] = &[
+    ("/unwalled/tx/1", WireFormat::MessagePack),
+    ("/unwalled/tx/json/1", WireFormat::Json),
+];
+
+/// Gossip topic for consensus messages between validators.
+pub const CONSENSUS_TOPIC: &str = "/unwalled/consensus/1";
+
+/// The largest gossip message accepted, leaving room for a full block
+/// proposal and the consensus engine's sync responses.
+const MAX_GOSSIP_MESSAGE_SIZE: usize = 8 * 1024 * 1024;
+
+/// Messages other components hand to the network task to publish.
+#[derive(Debug)]
+pub enum Outbound {
+    /// An encoded `ConsensusEnvelope`.
+    Consensus(Vec<u8>),
+    /// A transaction admitted to the local mempool, for other nodes' mempools.
+    Transaction(Box<Transaction>),
+}
+
 pub struct NetworkManager {
     pub swarm: Swarm<MyBehaviour>,
     pub peer_id: libp2p::PeerId,
     tx_to_consensus: mpsc::Sender<Transaction>,
+    syncer: BlockSyncer,
+    consensus_inbox: ConsensusInbox,
+    outbound: mpsc::UnboundedReceiver<Outbound>,
+    /// Outcomes of synced block replays, which run as tasks of their own.
+    replayed: mpsc::UnboundedReceiver<Result<()>>,
+    tx_replayed: mpsc::UnboundedSender<Result<()>>,
 }
 
 #[derive(NetworkBehaviour)]
 pub struct MyBehaviour {
     pub gossipsub: gossipsub::Behaviour,
     pub mdns: mdns::tokio::Behaviour,
+    pub sync: request_response::json::Behaviour<SyncRequest, SyncResponse>,
+}
+
+/// Loads the node's libp2p identity from the keystore at `path`, creating
+/// it on first start so the peer id survives restarts.
+pub fn load_or_create_key<P: AsRef<Path>>(path: P, password: &str) -> Result<libp2p::identity::Keypair> {
+    let path = path.as_ref();
+    if path.exists() {
+        let keystore = Keystore::load(path)?;
+        let local_key = libp2p::identity::Keypair::ed25519_from_bytes(keystore.decrypt(password)?)?;
+        if local_key.public().to_peer_id().to_string() != keystore.address {
+            return Err(anyhow::anyhow!("Keystore peer id {} does not match its key", keystore.address));
+        }
+        return Ok(local_key);
+    }
+
+    let mut secret = [0u8; 32];
+    rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut secret);
+    let local_key = libp2p::identity::Keypair::ed25519_from_bytes(secret)?;
+    Keystore::encrypt(&secret, local_key.public().to_peer_id().to_string(), password)?.save(path)?;
+    log::info!("Generated libp2p key {} in {}", local_key.public().to_peer_id(), path.display());
+    Ok(local_key)
+}
+
+/// Identifies gossip messages by transaction id, so peers deduplicate the
+/// same transaction even when different nodes published it, or published it
+/// on different topics. Anything that is not a transaction is identified by
+/// the hash of its bytes.
+fn transaction_message_id(message: &gossipsub::Message) -> gossipsub::MessageId {
+    let hash = TRANSACTION_TOPICS
+        .iter()
+        .find(|(topic, _)| message.topic.as_str() == *topic)
+        .and_then(|(_, format)| format.decode::<Transaction>(&message.data).ok())
+        .and_then(|transaction| transaction.hash().ok())
+        .unwrap_or_else(|| primitives::sha256(&message.data));
+    gossipsub::MessageId::from(hash.to_vec())
 }
 
 impl NetworkManager {
-    pub async fn new(tx_to_consensus: mpsc::Sender<Transaction>) -> Result<Self> {
-        let local_key = identity::Keypair::generate_ed25519();
+    pub async fn new(
+        local_key: libp2p::identity::Keypair,
+        tx_to_consensus: mpsc::Sender<Transaction>,
+        syncer: BlockSyncer,
+        consensus_inbox: ConsensusInbox,
+        outbound: mpsc::UnboundedReceiver<Outbound>,
+    ) -> Result<Self> {
         let peer_id = libp2p::PeerId::from(local_key.public());
 
-        let transport = tcp::tokio::Transport::new(tcp::Config::default())
-            .upgrade(libp2p::core::upgrade::Version::V1Lazy)
-            .authenticate(noise::Config::new(&local_key)?)
-            .multiplex(yamux::Config::default())
-            .boxed();
-
-        let gossipsub_config = gossipsub::Config::default();
+        let gossipsub_config = gossipsub::ConfigBuilder::default()
+            .message_id_fn(transaction_message_id)
+            .max_transmit_size(MAX_GOSSIP_MESSAGE_SIZE)
+            .build()?;
         let mut gossipsub = gossipsub::Behaviour::new(
             gossipsub::MessageAuthenticity::Signed(local_key.clone()),
             gossipsub_config,
-        )?;
-        let transaction_topic = gossipsub::IdentTopic::new("transactions");
-        gossipsub.subscribe(&transaction_topic)?;
+        )
+        .map_err(anyhow::Error::msg)?;
+        for (topic, _) in TRANSACTION_TOPICS {
+            gossipsub.subscribe(&gossipsub::IdentTopic::new(*topic))?;
+        }
+        gossipsub.subscribe(&gossipsub::IdentTopic::new(CONSENSUS_TOPIC))?;
 
         let mdns = mdns::tokio::Behaviour::new(mdns::Config::default(), peer_id)?;
 
-        let behaviour = MyBehaviour { gossipsub, mdns };
-        let mut swarm = SwarmBuilder::with_tokio_executor(transport, behaviour, peer_id).build();
+        let sync = request_response::json::Behaviour::new(
+            [(StreamProtocol::new(SYNC_PROTOCOL), ProtocolSupport::Full)],
+            request_response::Config::default(),
+        );
+
+        let behaviour = MyBehaviour { gossipsub, mdns, sync };
+        let mut swarm = SwarmBuilder::with_existing_identity(local_key)
+            .with_tokio()
+            .with_tcp(tcp::Config::default(), noise::Config::new, yamux::Config::default)?
+            .with_behaviour(|_| behaviour)?
+            .build();
 
         swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
+        let (tx_replayed, replayed) = mpsc::unbounded_channel();
 
         Ok(Self {
             swarm,
             peer_id,
             tx_to_consensus,
+            syncer,
+            consensus_inbox,
+            outbound,
+            replayed,
+            tx_replayed,
         })
     }
+
+    fn publish(&mut self, outbound: Outbound) {
+        let result = match outbound {
+            Outbound::Consensus(data) => self
+                .swarm
+                .behaviour_mut()
+                .gossipsub
+                .publish(gossipsub::IdentTopic::new(CONSENSUS_TOPIC), data)
+                .map(|_| ())
+                .map_err(anyhow::Error::from),
+            Outbound::Transaction(transaction) => self.publish_transaction(&transaction),
+        };
+        if let Err(e) = result {
+            // Expected while no peer is connected, and for transactions that
+            // arrived over gossip in the first place.
+            log::debug!("Failed to publish: {}", e);
+        }
+    }
+
+    /// Gossips a transaction to peers on the MessagePack topic.
+    pub fn publish_transaction(&mut self, transaction: &Transaction) -> Result<()> {
+        let (topic, format) = TRANSACTION_TOPICS[0];
+        let data = format.encode(transaction)?;
+        self.swarm.behaviour_mut().gossipsub.publish(gossipsub::IdentTopic::new(topic), data)?;
+        Ok(())
+    }
+
+    fn send_sync_request(&mut self, next: Option<(libp2p::PeerId, SyncRequest)>) {
+        if let Some((peer_id, request)) = next {
+            log::debug!("Sending {:?} to {}", request, peer_id);
+            self.swarm.behaviour_mut().sync.send_request(&peer_id, request);
+        }
+    }
+
+    fn handle_sync_request(
+        &mut self,
+        request: SyncRequest,
+        channel: request_response::ResponseChannel<SyncResponse>,
+    ) {
+        match self.syncer.handle_request(request) {
+            Ok(response) => {
+                if self.swarm.behaviour_mut().sync.send_response(channel, response).is_err() {
+                    log::warn!("Failed to send sync response, peer disconnected");
+                }
+            }
+            Err(e) => log::error!("Failed to serve sync request: {}", e),
+        }
+    }
+
+    fn handle_sync_response(&mut self, peer: libp2p::PeerId, response: SyncResponse) {
+        let next = match response {
+            SyncResponse::Status { height } => self.syncer.on_status(peer, height),
+            SyncResponse::Blocks(blocks) => match self.syncer.on_blocks(peer, blocks) {
+                Ok(blocks) if blocks.is_empty() => None,
+                Ok(blocks) => {
+                    // Replaying waits on the main loop for every block, so
+                    // it runs apart from the swarm.
+                    let replay = self.syncer.replay(blocks);
+                    let tx_replayed = self.tx_replayed.clone();
+                    tokio::spawn(async move {
+                        let _ = tx_replayed.send(replay.await);
+                    });
+                    None
+                }
+                Err(e) => {
+                    log::warn!("Block sync failed: {:#}", e);
+                    None
+                }
+            },
+            SyncResponse::SnapshotManifest(manifest) => {
+                match self.syncer.on_snapshot_manifest(peer, manifest) {
+                    Ok(next) => next,
+                    Err(e) => {
+                        log::warn!("Fast sync failed: {:#}", e);
+                        None
+                    }
+                }
+            }
+            SyncResponse::SnapshotChunk { height, index, data } => {
+                match self.syncer.on_snapshot_chunk(peer, height, index, data) {
+                    Ok(next) => next,
+                    Err(e) => {
+                        log::warn!("Fast sync failed: {:#}", e);
+                        None
+                    }
+                }
+            }
+        };
+        self.send_sync_request(next);
+    }
+
+    fn handle_replayed(&mut self, result: Result<()>) {
+        let next = match self.syncer.on_replayed(result) {
+            Ok(next) => next,
+            Err(e) => {
+                log::warn!("Block sync failed: {:#}", e);
+                None
+            }
+        };
+        self.send_sync_request(next);
+    }
 }
 
 pub async fn event_loop(mut network_manager: NetworkManager) -> Result<()> {
+    let mut sync_status_interval = tokio::time::interval(SYNC_STATUS_INTERVAL);
+
     loop {
         tokio::select! {
+            Some(outbound) = network_manager.outbound.recv() => {
+                network_manager.publish(outbound);
+            },
+            Some(result) = network_manager.replayed.recv() => {
+                network_manager.handle_replayed(result);
+            },
+            _ = sync_status_interval.tick() => {
+                let peers: Vec<_> = network_manager.swarm.connected_peers().cloned().collect();
+                for peer_id in peers {
+                    network_manager.swarm.behaviour_mut().sync.send_request(&peer_id, SyncRequest::Status);
+                }
+            },
             event = network_manager.swarm.select_next_some() => match event {
                 SwarmEvent::NewListenAddr { address, .. } => {
                     log::info!("Network listening on {address}");
@@ -70,18 +292,183 @@ pub async fn event_loop(mut network_manager: NetworkManager) -> Result<()> {
                     for (peer_id, _multiaddr) in list {
                         log::info!("mDNS discovered a new peer: {peer_id}");
                         network_manager.swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
+                        network_manager.swarm.behaviour_mut().sync.send_request(&peer_id, SyncRequest::Status);
+                    }
+                },
+                SwarmEvent::Behaviour(MyBehaviourEvent::Sync(request_response::Event::Message { peer, message })) => match message {
+                    request_response::Message::Request { request, channel, .. } => {
+                        network_manager.handle_sync_request(request, channel);
                     }
+                    request_response::Message::Response { response, .. } => {
+                        network_manager.handle_sync_response(peer, response);
+                    }
+                },
+                SwarmEvent::Behaviour(MyBehaviourEvent::Sync(request_response::Event::OutboundFailure { peer, error, .. })) => {
+                    log::warn!("Sync request to {} failed: {}", peer, error);
+                    network_manager.syncer.on_failure(peer);
                 },
                 SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(gossipsub::Event::Message { message, .. })) => {
-                    if let Ok(tx) = serde_json::from_slice::<Transaction>(&message.data) {
-                        log::info!("Received gossiped transaction, sending to consensus.");
-                        if let Err(e) = network_manager.tx_to_consensus.send(tx).await {
-                            log::error!("Failed to send transaction to consensus channel: {}", e);
+                    if message.topic.as_str() == CONSENSUS_TOPIC {
+                        network_manager.consensus_inbox.receive(&message.data);
+                        continue;
+                    }
+                    let Some((_, format)) = TRANSACTION_TOPICS.iter().find(|(topic, _)| message.topic.as_str() == *topic) else {
+                        continue;
+                    };
+                    match format.decode::<Transaction>(&message.data) {
+                        Ok(tx) => {
+                            log::info!("Received gossiped transaction, sending to consensus.");
+                            if let Err(e) = network_manager.tx_to_consensus.send(tx).await {
+                                log::error!("Failed to send transaction to consensus channel: {}", e);
+                            }
                         }
+                        Err(e) => log::debug!("Dropping undecodable gossip on {}: {}", message.topic, e),
                     }
                 },
                 _ => {}
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// A consensus message signed by the validator that sent it, so receivers
+/// learn the sender from the signature rather than from the gossip peer
+/// that relayed it.
+#[derive(Debug, Serialize, Deserialize)]
+struct ConsensusEnvelope {
+    sender: PublicKey,
+    /// The validator the message is for, or `None` for every validator.
+    recipient: Option<PublicKey>,
+    /// The borsh-encoded consensus `Message`.
+    #[serde(with = "crate::identity::raw_bytes")]
+    message: Vec<u8>,
+    signature: Signature,
+}
+
+impl ConsensusEnvelope {
+    fn signing_bytes(recipient: Option<&PublicKey>, message: &[u8]) -> Vec<u8> {
+        let mut bytes = CONSENSUS_TOPIC.as_bytes().to_vec();
+        match recipient {
+            Some(recipient) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&recipient.0);
+            }
+            None => bytes.push(0),
+        }
+        bytes.extend_from_slice(message);
+        bytes
+    }
+}
+
+/// Creates the consensus engine's side of the network for the validator
+/// with `signing_key`, together with the inbox the network task feeds.
+/// Messages to publish are handed to the network task on `outbound`.
+pub fn consensus_network(signing_key: SigningKey, outbound: mpsc::UnboundedSender<Outbound>) -> (ConsensusNetwork, ConsensusInbox) {
+    let (sender, receiver) = std_mpsc::channel();
+    let me = signing_key.verifying_key();
+    let network = ConsensusNetwork {
+        signing_key,
+        outbound,
+        loopback: sender.clone(),
+        inbound: Arc::new(Mutex::new(receiver)),
+    };
+    (network, ConsensusInbox { me, sender })
+}
+
+/// Carries consensus messages over the gossip network for the consensus
+/// engine. Every validator receives every message and drops those
+/// addressed to someone else; messages to ourselves never leave the node.
+#[derive(Clone)]
+pub struct ConsensusNetwork {
+    signing_key: SigningKey,
+    outbound: mpsc::UnboundedSender<Outbound>,
+    loopback: std_mpsc::Sender<(VerifyingKey, Message)>,
+    inbound: Arc<Mutex<std_mpsc::Receiver<(VerifyingKey, Message)>>>,
+}
+
+impl ConsensusNetwork {
+    fn publish(&self, recipient: Option<VerifyingKey>, message: &Message) {
+        let message = match message.try_to_vec() {
+            Ok(message) => message,
+            Err(e) => {
+                log::error!("Failed to encode consensus message: {}", e);
+                return;
+            }
+        };
+        let recipient = recipient.map(|key| PublicKey(key.to_bytes().to_vec()));
+        let signature = self.signing_key.sign(&ConsensusEnvelope::signing_bytes(recipient.as_ref(), &message));
+        let envelope = ConsensusEnvelope {
+            sender: PublicKey(self.signing_key.verifying_key().to_bytes().to_vec()),
+            recipient,
+            message,
+            signature: Signature(signature.to_bytes().to_vec()),
+        };
+        match WireFormat::MessagePack.encode(&envelope) {
+            Ok(data) => {
+                // Only fails once the network task has stopped.
+                let _ = self.outbound.send(Outbound::Consensus(data));
+            }
+            Err(e) => log::error!("Failed to encode consensus envelope: {}", e),
+        }
+    }
+}
+
+impl Network for ConsensusNetwork {
+    // Every validator reads the one consensus topic, so there are no
+    // per-validator connections to maintain.
+    fn init_validator_set(&mut self, _validator_set: ValidatorSet) {}
+
+    fn update_validator_set(&mut self, _updates: ValidatorSetUpdates) {}
+
+    fn broadcast(&mut self, message: Message) {
+        self.publish(None, &message);
+        let _ = self.loopback.send((self.signing_key.verifying_key(), message));
+    }
+
+    fn send(&mut self, peer: VerifyingKey, message: Message) {
+        if peer == self.signing_key.verifying_key() {
+            let _ = self.loopback.send((peer, message));
+        } else {
+            self.publish(Some(peer), &message);
+        }
+    }
+
+    fn recv(&mut self) -> Option<(VerifyingKey, Message)> {
+        self.inbound.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).try_recv().ok()
+    }
+}
+
+/// Hands consensus messages gossiped by other validators to the consensus
+/// engine, once their envelope signature checks out.
+pub struct ConsensusInbox {
+    me: VerifyingKey,
+    sender: std_mpsc::Sender<(VerifyingKey, Message)>,
+}
+
+impl ConsensusInbox {
+    pub fn receive(&self, data: &[u8]) {
+        let envelope: ConsensusEnvelope = match WireFormat::MessagePack.decode(data) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                log::debug!("Dropping undecodable consensus message: {}", e);
+                return;
+            }
+        };
+        if envelope.recipient.as_ref().is_some_and(|recipient| recipient.0 != self.me.to_bytes()) {
+            return;
+        }
+        let signing_bytes = ConsensusEnvelope::signing_bytes(envelope.recipient.as_ref(), &envelope.message);
+        if !identity::verify_signature(&envelope.sender, &signing_bytes, &envelope.signature).unwrap_or(false) {
+            log::warn!("Dropping consensus message with a bad signature");
+            return;
+        }
+        let (Ok(sender), Ok(message)) = (
+            crate::consensus::verifying_key(&envelope.sender),
+            Message::try_from_slice(&envelope.message),
+        ) else {
+            log::debug!("Dropping malformed consensus message");
+            return;
+        };
+        let _ = self.sender.send((sender, message));
+    }
+}
@@ -0,0 +1,443 @@
+use crate::identity::{PublicKey, Signature, SignatureScheme, Signer};
+use crate::primitives::{self, AuctionTrigger, IdentityTier, Match, Signed, SignedAuctionTrigger, DEFAULT_CHAIN_ID};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use uuid::Uuid;
+
+/// Prices on chain are integer multiples of 10^-6 KUSD, pegged to USD.
+pub const PRICE_UNITS_PER_KUSD: u64 = 1_000_000;
+
+/// The only currency the adapter accepts for floors and answers in.
+pub const CURRENCY: &str = "USD";
+
+/// An OpenRTB 2.6 bid request. Only the fields the adapter reads are modelled;
+/// everything else is ignored.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BidRequest {
+    pub id: String,
+    pub imp: Vec<Imp>,
+    pub site: Option<Site>,
+    pub device: Option<Device>,
+    pub user: Option<User>,
+    /// The caller's timeout in milliseconds, used as the wait deadline.
+    pub tmax: Option<u64>,
+    pub ext: Option<BidRequestExt>,
+}
+
+impl BidRequest {
+    /// Splits the request into one single-impression request per imp, each
+    /// auctioned by its own trigger. A single-imp request is its own split;
+    /// otherwise each part is signed in its imp's `ext.unwalled` and its id
+    /// is `<request id>:<imp id>`.
+    pub fn per_imp(&self) -> Vec<BidRequest> {
+        if self.imp.len() == 1 {
+            return vec![self.clone()];
+        }
+        self.imp
+            .iter()
+            .map(|imp| BidRequest {
+                id: format!("{}:{}", self.id, imp.id),
+                imp: vec![imp.clone()],
+                ext: Some(BidRequestExt { unwalled: imp.ext.as_ref().and_then(|ext| ext.unwalled.clone()) }),
+                ..self.clone()
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Imp {
+    pub id: String,
+    pub banner: Option<Banner>,
+    pub video: Option<Video>,
+    /// The floor as a CPM in `bidfloorcur`.
+    pub bidfloor: f64,
+    pub bidfloorcur: Option<String>,
+    pub ext: Option<ImpExt>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImpExt {
+    /// The publisher's signature over this imp's trigger, in requests with
+    /// more than one imp.
+    pub unwalled: Option<UnwalledExt>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Banner {
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+    pub format: Vec<Format>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Format {
+    pub w: u32,
+    pub h: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Video {
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Site {
+    pub domain: Option<String>,
+    /// IAB content categories.
+    pub cat: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Device {
+    /// OpenRTB device type code (1 mobile, 2 PC, 3 CTV, 4 phone, 5 tablet, 7 set-top box).
+    pub devicetype: Option<u32>,
+    pub os: Option<String>,
+    pub geo: Option<Geo>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Geo {
+    /// ISO-3166-1 alpha-3 country code.
+    pub country: Option<String>,
+    pub region: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct User {
+    pub data: Vec<Data>,
+}
+
+/// A data provider's segments for the user.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Data {
+    pub id: Option<String>,
+    pub segment: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Segment {
+    pub id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BidRequestExt {
+    pub unwalled: Option<UnwalledExt>,
+}
+
+/// The publisher's signature over the `AuctionTrigger` this request translates
+/// to, so the node can submit it without holding the publisher's key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnwalledExt {
+    pub signer: PublicKey,
+    #[serde(default)]
+    pub scheme: SignatureScheme,
+    /// Absent means `DEFAULT_CHAIN_ID`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<String>,
+    pub signature: Signature,
+    pub nonce: u64,
+    pub fee: u64,
+}
+
+/// An OpenRTB 2.6 bid response with one bid per filled imp, grouped into a
+/// seat bid per winning advertiser.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BidResponse {
+    pub id: String,
+    pub seatbid: Vec<SeatBid>,
+    /// The transaction hash of the first filled imp's auction trigger.
+    pub bidid: String,
+    pub cur: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeatBid {
+    /// The winning advertiser's address.
+    pub seat: String,
+    pub bid: Vec<ResponseBid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseBid {
+    pub id: String,
+    pub impid: String,
+    /// The clearing price as a CPM in `CURRENCY`.
+    pub price: f64,
+    pub adm: String,
+    /// The on-chain bid id.
+    pub crid: String,
+}
+
+/// Maps OpenRTB request fields to the `key=value` targeting attributes bids
+/// are placed against, e.g. `geo=usa`, `device=mobile`, `interest=sports`.
+///
+/// Publishers sign the trigger this produces, so the mapping must be identical
+/// on every node and in every client: changing it is a new `version`.
+#[derive(Debug, Clone)]
+pub struct AttributeRegistry {
+    pub version: u32,
+    /// IAB content category (tier 1) to interest name.
+    interests: HashMap<&'static str, &'static str>,
+    /// OpenRTB device type code to device class.
+    device_classes: HashMap<u32, &'static str>,
+}
+
+impl Default for AttributeRegistry {
+    fn default() -> Self {
+        let interests = [
+            ("IAB1", "arts"),
+            ("IAB2", "automotive"),
+            ("IAB3", "business"),
+            ("IAB4", "careers"),
+            ("IAB5", "education"),
+            ("IAB6", "family"),
+            ("IAB7", "health"),
+            ("IAB8", "food"),
+            ("IAB9", "hobbies"),
+            ("IAB10", "home"),
+            ("IAB11", "law"),
+            ("IAB12", "news"),
+            ("IAB13", "finance"),
+            ("IAB14", "society"),
+            ("IAB15", "science"),
+            ("IAB16", "pets"),
+            ("IAB17", "sports"),
+            ("IAB18", "fashion"),
+            ("IAB19", "technology"),
+            ("IAB20", "travel"),
+            ("IAB21", "real-estate"),
+            ("IAB22", "shopping"),
+            ("IAB23", "religion"),
+        ];
+        let device_classes = [(1, "mobile"), (2, "desktop"), (3, "ctv"), (4, "mobile"), (5, "tablet"), (7, "ctv")];
+        Self {
+            version: 2,
+            interests: interests.into_iter().collect(),
+            device_classes: device_classes.into_iter().collect(),
+        }
+    }
+}
+
+impl AttributeRegistry {
+    /// Translates a single-impression bid request into the trigger the
+    /// publisher signs. Attributes are sorted so the result is deterministic.
+    /// Split multi-imp requests with `BidRequest::per_imp` first.
+    pub fn to_trigger(&self, request: &BidRequest) -> Result<AuctionTrigger> {
+        let imp = single_imp(request)?;
+        let mut attributes = BTreeSet::new();
+
+        if imp.banner.is_some() {
+            attributes.insert("format=banner".to_string());
+        }
+        if imp.video.is_some() {
+            attributes.insert("format=video".to_string());
+        }
+        if let Some(banner) = &imp.banner {
+            if let (Some(w), Some(h)) = (banner.w, banner.h) {
+                attributes.insert(format!("size={}x{}", w, h));
+            }
+            for format in &banner.format {
+                attributes.insert(format!("size={}x{}", format.w, format.h));
+            }
+        }
+
+        if let Some(site) = &request.site {
+            if let Some(domain) = &site.domain {
+                attributes.insert(format!("site={}", domain.to_lowercase()));
+            }
+            for category in &site.cat {
+                // Subcategories such as IAB17-12 count towards their parent.
+                let tier1 = category.split('-').next().unwrap_or(category);
+                if let Some(interest) = self.interests.get(tier1) {
+                    attributes.insert(format!("interest={}", interest));
+                }
+            }
+        }
+
+        if let Some(device) = &request.device {
+            if let Some(class) = device.devicetype.and_then(|code| self.device_classes.get(&code)) {
+                attributes.insert(format!("device={}", class));
+            }
+            if let Some(os) = &device.os {
+                attributes.insert(format!("os={}", os.to_lowercase()));
+            }
+            if let Some(geo) = &device.geo {
+                if let Some(country) = &geo.country {
+                    attributes.insert(format!("geo={}", country.to_lowercase()));
+                    if let Some(region) = &geo.region {
+                        attributes.insert(format!("geo={}-{}", country.to_lowercase(), region.to_lowercase()));
+                    }
+                }
+            }
+        }
+
+        if let Some(user) = &request.user {
+            for data in &user.data {
+                let Some(provider) = &data.id else { continue };
+                for segment in data.segment.iter().filter_map(|segment| segment.id.as_ref()) {
+                    attributes.insert(format!("segment={}:{}", provider, segment));
+                }
+            }
+        }
+
+        Ok(AuctionTrigger {
+            id: auction_id(&request.id),
+            bid_floor: floor_units(imp)?,
+            attributes: attributes.into_iter().collect(),
+            presentation: None,
+            min_advertiser_tier: IdentityTier::Anonymous,
+        })
+    }
+
+    /// Translates the request and attaches the publisher's signature from
+    /// `ext.unwalled`.
+    pub fn to_signed_trigger(&self, request: &BidRequest) -> Result<SignedAuctionTrigger> {
+        let ext = request
+            .ext
+            .as_ref()
+            .and_then(|ext| ext.unwalled.clone())
+            .ok_or_else(|| anyhow::anyhow!("Missing ext.unwalled signature"))?;
+        Ok(Signed {
+            data: self.to_trigger(request)?,
+            signer: ext.signer,
+            scheme: ext.scheme,
+            chain_id: ext.chain_id.unwrap_or_else(|| DEFAULT_CHAIN_ID.to_string()),
+            signature: ext.signature,
+            nonce: ext.nonce,
+            fee: ext.fee,
+        })
+    }
+
+    /// Translates every imp of the request into a signed trigger, in imp order.
+    pub fn to_signed_triggers(&self, request: &BidRequest) -> Result<Vec<SignedAuctionTrigger>> {
+        if request.imp.is_empty() {
+            return Err(anyhow::anyhow!("Expected at least one imp"));
+        }
+        request.per_imp().iter().map(|single| self.to_signed_trigger(single)).collect()
+    }
+
+    /// Signs the trigger of every imp, with consecutive nonces from `nonce`.
+    /// A single-imp request is signed in `ext.unwalled`, and otherwise each
+    /// imp in its own `ext.unwalled`.
+    pub fn sign_request<S: Signer>(&self, request: &mut BidRequest, nonce: u64, fee: u64, signer: &S) -> Result<()> {
+        self.sign_request_for_chain(DEFAULT_CHAIN_ID, request, nonce, fee, signer)
+    }
+
+    /// Like `sign_request`, for the network `chain_id`.
+    pub fn sign_request_for_chain<S: Signer>(
+        &self,
+        chain_id: &str,
+        request: &mut BidRequest,
+        nonce: u64,
+        fee: u64,
+        signer: &S,
+    ) -> Result<()> {
+        if request.imp.len() == 1 {
+            let unwalled = self.sign_trigger(chain_id, request, nonce, fee, signer)?;
+            request.ext.get_or_insert_with(Default::default).unwalled = Some(unwalled);
+            return Ok(());
+        }
+        for (index, single) in (0..).zip(request.per_imp()) {
+            let unwalled = self.sign_trigger(chain_id, &single, nonce + index, fee, signer)?;
+            request.imp[index as usize].ext.get_or_insert_with(Default::default).unwalled = Some(unwalled);
+        }
+        Ok(())
+    }
+
+    fn sign_trigger<S: Signer>(&self, chain_id: &str, request: &BidRequest, nonce: u64, fee: u64, signer: &S) -> Result<UnwalledExt> {
+        let signed = Signed::sign_for_chain(chain_id, self.to_trigger(request)?, nonce, fee, signer)?;
+        Ok(UnwalledExt {
+            signer: signed.signer,
+            scheme: signed.scheme,
+            chain_id: Some(signed.chain_id),
+            signature: signed.signature,
+            nonce,
+            fee,
+        })
+    }
+}
+
+/// A filled imp: its trigger's transaction hash, the match and the winning
+/// bid's markup.
+#[derive(Debug, Clone)]
+pub struct FilledImp {
+    pub imp_id: String,
+    pub tx_hash: String,
+    pub auction_match: Match,
+    pub adm: String,
+}
+
+/// Builds the response with one bid per filled imp, or `None` if no imp filled.
+pub fn bid_response(request: &BidRequest, filled: Vec<FilledImp>) -> Option<BidResponse> {
+    let bidid = filled.first()?.tx_hash.clone();
+    let mut seatbid: Vec<SeatBid> = Vec::new();
+    for imp in filled {
+        let bid = ResponseBid {
+            id: imp.auction_match.bid_id.to_string(),
+            impid: imp.imp_id,
+            price: units_to_cpm(imp.auction_match.winning_price),
+            adm: imp.adm,
+            crid: imp.auction_match.bid_id.to_string(),
+        };
+        match seatbid.iter_mut().find(|seat| seat.seat == imp.auction_match.advertiser_addr) {
+            Some(seat) => seat.bid.push(bid),
+            None => seatbid.push(SeatBid { seat: imp.auction_match.advertiser_addr, bid: vec![bid] }),
+        }
+    }
+    Some(BidResponse { id: request.id.clone(), seatbid, bidid, cur: CURRENCY.to_string() })
+}
+
+/// Converts a CPM in `CURRENCY` to the per-impression on-chain price, rounding up.
+pub fn cpm_to_units(cpm: f64) -> Result<u64> {
+    if !cpm.is_finite() || cpm < 0.0 {
+        return Err(anyhow::anyhow!("Invalid price {}", cpm));
+    }
+    Ok((cpm * PRICE_UNITS_PER_KUSD as f64 / 1000.0).ceil() as u64)
+}
+
+/// Converts a per-impression on-chain price to a CPM in `CURRENCY`.
+pub fn units_to_cpm(units: u64) -> f64 {
+    units as f64 * 1000.0 / PRICE_UNITS_PER_KUSD as f64
+}
+
+/// An auction trigger covers exactly one ad opportunity.
+fn single_imp(request: &BidRequest) -> Result<&Imp> {
+    match request.imp.as_slice() {
+        [imp] => Ok(imp),
+        imps => Err(anyhow::anyhow!("Expected one imp per trigger, got {}; split the request with per_imp", imps.len())),
+    }
+}
+
+fn floor_units(imp: &Imp) -> Result<u64> {
+    match imp.bidfloorcur.as_deref() {
+        None | Some(CURRENCY) => cpm_to_units(imp.bidfloor),
+        Some(other) => Err(anyhow::anyhow!("Unsupported floor currency {}", other)),
+    }
+}
+
+/// Uses the request id as the auction id when it is a UUID, and otherwise
+/// derives one from its hash so retries of the same request collide.
+fn auction_id(request_id: &str) -> Uuid {
+    request_id.parse().unwrap_or_else(|_| {
+        let hash = primitives::sha256(request_id.as_bytes());
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&hash[..16]);
+        uuid::Builder::from_custom_bytes(bytes).into_uuid()
+    })
+}
@@ -0,0 +1,177 @@
+use crate::identity::Signer;
+use crate::openrtb::{self, AttributeRegistry, Banner, BidRequest, BidRequestExt, Device, Format, Imp, Site, UnwalledExt, User, Video};
+use crate::primitives::{Match, SignedAuctionTrigger};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// How long, in seconds, Prebid may cache a returned bid.
+pub const BID_TTL_SECONDS: u32 = 300;
+
+/// The request a Prebid bidder adapter sends: one entry per ad unit, plus the
+/// page's first-party data in OpenRTB form.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PrebidRequest {
+    pub bids: Vec<PrebidBidRequest>,
+    pub ortb2: Option<Ortb2>,
+    /// The auction timeout in milliseconds.
+    pub timeout: Option<u64>,
+}
+
+/// First-party data shared by every ad unit on the page.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Ortb2 {
+    pub site: Option<Site>,
+    pub device: Option<Device>,
+    pub user: Option<User>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PrebidBidRequest {
+    pub bid_id: String,
+    pub ad_unit_code: String,
+    pub media_types: MediaTypes,
+    pub params: PrebidParams,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MediaTypes {
+    pub banner: Option<BannerMediaType>,
+    pub video: Option<VideoMediaType>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BannerMediaType {
+    /// `[width, height]` pairs.
+    pub sizes: Vec<[u32; 2]>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct VideoMediaType {
+    pub player_size: Vec<[u32; 2]>,
+}
+
+/// The bidder-specific parameters configured on the ad unit.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrebidParams {
+    /// The floor as a CPM in `openrtb::CURRENCY`.
+    pub floor: f64,
+    /// The publisher's signature over the trigger for this ad unit.
+    pub unwalled: Option<UnwalledExt>,
+}
+
+/// The adapter's answer: one bid per filled ad unit.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PrebidResponse {
+    pub bids: Vec<PrebidBid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrebidBid {
+    /// The `bidId` this answers.
+    pub request_id: String,
+    pub cpm: f64,
+    pub currency: String,
+    pub width: u32,
+    pub height: u32,
+    pub ad: String,
+    pub ttl: u32,
+    /// The on-chain bid id.
+    pub creative_id: String,
+    pub net_revenue: bool,
+}
+
+impl PrebidRequest {
+    /// Expresses one ad unit as a single-impression OpenRTB request, so it is
+    /// translated by the same `AttributeRegistry` as the OpenRTB endpoint.
+    pub fn to_openrtb(&self, bid: &PrebidBidRequest) -> BidRequest {
+        let ortb2 = self.ortb2.clone().unwrap_or_default();
+        let banner = bid.media_types.banner.as_ref().map(|banner| Banner {
+            w: None,
+            h: None,
+            format: banner.sizes.iter().map(|[w, h]| Format { w: *w, h: *h }).collect(),
+        });
+        let video = bid.media_types.video.as_ref().map(|video| Video {
+            w: video.player_size.first().map(|size| size[0]),
+            h: video.player_size.first().map(|size| size[1]),
+        });
+
+        BidRequest {
+            id: bid.bid_id.clone(),
+            imp: vec![Imp {
+                id: bid.ad_unit_code.clone(),
+                banner,
+                video,
+                bidfloor: bid.params.floor,
+                bidfloorcur: None,
+                ext: None,
+            }],
+            site: ortb2.site,
+            device: ortb2.device,
+            user: ortb2.user,
+            tmax: self.timeout,
+            ext: Some(BidRequestExt { unwalled: bid.params.unwalled.clone() }),
+        }
+    }
+
+    /// Translates every ad unit into a signed trigger.
+    pub fn to_signed_triggers(&self, registry: &AttributeRegistry) -> Result<Vec<SignedAuctionTrigger>> {
+        self.bids
+            .iter()
+            .map(|bid| registry.to_signed_trigger(&self.to_openrtb(bid)))
+            .collect()
+    }
+
+    /// Signs the trigger for every ad unit, with consecutive nonces from `first_nonce`.
+    pub fn sign<S: Signer>(&mut self, registry: &AttributeRegistry, first_nonce: u64, fee: u64, signer: &S) -> Result<()> {
+        for index in 0..self.bids.len() {
+            let mut request = self.to_openrtb(&self.bids[index]);
+            registry.sign_request(&mut request, first_nonce + index as u64, fee, signer)?;
+            self.bids[index].params.unwalled = request.ext.and_then(|ext| ext.unwalled);
+        }
+        Ok(())
+    }
+}
+
+/// Builds the bid for a filled ad unit. The reported size is the first
+/// requested size the winning bid targets, or else the first requested size.
+pub fn prebid_bid(
+    bid: &PrebidBidRequest,
+    auction_match: &Match,
+    adm: String,
+    targeting: &[String],
+) -> PrebidBid {
+    let sizes: Vec<[u32; 2]> = bid
+        .media_types
+        .banner
+        .iter()
+        .flat_map(|banner| banner.sizes.iter())
+        .chain(bid.media_types.video.iter().flat_map(|video| video.player_size.iter()))
+        .copied()
+        .collect();
+    let [width, height] = sizes
+        .iter()
+        .find(|[w, h]| targeting.contains(&format!("size={}x{}", w, h)))
+        .or_else(|| sizes.first())
+        .copied()
+        .unwrap_or([0, 0]);
+
+    PrebidBid {
+        request_id: bid.bid_id.clone(),
+        cpm: openrtb::units_to_cpm(auction_match.winning_price),
+        currency: openrtb::CURRENCY.to_string(),
+        width,
+        height,
+        ad: adm,
+        ttl: BID_TTL_SECONDS,
+        creative_id: auction_match.bid_id.to_string(),
+        net_revenue: true,
+    }
+}
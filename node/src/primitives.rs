@@ -1,14 +1,44 @@
-use crate::identity::{PublicKey, Signature};
+use crate::consensus::CommittedBlock;
+use crate::credentials::{VerifiableCredential, VerifiablePresentation};
+use crate::error::ErrorCode;
+use crate::eip712;
+use crate::encoding;
+use crate::identity::{Address, PublicKey, Signature, SignatureScheme, Signer};
+use crate::settlement::DepositProof;
 use anyhow::Result;
-use ed25519_dalek::Verifier;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+/// A 32-byte SHA-256 digest, used for block hashes and state roots.
+pub type Hash = [u8; 32];
+
+/// Computes the SHA-256 digest of the given bytes.
+pub fn sha256(data: &[u8]) -> Hash {
+    let digest = ring::digest::digest(&ring::digest::SHA256, data);
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(digest.as_ref());
+    hash
+}
+
+/// The chain id actions are signed for unless a node or client is configured
+/// with another one.
+pub const DEFAULT_CHAIN_ID: &str = "unwalled-devnet";
+
+fn default_chain_id() -> String {
+    DEFAULT_CHAIN_ID.to_string()
+}
+
 /// A generic wrapper for a signed transaction, now including a fee.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Signed<T> {
     pub data: T,
     pub signer: PublicKey,
+    /// How the payload was encoded and signed. Absent means ed25519.
+    #[serde(default)]
+    pub scheme: SignatureScheme,
+    /// The network the signature is valid on. Absent means `DEFAULT_CHAIN_ID`.
+    #[serde(default = "default_chain_id")]
+    pub chain_id: String,
     pub signature: Signature,
     pub nonce: u64,
     /// The fee offered to the validator for processing this transaction,
@@ -16,28 +46,348 @@ pub struct Signed<T> {
     pub fee: u64,
 }
 
-impl<T: Serialize> Signed<T> {
-    /// Verifies the signature of the wrapped data.
+/// A payload that can be wrapped in `Signed`.
+pub trait Action: Serialize {
+    /// Names the action in signatures' domain separator and in EIP-712
+    /// messages, so a signature for one action type is never valid for
+    /// another.
+    const TYPE: &'static str;
+}
+
+impl<T: Action> Signed<T> {
+    /// Signs `data` together with the nonce and fee for `DEFAULT_CHAIN_ID`,
+    /// in the signer's scheme.
+    pub fn sign<S: Signer>(data: T, nonce: u64, fee: u64, signer: &S) -> Result<Self> {
+        Self::sign_for_chain(DEFAULT_CHAIN_ID, data, nonce, fee, signer)
+    }
+
+    /// Signs `data` together with the nonce and fee for the network `chain_id`.
+    pub fn sign_for_chain<S: Signer>(chain_id: &str, data: T, nonce: u64, fee: u64, signer: &S) -> Result<Self> {
+        let scheme = signer.scheme();
+        let signature = signer.sign(&Self::signing_bytes(scheme, chain_id, &data, nonce, fee)?)?;
+        Ok(Self {
+            data,
+            signer: signer.public_key(),
+            scheme,
+            chain_id: chain_id.to_string(),
+            signature,
+            nonce,
+            fee,
+        })
+    }
+
+    /// Verifies the signature of the wrapped data. Which chain it is valid
+    /// on is for the caller to check against `chain_id`.
     pub fn verify(&self) -> Result<bool> {
-        // The fee and nonce must be part of the signed payload to prevent tampering.
-        let mut bytes_to_verify = serde_json::to_vec(&self.data)?;
-        bytes_to_verify.extend_from_slice(&self.nonce.to_le_bytes());
-        bytes_to_verify.extend_from_slice(&self.fee.to_le_bytes());
+        let bytes_to_verify = Self::signing_bytes(self.scheme, &self.chain_id, &self.data, self.nonce, self.fee)?;
+        self.scheme.verify(&self.signer, &bytes_to_verify, &self.signature)
+    }
 
-        let public_key = ed25519_dalek::PublicKey::from_bytes(&self.signer.0)?;
-        let signature = ed25519_dalek::Signature::from_bytes(&self.signature.0)?;
+    /// The transaction id: a hash of the canonical encoding of this signed
+    /// action. Mempools, gossip, receipts and RPC lookups all key on it.
+    pub fn hash(&self) -> Result<Hash> {
+        encoding::tx_hash(&self.chain_id, T::TYPE, self)
+    }
 
-        Ok(public_key.verify(&bytes_to_verify, &signature).is_ok())
+    /// The EIP-712 typed data for `data`, nonce and fee on `chain_id`, for
+    /// signing with an external Ethereum wallet via `eth_signTypedData_v4`.
+    /// The resulting signature goes in a `Signed` with the `Eip712Secp256k1`
+    /// scheme.
+    pub fn typed_data(chain_id: &str, data: &T, nonce: u64, fee: u64) -> Result<serde_json::Value> {
+        eip712::typed_data(chain_id, T::TYPE, data, nonce, fee)
+    }
+
+    /// The exact bytes covered by the signature. Both schemes bind the chain
+    /// id, action type, nonce and fee, so none can be changed or replayed
+    /// elsewhere.
+    fn signing_bytes(scheme: SignatureScheme, chain_id: &str, data: &T, nonce: u64, fee: u64) -> Result<Vec<u8>> {
+        match scheme {
+            SignatureScheme::Ed25519 => encoding::signing_bytes(chain_id, T::TYPE, data, nonce, fee),
+            SignatureScheme::Eip712Secp256k1 => eip712::encode(chain_id, T::TYPE, data, nonce, fee),
+        }
     }
 }
 
 /// Represents a bid from an advertiser.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Bid {
+    /// Chosen by the advertiser when signing. Once placed, the bid is stored,
+    /// matched and cancelled under `bid_id(advertiser, id)`.
     pub id: Uuid,
     pub price: u64,
     pub targeting: Vec<String>,
     pub adm: String,
+    /// The bid is removed from the book at the start of this block height.
+    #[serde(default)]
+    pub expires_at_height: Option<u64>,
+    /// Only match impressions whose credential-verified attributes cover
+    /// `targeting`; self-asserted attributes do not count.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub requires_verified_targeting: bool,
+    /// Only match users presenting at least this identity tier.
+    #[serde(default, skip_serializing_if = "IdentityTier::is_anonymous")]
+    pub min_user_tier: IdentityTier,
+}
+
+/// The id a bid is stored under once placed: a UUIDv8 holding the first
+/// 16 bytes of
+///
+/// `sha256("unwalled-bid" || len(advertiser) || advertiser || id)`
+///
+/// with `id` the one the advertiser chose and the length a big-endian u32.
+/// Advertisers pick ids freely, but two can never land on the same on-chain
+/// id, so one cannot squat or front-run another's.
+pub fn bid_id(advertiser: &Address, id: &Uuid) -> Uuid {
+    let mut bytes = b"unwalled-bid".to_vec();
+    bytes.extend_from_slice(&(advertiser.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(advertiser.as_bytes());
+    bytes.extend_from_slice(id.as_bytes());
+    let hash = sha256(&bytes);
+    let mut custom_bytes = [0u8; 16];
+    custom_bytes.copy_from_slice(&hash[..16]);
+    uuid::Builder::from_custom_bytes(custom_bytes).into_uuid()
+}
+
+/// Withdraws an open bid from the book. Only the bid's advertiser may cancel it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CancelBid {
+    pub bid_id: Uuid,
+}
+
+/// Credits funds locked on the settlement layer to the signer's account, or
+/// to `account` when funding another one such as a multisig account.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Deposit {
+    pub amount: u64,
+    /// The settlement-layer transaction that locked the funds. Each one can be
+    /// claimed once.
+    pub settlement_ref: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<Address>,
+    /// Attesters' signatures that the deposit is final and made out to the
+    /// claimant.
+    pub proof: DepositProof,
+}
+
+/// Debits the signer's account and releases the funds to `recipient` on the
+/// settlement layer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Withdraw {
+    pub amount: u64,
+    /// The settlement-layer address that receives the funds.
+    pub recipient: String,
+}
+
+/// A change to the trusted credential issuer registry. Adding, removing and
+/// re-scoping issuers is reserved to governance; an issuer's current key may
+/// also rotate itself and revoke its own credentials.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum IssuerRegistryUpdate {
+    /// Trusts `issuer`, a `did:key` DID that also becomes its first signing key.
+    AddIssuer { issuer: String, namespaces: Vec<String> },
+    RemoveIssuer { issuer: String },
+    /// Replaces the attribute namespaces `issuer` may attest.
+    SetIssuerScope { issuer: String, namespaces: Vec<String> },
+    /// Retires the issuer's signing key in favour of `new_key`. Credentials
+    /// signed with the old key are no longer trusted.
+    RotateIssuerKey { issuer: String, new_key: String },
+    /// Adds credential ids to the issuer's revocation list.
+    RevokeCredentials { issuer: String, credential_ids: Vec<String> },
+}
+
+/// A credential issuer trusted by the chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrustedIssuer {
+    /// The DID the issuer was added under, which stays its registry id
+    /// across key rotations.
+    pub id: String,
+    /// The DID of the key its credentials must currently be signed with.
+    pub key: String,
+    /// The attribute namespaces it may attest, e.g. `geo` for `geo=de`.
+    pub namespaces: Vec<String>,
+    pub added_at_height: u64,
+}
+
+/// How strongly an account's identity has been established. Tiers are
+/// ordered, so a requirement of `Verified` is also met by `Kyc`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdentityTier {
+    /// Nothing is known beyond the DID. The tier of every account by default.
+    #[default]
+    Anonymous,
+    /// Some attributes are backed by trusted credentials.
+    Verified,
+    /// The identity has passed know-your-customer checks.
+    Kyc,
+}
+
+/// The attribute namespace of tier claims, e.g. `tier=kyc`. Issuers scoped
+/// for it may attest tiers.
+pub const IDENTITY_TIER_NAMESPACE: &str = "tier";
+
+impl IdentityTier {
+    pub fn is_anonymous(&self) -> bool {
+        *self == IdentityTier::Anonymous
+    }
+}
+
+impl std::fmt::Display for IdentityTier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            IdentityTier::Anonymous => "anonymous",
+            IdentityTier::Verified => "verified",
+            IdentityTier::Kyc => "kyc",
+        })
+    }
+}
+
+impl std::str::FromStr for IdentityTier {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "anonymous" => Ok(IdentityTier::Anonymous),
+            "verified" => Ok(IdentityTier::Verified),
+            "kyc" => Ok(IdentityTier::Kyc),
+            _ => Err(anyhow::anyhow!("Unknown identity tier {:?}, expected anonymous, verified or kyc", s)),
+        }
+    }
+}
+
+/// Sets an account's identity tier.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum IdentityTierUpdate {
+    /// Sets `subject`'s tier. The signer must be the current key of a
+    /// trusted issuer scoped for the `tier` namespace.
+    Attest { subject: Address, tier: IdentityTier },
+    /// Sets the signer's own tier from a credential about the signer with a
+    /// `tier` claim, from an issuer trusted for the `tier` namespace.
+    Claim { credential: Box<VerifiableCredential> },
+}
+
+/// Authorises or revokes a session key that signs for the signer's account.
+/// Only the account's own key may manage its sessions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum SessionKeyUpdate {
+    /// Lets `session_key`, a `did:key` DID, sign the listed action types
+    /// (`Action::TYPE`s such as `PlaceBid`) for the signer's account.
+    /// Re-authorising a key replaces its grant and resets what it has spent.
+    Authorize {
+        session_key: Address,
+        actions: Vec<String>,
+        /// The most the session may spend in fees, withdrawals and winning
+        /// prices of its bids. Unlimited if absent.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        spend_limit: Option<u64>,
+        /// The session may not sign transactions from this height on.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires_at_height: Option<u64>,
+    },
+    Revoke { session_key: Address },
+}
+
+/// The action types a session key may be authorised for. Managing sessions,
+/// issuers and identity tiers always takes the account's own key.
+pub const DELEGABLE_ACTIONS: &[&str] =
+    &[Bid::TYPE, CancelBid::TYPE, AuctionTrigger::TYPE, Deposit::TYPE, Withdraw::TYPE];
+
+/// A session key's standing grant from its principal.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionKeyInfo {
+    pub session_key: Address,
+    /// The account the session signs for.
+    pub principal: Address,
+    pub actions: Vec<String>,
+    pub spend_limit: Option<u64>,
+    pub spent: u64,
+    pub expires_at_height: Option<u64>,
+    pub authorized_at_height: u64,
+}
+
+impl SessionKeyInfo {
+    /// What the session may still spend, or `None` if it is unlimited.
+    pub fn remaining(&self) -> Option<u64> {
+        self.spend_limit.map(|limit| limit.saturating_sub(self.spent))
+    }
+
+    /// Whether the session no longer acts for its principal at `height`.
+    pub fn is_expired(&self, height: u64) -> bool {
+        self.expires_at_height.is_some_and(|expires_at_height| expires_at_height <= height)
+    }
+}
+
+/// Creates and operates an M-of-N multisig account. Actions on the account
+/// are proposed by one member and run once `threshold` members approve.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum MultisigUpdate {
+    /// Creates an account at `identity::multisig_address(signer, nonce)`.
+    /// The signer need not be a member.
+    Create { members: Vec<Address>, threshold: u32 },
+    /// Proposes an action for the account. The proposer, who must be a
+    /// member, approves it by proposing.
+    Propose { account: Address, proposal: MultisigAction },
+    Approve { account: Address, proposal_id: u64 },
+}
+
+/// What a multisig account can be made to do.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MultisigAction {
+    Withdraw(Withdraw),
+    PlaceBid(Bid),
+    CancelBid(CancelBid),
+    /// Replaces the members and threshold.
+    SetMembers { members: Vec<Address>, threshold: u32 },
+}
+
+/// An account controlled by `threshold` of its `members`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MultisigAccount {
+    pub address: Address,
+    pub members: Vec<Address>,
+    pub threshold: u32,
+    pub created_at_height: u64,
+    /// The number of proposals made so far; the next proposal's id.
+    pub proposal_count: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultisigProposal {
+    pub id: u64,
+    pub account: Address,
+    pub action: MultisigAction,
+    pub proposer: Address,
+    /// Members who approved, in order. Only current members count towards
+    /// the threshold.
+    pub approvals: Vec<Address>,
+    pub status: ProposalStatus,
+    pub created_at_height: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ProposalStatus {
+    Open,
+    Executed { height: u64 },
+    /// The action was run on reaching the threshold and failed. A new
+    /// proposal is needed to try again.
+    Failed { code: ErrorCode, reason: String },
+}
+
+/// An account's recorded identity tier and who set it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityInfo {
+    pub did: Address,
+    pub tier: IdentityTier,
+    /// The registry id of the issuer that attested the tier or issued the
+    /// credential it was claimed with.
+    pub attested_by: String,
+    pub updated_at_height: u64,
 }
 
 /// Represents a request from a publisher for an ad.
@@ -46,6 +396,13 @@ pub struct AuctionTrigger {
     pub id: Uuid,
     pub bid_floor: u64,
     pub attributes: Vec<String>,
+    /// The user's credentials backing some of the attributes, presented with
+    /// `presentation_challenge` of the chain, publisher and auction id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presentation: Option<VerifiablePresentation>,
+    /// Only match bids from advertisers with at least this identity tier.
+    #[serde(default, skip_serializing_if = "IdentityTier::is_anonymous")]
+    pub min_advertiser_tier: IdentityTier,
 }
 
 /// Represents a successful match between a Bid and an AuctionTrigger.
@@ -58,6 +415,245 @@ pub struct Match {
     pub publisher_addr: String,
 }
 
+impl Action for Bid {
+    const TYPE: &'static str = "PlaceBid";
+}
+
+impl Action for AuctionTrigger {
+    const TYPE: &'static str = "TriggerAuction";
+}
+
+impl Action for CancelBid {
+    const TYPE: &'static str = "CancelBid";
+}
+
+impl Action for Deposit {
+    const TYPE: &'static str = "Deposit";
+}
+
+impl Action for Withdraw {
+    const TYPE: &'static str = "Withdraw";
+}
+
+impl Action for IssuerRegistryUpdate {
+    const TYPE: &'static str = "UpdateIssuerRegistry";
+}
+
+impl Action for IdentityTierUpdate {
+    const TYPE: &'static str = "UpdateIdentityTier";
+}
+
+impl Action for SessionKeyUpdate {
+    const TYPE: &'static str = "UpdateSessionKey";
+}
+
+impl Action for MultisigUpdate {
+    const TYPE: &'static str = "UpdateMultisig";
+}
+
 // Type aliases for signed transactions
 pub type SignedBid = Signed<Bid>;
 pub type SignedAuctionTrigger = Signed<AuctionTrigger>;
+pub type SignedCancelBid = Signed<CancelBid>;
+pub type SignedDeposit = Signed<Deposit>;
+pub type SignedWithdraw = Signed<Withdraw>;
+pub type SignedIssuerRegistryUpdate = Signed<IssuerRegistryUpdate>;
+pub type SignedIdentityTierUpdate = Signed<IdentityTierUpdate>;
+pub type SignedSessionKeyUpdate = Signed<SessionKeyUpdate>;
+pub type SignedMultisigUpdate = Signed<MultisigUpdate>;
+
+/// Returned by the RPC when a transaction is accepted for ordering.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubmitResponse {
+    /// Hex-encoded hash of the submitted transaction.
+    pub tx_hash: String,
+}
+
+/// Returned by the RPC when a request fails.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    pub error: String,
+}
+
+/// The lifecycle state of a bid on the book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BidStatus {
+    Open,
+    Cancelled,
+    Expired,
+    /// Won an auction; a bid is paid out at most once.
+    Filled,
+}
+
+/// A bid as stored on the book, with who placed it and when.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BidInfo {
+    pub bid: Bid,
+    pub advertiser: Address,
+    pub status: BidStatus,
+    pub placed_at_height: u64,
+    /// Global insertion order, used for time priority between equal prices.
+    pub sequence: u64,
+    /// The session key that placed the bid on the advertiser's behalf. Its
+    /// wins count against the session's spend limit, and revoking the
+    /// session pulls the bid from matching.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_key: Option<Address>,
+}
+
+/// An account's spendable balance and next expected nonce.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountInfo {
+    pub address: Address,
+    pub balance: u64,
+    pub nonce: u64,
+}
+
+/// Whether a delivered transaction changed state.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReceiptStatus {
+    Applied,
+    Rejected { code: ErrorCode, reason: String },
+}
+
+/// What became of an auction trigger submitted with a deadline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum AuctionOutcome {
+    /// The trigger committed and filled.
+    Matched { auction_match: Match },
+    /// The trigger committed but no bid qualified.
+    NoFill,
+    /// The trigger committed but was rejected, e.g. for a bad nonce.
+    Rejected { code: ErrorCode, reason: String },
+    /// The deadline passed before the trigger's block committed. The trigger
+    /// may still commit later; its receipt is then available by `tx_hash`.
+    Timeout,
+}
+
+/// The response to `POST /rpc/trigger_auction?wait_ms=...`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuctionResult {
+    pub tx_hash: String,
+    #[serde(flatten)]
+    pub outcome: AuctionOutcome,
+}
+
+impl From<Receipt> for AuctionOutcome {
+    fn from(receipt: Receipt) -> Self {
+        match (receipt.status, receipt.auction_match) {
+            (ReceiptStatus::Rejected { code, reason }, _) => AuctionOutcome::Rejected { code, reason },
+            (ReceiptStatus::Applied, Some(auction_match)) => AuctionOutcome::Matched { auction_match },
+            (ReceiptStatus::Applied, None) => AuctionOutcome::NoFill,
+        }
+    }
+}
+
+/// The outcome of a transaction delivered in a committed block.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Receipt {
+    /// Hex-encoded transaction hash.
+    pub tx_hash: String,
+    pub height: u64,
+    pub status: ReceiptStatus,
+    /// For auction triggers, the match produced, if any.
+    pub auction_match: Option<Match>,
+}
+
+/// A withdrawal debited by a committed transaction and waiting for the
+/// relayer to pay it out on the settlement layer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingWithdrawal {
+    /// The transaction that made the withdrawal, which also names the payout
+    /// on the settlement layer.
+    pub id: Hash,
+    pub height: u64,
+    pub address: Address,
+    pub amount: u64,
+    pub recipient: String,
+}
+
+/// A committed block together with its hex-encoded hash.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockInfo {
+    pub hash: String,
+    pub committed: CommittedBlock,
+}
+
+/// The latest committed block as seen by the queried node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainHead {
+    pub height: u64,
+    /// Hex-encoded hash of the head block, empty before the first block.
+    pub block_hash: String,
+    /// Hex-encoded state root of the head block, empty before the first block.
+    pub state_root: String,
+}
+
+/// A state change pushed to subscribers as blocks are applied.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ChainEvent {
+    BlockCommitted { height: u64, hash: String },
+    Matched { tx_hash: String, auction_match: Match },
+    BidExpired { bid_id: Uuid, advertiser: Address },
+    BidCancelled { bid_id: Uuid, advertiser: Address },
+}
+
+/// The kind of a `ChainEvent`, used to filter subscriptions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EventType {
+    BlockCommitted,
+    Matched,
+    BidExpired,
+    BidCancelled,
+}
+
+impl ChainEvent {
+    pub fn event_type(&self) -> EventType {
+        match self {
+            ChainEvent::BlockCommitted { .. } => EventType::BlockCommitted,
+            ChainEvent::Matched { .. } => EventType::Matched,
+            ChainEvent::BidExpired { .. } => EventType::BidExpired,
+            ChainEvent::BidCancelled { .. } => EventType::BidCancelled,
+        }
+    }
+}
+
+/// Selects which events a subscriber receives. Every non-empty list must
+/// contain a value from the event, so events without that kind of value
+/// (e.g. blocks when filtering by address) are excluded. An empty filter
+/// receives everything.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SubscriptionFilter {
+    #[serde(default)]
+    pub event_types: Vec<EventType>,
+    /// Matches the advertiser or publisher of an event.
+    #[serde(default)]
+    pub addresses: Vec<Address>,
+    #[serde(default)]
+    pub bid_ids: Vec<Uuid>,
+    #[serde(default)]
+    pub auction_ids: Vec<Uuid>,
+}
+
+impl SubscriptionFilter {
+    pub fn matches(&self, event: &ChainEvent) -> bool {
+        let (addresses, bid_id, auction_id): (Vec<&Address>, Option<&Uuid>, Option<&Uuid>) = match event {
+            ChainEvent::BlockCommitted { .. } => (vec![], None, None),
+            ChainEvent::Matched { auction_match, .. } => (
+                vec![&auction_match.advertiser_addr, &auction_match.publisher_addr],
+                Some(&auction_match.bid_id),
+                Some(&auction_match.auction_id),
+            ),
+            ChainEvent::BidExpired { bid_id, advertiser } | ChainEvent::BidCancelled { bid_id, advertiser } => {
+                (vec![advertiser], Some(bid_id), None)
+            }
+        };
+
+        (self.event_types.is_empty() || self.event_types.contains(&event.event_type()))
+            && (self.addresses.is_empty() || addresses.iter().any(|a| self.addresses.contains(a)))
+            && (self.bid_ids.is_empty() || bid_id.is_some_and(|id| self.bid_ids.contains(id)))
+            && (self.auction_ids.is_empty() || auction_id.is_some_and(|id| self.auction_ids.contains(id)))
+    }
+}
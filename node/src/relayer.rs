@@ -0,0 +1,90 @@
+use crate::settlement::{SettlementLayer, StateCommitment};
+use crate::state::StateManager;
+use anyhow::Result;
+use std::sync::Arc;
+
+/// Carries committed state to the settlement layer: pays out the
+/// withdrawals each block queued and anchors state commitments.
+///
+/// It runs beside consensus and only reads committed state, so executing,
+/// replaying or syncing blocks never pays or anchors anything. What it has
+/// done is recorded per withdrawal and per commitment, and the settlement
+/// layer pays each withdrawal id at most once, so a run cut short by a crash
+/// or an error is simply repeated.
+#[derive(Debug)]
+pub struct Relayer {
+    state_manager: StateManager,
+    settlement: Arc<dyn SettlementLayer>,
+    chain_id: String,
+    /// Anchor a state commitment every this many blocks; 0 never does.
+    commitment_interval: u64,
+}
+
+impl Relayer {
+    pub fn new(state_manager: StateManager, settlement: impl SettlementLayer + 'static, chain_id: &str) -> Self {
+        Self {
+            state_manager,
+            settlement: Arc::new(settlement),
+            chain_id: chain_id.to_string(),
+            commitment_interval: 0,
+        }
+    }
+
+    /// Anchors a state commitment every `interval` committed blocks.
+    pub fn with_commitment_interval(mut self, interval: u64) -> Self {
+        self.commitment_interval = interval;
+        self
+    }
+
+    /// Relays every block committed since the last run, oldest first. Stops
+    /// at the first failure, leaving that block to the next run.
+    pub fn run(&self) -> Result<()> {
+        let latest = self.state_manager.latest_height()?;
+        for height in self.state_manager.relayed_height()? + 1..=latest {
+            self.relay_block(height)?;
+            self.state_manager.set_relayed_height(height)?;
+        }
+        Ok(())
+    }
+
+    fn relay_block(&self, height: u64) -> Result<()> {
+        for withdrawal in self.state_manager.withdrawals_at(height)? {
+            let name = format!("withdrawal:{}", hex::encode(withdrawal.id));
+            if self.state_manager.relayed(&name)?.is_some() {
+                continue;
+            }
+            let settlement_ref =
+                self.settlement
+                    .withdraw(&withdrawal.id, &withdrawal.address, withdrawal.amount, &withdrawal.recipient)?;
+            log::info!(
+                "Paid out {} from {} to {} in {}",
+                withdrawal.amount,
+                withdrawal.address,
+                withdrawal.recipient,
+                settlement_ref
+            );
+            self.state_manager.record_relayed(&name, &settlement_ref)?;
+        }
+
+        if self.commitment_interval == 0 || !height.is_multiple_of(self.commitment_interval) {
+            return Ok(());
+        }
+        let name = format!("commitment:{}", height);
+        if self.state_manager.relayed(&name)?.is_some() {
+            return Ok(());
+        }
+        // Nodes that started from a snapshot hold no blocks below it.
+        let Some(committed) = self.state_manager.get_block(height)? else {
+            return Ok(());
+        };
+        let commitment = StateCommitment {
+            chain_id: self.chain_id.clone(),
+            height,
+            block_hash: committed.block.hash()?,
+            state_root: committed.block.state_root,
+        };
+        let settlement_ref = self.settlement.submit_commitment(&commitment)?;
+        log::info!("Anchored height {} in {}", height, settlement_ref);
+        self.state_manager.record_relayed(&name, &settlement_ref)
+    }
+}
@@ -1,31 +1,509 @@
-use crate::consensus::Transaction;
+use crate::consensus::{ReceiptWaiters, Transaction};
+use crate::error::{self, ErrorCode, NodeError};
+use crate::identity;
+use crate::openrtb::{self, AttributeRegistry, BidRequest};
+use crate::prebid::{self, PrebidRequest, PrebidResponse};
+use crate::primitives::{
+    AccountInfo, AuctionOutcome, AuctionResult, BlockInfo, ChainEvent, ChainHead, ErrorResponse, Hash, SignedAuctionTrigger, SignedBid,
+    SignedCancelBid, SignedDeposit, SignedIdentityTierUpdate, SignedIssuerRegistryUpdate, SignedMultisigUpdate, SignedSessionKeyUpdate,
+    SignedWithdraw, SubmitResponse,
+};
+use crate::state::StateManager;
+use crate::wire::{Negotiated, WireFormat};
 use anyhow::Result;
+use quiche::h3::NameValue;
+use serde::Serialize;
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::net::UdpSocket;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 
 const MAX_DATAGRAM_SIZE: usize = 1350;
 
-/// Runs the HTTP/3 RPC server.
-pub async fn run_server(
-    listen_address: SocketAddr,
+/// The largest request body the RPC servers will buffer.
+pub const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// The longest an auction trigger may hold its request open waiting to commit.
+pub const MAX_TRIGGER_WAIT: Duration = Duration::from_secs(2);
+
+/// Paths to the PEM-encoded certificate chain and private key served over QUIC.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+impl TlsConfig {
+    /// Generates a self-signed certificate for `localhost` and writes it to `dir`.
+    pub fn self_signed<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
+        let cert_path = dir.join("rpc-cert.pem");
+        let key_path = dir.join("rpc-key.pem");
+        std::fs::write(&cert_path, cert.serialize_pem()?)?;
+        std::fs::write(&key_path, cert.serialize_private_key_pem())?;
+
+        Ok(Self { cert_path, key_path })
+    }
+}
+
+/// The status code and body produced for an RPC request.
+#[derive(Debug, Clone)]
+pub struct RpcResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+    pub content_type: &'static str,
+}
+
+impl From<NodeError> for RpcResponse {
+    fn from(error: NodeError) -> Self {
+        Self::error(error.code, error.message)
+    }
+}
+
+impl RpcResponse {
+    pub fn json<T: Serialize>(status: u16, value: &T) -> Self {
+        Self::encoded(WireFormat::Json, status, value)
+    }
+
+    pub fn encoded<T: Serialize>(format: WireFormat, status: u16, value: &T) -> Self {
+        match format.encode(value) {
+            Ok(body) => Self { status, body, content_type: format.content_type() },
+            Err(e) => Self::error(ErrorCode::Internal, format!("Failed to encode response: {}", e)),
+        }
+    }
+
+    pub fn no_content() -> Self {
+        Self { status: 204, body: Vec::new(), content_type: WireFormat::Json.content_type() }
+    }
+
+    /// An `ErrorResponse` body with the HTTP status for `code`. Errors are
+    /// always JSON, whatever format the request asked for.
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        let body = serde_json::to_vec(&ErrorResponse { code, error: message.into() }).unwrap_or_default();
+        Self { status: code.http_status(), body, content_type: WireFormat::Json.content_type() }
+    }
+}
+
+/// Routes decoded RPC requests to the node. Transport-independent, so every
+/// listener answers the same paths the same way.
+#[derive(Clone)]
+pub struct RpcHandler {
     tx_to_consensus: mpsc::Sender<Transaction>,
-) -> Result<()> {
-    let socket = UdpSocket::bind(listen_address).await?;
-    log::info!("RPC server listening on {} with HTTP/3", listen_address);
+    state_manager: StateManager,
+    events: broadcast::Sender<ChainEvent>,
+    receipt_waiters: ReceiptWaiters,
+    attribute_registry: AttributeRegistry,
+}
+
+impl RpcHandler {
+    /// `receipt_waiters` must be the set the `ConsensusApp` notifies, or
+    /// waiting auction triggers always time out.
+    pub fn new(
+        tx_to_consensus: mpsc::Sender<Transaction>,
+        state_manager: StateManager,
+        events: broadcast::Sender<ChainEvent>,
+        receipt_waiters: ReceiptWaiters,
+    ) -> Self {
+        Self {
+            tx_to_consensus,
+            state_manager,
+            events,
+            receipt_waiters,
+            attribute_registry: AttributeRegistry::default(),
+        }
+    }
+
+    /// Starts receiving chain events for a new subscriber.
+    pub fn subscribe(&self) -> broadcast::Receiver<ChainEvent> {
+        self.events.subscribe()
+    }
+
+    /// Routes a request. Transactions are submitted with `POST`; state is read
+    /// with `GET` on the query paths below.
+    ///
+    /// `POST /rpc/trigger_auction?wait_ms=N` holds the request until the
+    /// trigger commits or `N` milliseconds pass (at most `MAX_TRIGGER_WAIT`),
+    /// and answers with an `AuctionResult` instead of a `SubmitResponse`.
+    ///
+    /// `POST /rpc/openrtb/bid` does the same for an OpenRTB 2.6 `BidRequest`,
+    /// with one trigger per imp, answering with a `BidResponse` holding a bid
+    /// per filled imp, or 204 if none filled in time.
+    /// `POST /rpc/prebid/bid` answers a Prebid bidder request with one bid per
+    /// filled ad unit. OpenRTB and Prebid are always JSON.
+    pub async fn handle(&self, method: &str, path: &str, body: &[u8]) -> RpcResponse {
+        self.handle_as(method, path, body, Negotiated::default()).await
+    }
+
+    /// Routes a request whose body is in `formats.request`, answering in
+    /// `formats.response`.
+    pub async fn handle_as(&self, method: &str, path: &str, body: &[u8], formats: Negotiated) -> RpcResponse {
+        let Negotiated { request: decode, response: format } = formats;
+        let (path, query_string) = path.split_once('?').unwrap_or((path, ""));
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match (method, segments.as_slice()) {
+            ("POST", ["rpc", "place_bid"]) => match decode.decode::<SignedBid>(body) {
+                Ok(signed_bid) => self.submit(format, Transaction::PlaceBid(signed_bid)).await,
+                Err(e) => RpcResponse::error(ErrorCode::InvalidRequest, format!("Invalid bid: {}", e)),
+            },
+            ("POST", ["rpc", "trigger_auction"]) => match decode.decode::<SignedAuctionTrigger>(body) {
+                Ok(signed_auction) => match query_param(query_string, "wait_ms") {
+                    None => self.submit(format, Transaction::TriggerAuction(signed_auction)).await,
+                    Some(wait_ms) => match wait_ms.parse() {
+                        Ok(wait_ms) => self.trigger_and_wait(format, signed_auction, Duration::from_millis(wait_ms)).await,
+                        Err(e) => RpcResponse::error(ErrorCode::InvalidRequest, format!("Invalid wait_ms: {}", e)),
+                    },
+                },
+                Err(e) => RpcResponse::error(ErrorCode::InvalidRequest, format!("Invalid auction trigger: {}", e)),
+            },
+            ("POST", ["rpc", "cancel_bid"]) => match decode.decode::<SignedCancelBid>(body) {
+                Ok(signed_cancel) => self.submit(format, Transaction::CancelBid(signed_cancel)).await,
+                Err(e) => RpcResponse::error(ErrorCode::InvalidRequest, format!("Invalid bid cancellation: {}", e)),
+            },
+            ("POST", ["rpc", "deposit"]) => match decode.decode::<SignedDeposit>(body) {
+                Ok(signed_deposit) => self.submit(format, Transaction::Deposit(signed_deposit)).await,
+                Err(e) => RpcResponse::error(ErrorCode::InvalidRequest, format!("Invalid deposit: {}", e)),
+            },
+            ("POST", ["rpc", "withdraw"]) => match decode.decode::<SignedWithdraw>(body) {
+                Ok(signed_withdraw) => self.submit(format, Transaction::Withdraw(signed_withdraw)).await,
+                Err(e) => RpcResponse::error(ErrorCode::InvalidRequest, format!("Invalid withdrawal: {}", e)),
+            },
+            ("POST", ["rpc", "issuer_registry"]) => match decode.decode::<SignedIssuerRegistryUpdate>(body) {
+                Ok(signed_update) => self.submit(format, Transaction::UpdateIssuerRegistry(signed_update)).await,
+                Err(e) => RpcResponse::error(ErrorCode::InvalidRequest, format!("Invalid issuer registry update: {}", e)),
+            },
+            ("POST", ["rpc", "identity_tier"]) => match decode.decode::<SignedIdentityTierUpdate>(body) {
+                Ok(signed_update) => self.submit(format, Transaction::UpdateIdentityTier(signed_update)).await,
+                Err(e) => RpcResponse::error(ErrorCode::InvalidRequest, format!("Invalid identity tier update: {}", e)),
+            },
+            ("POST", ["rpc", "session_key"]) => match decode.decode::<SignedSessionKeyUpdate>(body) {
+                Ok(signed_update) => self.submit(format, Transaction::UpdateSessionKey(signed_update)).await,
+                Err(e) => RpcResponse::error(ErrorCode::InvalidRequest, format!("Invalid session key update: {}", e)),
+            },
+            ("POST", ["rpc", "multisig"]) => match decode.decode::<SignedMultisigUpdate>(body) {
+                Ok(signed_update) => self.submit(format, Transaction::UpdateMultisig(signed_update)).await,
+                Err(e) => RpcResponse::error(ErrorCode::InvalidRequest, format!("Invalid multisig update: {}", e)),
+            },
+            ("POST", ["rpc", "openrtb", "bid"]) => match serde_json::from_slice::<BidRequest>(body) {
+                Ok(request) => self.openrtb_bid(request).await,
+                Err(e) => RpcResponse::error(ErrorCode::InvalidRequest, format!("Invalid OpenRTB bid request: {}", e)),
+            },
+            ("POST", ["rpc", "prebid", "bid"]) => match serde_json::from_slice::<PrebidRequest>(body) {
+                Ok(request) => self.prebid_bid(request).await,
+                Err(e) => RpcResponse::error(ErrorCode::InvalidRequest, format!("Invalid Prebid request: {}", e)),
+            },
+            (_, ["rpc", "place_bid"])
+            | (_, ["rpc", "trigger_auction"])
+            | (_, ["rpc", "cancel_bid"])
+            | (_, ["rpc", "deposit"])
+            | (_, ["rpc", "withdraw"])
+            | (_, ["rpc", "issuer_registry"])
+            | (_, ["rpc", "identity_tier"])
+            | (_, ["rpc", "session_key"])
+            | (_, ["rpc", "multisig"])
+            | (_, ["rpc", "openrtb", "bid"])
+            | (_, ["rpc", "prebid", "bid"]) => {
+                RpcResponse::error(ErrorCode::MethodNotAllowed, "Method not allowed")
+            }
+            ("GET", ["rpc", "account", address]) => self.account(format, address),
+            ("GET", ["rpc", "did", did]) => match identity::resolve_did(did) {
+                Ok(document) => RpcResponse::encoded(format, 200, &document),
+                Err(e) => RpcResponse::error(ErrorCode::InvalidRequest, format!("Invalid DID: {}", e)),
+            },
+            ("GET", ["rpc", "issuer", issuer]) => query(format, self.state_manager.get_trusted_issuer(issuer), "Issuer"),
+            ("GET", ["rpc", "identity", did]) => query(format, self.state_manager.get_identity(did), "Identity"),
+            ("GET", ["rpc", "session_key", session_key]) => {
+                query(format, self.state_manager.get_session_key(session_key), "Session key")
+            }
+            ("GET", ["rpc", "multisig", address]) => {
+                query(format, self.state_manager.get_multisig_account(address), "Multisig account")
+            }
+            ("GET", ["rpc", "multisig", address, "proposal", id]) => match id.parse() {
+                Ok(id) => query(format, self.state_manager.get_multisig_proposal(address, id), "Proposal"),
+                Err(e) => RpcResponse::error(ErrorCode::InvalidRequest, format!("Invalid proposal id: {}", e)),
+            },
+            ("GET", ["rpc", "bid", id]) => match id.parse() {
+                Ok(id) => query(format, self.state_manager.get_bid(&id), "Bid"),
+                Err(e) => RpcResponse::error(ErrorCode::InvalidRequest, format!("Invalid bid id: {}", e)),
+            },
+            ("GET", ["rpc", "advertiser", address, "bids"]) => {
+                query(format, self.state_manager.get_bids_by_advertiser(&address.to_string()).map(Some), "Advertiser")
+            }
+            ("GET", ["rpc", "receipt", tx_hash]) => match parse_hash(tx_hash) {
+                Ok(tx_hash) => query(format, self.state_manager.get_receipt(&tx_hash), "Receipt"),
+                Err(response) => response,
+            },
+            ("GET", ["rpc", "block", "hash", hash]) => match parse_hash(hash) {
+                Ok(hash) => query(format, self.block_info(self.state_manager.get_block_by_hash(&hash)), "Block"),
+                Err(response) => response,
+            },
+            ("GET", ["rpc", "block", height]) => match height.parse() {
+                Ok(height) => query(format, self.block_info(self.state_manager.get_block(height)), "Block"),
+                Err(e) => RpcResponse::error(ErrorCode::InvalidRequest, format!("Invalid block height: {}", e)),
+            },
+            ("GET", ["rpc", "head"]) => query(format, self.head().map(Some), "Head"),
+            _ => RpcResponse::error(ErrorCode::NotFound, format!("Unknown path {}", path)),
+        }
+    }
+
+    fn account(&self, format: WireFormat, address: &str) -> RpcResponse {
+        let address = address.to_string();
+        let info = self.state_manager.get_balance(&address).and_then(|balance| {
+            Ok(Some(AccountInfo {
+                nonce: self.state_manager.get_nonce(&address)?,
+                address: address.clone(),
+                balance,
+            }))
+        });
+        query(format, info, "Account")
+    }
+
+    fn block_info(
+        &self,
+        committed: Result<Option<crate::consensus::CommittedBlock>>,
+    ) -> Result<Option<BlockInfo>> {
+        match committed? {
+            Some(committed) => Ok(Some(BlockInfo {
+                hash: hex::encode(committed.block.hash()?),
+                committed,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    fn head(&self) -> Result<ChainHead> {
+        let height = self.state_manager.latest_height()?;
+        match self.state_manager.get_block(height)? {
+            Some(committed) => Ok(ChainHead {
+                height,
+                block_hash: hex::encode(committed.block.hash()?),
+                state_root: hex::encode(committed.block.state_root),
+            }),
+            None => Ok(ChainHead {
+                height,
+                block_hash: String::new(),
+                state_root: String::new(),
+            }),
+        }
+    }
+
+    /// Checks the signature and forwards the transaction to consensus.
+    async fn submit(&self, format: WireFormat, transaction: Transaction) -> RpcResponse {
+        let tx_hash = match self.check(&transaction) {
+            Ok(tx_hash) => tx_hash,
+            Err(response) => return response,
+        };
+        if let Err(response) = self.forward(transaction).await {
+            return response;
+        }
+        RpcResponse::encoded(format, 200, &SubmitResponse { tx_hash: hex::encode(tx_hash) })
+    }
+
+    /// Submits an auction trigger and waits up to `wait` for its receipt.
+    async fn trigger_and_wait(&self, format: WireFormat, signed_auction: SignedAuctionTrigger, wait: Duration) -> RpcResponse {
+        match self.wait_for_trigger(signed_auction, wait).await {
+            Ok((tx_hash, outcome)) => {
+                RpcResponse::encoded(format, 200, &AuctionResult { tx_hash: hex::encode(tx_hash), outcome })
+            }
+            Err(response) => response,
+        }
+    }
+
+    /// Translates an OpenRTB request into one signed trigger per imp, runs
+    /// them concurrently and answers with the winning bids' markup. A lone
+    /// imp's rejection is the response; in a multi-imp request, imps whose
+    /// trigger is rejected are left out like unfilled ones.
+    async fn openrtb_bid(&self, request: BidRequest) -> RpcResponse {
+        let signed_auctions = match self.attribute_registry.to_signed_triggers(&request) {
+            Ok(signed_auctions) => signed_auctions,
+            Err(e) => return RpcResponse::error(ErrorCode::InvalidRequest, e.to_string()),
+        };
+        let wait = request.tmax.map_or(MAX_TRIGGER_WAIT, Duration::from_millis);
+        let single = signed_auctions.len() == 1;
+        let outcomes = futures_util::future::join_all(
+            signed_auctions.into_iter().map(|signed_auction| self.wait_for_trigger(signed_auction, wait)),
+        )
+        .await;
+
+        let mut filled = Vec::new();
+        for (imp, outcome) in request.imp.iter().zip(outcomes) {
+            let (tx_hash, auction_match) = match outcome {
+                Ok((tx_hash, AuctionOutcome::Matched { auction_match })) => (tx_hash, auction_match),
+                Ok((_, AuctionOutcome::NoFill | AuctionOutcome::Timeout)) => continue,
+                Ok((_, AuctionOutcome::Rejected { code, reason })) if single => return RpcResponse::error(code, reason),
+                Err(rejected) if single => return rejected,
+                Ok((_, AuctionOutcome::Rejected { reason, .. })) => {
+                    log::warn!("OpenRTB trigger for imp {} was rejected: {}", imp.id, reason);
+                    continue;
+                }
+                Err(rejected) => {
+                    log::warn!("OpenRTB trigger for imp {} was not submitted: {}", imp.id, rejected.status);
+                    continue;
+                }
+            };
+            let adm = match self.state_manager.get_bid(&auction_match.bid_id) {
+                Ok(Some(info)) => info.bid.adm,
+                Ok(None) => return RpcResponse::error(ErrorCode::Internal, "Winning bid is missing from the book"),
+                Err(e) => return RpcResponse::error(ErrorCode::Internal, e.to_string()),
+            };
+            filled.push(openrtb::FilledImp { imp_id: imp.id.clone(), tx_hash: hex::encode(tx_hash), auction_match, adm });
+        }
+
+        match openrtb::bid_response(&request, filled) {
+            Some(response) => RpcResponse::json(200, &response),
+            None => RpcResponse::no_content(),
+        }
+    }
+
+    /// Runs one trigger per ad unit concurrently. Units that do not fill, or
+    /// whose trigger is rejected, are left out of the response.
+    async fn prebid_bid(&self, request: PrebidRequest) -> RpcResponse {
+        let signed_auctions = match request.to_signed_triggers(&self.attribute_registry) {
+            Ok(signed_auctions) => signed_auctions,
+            Err(e) => return RpcResponse::error(ErrorCode::InvalidRequest, e.to_string()),
+        };
+        let wait = request.timeout.map_or(MAX_TRIGGER_WAIT, Duration::from_millis);
+        let outcomes = futures_util::future::join_all(
+            signed_auctions.into_iter().map(|signed_auction| self.wait_for_trigger(signed_auction, wait)),
+        )
+        .await;
+
+        let mut response = PrebidResponse::default();
+        for (bid, outcome) in request.bids.iter().zip(outcomes) {
+            let auction_match = match outcome {
+                Ok((_, AuctionOutcome::Matched { auction_match })) => auction_match,
+                Ok((_, AuctionOutcome::Rejected { reason, .. })) => {
+                    log::warn!("Prebid trigger for {} was rejected: {}", bid.bid_id, reason);
+                    continue;
+                }
+                Ok(_) => continue,
+                Err(rejected) => {
+                    log::warn!("Prebid trigger for {} was not submitted: {}", bid.bid_id, rejected.status);
+                    continue;
+                }
+            };
+            match self.state_manager.get_bid(&auction_match.bid_id) {
+                Ok(Some(info)) => response.bids.push(prebid::prebid_bid(bid, &auction_match, info.bid.adm, &info.bid.targeting)),
+                Ok(None) => log::error!("Winning bid {} is missing from the book", auction_match.bid_id),
+                Err(e) => return RpcResponse::error(ErrorCode::Internal, e.to_string()),
+            }
+        }
+        RpcResponse::json(200, &response)
+    }
+
+    async fn wait_for_trigger(
+        &self,
+        signed_auction: SignedAuctionTrigger,
+        wait: Duration,
+    ) -> std::result::Result<(Hash, AuctionOutcome), RpcResponse> {
+        let transaction = Transaction::TriggerAuction(signed_auction);
+        let tx_hash = self.check(&transaction)?;
+
+        // Register before submitting so the receipt cannot slip past us.
+        let receipt = self.receipt_waiters.register(tx_hash);
+        let outcome = match self.state_manager.get_receipt(&tx_hash) {
+            // A resubmitted trigger that has already committed.
+            Ok(Some(committed)) => AuctionOutcome::from(committed),
+            _ => {
+                if let Err(response) = self.forward(transaction).await {
+                    self.receipt_waiters.forget(&tx_hash);
+                    return Err(response);
+                }
+                match tokio::time::timeout(wait.min(MAX_TRIGGER_WAIT), receipt).await {
+                    Ok(Ok(committed)) => AuctionOutcome::from(committed),
+                    _ => AuctionOutcome::Timeout,
+                }
+            }
+        };
+        self.receipt_waiters.forget(&tx_hash);
+        Ok((tx_hash, outcome))
+    }
+
+    /// Verifies the transaction's signature, prechecks its nonce and fee and
+    /// returns its hash.
+    fn check(&self, transaction: &Transaction) -> std::result::Result<Hash, RpcResponse> {
+        if !transaction.verify().unwrap_or(false) {
+            return Err(RpcResponse::error(ErrorCode::InvalidSignature, "Invalid signature"));
+        }
+        if let Err(e) = transaction.precheck(&self.state_manager) {
+            return Err(error::classify(&e).into());
+        }
+        transaction
+            .hash()
+            .map_err(|e| RpcResponse::error(ErrorCode::InvalidRequest, format!("Failed to hash transaction: {}", e)))
+    }
+
+    async fn forward(&self, transaction: Transaction) -> std::result::Result<(), RpcResponse> {
+        self.tx_to_consensus.send(transaction).await.map_err(|e| {
+            log::error!("Failed to send transaction from RPC to consensus: {}", e);
+            RpcResponse::error(ErrorCode::Unavailable, "Node is shutting down")
+        })
+    }
+}
+
+/// Returns the value of `name` in a `a=1&b=2` query string.
+fn query_param<'a>(query_string: &'a str, name: &str) -> Option<&'a str> {
+    query_string
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Turns a state lookup into a 200, a 404 naming `what`, or a 500.
+fn query<T: Serialize>(format: WireFormat, result: Result<Option<T>>, what: &str) -> RpcResponse {
+    match result {
+        Ok(Some(value)) => RpcResponse::encoded(format, 200, &value),
+        Ok(None) => RpcResponse::error(ErrorCode::NotFound, format!("{} not found", what)),
+        Err(e) => error::classify(&e).into(),
+    }
+}
 
-    let mut config = quiche::Config::new(quiche::VERSION_1)?;
+fn parse_hash(hex_hash: &str) -> std::result::Result<Hash, RpcResponse> {
+    hex::decode(hex_hash)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| RpcResponse::error(ErrorCode::InvalidRequest, "Invalid hash, expected 32 hex-encoded bytes"))
+}
 
-    // Generate a dummy self-signed certificate for scaffolding.
-    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
-    let cert_pem = cert.serialize_pem()?;
-    let key_pem = cert.serialize_private_key_pem();
-    
-    let priv_key = quiche::PrivateKey::from_pem(key_pem.as_bytes())?;
-    // NOTE: This method of loading certs from a slice is not directly in the quiche API.
-    // A real implementation would write to a temp file or use a more direct method.
-    config.load_cert_chain_from_pem_file(&cert_pem)?;
-    config.load_priv_key(&priv_key)?;
+/// A request being read off an HTTP/3 stream.
+#[derive(Default)]
+struct PartialRequest {
+    method: String,
+    path: String,
+    content_type: Option<String>,
+    accept: Option<String>,
+    body: Vec<u8>,
+}
+
+/// A response that could not be written to its stream in one go.
+struct PartialResponse {
+    headers: Option<Vec<quiche::h3::Header>>,
+    body: Vec<u8>,
+    written: usize,
+}
+
+/// A QUIC connection and its HTTP/3 state.
+struct Client {
+    conn: quiche::Connection,
+    h3_conn: Option<quiche::h3::Connection>,
+    requests: HashMap<u64, PartialRequest>,
+    responses: HashMap<u64, PartialResponse>,
+}
+
+/// Runs the HTTP/3 RPC server.
+pub async fn run_server(listen_address: SocketAddr, tls: TlsConfig, handler: RpcHandler) -> Result<()> {
+    let socket = UdpSocket::bind(listen_address).await?;
+    let local_addr = socket.local_addr()?;
+    log::info!("RPC server listening on {} with HTTP/3", local_addr);
+
+    let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    config.load_cert_chain_from_pem_file(path_str(&tls.cert_path)?)?;
+    config.load_priv_key_from_pem_file(path_str(&tls.key_path)?)?;
 
     config.set_application_protos(quiche::h3::APPLICATION_PROTOCOL)?;
     config.set_max_idle_timeout(5000);
@@ -34,21 +512,277 @@ pub async fn run_server(
     config.set_initial_max_data(10_000_000);
     config.set_initial_max_stream_data_bidi_local(1_000_000);
     config.set_initial_max_stream_data_bidi_remote(1_000_000);
+    config.set_initial_max_stream_data_uni(1_000_000);
     config.set_initial_max_streams_bidi(100);
+    config.set_initial_max_streams_uni(100);
+    config.set_disable_active_migration(true);
+    // Requests in 0-RTT data may be replayed; every transaction is protected
+    // by its nonce, so a replay is rejected on delivery.
+    config.enable_early_data();
 
+    let h3_config = quiche::h3::Config::new()?;
+
+    // Server connection ids are derived from the client's initial dcid with a
+    // per-process key, so retransmitted Initial packets map to the same connection.
+    let rng = ring::rand::SystemRandom::new();
+    let conn_id_seed = ring::hmac::Key::generate(ring::hmac::HMAC_SHA256, &rng)
+        .map_err(|_| anyhow::anyhow!("Failed to generate connection id seed"))?;
+
+    // Requests are handled on their own tasks, since an auction trigger may
+    // wait for its block; finished responses come back through this channel.
+    let (tx_completed, mut rx_completed) = mpsc::unbounded_channel::<(quiche::ConnectionId<'static>, u64, RpcResponse)>();
+
+    let mut clients: HashMap<quiche::ConnectionId<'static>, Client> = HashMap::new();
     let mut buf = [0; 65535];
+    let mut out = [0; MAX_DATAGRAM_SIZE];
+
+    loop {
+        let timeout = clients.values().filter_map(|c| c.conn.timeout()).min();
+
+        tokio::select! {
+            recv = socket.recv_from(&mut buf) => {
+                // A failed receive, e.g. an ICMP error surfaced by the OS,
+                // must not stop the server.
+                let (len, from) = match recv {
+                    Ok(v) => v,
+                    Err(e) => {
+                        log::warn!("Failed to receive QUIC packet: {}", e);
+                        continue;
+                    }
+                };
+                log::trace!("Received packet from {}", from);
+                let pkt_buf = &mut buf[..len];
+
+                let hdr = match quiche::Header::from_slice(pkt_buf, quiche::MAX_CONN_ID_LEN) {
+                    Ok(hdr) => hdr,
+                    Err(e) => {
+                        log::debug!("Failed to parse QUIC header from {}: {}", from, e);
+                        continue;
+                    }
+                };
+
+                let conn_id = ring::hmac::sign(&conn_id_seed, &hdr.dcid);
+                let conn_id = quiche::ConnectionId::from_vec(conn_id.as_ref()[..quiche::MAX_CONN_ID_LEN].to_vec());
+
+                let key = if clients.contains_key(&hdr.dcid) {
+                    hdr.dcid.clone().into_owned()
+                } else if clients.contains_key(&conn_id) {
+                    conn_id
+                } else {
+                    if hdr.ty != quiche::Type::Initial {
+                        log::debug!("Dropping non-Initial packet for unknown connection");
+                        continue;
+                    }
+                    if !quiche::version_is_supported(hdr.version) {
+                        match quiche::negotiate_version(&hdr.scid, &hdr.dcid, &mut out) {
+                            Ok(len) => {
+                                if let Err(e) = socket.send_to(&out[..len], from).await {
+                                    log::debug!("Failed to send version negotiation to {}: {}", from, e);
+                                }
+                            }
+                            Err(e) => log::debug!("Failed to negotiate QUIC version with {}: {}", from, e),
+                        }
+                        continue;
+                    }
+
+                    let conn = match quiche::accept(&conn_id, None, local_addr, from, &mut config) {
+                        Ok(conn) => conn,
+                        Err(e) => {
+                            log::debug!("Failed to accept QUIC connection from {}: {}", from, e);
+                            continue;
+                        }
+                    };
+                    log::debug!("New QUIC connection from {}", from);
+                    clients.insert(conn_id.clone(), Client {
+                        conn,
+                        h3_conn: None,
+                        requests: HashMap::new(),
+                        responses: HashMap::new(),
+                    });
+                    conn_id
+                };
+
+                let client = clients.get_mut(&key).expect("client was just looked up");
+                let recv_info = quiche::RecvInfo { from, to: local_addr };
+                if let Err(e) = client.conn.recv(pkt_buf, recv_info) {
+                    log::debug!("QUIC recv failed: {}", e);
+                    continue;
+                }
+
+                if client.h3_conn.is_none() && (client.conn.is_in_early_data() || client.conn.is_established()) {
+                    match quiche::h3::Connection::with_transport(&mut client.conn, &h3_config) {
+                        Ok(h3_conn) => client.h3_conn = Some(h3_conn),
+                        Err(e) => {
+                            log::debug!("Failed to start HTTP/3 on connection from {}: {}", from, e);
+                            client.conn.close(false, 0x1, b"h3 setup failed").ok();
+                        }
+                    }
+                }
+                if client.h3_conn.is_some() {
+                    handle_writable(client);
+                    poll_requests(client, &key, &handler, &tx_completed);
+                }
+            },
+            Some((key, stream_id, response)) = rx_completed.recv() => {
+                // The connection may have closed while the request was handled.
+                if let Some(client) = clients.get_mut(&key) {
+                    send_response(client, stream_id, response);
+                }
+            },
+            _ = tokio::time::sleep(timeout.unwrap_or(Duration::from_secs(60))), if timeout.is_some() => {
+                for client in clients.values_mut() {
+                    client.conn.on_timeout();
+                }
+            },
+        }
+
+        for client in clients.values_mut() {
+            loop {
+                let (write, send_info) = match client.conn.send(&mut out) {
+                    Ok(v) => v,
+                    Err(quiche::Error::Done) => break,
+                    Err(e) => {
+                        log::debug!("QUIC send failed: {}", e);
+                        client.conn.close(false, 0x1, b"fail").ok();
+                        break;
+                    }
+                };
+                if let Err(e) = socket.send_to(&out[..write], send_info.to).await {
+                    log::debug!("Failed to send QUIC packet to {}: {}", send_info.to, e);
+                    break;
+                }
+            }
+        }
+
+        clients.retain(|_, client| !client.conn.is_closed());
+    }
+}
+
+/// Reads HTTP/3 events for a connection, dispatching each complete request to
+/// a task that reports its response on `tx_completed`.
+fn poll_requests(
+    client: &mut Client,
+    key: &quiche::ConnectionId<'static>,
+    handler: &RpcHandler,
+    tx_completed: &mpsc::UnboundedSender<(quiche::ConnectionId<'static>, u64, RpcResponse)>,
+) {
+    let mut body_buf = [0; 65535];
 
     loop {
-        let (_len, from) = socket.recv_from(&mut buf).await?;
-        log::trace!("Received packet from {}", from);
-
-        // TODO: Full QUIC connection and H3 request handling is complex.
-        // This placeholder shows where a deserialized transaction would be
-        // sent to the consensus engine.
-        
-        // let transaction: Transaction = ... deserialize from H3 request body ...;
-        // if let Err(e) = tx_to_consensus.send(transaction).await {
-        //     log::error!("Failed to send transaction from RPC to consensus: {}", e);
-        // }
-    }
-}
\ No newline at end of file
+        let h3_conn = client.h3_conn.as_mut().expect("h3 connection is established");
+        match h3_conn.poll(&mut client.conn) {
+            Ok((stream_id, quiche::h3::Event::Headers { list, .. })) => {
+                let mut request = PartialRequest::default();
+                for header in &list {
+                    match header.name() {
+                        b":method" => request.method = String::from_utf8_lossy(header.value()).into_owned(),
+                        b":path" => request.path = String::from_utf8_lossy(header.value()).into_owned(),
+                        b"content-type" => request.content_type = Some(String::from_utf8_lossy(header.value()).into_owned()),
+                        b"accept" => request.accept = Some(String::from_utf8_lossy(header.value()).into_owned()),
+                        _ => {}
+                    }
+                }
+                client.requests.insert(stream_id, request);
+            }
+            Ok((stream_id, quiche::h3::Event::Data)) => {
+                let mut too_large = false;
+                while let Ok(read) = h3_conn.recv_body(&mut client.conn, stream_id, &mut body_buf) {
+                    if let Some(request) = client.requests.get_mut(&stream_id) {
+                        if request.body.len() + read > MAX_BODY_SIZE {
+                            too_large = true;
+                            break;
+                        }
+                        request.body.extend_from_slice(&body_buf[..read]);
+                    }
+                }
+                if too_large {
+                    client.requests.remove(&stream_id);
+                    send_response(client, stream_id, RpcResponse::error(ErrorCode::PayloadTooLarge, "Request body too large"));
+                }
+            }
+            Ok((stream_id, quiche::h3::Event::Finished)) => {
+                if let Some(request) = client.requests.remove(&stream_id) {
+                    let handler = handler.clone();
+                    let tx_completed = tx_completed.clone();
+                    let key = key.clone();
+                    tokio::spawn(async move {
+                        let formats = Negotiated::from_headers(request.content_type.as_deref(), request.accept.as_deref());
+                        let response = handler.handle_as(&request.method, &request.path, &request.body, formats).await;
+                        let _ = tx_completed.send((key, stream_id, response));
+                    });
+                }
+            }
+            Ok((stream_id, quiche::h3::Event::Reset(_))) => {
+                client.requests.remove(&stream_id);
+                client.responses.remove(&stream_id);
+            }
+            Ok(_) => {}
+            Err(quiche::h3::Error::Done) => break,
+            Err(e) => {
+                log::debug!("HTTP/3 error: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+/// Starts writing a response; whatever does not fit is finished by `handle_writable`.
+fn send_response(client: &mut Client, stream_id: u64, response: RpcResponse) {
+    let headers = vec![
+        quiche::h3::Header::new(b":status", response.status.to_string().as_bytes()),
+        quiche::h3::Header::new(b"content-type", response.content_type.as_bytes()),
+        quiche::h3::Header::new(b"content-length", response.body.len().to_string().as_bytes()),
+    ];
+    client.responses.insert(
+        stream_id,
+        PartialResponse { headers: Some(headers), body: response.body, written: 0 },
+    );
+    write_response(client, stream_id);
+}
+
+/// Continues any responses whose streams have become writable.
+fn handle_writable(client: &mut Client) {
+    let writable: Vec<u64> = client.conn.writable().collect();
+    for stream_id in writable {
+        write_response(client, stream_id);
+    }
+}
+
+fn write_response(client: &mut Client, stream_id: u64) {
+    let Some(h3_conn) = client.h3_conn.as_mut() else {
+        return;
+    };
+    let Some(response) = client.responses.get_mut(&stream_id) else {
+        return;
+    };
+
+    if let Some(headers) = &response.headers {
+        match h3_conn.send_response(&mut client.conn, stream_id, headers.as_slice(), false) {
+            Ok(()) => response.headers = None,
+            Err(quiche::h3::Error::StreamBlocked) => return,
+            Err(e) => {
+                log::debug!("Failed to send response headers: {}", e);
+                client.responses.remove(&stream_id);
+                return;
+            }
+        }
+    }
+
+    match h3_conn.send_body(&mut client.conn, stream_id, &response.body[response.written..], true) {
+        Ok(written) => response.written += written,
+        Err(quiche::h3::Error::Done) => {}
+        Err(e) => {
+            log::debug!("Failed to send response body: {}", e);
+            client.responses.remove(&stream_id);
+            return;
+        }
+    }
+    if response.written == response.body.len() {
+        client.responses.remove(&stream_id);
+    }
+}
+
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| anyhow::anyhow!("Path is not valid UTF-8: {}", path.display()))
+}
@@ -1,29 +1,322 @@
 use anyhow::Result;
-use crate::identity::Address;
+use crate::encoding;
+use crate::error::{ErrorCode, NodeError};
+use crate::identity::{self, Address, PublicKey, Signature, Signer};
+use crate::primitives::{Deposit, Hash};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 
-/// A placeholder for managing interactions with the L1 settlement layer (e.g., Keeta).
-pub struct SettlementManager;
+/// A deposit of KUSD locked on the settlement layer for an Unwalled address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SettlementDeposit {
+    pub recipient: Address,
+    pub amount: u64,
+}
 
-impl SettlementManager {
-    pub fn new() -> Self {
-        Self
+/// How settled a settlement-layer transaction is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Finality {
+    /// The settlement layer has no such transaction.
+    Unknown,
+    /// Included, but it could still be reverted.
+    Pending,
+    /// Irreversible.
+    Final,
+}
+
+/// The L1's state at a committed block, anchored on the settlement layer so
+/// that deposits and withdrawals can be checked against it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateCommitment {
+    pub chain_id: String,
+    pub height: u64,
+    pub block_hash: Hash,
+    pub state_root: Hash,
+}
+
+/// A settlement attester's ed25519 signature over a deposit claim.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attestation {
+    pub attester: PublicKey,
+    pub signature: Signature,
+}
+
+/// Proof carried by a `Deposit` that its settlement-layer transaction is a
+/// final deposit to `recipient`: attesters' signatures over the claim, which
+/// every node checks the same way instead of asking the settlement layer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepositProof {
+    pub recipient: Address,
+    pub attestations: Vec<Attestation>,
+}
+
+/// The bytes an attestation signs for a deposit of `amount` to `recipient`
+/// made by `settlement_ref`, on the chain `chain_id`.
+pub fn deposit_claim(chain_id: &str, settlement_ref: &str, recipient: &Address, amount: u64) -> Result<Vec<u8>> {
+    encoding::canonical(&json!({
+        "chain": chain_id,
+        "settlement_ref": settlement_ref,
+        "recipient": recipient,
+        "amount": amount,
+    }))
+}
+
+/// Checks on `settlement` that `settlement_ref` is a final deposit of
+/// `amount` to `recipient`, then attests to it. Run by attesters off-chain,
+/// so block execution never has to reach the settlement layer.
+pub fn attest_deposit(
+    settlement: &dyn SettlementLayer,
+    attester: &impl Signer,
+    chain_id: &str,
+    settlement_ref: &str,
+    recipient: &Address,
+    amount: u64,
+) -> Result<Attestation> {
+    settlement.confirm_deposit(recipient, amount, settlement_ref)?;
+    Ok(Attestation {
+        attester: attester.public_key(),
+        signature: attester.sign(&deposit_claim(chain_id, settlement_ref, recipient, amount)?)?,
+    })
+}
+
+/// The chain's settlement attesters, and how many of them must attest to a
+/// deposit before it can be credited.
+#[derive(Debug, Clone)]
+pub struct SettlementAttesters {
+    chain_id: String,
+    attesters: Vec<PublicKey>,
+    threshold: usize,
+}
+
+impl SettlementAttesters {
+    pub fn new(chain_id: &str, attesters: Vec<PublicKey>, threshold: usize) -> Result<Self> {
+        let distinct = attesters.iter().collect::<HashSet<_>>().len();
+        if threshold == 0 || threshold > distinct {
+            return Err(anyhow::anyhow!("Attestation threshold {} is not within 1..={}", threshold, distinct));
+        }
+        Ok(Self { chain_id: chain_id.to_string(), attesters, threshold })
     }
 
-    /// Placeholder for onboarding funds from the settlement layer to the Unwalled L1.
-    pub fn onboard_funds(&self, user_address: &Address, amount: u64) -> Result<()> {
-        log::info!("Onboarding {} KUSD for address {}", amount, user_address);
-        // In a real implementation, this would involve:
-        // 1. Watching for deposit events on the settlement layer contract.
-        // 2. Crediting the corresponding user's account on the Unwalled L1.
+    /// Checks that `deposit` carries valid attestations from at least
+    /// `threshold` distinct attesters. Depends only on the deposit itself, so
+    /// it can decide block validity as well as execution.
+    pub fn verify(&self, deposit: &Deposit) -> Result<()> {
+        let claim = deposit_claim(&self.chain_id, &deposit.settlement_ref, &deposit.proof.recipient, deposit.amount)?;
+        let mut attested = HashSet::new();
+        for attestation in &deposit.proof.attestations {
+            if self.attesters.contains(&attestation.attester)
+                && identity::verify_signature(&attestation.attester, &claim, &attestation.signature).unwrap_or(false)
+            {
+                attested.insert(&attestation.attester);
+            }
+        }
+        if attested.len() < self.threshold {
+            let message = format!(
+                "Deposit {} has {} of the {} attestations required",
+                deposit.settlement_ref,
+                attested.len(),
+                self.threshold
+            );
+            return Err(NodeError::new(ErrorCode::DepositNotConfirmed, message).into());
+        }
         Ok(())
     }
+}
 
-    /// Placeholder for offboarding funds from the Unwalled L1 to the settlement layer.
-    pub fn offboard_funds(&self, user_address: &Address, amount: u64) -> Result<()> {
-        log::info!("Offboarding {} KUSD for address {}", amount, user_address);
-        // In a real implementation, this would involve:
-        // 1. Debiting the user's account on the Unwalled L1.
-        // 2. Initiating a withdrawal transaction on the settlement layer.
+/// The interface a settlement layer (e.g. Keeta) implements for the L1.
+/// The node only talks to settlement through it, so adapters plug in
+/// without changing node logic. It is only used off-chain, by attesters and
+/// the relayer; block execution never calls it.
+///
+/// Settlement-layer transactions are named by an opaque `settlement_ref`
+/// chosen by the backend.
+pub trait SettlementLayer: std::fmt::Debug + Send + Sync {
+    /// The deposit made by the settlement-layer transaction `settlement_ref`,
+    /// or `None` if it made none.
+    fn deposit(&self, settlement_ref: &str) -> Result<Option<SettlementDeposit>>;
+
+    /// Pays `amount` KUSD debited from `address` out to `recipient` on the
+    /// settlement layer, returning the payout's `settlement_ref`.
+    ///
+    /// Every validator's relayer submits the same withdrawals, and may retry
+    /// after a crash, so each `withdrawal_id` must be paid at most once:
+    /// repeats return the original payout's `settlement_ref`.
+    fn withdraw(&self, withdrawal_id: &Hash, address: &Address, amount: u64, recipient: &str) -> Result<String>;
+
+    /// Anchors `commitment` on the settlement layer, returning the
+    /// submission's `settlement_ref`. Like withdrawals, a commitment already
+    /// anchored returns its original `settlement_ref`.
+    fn submit_commitment(&self, commitment: &StateCommitment) -> Result<String>;
+
+    /// How settled the transaction `settlement_ref` is.
+    fn finality(&self, settlement_ref: &str) -> Result<Finality>;
+
+    /// Checks that `settlement_ref` is a final deposit of `amount` KUSD made
+    /// out to `user_address`.
+    fn confirm_deposit(&self, user_address: &Address, amount: u64, settlement_ref: &str) -> Result<()> {
+        let not_confirmed = |message: String| -> anyhow::Error { NodeError::new(ErrorCode::DepositNotConfirmed, message).into() };
+        let deposit = self
+            .deposit(settlement_ref)?
+            .ok_or_else(|| not_confirmed(format!("No deposit found for {}", settlement_ref)))?;
+        if &deposit.recipient != user_address || deposit.amount != amount {
+            return Err(not_confirmed(format!("Deposit {} does not match the claim", settlement_ref)));
+        }
+        if self.finality(settlement_ref)? != Finality::Final {
+            return Err(not_confirmed(format!("Deposit {} is not final yet", settlement_ref)));
+        }
         Ok(())
     }
 }
+
+/// The settlement layer of a node with no settlement client connected.
+///
+/// No deposit can be confirmed and no withdrawal paid out, so attesting and
+/// relaying fail, leaving queued withdrawals to be paid once a client is
+/// connected.
+#[derive(Debug, Clone, Default)]
+pub struct Disconnected;
+
+impl Disconnected {
+    fn error(code: ErrorCode) -> anyhow::Error {
+        NodeError::new(code, "Settlement layer is not connected").into()
+    }
+}
+
+impl SettlementLayer for Disconnected {
+    fn deposit(&self, settlement_ref: &str) -> Result<Option<SettlementDeposit>> {
+        log::warn!("Cannot look up deposit {}: settlement layer not connected", settlement_ref);
+        Err(Self::error(ErrorCode::DepositNotConfirmed))
+    }
+
+    fn withdraw(&self, _withdrawal_id: &Hash, address: &Address, amount: u64, recipient: &str) -> Result<String> {
+        log::warn!("Cannot pay out {} KUSD from {} to {}: settlement layer not connected", amount, address, recipient);
+        Err(Self::error(ErrorCode::Unavailable))
+    }
+
+    fn submit_commitment(&self, commitment: &StateCommitment) -> Result<String> {
+        log::debug!("Not anchoring height {}: settlement layer not connected", commitment.height);
+        Err(Self::error(ErrorCode::Unavailable))
+    }
+
+    fn finality(&self, _settlement_ref: &str) -> Result<Finality> {
+        Ok(Finality::Unknown)
+    }
+}
+
+/// A payout recorded by `MockSettlement`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockWithdrawal {
+    pub withdrawal_id: Hash,
+    pub settlement_ref: String,
+    pub address: Address,
+    pub amount: u64,
+    pub recipient: String,
+}
+
+#[derive(Debug, Default)]
+struct MockLedger {
+    deposits: HashMap<String, SettlementDeposit>,
+    withdrawals: Vec<MockWithdrawal>,
+    commitments: Vec<(String, StateCommitment)>,
+    finality: HashMap<String, Finality>,
+    next_ref: u64,
+}
+
+impl MockLedger {
+    /// Records a new pending settlement-layer transaction.
+    fn record(&mut self) -> String {
+        let settlement_ref = format!("mock:{}", self.next_ref);
+        self.next_ref += 1;
+        self.finality.insert(settlement_ref.clone(), Finality::Pending);
+        settlement_ref
+    }
+}
+
+/// An in-process settlement layer for tests. Clones share one ledger, so a
+/// test keeps a handle to lock deposits, finalize transactions and inspect
+/// what the node paid out and anchored.
+///
+/// Everything starts out `Pending` until `finalize`d, except deposits made
+/// with `deposit_final`.
+#[derive(Debug, Clone, Default)]
+pub struct MockSettlement {
+    ledger: Arc<Mutex<MockLedger>>,
+}
+
+impl MockSettlement {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Locks `amount` KUSD for `recipient`, returning the pending deposit's
+    /// `settlement_ref`.
+    pub fn lock_deposit(&self, recipient: &Address, amount: u64) -> String {
+        let mut ledger = self.ledger.lock().unwrap();
+        let settlement_ref = ledger.record();
+        ledger
+            .deposits
+            .insert(settlement_ref.clone(), SettlementDeposit { recipient: recipient.clone(), amount });
+        settlement_ref
+    }
+
+    /// Locks a deposit that is final straight away.
+    pub fn deposit_final(&self, recipient: &Address, amount: u64) -> String {
+        let settlement_ref = self.lock_deposit(recipient, amount);
+        self.finalize(&settlement_ref);
+        settlement_ref
+    }
+
+    /// Marks `settlement_ref` as final.
+    pub fn finalize(&self, settlement_ref: &str) {
+        self.ledger.lock().unwrap().finality.insert(settlement_ref.to_string(), Finality::Final);
+    }
+
+    /// The payouts made so far, oldest first.
+    pub fn withdrawals(&self) -> Vec<MockWithdrawal> {
+        self.ledger.lock().unwrap().withdrawals.clone()
+    }
+
+    /// The commitments anchored so far with their `settlement_ref`s, oldest
+    /// first.
+    pub fn commitments(&self) -> Vec<(String, StateCommitment)> {
+        self.ledger.lock().unwrap().commitments.clone()
+    }
+}
+
+impl SettlementLayer for MockSettlement {
+    fn deposit(&self, settlement_ref: &str) -> Result<Option<SettlementDeposit>> {
+        Ok(self.ledger.lock().unwrap().deposits.get(settlement_ref).cloned())
+    }
+
+    fn withdraw(&self, withdrawal_id: &Hash, address: &Address, amount: u64, recipient: &str) -> Result<String> {
+        let mut ledger = self.ledger.lock().unwrap();
+        if let Some(paid) = ledger.withdrawals.iter().find(|paid| &paid.withdrawal_id == withdrawal_id) {
+            return Ok(paid.settlement_ref.clone());
+        }
+        let settlement_ref = ledger.record();
+        ledger.withdrawals.push(MockWithdrawal {
+            withdrawal_id: *withdrawal_id,
+            settlement_ref: settlement_ref.clone(),
+            address: address.clone(),
+            amount,
+            recipient: recipient.to_string(),
+        });
+        Ok(settlement_ref)
+    }
+
+    fn submit_commitment(&self, commitment: &StateCommitment) -> Result<String> {
+        let mut ledger = self.ledger.lock().unwrap();
+        if let Some((settlement_ref, _)) = ledger.commitments.iter().find(|(_, anchored)| anchored == commitment) {
+            return Ok(settlement_ref.clone());
+        }
+        let settlement_ref = ledger.record();
+        ledger.commitments.push((settlement_ref.clone(), commitment.clone()));
+        Ok(settlement_ref)
+    }
+
+    fn finality(&self, settlement_ref: &str) -> Result<Finality> {
+        Ok(self.ledger.lock().unwrap().finality.get(settlement_ref).copied().unwrap_or(Finality::Unknown))
+    }
+}
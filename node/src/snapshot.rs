@@ -0,0 +1,176 @@
+use crate::consensus::CommittedBlock;
+use crate::primitives::{self, Hash};
+use crate::state::StateManager;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The target size of a serialized snapshot chunk, well under the sync
+/// protocol's response limit.
+pub const SNAPSHOT_CHUNK_SIZE: usize = 512 * 1024;
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// A single key/value pair from one of the state column families.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    pub cf: String,
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+/// Describes a snapshot: the block it is anchored to and the hash of each chunk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    /// The committed block whose `state_root` the snapshot reproduces.
+    pub anchor: CommittedBlock,
+    pub chunk_hashes: Vec<Hash>,
+}
+
+impl SnapshotManifest {
+    pub fn height(&self) -> u64 {
+        self.anchor.block.height
+    }
+}
+
+/// Snapshots stored on disk, one directory per height.
+#[derive(Debug, Clone)]
+pub struct SnapshotStore {
+    dir: PathBuf,
+}
+
+impl SnapshotStore {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self { dir: dir.as_ref().to_path_buf() }
+    }
+
+    /// Creates a snapshot of the current state, anchored to the latest committed block.
+    pub fn create(&self, state_manager: &StateManager) -> Result<SnapshotManifest> {
+        let height = state_manager.latest_height()?;
+        let anchor = state_manager
+            .get_block(height)?
+            .ok_or_else(|| anyhow::anyhow!("No committed block to anchor a snapshot to"))?;
+
+        let snapshot_dir = self.height_dir(height);
+        fs::create_dir_all(&snapshot_dir)?;
+
+        let mut chunk_hashes = Vec::new();
+        let mut chunk: Vec<SnapshotEntry> = Vec::new();
+        let mut chunk_size = 0;
+        let mut write_chunk = |chunk: &mut Vec<SnapshotEntry>| -> Result<()> {
+            let bytes = serde_json::to_vec(chunk)?;
+            fs::write(snapshot_dir.join(chunk_file(chunk_hashes.len())), &bytes)?;
+            chunk_hashes.push(primitives::sha256(&bytes));
+            chunk.clear();
+            Ok(())
+        };
+
+        let state_root = state_manager.export_state(|entry| {
+            // Count the encoded size, separator included: JSON spells every
+            // byte out as a number.
+            chunk_size += serde_json::to_vec(&entry)?.len() + 1;
+            chunk.push(entry);
+            if chunk_size >= SNAPSHOT_CHUNK_SIZE {
+                write_chunk(&mut chunk)?;
+                chunk_size = 0;
+            }
+            Ok(())
+        })?;
+        if !chunk.is_empty() {
+            write_chunk(&mut chunk)?;
+        }
+
+        if state_root != anchor.block.state_root {
+            fs::remove_dir_all(&snapshot_dir)?;
+            return Err(anyhow::anyhow!(
+                "State root does not match block at height {}, refusing to snapshot",
+                height
+            ));
+        }
+
+        let manifest = SnapshotManifest { anchor, chunk_hashes };
+        fs::write(snapshot_dir.join(MANIFEST_FILE), serde_json::to_vec(&manifest)?)?;
+        log::info!(
+            "Created snapshot at height {} with {} chunks",
+            height,
+            manifest.chunk_hashes.len()
+        );
+        Ok(manifest)
+    }
+
+    pub fn manifest(&self, height: u64) -> Result<Option<SnapshotManifest>> {
+        let path = self.height_dir(height).join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+    }
+
+    /// Reads a raw chunk exactly as it was hashed into the manifest.
+    pub fn chunk(&self, height: u64, index: usize) -> Result<Option<Vec<u8>>> {
+        let path = self.height_dir(height).join(chunk_file(index));
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(fs::read(path)?))
+    }
+
+    /// Restores the snapshot stored on disk at `height` into `state_manager`.
+    pub fn restore(&self, state_manager: &StateManager, height: u64) -> Result<SnapshotManifest> {
+        let manifest = self
+            .manifest(height)?
+            .ok_or_else(|| anyhow::anyhow!("No snapshot at height {}", height))?;
+        let chunks = (0..manifest.chunk_hashes.len())
+            .map(|index| {
+                self.chunk(height, index)?
+                    .ok_or_else(|| anyhow::anyhow!("Snapshot chunk {} is missing", index))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        restore(state_manager, &manifest, &chunks)?;
+        Ok(manifest)
+    }
+
+    fn height_dir(&self, height: u64) -> PathBuf {
+        self.dir.join(height.to_string())
+    }
+}
+
+/// Checks a downloaded chunk against the hash recorded in the manifest.
+pub fn verify_chunk(manifest: &SnapshotManifest, index: usize, chunk: &[u8]) -> Result<()> {
+    match manifest.chunk_hashes.get(index) {
+        Some(expected) if *expected == primitives::sha256(chunk) => Ok(()),
+        Some(_) => Err(anyhow::anyhow!("Snapshot chunk {} hash mismatch", index)),
+        None => Err(anyhow::anyhow!("Snapshot chunk {} is not in the manifest", index)),
+    }
+}
+
+/// Verifies every chunk of a snapshot, then imports them and records the
+/// anchor block as the local tip in a single write, once the state root
+/// matches. The caller is responsible for checking the anchor's commit
+/// certificate.
+pub fn restore(state_manager: &StateManager, manifest: &SnapshotManifest, chunks: &[Vec<u8>]) -> Result<()> {
+    if !state_manager.is_empty()? {
+        return Err(anyhow::anyhow!("Snapshots can only be restored into an empty database"));
+    }
+    if chunks.len() != manifest.chunk_hashes.len() {
+        return Err(anyhow::anyhow!(
+            "Expected {} snapshot chunks, got {}",
+            manifest.chunk_hashes.len(),
+            chunks.len()
+        ));
+    }
+
+    let mut entries = Vec::new();
+    for (index, chunk) in chunks.iter().enumerate() {
+        verify_chunk(manifest, index, chunk)?;
+        entries.extend(serde_json::from_slice::<Vec<SnapshotEntry>>(chunk)?);
+    }
+    state_manager.import_state(entries, &manifest.anchor)?;
+    log::info!("Restored snapshot at height {}", manifest.height());
+    Ok(())
+}
+
+fn chunk_file(index: usize) -> String {
+    format!("chunk-{}.json", index)
+}
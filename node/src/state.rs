@@ -1,29 +1,134 @@
+use crate::consensus::CommittedBlock;
+use crate::error::{ErrorCode, NodeError};
 use crate::identity::Address;
-use crate::primitives::{AuctionTrigger, Bid, Match};
+use crate::credentials::VerifiableCredential;
+use crate::primitives::{
+    self, AuctionTrigger, Bid, BidInfo, BidStatus, Hash, IdentityInfo, IdentityTier, Match, MultisigAccount, MultisigProposal, PendingWithdrawal, Receipt,
+    SessionKeyInfo, TrustedIssuer,
+};
+use crate::snapshot::SnapshotEntry;
 use anyhow::Result;
-use rocksdb::{ColumnFamilyDescriptor, Options, DB};
+use rocksdb::{ColumnFamilyDescriptor, Direction, IteratorMode, Options, WriteBatch, DB};
+use std::collections::HashMap;
 use std::path::Path;
+use std::sync::Arc;
+use uuid::Uuid;
 
 const CF_BIDS: &str = "bids";
 const CF_ACCOUNTS: &str = "accounts";
+const CF_NONCES: &str = "nonces";
+/// Index of bids by advertiser, keyed by address bytes followed by the bid id.
+const CF_ADVERTISER_BIDS: &str = "advertiser_bids";
+/// Index of open bids by expiry, keyed by big-endian height followed by the bid id.
+const CF_BID_EXPIRIES: &str = "bid_expiries";
+/// Counters and other singleton values, keyed by name.
+const CF_META: &str = "meta";
+const CF_BLOCKS: &str = "blocks";
+/// Index of block heights by block hash.
+const CF_BLOCK_HASHES: &str = "block_hashes";
+const CF_RECEIPTS: &str = "receipts";
+/// Settlement-layer deposits already credited, keyed by settlement reference.
+const CF_DEPOSITS: &str = "deposits";
+/// Issuers whose credentials back verified targeting attributes, keyed by issuer id.
+const CF_TRUSTED_ISSUERS: &str = "trusted_issuers";
+/// Index of issuer ids by the DID of their current signing key.
+const CF_ISSUER_KEYS: &str = "issuer_keys";
+/// Revoked credentials, keyed by length-prefixed issuer id followed by credential id.
+const CF_REVOKED_CREDENTIALS: &str = "revoked_credentials";
+/// Identity tiers above anonymous, keyed by DID.
+const CF_IDENTITY_TIERS: &str = "identity_tiers";
+/// Session key grants, keyed by the session key's DID.
+const CF_SESSION_KEYS: &str = "session_keys";
+/// Multisig accounts, keyed by their `multisig:` address.
+const CF_MULTISIG_ACCOUNTS: &str = "multisig_accounts";
+/// Multisig proposals, keyed by length-prefixed account address followed by
+/// the big-endian proposal id.
+const CF_MULTISIG_PROPOSALS: &str = "multisig_proposals";
+/// The consensus engine's own block tree and validator set storage.
+const CF_CONSENSUS: &str = "consensus";
+/// Withdrawals waiting to be paid out, keyed by big-endian height followed by
+/// the withdrawing transaction's hash.
+const CF_WITHDRAWALS: &str = "withdrawals";
+/// Triggered auctions, keyed by length-prefixed publisher address followed by
+/// the auction id, with the big-endian height they ran at.
+const CF_AUCTIONS: &str = "auctions";
+/// This node's relayer progress, which is not part of the state: what it has
+/// paid or anchored, keyed by name, and the last height it finished.
+const CF_RELAYER: &str = "relayer";
+
+const META_BID_SEQUENCE: &[u8] = b"bid_sequence";
+const RELAYER_HEIGHT: &[u8] = b"height";
+
+/// Every column family the node opens.
+const ALL_COLUMN_FAMILIES: &[&str] = &[
+    CF_BIDS,
+    CF_ACCOUNTS,
+    CF_NONCES,
+    CF_ADVERTISER_BIDS,
+    CF_BID_EXPIRIES,
+    CF_META,
+    CF_BLOCKS,
+    CF_BLOCK_HASHES,
+    CF_RECEIPTS,
+    CF_DEPOSITS,
+    CF_TRUSTED_ISSUERS,
+    CF_ISSUER_KEYS,
+    CF_REVOKED_CREDENTIALS,
+    CF_IDENTITY_TIERS,
+    CF_SESSION_KEYS,
+    CF_MULTISIG_ACCOUNTS,
+    CF_MULTISIG_PROPOSALS,
+    CF_CONSENSUS,
+    CF_WITHDRAWALS,
+    CF_RELAYER,
+    CF_AUCTIONS,
+];
+
+/// The column families that make up the application state. These are covered
+/// by the state root and included in snapshots; block history and receipts are not.
+const STATE_COLUMN_FAMILIES: &[&str] = &[
+    CF_ACCOUNTS,
+    CF_NONCES,
+    CF_BIDS,
+    CF_ADVERTISER_BIDS,
+    CF_BID_EXPIRIES,
+    CF_META,
+    CF_DEPOSITS,
+    CF_TRUSTED_ISSUERS,
+    CF_ISSUER_KEYS,
+    CF_REVOKED_CREDENTIALS,
+    CF_IDENTITY_TIERS,
+    CF_SESSION_KEYS,
+    CF_MULTISIG_ACCOUNTS,
+    CF_MULTISIG_PROPOSALS,
+    CF_WITHDRAWALS,
+    CF_AUCTIONS,
+];
 
 /// Manages the state of the blockchain, backed by RocksDB.
-#[derive(Debug)]
+/// Cloning is cheap and shares the underlying database handle.
+#[derive(Debug, Clone)]
 pub struct StateManager {
-    db: DB,
+    db: Arc<DB>,
 }
 
 impl StateManager {
+    /// Storage for the consensus engine, sharing this database.
+    pub fn consensus_store(&self) -> ConsensusStore {
+        ConsensusStore { db: self.db.clone() }
+    }
+
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
         let mut db_opts = Options::default();
         db_opts.create_if_missing(true);
         db_opts.create_missing_column_families(true);
 
-        let cf_bids = ColumnFamilyDescriptor::new(CF_BIDS, Options::default());
-        let cf_accounts = ColumnFamilyDescriptor::new(CF_ACCOUNTS, Options::default());
+        let cf_descriptors = ALL_COLUMN_FAMILIES
+            .iter()
+            .map(|name| ColumnFamilyDescriptor::new(*name, Options::default()));
 
-        let db = DB::open_cf_descriptors(&db_opts, path, vec![cf_bids, cf_accounts])?;
-        Ok(Self { db })
+        let db = DB::open_cf_descriptors(&db_opts, path, cf_descriptors)?;
+        Ok(Self { db: Arc::new(db) })
     }
 
     // --- Account Methods ---
@@ -37,14 +142,14 @@ impl StateManager {
 
     pub fn set_balance(&self, address: &Address, amount: u64) -> Result<()> {
         let cf = self.db.cf_handle(CF_ACCOUNTS).unwrap();
-        self.db.put_cf(cf, address.as_bytes(), &amount.to_le_bytes())?;
+        self.db.put_cf(cf, address.as_bytes(), amount.to_le_bytes())?;
         Ok(())
     }
     
     pub fn apply_fees(&self, signer_address: &Address, fee: u64) -> Result<()> {
         let mut balance = self.get_balance(signer_address)?;
         if balance < fee {
-            return Err(anyhow::anyhow!("Insufficient funds for fee"));
+            return Err(NodeError::new(ErrorCode::InsufficientFunds, "Insufficient funds for fee").into());
         }
         balance -= fee;
         self.set_balance(signer_address, balance)?;
@@ -52,19 +157,905 @@ impl StateManager {
         Ok(())
     }
 
+    /// Credits a confirmed settlement-layer deposit, refusing references that
+    /// have already been claimed.
+    pub fn credit_deposit(&self, address: &Address, amount: u64, settlement_ref: &str) -> Result<()> {
+        if self.is_deposit_claimed(settlement_ref)? {
+            let message = format!("Deposit {} has already been claimed", settlement_ref);
+            return Err(NodeError::new(ErrorCode::DepositAlreadyClaimed, message).into());
+        }
+        let balance = self.get_balance(address)?;
+        let credited = balance
+            .checked_add(amount)
+            .ok_or_else(|| NodeError::new(ErrorCode::BalanceOverflow, format!("Balance overflow for {}", address)))?;
+        self.set_balance(address, credited)?;
+        let cf = self.db.cf_handle(CF_DEPOSITS).unwrap();
+        self.db.put_cf(cf, settlement_ref.as_bytes(), address.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn is_deposit_claimed(&self, settlement_ref: &str) -> Result<bool> {
+        let cf = self.db.cf_handle(CF_DEPOSITS).unwrap();
+        Ok(self.db.get_cf(cf, settlement_ref.as_bytes())?.is_some())
+    }
+
+    /// Debits funds leaving for the settlement layer and queues them for the
+    /// relayer to pay out.
+    pub fn queue_withdrawal(&self, withdrawal: &PendingWithdrawal) -> Result<()> {
+        let balance = self.get_balance(&withdrawal.address)?;
+        if balance < withdrawal.amount {
+            return Err(NodeError::new(ErrorCode::InsufficientFunds, "Insufficient funds for withdrawal").into());
+        }
+        self.set_balance(&withdrawal.address, balance - withdrawal.amount)?;
+        let cf = self.db.cf_handle(CF_WITHDRAWALS).unwrap();
+        let mut key = withdrawal.height.to_be_bytes().to_vec();
+        key.extend_from_slice(&withdrawal.id);
+        self.db.put_cf(cf, key, serde_json::to_vec(withdrawal)?)?;
+        Ok(())
+    }
+
+    /// The withdrawals queued by the block at `height`.
+    pub fn withdrawals_at(&self, height: u64) -> Result<Vec<PendingWithdrawal>> {
+        let cf = self.db.cf_handle(CF_WITHDRAWALS).unwrap();
+        let prefix = height.to_be_bytes();
+        let mut withdrawals = Vec::new();
+        for item in self.db.iterator_cf(cf, IteratorMode::From(&prefix, Direction::Forward)) {
+            let (key, value) = item?;
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            withdrawals.push(serde_json::from_slice(&value)?);
+        }
+        Ok(withdrawals)
+    }
+
+    // --- Relayer Methods ---
+
+    /// The settlement reference the relayer recorded for `name`, if it has
+    /// already paid or anchored it.
+    pub fn relayed(&self, name: &str) -> Result<Option<String>> {
+        let cf = self.db.cf_handle(CF_RELAYER).unwrap();
+        Ok(self.db.get_cf(cf, name.as_bytes())?.map(|bytes| String::from_utf8_lossy(&bytes).into_owned()))
+    }
+
+    pub fn record_relayed(&self, name: &str, settlement_ref: &str) -> Result<()> {
+        let cf = self.db.cf_handle(CF_RELAYER).unwrap();
+        self.db.put_cf(cf, name.as_bytes(), settlement_ref.as_bytes())?;
+        Ok(())
+    }
+
+    /// The last height the relayer has fully acted on.
+    pub fn relayed_height(&self) -> Result<u64> {
+        let cf = self.db.cf_handle(CF_RELAYER).unwrap();
+        let height_bytes = self.db.get_cf(cf, RELAYER_HEIGHT)?.unwrap_or_default();
+        Ok(height_bytes.try_into().map(u64::from_le_bytes).unwrap_or(0))
+    }
+
+    pub fn set_relayed_height(&self, height: u64) -> Result<()> {
+        let cf = self.db.cf_handle(CF_RELAYER).unwrap();
+        self.db.put_cf(cf, RELAYER_HEIGHT, height.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// The next nonce the account is expected to sign with.
+    pub fn get_nonce(&self, address: &Address) -> Result<u64> {
+        let cf = self.db.cf_handle(CF_NONCES).unwrap();
+        let nonce_bytes = self.db.get_cf(cf, address.as_bytes())?.unwrap_or_default();
+        let nonce = nonce_bytes.try_into().map(u64::from_le_bytes).unwrap_or(0);
+        Ok(nonce)
+    }
+
+    pub fn increment_nonce(&self, address: &Address) -> Result<()> {
+        let cf = self.db.cf_handle(CF_NONCES).unwrap();
+        let nonce = self.get_nonce(address)? + 1;
+        self.db.put_cf(cf, address.as_bytes(), nonce.to_le_bytes())?;
+        Ok(())
+    }
+
     // --- Bid/Auction Methods ---
 
-    pub fn place_bid(&self, bid: &Bid) -> Result<()> {
+    pub fn place_bid(&self, bid: &Bid, advertiser: &Address, height: u64) -> Result<Uuid> {
+        self.place_bid_as(bid, advertiser, None, height)
+    }
+
+    /// Places a bid for `advertiser`, signed by `session_key` if a session
+    /// placed it. The bid is stored under `primitives::bid_id`, which
+    /// replaces the advertiser's own id in the stored copy; that id is
+    /// returned.
+    pub fn place_bid_as(&self, bid: &Bid, advertiser: &Address, session_key: Option<&Address>, height: u64) -> Result<Uuid> {
+        let bid = Bid { id: primitives::bid_id(advertiser, &bid.id), ..bid.clone() };
+        if self.get_bid(&bid.id)?.is_some() {
+            return Err(NodeError::new(ErrorCode::BidAlreadyExists, format!("Bid {} already exists", bid.id)).into());
+        }
+        if let Some(expires_at_height) = bid.expires_at_height {
+            if expires_at_height <= height {
+                return Err(NodeError::new(ErrorCode::BidExpired, format!("Bid {} has already expired", bid.id)).into());
+            }
+            let cf = self.db.cf_handle(CF_BID_EXPIRIES).unwrap();
+            self.db.put_cf(cf, bid_expiry_key(expires_at_height, &bid.id), [])?;
+        }
+
+        let id = bid.id;
+        let info = BidInfo {
+            bid,
+            advertiser: advertiser.clone(),
+            status: BidStatus::Open,
+            placed_at_height: height,
+            sequence: self.next_bid_sequence()?,
+            session_key: session_key.cloned(),
+        };
+        self.put_bid(&info)?;
+
+        let cf = self.db.cf_handle(CF_ADVERTISER_BIDS).unwrap();
+        self.db.put_cf(cf, advertiser_bid_key(advertiser, &id), [])?;
+        Ok(id)
+    }
+
+    pub fn get_bid(&self, id: &Uuid) -> Result<Option<BidInfo>> {
         let cf = self.db.cf_handle(CF_BIDS).unwrap();
-        let key = bid.id.as_bytes();
-        let value = serde_json::to_vec(bid)?;
-        self.db.put_cf(cf, key, value)?;
+        match self.db.get_cf(cf, id.as_bytes())? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns every bid placed by `advertiser`, in bid id order.
+    pub fn get_bids_by_advertiser(&self, advertiser: &Address) -> Result<Vec<BidInfo>> {
+        let cf = self.db.cf_handle(CF_ADVERTISER_BIDS).unwrap();
+        let prefix = advertiser_bid_prefix(advertiser);
+        let mut bids = Vec::new();
+        for item in self.db.iterator_cf(cf, IteratorMode::From(&prefix, Direction::Forward)) {
+            let (key, _) = item?;
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            let id = Uuid::from_slice(&key[prefix.len()..])?;
+            if let Some(info) = self.get_bid(&id)? {
+                bids.push(info);
+            }
+        }
+        Ok(bids)
+    }
+
+    /// Withdraws an open bid placed by `advertiser`.
+    pub fn cancel_bid(&self, id: &Uuid, advertiser: &Address) -> Result<BidInfo> {
+        let mut info = self
+            .get_bid(id)?
+            .ok_or_else(|| NodeError::new(ErrorCode::BidNotFound, format!("Bid {} not found", id)))?;
+        if &info.advertiser != advertiser {
+            return Err(NodeError::new(ErrorCode::NotBidOwner, format!("Bid {} belongs to another advertiser", id)).into());
+        }
+        if info.status != BidStatus::Open {
+            return Err(NodeError::new(ErrorCode::BidNotOpen, format!("Bid {} is not open", id)).into());
+        }
+
+        info.status = BidStatus::Cancelled;
+        self.put_bid(&info)?;
+        if let Some(expires_at_height) = info.bid.expires_at_height {
+            let cf = self.db.cf_handle(CF_BID_EXPIRIES).unwrap();
+            self.db.delete_cf(cf, bid_expiry_key(expires_at_height, id))?;
+        }
+        Ok(info)
+    }
+
+    /// Marks every open bid expiring at or before `height` as expired and
+    /// returns them.
+    pub fn expire_bids(&self, height: u64) -> Result<Vec<BidInfo>> {
+        let cf = self.db.cf_handle(CF_BID_EXPIRIES).unwrap();
+        let mut due = Vec::new();
+        for item in self.db.iterator_cf(cf, IteratorMode::Start) {
+            let (key, _) = item?;
+            let expires_at_height = u64::from_be_bytes(key[..8].try_into()?);
+            if expires_at_height > height {
+                break;
+            }
+            due.push((key.to_vec(), Uuid::from_slice(&key[8..])?));
+        }
+
+        let mut expired = Vec::new();
+        for (key, id) in due {
+            self.db.delete_cf(cf, key)?;
+            if let Some(mut info) = self.get_bid(&id)? {
+                if info.status == BidStatus::Open {
+                    info.status = BidStatus::Expired;
+                    self.put_bid(&info)?;
+                    expired.push(info);
+                }
+            }
+        }
+        Ok(expired)
+    }
+
+    /// Finds the winning open bid for an auction using price-time priority:
+    /// the highest price whose targeting is fully covered by the trigger's
+    /// attributes and meets the floor, oldest first among equal prices.
+    /// `verified` are the attributes backed by the trigger's presentation;
+    /// they count for every bid, and are the only ones that count for bids
+    /// requiring verified targeting. `user_tier` must meet each bid's
+    /// `min_user_tier`, and the advertiser's tier the auction's
+    /// `min_advertiser_tier`. Bids whose advertiser cannot cover the price
+    /// are skipped, as are bids from sessions that are revoked or expired at
+    /// `height`, or whose remaining spend limit is below the price.
+    pub fn find_match(
+        &self,
+        auction: &AuctionTrigger,
+        verified: &[String],
+        user_tier: IdentityTier,
+        publisher: &Address,
+        height: u64,
+    ) -> Result<Option<Match>> {
+        // TODO: Replace the full scan with a price-indexed book once the
+        // attribute registry lands.
+        let cf = self.db.cf_handle(CF_BIDS).unwrap();
+        let mut best: Option<BidInfo> = None;
+        for item in self.db.iterator_cf(cf, IteratorMode::Start) {
+            let (_, value) = item?;
+            let info: BidInfo = serde_json::from_slice(&value)?;
+            if info.status != BidStatus::Open
+                || info.bid.price < auction.bid_floor
+                || info.bid.min_user_tier > user_tier
+                || !info.bid.targeting.iter().all(|t| {
+                    verified.contains(t) || (!info.bid.requires_verified_targeting && auction.attributes.contains(t))
+                })
+            {
+                continue;
+            }
+            let better = match &best {
+                Some(current) => {
+                    info.bid.price > current.bid.price
+                        || (info.bid.price == current.bid.price && info.sequence < current.sequence)
+                }
+                None => true,
+            };
+            if better
+                && self.get_balance(&info.advertiser)? >= info.bid.price
+                && self.get_identity_tier(&info.advertiser)? >= auction.min_advertiser_tier
+                && self.session_can_spend(&info, info.bid.price, height)?
+            {
+                best = Some(info);
+            }
+        }
+
+        Ok(best.map(|info| Match {
+            bid_id: info.bid.id,
+            auction_id: auction.id,
+            winning_price: info.bid.price,
+            advertiser_addr: info.advertiser,
+            publisher_addr: publisher.clone(),
+        }))
+    }
+
+    /// Moves the winning price from the advertiser to the publisher and marks
+    /// the bid filled, in the block at `height`. Everything is written in one
+    /// batch, so a settlement that fails part way changes nothing.
+    pub fn settle_match(&self, auction_match: &Match, height: u64) -> Result<()> {
+        let mut info = self
+            .get_bid(&auction_match.bid_id)?
+            .ok_or_else(|| NodeError::new(ErrorCode::BidNotFound, format!("Bid {} not found", auction_match.bid_id)))?;
+        if info.status != BidStatus::Open {
+            return Err(NodeError::new(ErrorCode::BidNotOpen, format!("Bid {} is not open", info.bid.id)).into());
+        }
+        let price = auction_match.winning_price;
+        let advertiser_balance = self.get_balance(&auction_match.advertiser_addr)?;
+        if advertiser_balance < price {
+            return Err(NodeError::new(ErrorCode::InsufficientFunds, "Advertiser cannot cover the winning price").into());
+        }
+
+        let mut batch = WriteBatch::default();
+        if let Some(session_key) = &info.session_key {
+            let mut session = self.check_session_spend(session_key, price)?;
+            if session.is_expired(height) {
+                return Err(NodeError::new(ErrorCode::Unauthorized, format!("Session key {} has expired", session_key)).into());
+            }
+            session.spent += price;
+            let cf = self.db.cf_handle(CF_SESSION_KEYS).unwrap();
+            batch.put_cf(cf, session.session_key.as_bytes(), serde_json::to_vec(&session)?);
+        }
+
+        let cf_accounts = self.db.cf_handle(CF_ACCOUNTS).unwrap();
+        let debited = advertiser_balance - price;
+        // An advertiser winning its own auction gets the price straight back.
+        let publisher_balance = if auction_match.publisher_addr == auction_match.advertiser_addr {
+            debited
+        } else {
+            self.get_balance(&auction_match.publisher_addr)?
+        };
+        let credited = publisher_balance.checked_add(price).ok_or_else(|| {
+            let message = format!("Balance overflow for {}", auction_match.publisher_addr);
+            NodeError::new(ErrorCode::BalanceOverflow, message)
+        })?;
+        batch.put_cf(cf_accounts, auction_match.advertiser_addr.as_bytes(), debited.to_le_bytes());
+        batch.put_cf(cf_accounts, auction_match.publisher_addr.as_bytes(), credited.to_le_bytes());
+
+        info.status = BidStatus::Filled;
+        batch.put_cf(self.db.cf_handle(CF_BIDS).unwrap(), info.bid.id.as_bytes(), serde_json::to_vec(&info)?);
+        if let Some(expires_at_height) = info.bid.expires_at_height {
+            let cf = self.db.cf_handle(CF_BID_EXPIRIES).unwrap();
+            batch.delete_cf(cf, bid_expiry_key(expires_at_height, &info.bid.id));
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    /// Whether `publisher` has already run an auction with this id.
+    pub fn is_auction_triggered(&self, publisher: &Address, auction_id: &Uuid) -> Result<bool> {
+        let cf = self.db.cf_handle(CF_AUCTIONS).unwrap();
+        Ok(self.db.get_cf(cf, auction_key(publisher, auction_id))?.is_some())
+    }
+
+    /// Records that `publisher` ran the auction `auction_id` at `height`.
+    pub fn record_auction(&self, publisher: &Address, auction_id: &Uuid, height: u64) -> Result<()> {
+        let cf = self.db.cf_handle(CF_AUCTIONS).unwrap();
+        self.db.put_cf(cf, auction_key(publisher, auction_id), height.to_be_bytes())?;
         Ok(())
     }
 
-    pub fn find_match(&self, _auction: &AuctionTrigger) -> Result<Option<Match>> {
-        // Logic for finding a match would go here, iterating over the `bids` CF.
-        // For this scaffolding step, the conceptual placement is what matters.
-        Ok(None) 
+    /// Whether the session that placed a bid, if any, is still authorised at
+    /// `height` and may spend `amount` more.
+    fn session_can_spend(&self, info: &BidInfo, amount: u64, height: u64) -> Result<bool> {
+        let Some(session_key) = &info.session_key else {
+            return Ok(true);
+        };
+        Ok(match self.get_session_key(session_key)? {
+            Some(session) => {
+                session.principal == info.advertiser
+                    && !session.is_expired(height)
+                    && !matches!(session.remaining(), Some(left) if left < amount)
+            }
+            None => false,
+        })
     }
-}
\ No newline at end of file
+
+    fn put_bid(&self, info: &BidInfo) -> Result<()> {
+        let cf = self.db.cf_handle(CF_BIDS).unwrap();
+        self.db.put_cf(cf, info.bid.id.as_bytes(), serde_json::to_vec(info)?)?;
+        Ok(())
+    }
+
+    fn next_bid_sequence(&self) -> Result<u64> {
+        let cf = self.db.cf_handle(CF_META).unwrap();
+        let sequence_bytes = self.db.get_cf(cf, META_BID_SEQUENCE)?.unwrap_or_default();
+        let sequence = sequence_bytes.try_into().map(u64::from_le_bytes).unwrap_or(0);
+        self.db.put_cf(cf, META_BID_SEQUENCE, (sequence + 1).to_le_bytes())?;
+        Ok(sequence)
+    }
+
+    // --- Credential Issuer Methods ---
+
+    /// Trusts `issuer` for `namespaces`, with its own DID as signing key.
+    pub fn add_trusted_issuer(&self, issuer: &str, namespaces: &[String], height: u64) -> Result<TrustedIssuer> {
+        if self.get_trusted_issuer(issuer)?.is_some() || self.issuer_for_key(issuer)?.is_some() {
+            return Err(NodeError::new(ErrorCode::IssuerAlreadyExists, format!("Issuer {} is already trusted", issuer)).into());
+        }
+        let record = TrustedIssuer {
+            id: issuer.to_string(),
+            key: issuer.to_string(),
+            namespaces: namespaces.to_vec(),
+            added_at_height: height,
+        };
+        self.put_trusted_issuer(&record)?;
+        let cf = self.db.cf_handle(CF_ISSUER_KEYS).unwrap();
+        self.db.put_cf(cf, record.key.as_bytes(), record.id.as_bytes())?;
+        Ok(record)
+    }
+
+    /// Stops trusting an issuer. Its revocation list is kept.
+    pub fn remove_trusted_issuer(&self, issuer: &str) -> Result<()> {
+        let record = self.require_trusted_issuer(issuer)?;
+        let cf = self.db.cf_handle(CF_ISSUER_KEYS).unwrap();
+        self.db.delete_cf(cf, record.key.as_bytes())?;
+        let cf = self.db.cf_handle(CF_TRUSTED_ISSUERS).unwrap();
+        self.db.delete_cf(cf, issuer.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn set_issuer_scope(&self, issuer: &str, namespaces: &[String]) -> Result<()> {
+        let mut record = self.require_trusted_issuer(issuer)?;
+        record.namespaces = namespaces.to_vec();
+        self.put_trusted_issuer(&record)
+    }
+
+    /// Replaces an issuer's signing key, retiring the old one.
+    pub fn rotate_issuer_key(&self, issuer: &str, new_key: &str) -> Result<()> {
+        let mut record = self.require_trusted_issuer(issuer)?;
+        if self.issuer_for_key(new_key)?.is_some() {
+            return Err(NodeError::new(ErrorCode::IssuerAlreadyExists, format!("Key {} already belongs to an issuer", new_key)).into());
+        }
+        let cf = self.db.cf_handle(CF_ISSUER_KEYS).unwrap();
+        self.db.delete_cf(cf, record.key.as_bytes())?;
+        self.db.put_cf(cf, new_key.as_bytes(), issuer.as_bytes())?;
+        record.key = new_key.to_string();
+        self.put_trusted_issuer(&record)
+    }
+
+    pub fn revoke_credential(&self, issuer: &str, credential_id: &str) -> Result<()> {
+        let cf = self.db.cf_handle(CF_REVOKED_CREDENTIALS).unwrap();
+        self.db.put_cf(cf, revoked_credential_key(issuer, credential_id), [])?;
+        Ok(())
+    }
+
+    pub fn is_credential_revoked(&self, issuer: &str, credential_id: &str) -> Result<bool> {
+        let cf = self.db.cf_handle(CF_REVOKED_CREDENTIALS).unwrap();
+        Ok(self.db.get_cf(cf, revoked_credential_key(issuer, credential_id))?.is_some())
+    }
+
+    pub fn get_trusted_issuer(&self, issuer: &str) -> Result<Option<TrustedIssuer>> {
+        let cf = self.db.cf_handle(CF_TRUSTED_ISSUERS).unwrap();
+        match self.db.get_cf(cf, issuer.as_bytes())? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The trusted issuer whose current signing key is `key`.
+    pub fn issuer_for_key(&self, key: &str) -> Result<Option<TrustedIssuer>> {
+        let cf = self.db.cf_handle(CF_ISSUER_KEYS).unwrap();
+        match self.db.get_cf(cf, key.as_bytes())? {
+            Some(issuer) => self.get_trusted_issuer(&String::from_utf8(issuer)?),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether `credential` backs `attribute`: it must be signed with a trusted
+    /// issuer's current key, the issuer must be scoped for the attribute's
+    /// namespace, and the credential must not be revoked.
+    pub fn trusts_credential(&self, credential: &VerifiableCredential, attribute: &str) -> Result<bool> {
+        let Some(issuer) = self.issuer_for_key(&credential.issuer)? else {
+            return Ok(false);
+        };
+        let namespace = attribute.split('=').next().unwrap_or_default();
+        if !issuer.namespaces.iter().any(|scope| scope == namespace) {
+            return Ok(false);
+        }
+        match &credential.id {
+            Some(id) => Ok(!self.is_credential_revoked(&issuer.id, id)?),
+            None => Ok(false),
+        }
+    }
+
+    fn require_trusted_issuer(&self, issuer: &str) -> Result<TrustedIssuer> {
+        self.get_trusted_issuer(issuer)?
+            .ok_or_else(|| NodeError::new(ErrorCode::IssuerNotFound, format!("Issuer {} is not trusted", issuer)).into())
+    }
+
+    fn put_trusted_issuer(&self, record: &TrustedIssuer) -> Result<()> {
+        let cf = self.db.cf_handle(CF_TRUSTED_ISSUERS).unwrap();
+        self.db.put_cf(cf, record.id.as_bytes(), serde_json::to_vec(record)?)?;
+        Ok(())
+    }
+
+    // --- Identity Tier Methods ---
+
+    /// Records `did`'s tier. Setting it back to anonymous forgets the record.
+    pub fn set_identity_tier(&self, did: &str, tier: IdentityTier, attested_by: &str, height: u64) -> Result<()> {
+        let cf = self.db.cf_handle(CF_IDENTITY_TIERS).unwrap();
+        if tier.is_anonymous() {
+            self.db.delete_cf(cf, did.as_bytes())?;
+            return Ok(());
+        }
+        let info = IdentityInfo {
+            did: did.to_string(),
+            tier,
+            attested_by: attested_by.to_string(),
+            updated_at_height: height,
+        };
+        self.db.put_cf(cf, did.as_bytes(), serde_json::to_vec(&info)?)?;
+        Ok(())
+    }
+
+    pub fn get_identity(&self, did: &str) -> Result<Option<IdentityInfo>> {
+        let cf = self.db.cf_handle(CF_IDENTITY_TIERS).unwrap();
+        match self.db.get_cf(cf, did.as_bytes())? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// `did`'s tier, anonymous unless one was recorded.
+    pub fn get_identity_tier(&self, did: &str) -> Result<IdentityTier> {
+        Ok(self.get_identity(did)?.map(|info| info.tier).unwrap_or_default())
+    }
+
+    // --- Session Key Methods ---
+
+    pub fn put_session_key(&self, info: &SessionKeyInfo) -> Result<()> {
+        let cf = self.db.cf_handle(CF_SESSION_KEYS).unwrap();
+        self.db.put_cf(cf, info.session_key.as_bytes(), serde_json::to_vec(info)?)?;
+        Ok(())
+    }
+
+    pub fn get_session_key(&self, session_key: &str) -> Result<Option<SessionKeyInfo>> {
+        let cf = self.db.cf_handle(CF_SESSION_KEYS).unwrap();
+        match self.db.get_cf(cf, session_key.as_bytes())? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Revokes a session key authorised by `principal` and cancels the open
+    /// bids it placed, so authorising the key again does not revive them.
+    /// Returns the cancelled bids.
+    pub fn revoke_session_key(&self, session_key: &str, principal: &Address) -> Result<Vec<BidInfo>> {
+        match self.get_session_key(session_key)? {
+            Some(session) if &session.principal == principal => {
+                let mut batch = WriteBatch::default();
+                let cf = self.db.cf_handle(CF_SESSION_KEYS).unwrap();
+                batch.delete_cf(cf, session_key.as_bytes());
+
+                let mut cancelled = Vec::new();
+                for mut info in self.get_bids_by_advertiser(principal)? {
+                    if info.status != BidStatus::Open || info.session_key.as_deref() != Some(session_key) {
+                        continue;
+                    }
+                    info.status = BidStatus::Cancelled;
+                    let cf = self.db.cf_handle(CF_BIDS).unwrap();
+                    batch.put_cf(cf, info.bid.id.as_bytes(), serde_json::to_vec(&info)?);
+                    if let Some(expires_at_height) = info.bid.expires_at_height {
+                        let cf = self.db.cf_handle(CF_BID_EXPIRIES).unwrap();
+                        batch.delete_cf(cf, bid_expiry_key(expires_at_height, &info.bid.id));
+                    }
+                    cancelled.push(info);
+                }
+                self.db.write(batch)?;
+                Ok(cancelled)
+            }
+            Some(_) => Err(NodeError::new(ErrorCode::Unauthorized, format!("Session key {} belongs to another account", session_key)).into()),
+            None => Err(NodeError::new(ErrorCode::NotFound, format!("Session key {} not found", session_key)).into()),
+        }
+    }
+
+    /// Fails with `SpendLimitExceeded` unless the session may spend `amount`.
+    pub fn check_session_spend(&self, session_key: &str, amount: u64) -> Result<SessionKeyInfo> {
+        let session = self
+            .get_session_key(session_key)?
+            .ok_or_else(|| NodeError::new(ErrorCode::NotFound, format!("Session key {} not found", session_key)))?;
+        match session.remaining() {
+            Some(left) if left < amount => {
+                let message = format!("Session key {} has {} left to spend, needs {}", session_key, left, amount);
+                Err(NodeError::new(ErrorCode::SpendLimitExceeded, message).into())
+            }
+            _ => Ok(session),
+        }
+    }
+
+    /// Adds `amount` to what the session has spent, within its limit.
+    pub fn record_session_spend(&self, session_key: &str, amount: u64) -> Result<()> {
+        let mut session = self.check_session_spend(session_key, amount)?;
+        session.spent += amount;
+        self.put_session_key(&session)
+    }
+
+    // --- Multisig Methods ---
+
+    pub fn put_multisig_account(&self, account: &MultisigAccount) -> Result<()> {
+        let cf = self.db.cf_handle(CF_MULTISIG_ACCOUNTS).unwrap();
+        self.db.put_cf(cf, account.address.as_bytes(), serde_json::to_vec(account)?)?;
+        Ok(())
+    }
+
+    pub fn get_multisig_account(&self, address: &str) -> Result<Option<MultisigAccount>> {
+        let cf = self.db.cf_handle(CF_MULTISIG_ACCOUNTS).unwrap();
+        match self.db.get_cf(cf, address.as_bytes())? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn put_multisig_proposal(&self, proposal: &MultisigProposal) -> Result<()> {
+        let cf = self.db.cf_handle(CF_MULTISIG_PROPOSALS).unwrap();
+        self.db.put_cf(cf, multisig_proposal_key(&proposal.account, proposal.id), serde_json::to_vec(proposal)?)?;
+        Ok(())
+    }
+
+    pub fn get_multisig_proposal(&self, account: &str, id: u64) -> Result<Option<MultisigProposal>> {
+        let cf = self.db.cf_handle(CF_MULTISIG_PROPOSALS).unwrap();
+        match self.db.get_cf(cf, multisig_proposal_key(account, id))? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    // --- Receipt Methods ---
+
+    pub fn put_receipt(&self, receipt: &Receipt) -> Result<()> {
+        let cf = self.db.cf_handle(CF_RECEIPTS).unwrap();
+        self.db.put_cf(cf, hex::decode(&receipt.tx_hash)?, serde_json::to_vec(receipt)?)?;
+        Ok(())
+    }
+
+    pub fn get_receipt(&self, tx_hash: &Hash) -> Result<Option<Receipt>> {
+        let cf = self.db.cf_handle(CF_RECEIPTS).unwrap();
+        match self.db.get_cf(cf, tx_hash)? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    // --- Block Methods ---
+
+    /// Stores a committed block, keyed by big-endian height so iteration follows chain order.
+    pub fn put_block(&self, committed: &CommittedBlock) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        self.batch_put_block(&mut batch, committed)?;
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    fn batch_put_block(&self, batch: &mut WriteBatch, committed: &CommittedBlock) -> Result<()> {
+        let cf = self.db.cf_handle(CF_BLOCKS).unwrap();
+        let key = committed.block.height.to_be_bytes();
+        batch.put_cf(cf, key, serde_json::to_vec(committed)?);
+
+        let cf_hashes = self.db.cf_handle(CF_BLOCK_HASHES).unwrap();
+        batch.put_cf(cf_hashes, committed.block.hash()?, key);
+        Ok(())
+    }
+
+    pub fn get_block_by_hash(&self, hash: &Hash) -> Result<Option<CommittedBlock>> {
+        let cf = self.db.cf_handle(CF_BLOCK_HASHES).unwrap();
+        match self.db.get_cf(cf, hash)? {
+            Some(height) => self.get_block(height.as_slice().try_into().map(u64::from_be_bytes)?),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_block(&self, height: u64) -> Result<Option<CommittedBlock>> {
+        let cf = self.db.cf_handle(CF_BLOCKS).unwrap();
+        match self.db.get_cf(cf, height.to_be_bytes())? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Up to `limit` consecutive blocks from `from_height`, stopping before
+    /// their JSON encoding would exceed `max_bytes`. The first block is
+    /// always included.
+    pub fn get_blocks(&self, from_height: u64, limit: u64, max_bytes: usize) -> Result<Vec<CommittedBlock>> {
+        let cf = self.db.cf_handle(CF_BLOCKS).unwrap();
+        let start = from_height.to_be_bytes();
+        let mut blocks = Vec::new();
+        let mut bytes = 0;
+        for item in self.db.iterator_cf(cf, IteratorMode::From(&start, Direction::Forward)) {
+            if blocks.len() as u64 >= limit {
+                break;
+            }
+            let (_, value) = item?;
+            if !blocks.is_empty() && bytes + value.len() > max_bytes {
+                break;
+            }
+            bytes += value.len();
+            blocks.push(serde_json::from_slice(&value)?);
+        }
+        Ok(blocks)
+    }
+
+    /// The height of the latest committed block, or 0 before the first block.
+    pub fn latest_height(&self) -> Result<u64> {
+        let cf = self.db.cf_handle(CF_BLOCKS).unwrap();
+        match self.db.iterator_cf(cf, IteratorMode::End).next() {
+            Some(item) => {
+                let (key, _) = item?;
+                let height = key.as_ref().try_into().map(u64::from_be_bytes)?;
+                Ok(height)
+            }
+            None => Ok(0),
+        }
+    }
+
+    // --- Snapshot Methods ---
+
+    /// Computes a hash over every key and value in the state column families.
+    pub fn state_root(&self) -> Result<Hash> {
+        self.export_state(|_| Ok(()))
+    }
+
+    /// Streams every entry of the state column families to `f` from a
+    /// consistent point-in-time view, returning the state root of that view.
+    pub fn export_state<F>(&self, mut f: F) -> Result<Hash>
+    where
+        F: FnMut(SnapshotEntry) -> Result<()>,
+    {
+        let snapshot = self.db.snapshot();
+        let mut hasher = ring::digest::Context::new(&ring::digest::SHA256);
+
+        for cf_name in STATE_COLUMN_FAMILIES {
+            let cf = self.db.cf_handle(cf_name).unwrap();
+            for item in snapshot.iterator_cf(cf, IteratorMode::Start) {
+                let (key, value) = item?;
+                hash_entry(&mut hasher, cf_name, &key, &value);
+                f(SnapshotEntry {
+                    cf: cf_name.to_string(),
+                    key: key.to_vec(),
+                    value: value.to_vec(),
+                })?;
+            }
+        }
+
+        let mut root = [0u8; 32];
+        root.copy_from_slice(hasher.finish().as_ref());
+        Ok(root)
+    }
+
+    /// Whether the database holds no state, blocks, receipts or relayer
+    /// progress. The consensus engine's own column family is not checked: the
+    /// engine initializes it at startup, before any state exists.
+    pub fn is_empty(&self) -> Result<bool> {
+        for cf_name in ALL_COLUMN_FAMILIES.iter().filter(|name| **name != CF_CONSENSUS) {
+            let cf = self.db.cf_handle(cf_name).unwrap();
+            if self.db.iterator_cf(cf, IteratorMode::Start).next().transpose()?.is_some() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Imports a full set of snapshot entries and records `anchor` as the tip,
+    /// in one write. The entries' state root is checked against the anchor
+    /// first, so a bad snapshot leaves the database untouched.
+    pub fn import_state(&self, mut entries: Vec<SnapshotEntry>, anchor: &CommittedBlock) -> Result<()> {
+        let mut positions = HashMap::new();
+        for entry in &entries {
+            let position = STATE_COLUMN_FAMILIES
+                .iter()
+                .position(|name| *name == entry.cf)
+                .ok_or_else(|| anyhow::anyhow!("Unknown column family in snapshot: {}", entry.cf))?;
+            positions.insert(entry.cf.clone(), position);
+        }
+        // Hash in the order `export_state` iterates: column family, then key.
+        entries.sort_by(|a, b| (positions[&a.cf], &a.key).cmp(&(positions[&b.cf], &b.key)));
+
+        let mut hasher = ring::digest::Context::new(&ring::digest::SHA256);
+        let mut batch = WriteBatch::default();
+        for (index, entry) in entries.iter().enumerate() {
+            if index > 0 && entries[index - 1].cf == entry.cf && entries[index - 1].key == entry.key {
+                return Err(anyhow::anyhow!("Duplicate key in snapshot column family {}", entry.cf));
+            }
+            hash_entry(&mut hasher, &entry.cf, &entry.key, &entry.value);
+            batch.put_cf(self.db.cf_handle(&entry.cf).unwrap(), &entry.key, &entry.value);
+        }
+        if hasher.finish().as_ref() != anchor.block.state_root {
+            return Err(anyhow::anyhow!(
+                "Snapshot does not match the state root at height {}",
+                anchor.block.height
+            ));
+        }
+        self.batch_put_block(&mut batch, anchor)?;
+        self.db.write(batch)?;
+        Ok(())
+    }
+}
+
+/// Feeds one state entry into a state root, length-prefixing each part.
+fn hash_entry(hasher: &mut ring::digest::Context, cf_name: &str, key: &[u8], value: &[u8]) {
+    hasher.update(&(cf_name.len() as u64).to_le_bytes());
+    hasher.update(cf_name.as_bytes());
+    hasher.update(&(key.len() as u64).to_le_bytes());
+    hasher.update(key);
+    hasher.update(&(value.len() as u64).to_le_bytes());
+    hasher.update(value);
+}
+
+fn advertiser_bid_prefix(advertiser: &Address) -> Vec<u8> {
+    // Length-prefix the address so one address is never a prefix of another.
+    let mut prefix = (advertiser.len() as u32).to_be_bytes().to_vec();
+    prefix.extend_from_slice(advertiser.as_bytes());
+    prefix
+}
+
+fn advertiser_bid_key(advertiser: &Address, bid_id: &Uuid) -> Vec<u8> {
+    let mut key = advertiser_bid_prefix(advertiser);
+    key.extend_from_slice(bid_id.as_bytes());
+    key
+}
+
+fn auction_key(publisher: &Address, auction_id: &Uuid) -> Vec<u8> {
+    let mut key = (publisher.len() as u32).to_be_bytes().to_vec();
+    key.extend_from_slice(publisher.as_bytes());
+    key.extend_from_slice(auction_id.as_bytes());
+    key
+}
+
+fn revoked_credential_key(issuer: &str, credential_id: &str) -> Vec<u8> {
+    let mut key = (issuer.len() as u32).to_be_bytes().to_vec();
+    key.extend_from_slice(issuer.as_bytes());
+    key.extend_from_slice(credential_id.as_bytes());
+    key
+}
+
+fn multisig_proposal_key(account: &str, id: u64) -> Vec<u8> {
+    let mut key = (account.len() as u32).to_be_bytes().to_vec();
+    key.extend_from_slice(account.as_bytes());
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+fn bid_expiry_key(expires_at_height: u64, bid_id: &Uuid) -> Vec<u8> {
+    let mut key = expires_at_height.to_be_bytes().to_vec();
+    key.extend_from_slice(bid_id.as_bytes());
+    key
+}
+
+/// The consensus engine's key-value store, kept in its own column family
+/// so it stays out of the state root and snapshots.
+#[derive(Clone)]
+pub struct ConsensusStore {
+    db: Arc<DB>,
+}
+
+fn consensus_get(db: &DB, key: &[u8]) -> Option<Vec<u8>> {
+    let cf = db.cf_handle(CF_CONSENSUS).unwrap();
+    // The consensus engine treats its storage as infallible.
+    db.get_cf(cf, key).expect("Failed to read consensus storage")
+}
+
+impl hotstuff_rs::block_tree::pluggables::KVGet for ConsensusStore {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        consensus_get(&self.db, key)
+    }
+}
+
+impl hotstuff_rs::block_tree::pluggables::KVStore for ConsensusStore {
+    type WriteBatch = ConsensusWriteBatch;
+    type Snapshot<'a> = ConsensusSnapshot<'a>;
+
+    fn write(&mut self, wb: Self::WriteBatch) {
+        let cf = self.db.cf_handle(CF_CONSENSUS).unwrap();
+        let mut batch = WriteBatch::default();
+        for (key, value) in wb.operations {
+            match value {
+                Some(value) => batch.put_cf(cf, key, value),
+                None => batch.delete_cf(cf, key),
+            }
+        }
+        self.db.write(batch).expect("Failed to write consensus storage");
+    }
+
+    fn clear(&mut self) {
+        let cf = self.db.cf_handle(CF_CONSENSUS).unwrap();
+        let mut batch = WriteBatch::default();
+        for item in self.db.iterator_cf(cf, IteratorMode::Start) {
+            let (key, _) = item.expect("Failed to read consensus storage");
+            batch.delete_cf(cf, key);
+        }
+        self.db.write(batch).expect("Failed to write consensus storage");
+    }
+
+    fn snapshot(&self) -> Self::Snapshot<'_> {
+        ConsensusSnapshot {
+            db: &self.db,
+            snapshot: self.db.snapshot(),
+        }
+    }
+}
+
+/// Writes to the consensus store, applied in order.
+pub struct ConsensusWriteBatch {
+    operations: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl hotstuff_rs::block_tree::pluggables::WriteBatch for ConsensusWriteBatch {
+    fn new() -> Self {
+        Self { operations: Vec::new() }
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.operations.push((key.to_vec(), Some(value.to_vec())));
+    }
+
+    fn delete(&mut self, key: &[u8]) {
+        self.operations.push((key.to_vec(), None));
+    }
+}
+
+/// A consistent view of the consensus store.
+pub struct ConsensusSnapshot<'a> {
+    db: &'a DB,
+    snapshot: rocksdb::Snapshot<'a>,
+}
+
+impl hotstuff_rs::block_tree::pluggables::KVGet for ConsensusSnapshot<'_> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let cf = self.db.cf_handle(CF_CONSENSUS).unwrap();
+        self.snapshot.get_cf(cf, key).expect("Failed to read consensus storage")
+    }
+}
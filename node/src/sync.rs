@@ -0,0 +1,401 @@
+use crate::consensus::{CommittedBlock, ValidatorSet};
+use crate::primitives::Hash;
+use crate::snapshot::{self, SnapshotManifest, SnapshotStore};
+use crate::state::StateManager;
+use anyhow::Result;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use tokio::sync::{mpsc, oneshot};
+
+/// The libp2p protocol name for block sync.
+pub const SYNC_PROTOCOL: &str = "/unwalled/sync/1";
+
+/// The maximum number of blocks served in a single response.
+pub const MAX_BLOCKS_PER_RESPONSE: u64 = 128;
+
+/// The most JSON-encoded block bytes served in a single response, leaving
+/// headroom under the 10 MiB response limit of libp2p's JSON codec.
+pub const MAX_BLOCK_BYTES_PER_RESPONSE: usize = 8 * 1024 * 1024;
+
+/// A request sent to a peer over the block sync protocol.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SyncRequest {
+    /// Asks the peer for the height of its latest committed block.
+    Status,
+    /// Asks for the committed blocks in `from_height..=to_height`.
+    Blocks { from_height: u64, to_height: u64 },
+    /// Asks for the manifest of the peer's snapshot at `height`.
+    SnapshotManifest { height: u64 },
+    /// Asks for one chunk of the snapshot at `height`.
+    SnapshotChunk { height: u64, index: usize },
+}
+
+/// A peer's answer to a `SyncRequest`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SyncResponse {
+    Status { height: u64 },
+    Blocks(Vec<CommittedBlock>),
+    SnapshotManifest(Option<SnapshotManifest>),
+    SnapshotChunk { height: u64, index: usize, data: Option<Vec<u8>> },
+}
+
+/// A block the operator trusts, given as `<height>:<block hash hex>`, e.g.
+/// read from a node they run. Commit certificates cover a block's
+/// transactions but not its state root, so fast sync restores only a
+/// snapshot anchored at exactly this block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub height: u64,
+    pub block_hash: Hash,
+}
+
+impl std::str::FromStr for Checkpoint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (height, block_hash) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("Expected a checkpoint as <height>:<block hash>, got {:?}", s))?;
+        let block_hash = hex::decode(block_hash)?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("A checkpoint block hash must be 32 bytes"))?;
+        Ok(Self { height: height.parse()?, block_hash })
+    }
+}
+
+/// A verified block handed to the main loop for replay. The result of
+/// applying it is sent back on `applied`.
+#[derive(Debug)]
+pub struct SyncedBlock {
+    pub block: CommittedBlock,
+    pub applied: oneshot::Sender<Result<()>>,
+}
+
+/// Progress of a snapshot download during fast sync.
+struct SnapshotDownload {
+    peer: PeerId,
+    manifest: Option<SnapshotManifest>,
+    chunks: Vec<Vec<u8>>,
+}
+
+/// Tracks how far behind the network this node is and drives block download.
+///
+/// Downloaded blocks are verified against the validator set and their parent
+/// hashes, then forwarded to the main loop to be replayed through
+/// `ConsensusApp::apply_block`, one at a time, by a task of their own so
+/// the network keeps running meanwhile. Given a checkpoint, an empty
+/// node first restores a peer's snapshot anchored at the checkpoint block
+/// and syncs blocks from there.
+pub struct BlockSyncer {
+    state_manager: StateManager,
+    validators: ValidatorSet,
+    snapshots: SnapshotStore,
+    /// Set while an empty node may still fast sync to this checkpoint.
+    fast_sync: Option<Checkpoint>,
+    download: Option<SnapshotDownload>,
+    tx_to_app: mpsc::Sender<SyncedBlock>,
+    /// The highest height verified so far. Blocks up to it are applied
+    /// unless a replay is under way.
+    verified_height: u64,
+    /// The hash of the block at `verified_height`.
+    verified_hash: Hash,
+    /// The highest height advertised by any peer, and that peer.
+    target: Option<(PeerId, u64)>,
+    in_flight: bool,
+    /// Set while verified blocks are being replayed.
+    replaying: bool,
+}
+
+impl BlockSyncer {
+    pub fn new(
+        state_manager: StateManager,
+        validators: ValidatorSet,
+        snapshots: SnapshotStore,
+        checkpoint: Option<Checkpoint>,
+        tx_to_app: mpsc::Sender<SyncedBlock>,
+    ) -> Result<Self> {
+        // No block could ever be verified, so refuse to start rather than
+        // stall silently.
+        if validators.is_empty() {
+            return Err(anyhow::anyhow!("No validators configured; block sync cannot verify any block"));
+        }
+        let mut syncer = Self {
+            fast_sync: None,
+            state_manager,
+            validators,
+            snapshots,
+            download: None,
+            tx_to_app,
+            verified_height: 0,
+            verified_hash: [0u8; 32],
+            target: None,
+            in_flight: false,
+            replaying: false,
+        };
+        syncer.reset_to_state()?;
+        syncer.fast_sync = checkpoint.filter(|_| syncer.verified_height == 0);
+        Ok(syncer)
+    }
+
+    /// Answers a peer's sync request from local state.
+    pub fn handle_request(&self, request: SyncRequest) -> Result<SyncResponse> {
+        match request {
+            SyncRequest::Status => Ok(SyncResponse::Status {
+                height: self.state_manager.latest_height()?,
+            }),
+            SyncRequest::Blocks { from_height, to_height } => {
+                let limit = to_height
+                    .saturating_sub(from_height)
+                    .saturating_add(1)
+                    .min(MAX_BLOCKS_PER_RESPONSE);
+                let blocks = self.state_manager.get_blocks(from_height, limit, MAX_BLOCK_BYTES_PER_RESPONSE)?;
+                Ok(SyncResponse::Blocks(blocks))
+            }
+            SyncRequest::SnapshotManifest { height } => {
+                Ok(SyncResponse::SnapshotManifest(self.snapshots.manifest(height)?))
+            }
+            SyncRequest::SnapshotChunk { height, index } => Ok(SyncResponse::SnapshotChunk {
+                height,
+                index,
+                data: self.snapshots.chunk(height, index)?,
+            }),
+        }
+    }
+
+    /// Records a peer's advertised height and returns the next request to send, if any.
+    pub fn on_status(&mut self, peer: PeerId, height: u64) -> Option<(PeerId, SyncRequest)> {
+        // Consensus may have committed blocks since the last batch.
+        if !self.in_flight && !self.replaying && self.download.is_none() {
+            if let Err(e) = self.reset_to_state() {
+                log::warn!("Failed to read the local chain height: {:#}", e);
+            }
+        }
+        let best_known = self.target.map(|(_, h)| h).unwrap_or(0);
+        if height > self.verified_height && height > best_known {
+            log::info!("Peer {} is at height {}, we are at {}", peer, height, self.verified_height);
+            self.target = Some((peer, height));
+        }
+        if let Some(checkpoint) = self.fast_sync {
+            if self.download.is_none() && height >= checkpoint.height {
+                log::info!("Requesting the snapshot at height {} from {} for fast sync", checkpoint.height, peer);
+                self.download = Some(SnapshotDownload { peer, manifest: None, chunks: Vec::new() });
+                return Some((peer, SyncRequest::SnapshotManifest { height: checkpoint.height }));
+            }
+        }
+        self.next_request()
+    }
+
+    /// Starts downloading a peer's snapshot once its anchor block is verified
+    /// and matches the checkpoint, or falls back to block sync if the peer
+    /// has no such snapshot.
+    pub fn on_snapshot_manifest(
+        &mut self,
+        peer: PeerId,
+        manifest: Option<SnapshotManifest>,
+    ) -> Result<Option<(PeerId, SyncRequest)>> {
+        let Some(manifest) = manifest else {
+            log::info!("Peer {} has no snapshot, falling back to block sync", peer);
+            self.abandon_fast_sync();
+            return Ok(self.next_request());
+        };
+        if let Err(e) = self.verify_anchor(&manifest) {
+            self.abandon_fast_sync();
+            return Err(e.context(format!("Rejected snapshot manifest from peer {}", peer)));
+        }
+
+        log::info!(
+            "Downloading snapshot at height {} ({} chunks) from {}",
+            manifest.height(),
+            manifest.chunk_hashes.len(),
+            peer
+        );
+        let height = manifest.height();
+        self.download = Some(SnapshotDownload { peer, manifest: Some(manifest), chunks: Vec::new() });
+        self.next_chunk_request(height)
+    }
+
+    /// Verifies a downloaded chunk and, once all chunks have arrived, restores
+    /// the snapshot and resumes block sync from its height.
+    pub fn on_snapshot_chunk(
+        &mut self,
+        peer: PeerId,
+        height: u64,
+        index: usize,
+        data: Option<Vec<u8>>,
+    ) -> Result<Option<(PeerId, SyncRequest)>> {
+        let Some(download) = self.download.as_mut() else {
+            return Ok(None);
+        };
+        let Some(manifest) = download.manifest.as_ref() else {
+            return Ok(None);
+        };
+        if download.peer != peer || manifest.height() != height || download.chunks.len() != index {
+            log::warn!("Ignoring unexpected snapshot chunk {} from {}", index, peer);
+            return Ok(None);
+        }
+
+        let chunk = match data {
+            Some(chunk) => chunk,
+            None => {
+                self.abandon_fast_sync();
+                return Err(anyhow::anyhow!("Peer {} no longer has snapshot chunk {}", peer, index));
+            }
+        };
+        if let Err(e) = snapshot::verify_chunk(manifest, index, &chunk) {
+            self.abandon_fast_sync();
+            return Err(e);
+        }
+        download.chunks.push(chunk);
+        self.next_chunk_request(height)
+    }
+
+    /// Verifies a batch of blocks from a peer and returns them for `replay`,
+    /// which runs apart from the network task. Sync resumes from
+    /// `on_replayed` once they are applied.
+    pub fn on_blocks(&mut self, peer: PeerId, blocks: Vec<CommittedBlock>) -> Result<Vec<CommittedBlock>> {
+        self.in_flight = false;
+
+        if blocks.is_empty() {
+            log::warn!("Peer {} returned no blocks, dropping it as a sync target", peer);
+            self.target = None;
+            return Ok(blocks);
+        }
+
+        for committed in &blocks {
+            if let Err(e) = self.verify(committed) {
+                self.target = None;
+                self.reset_to_state()?;
+                return Err(e.context(format!("Rejected block from peer {}", peer)));
+            }
+            self.verified_height = committed.block.height;
+            self.verified_hash = committed.block.hash()?;
+        }
+        self.replaying = true;
+        Ok(blocks)
+    }
+
+    /// Replays verified blocks through the main loop one at a time, stopping
+    /// at the first that fails to apply.
+    pub fn replay(&self, blocks: Vec<CommittedBlock>) -> impl Future<Output = Result<()>> + Send + 'static {
+        let tx_to_app = self.tx_to_app.clone();
+        async move {
+            for committed in blocks {
+                let height = committed.block.height;
+                let (applied, result) = oneshot::channel();
+                tx_to_app.send(SyncedBlock { block: committed, applied }).await?;
+                match result.await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => return Err(e.context(format!("Failed to apply synced block at height {}", height))),
+                    Err(_) => return Err(anyhow::anyhow!("Block at height {} was dropped before being applied", height)),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Resumes sync once a `replay` has finished, and returns the next
+    /// request to send if we are still behind. Only applied blocks count as
+    /// synced: if one failed, sync resumes from the state's height.
+    pub fn on_replayed(&mut self, result: Result<()>) -> Result<Option<(PeerId, SyncRequest)>> {
+        self.replaying = false;
+        self.reset_to_state()?;
+        result?;
+        Ok(self.next_request())
+    }
+
+    /// Called when a request to `peer` failed, so another peer can be tried.
+    pub fn on_failure(&mut self, peer: PeerId) {
+        self.in_flight = false;
+        if matches!(&self.download, Some(download) if download.peer == peer) {
+            self.download = None;
+        }
+        if matches!(self.target, Some((target, _)) if target == peer) {
+            self.target = None;
+        }
+    }
+
+    fn next_chunk_request(&mut self, height: u64) -> Result<Option<(PeerId, SyncRequest)>> {
+        let download = self.download.as_ref().expect("snapshot download in progress");
+        let manifest = download.manifest.as_ref().expect("snapshot manifest received");
+        if download.chunks.len() < manifest.chunk_hashes.len() {
+            let index = download.chunks.len();
+            return Ok(Some((download.peer, SyncRequest::SnapshotChunk { height, index })));
+        }
+
+        let download = self.download.take().expect("snapshot download in progress");
+        let manifest = download.manifest.expect("snapshot manifest received");
+        self.fast_sync = None;
+        snapshot::restore(&self.state_manager, &manifest, &download.chunks)?;
+        self.verified_height = manifest.height();
+        self.verified_hash = manifest.anchor.block.hash()?;
+        Ok(self.next_request())
+    }
+
+    /// Resumes from the latest block in state, e.g. after a block failed to
+    /// apply or consensus committed blocks in the meantime.
+    fn reset_to_state(&mut self) -> Result<()> {
+        self.verified_height = self.state_manager.latest_height()?;
+        self.verified_hash = match self.state_manager.get_block(self.verified_height)? {
+            Some(committed) => committed.block.hash()?,
+            None => [0u8; 32],
+        };
+        Ok(())
+    }
+
+    fn abandon_fast_sync(&mut self) {
+        self.fast_sync = None;
+        self.download = None;
+    }
+
+    /// The restore checks the chunks against the anchor's state root, so the
+    /// anchor itself must be the checkpoint block.
+    fn verify_anchor(&self, manifest: &SnapshotManifest) -> Result<()> {
+        let Some(checkpoint) = self.fast_sync else {
+            return Err(anyhow::anyhow!("Fast sync is not enabled"));
+        };
+        if manifest.height() != checkpoint.height || manifest.anchor.block.hash()? != checkpoint.block_hash {
+            return Err(anyhow::anyhow!(
+                "Snapshot at height {} is not anchored at the checkpoint block at height {}",
+                manifest.height(),
+                checkpoint.height
+            ));
+        }
+        self.validators.verify(&manifest.anchor)
+    }
+
+    fn verify(&self, committed: &CommittedBlock) -> Result<()> {
+        let expected_height = self.verified_height + 1;
+        if committed.block.height != expected_height {
+            return Err(anyhow::anyhow!(
+                "Expected block at height {}, got {}",
+                expected_height,
+                committed.block.height
+            ));
+        }
+        if committed.block.parent_hash != self.verified_hash {
+            return Err(anyhow::anyhow!(
+                "Block at height {} does not extend our chain",
+                committed.block.height
+            ));
+        }
+        self.validators.verify(committed)
+    }
+
+    fn next_request(&mut self) -> Option<(PeerId, SyncRequest)> {
+        if self.in_flight || self.replaying || self.download.is_some() {
+            return None;
+        }
+        let (peer, target_height) = self.target?;
+        if target_height <= self.verified_height {
+            log::info!("Block sync caught up at height {}", self.verified_height);
+            self.target = None;
+            return None;
+        }
+
+        let from_height = self.verified_height + 1;
+        let to_height = target_height.min(from_height + MAX_BLOCKS_PER_RESPONSE - 1);
+        self.in_flight = true;
+        Some((peer, SyncRequest::Blocks { from_height, to_height }))
+    }
+}
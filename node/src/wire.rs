@@ -0,0 +1,79 @@
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+pub const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+
+/// How transactions and other primitives are encoded on the wire.
+///
+/// MessagePack is the compact format for RPC and gossip. Structs are written
+/// as maps keyed by field name, so the schema matches JSON field for field,
+/// while keys, signatures and ids are raw bytes rather than number arrays or
+/// strings. JSON stays available for debugging and for clients without a
+/// MessagePack library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WireFormat {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+impl WireFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            WireFormat::Json => JSON_CONTENT_TYPE,
+            WireFormat::MessagePack => MSGPACK_CONTENT_TYPE,
+        }
+    }
+
+    /// The format named by a `Content-Type` or `Accept` value, ignoring
+    /// parameters such as `; charset=utf-8`. `application/x-msgpack` is
+    /// accepted as an alias.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let media_type = content_type.split(';').next().unwrap_or_default().trim();
+        if media_type.eq_ignore_ascii_case(JSON_CONTENT_TYPE) {
+            Some(WireFormat::Json)
+        } else if media_type.eq_ignore_ascii_case(MSGPACK_CONTENT_TYPE)
+            || media_type.eq_ignore_ascii_case("application/x-msgpack")
+        {
+            Some(WireFormat::MessagePack)
+        } else {
+            None
+        }
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            WireFormat::Json => Ok(serde_json::to_vec(value)?),
+            WireFormat::MessagePack => Ok(rmp_serde::to_vec_named(value)?),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T> {
+        match self {
+            WireFormat::Json => Ok(serde_json::from_slice(bytes)?),
+            WireFormat::MessagePack => Ok(rmp_serde::from_slice(bytes)?),
+        }
+    }
+}
+
+/// The formats of an RPC request's body and of the response it asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Negotiated {
+    pub request: WireFormat,
+    pub response: WireFormat,
+}
+
+impl Negotiated {
+    /// Reads the request's format from `Content-Type`, defaulting to JSON.
+    /// The response is in the first format `Accept` names, or else the
+    /// request's format.
+    pub fn from_headers(content_type: Option<&str>, accept: Option<&str>) -> Self {
+        let request = content_type.and_then(WireFormat::from_content_type).unwrap_or_default();
+        let response = accept
+            .and_then(|accept| accept.split(',').find_map(WireFormat::from_content_type))
+            .unwrap_or(request);
+        Self { request, response }
+    }
+}
@@ -0,0 +1,105 @@
+//! Fixtures shared by the integration tests: state databases in temporary
+//! directories, and a `Chain` that delivers transactions straight to a
+//! `ConsensusApp` and reads back their receipts.
+#![allow(dead_code)]
+
+use anyhow::Result;
+use tempfile::TempDir;
+use unwalled_node::consensus::{ConsensusApp, Transaction};
+use unwalled_node::error::ErrorCode;
+use unwalled_node::identity::{LocalWallet, Signer};
+use unwalled_node::primitives::{AuctionTrigger, Bid, IdentityTier, Receipt, ReceiptStatus};
+use unwalled_node::state::StateManager;
+use uuid::Uuid;
+
+/// The fee the fixtures sign transactions with.
+pub const FEE: u64 = 10;
+
+/// An empty directory, removed with its contents when dropped.
+pub fn temp_dir() -> Result<TempDir> {
+    Ok(tempfile::Builder::new().prefix("unwalled-test-").tempdir()?)
+}
+
+/// A fresh state database. Keep the directory alive as long as the state is
+/// used; dropping it removes the database.
+pub fn temp_state() -> Result<(TempDir, StateManager)> {
+    let dir = temp_dir()?;
+    let state_manager = StateManager::new(dir.path().join("db"))?;
+    Ok((dir, state_manager))
+}
+
+/// A `ConsensusApp` over a fresh state database.
+pub struct Chain {
+    pub app: ConsensusApp,
+    pub state_manager: StateManager,
+    _dir: TempDir,
+}
+
+impl Chain {
+    pub fn new() -> Result<Self> {
+        Self::with_app(|app| app)
+    }
+
+    /// A chain whose app is configured by `configure`, e.g. with governors
+    /// or settlement attesters.
+    pub fn with_app(configure: impl FnOnce(ConsensusApp) -> ConsensusApp) -> Result<Self> {
+        let (dir, state_manager) = temp_state()?;
+        let app = configure(ConsensusApp::new(state_manager.clone()));
+        Ok(Self { app, state_manager, _dir: dir })
+    }
+
+    /// Delivers the transaction built from `signer`'s next nonce and returns
+    /// its receipt.
+    pub fn deliver<F>(&mut self, signer: &LocalWallet, build: F) -> Result<Receipt>
+    where
+        F: FnOnce(u64) -> Result<Transaction>,
+    {
+        let transaction = build(self.state_manager.get_nonce(&signer.address())?)?;
+        let tx_hash = transaction.hash()?;
+        self.app.deliver(transaction);
+        Ok(self.state_manager.get_receipt(&tx_hash)?.expect("receipt"))
+    }
+
+    /// Credits `signer` with `FEE` first, so the signer's balance only moves
+    /// by what the transaction itself does.
+    pub fn deliver_funded<F>(&mut self, signer: &LocalWallet, build: F) -> Result<Receipt>
+    where
+        F: FnOnce(u64) -> Result<Transaction>,
+    {
+        let balance = self.state_manager.get_balance(&signer.address())?;
+        self.state_manager.set_balance(&signer.address(), balance + FEE)?;
+        self.deliver(signer, build)
+    }
+}
+
+/// The error code a receipt was rejected with, or `None` if it applied.
+pub fn rejected_with(status: ReceiptStatus) -> Option<ErrorCode> {
+    match status {
+        ReceiptStatus::Rejected { code, .. } => Some(code),
+        ReceiptStatus::Applied => None,
+    }
+}
+
+/// An untargeted bid open to anonymous users, without expiry.
+pub fn sample_bid(price: u64) -> Bid {
+    Bid {
+        id: Uuid::new_v4(),
+        price,
+        targeting: vec![],
+        adm: "<div>ad</div>".to_string(),
+        expires_at_height: None,
+        requires_verified_targeting: false,
+        min_user_tier: IdentityTier::Anonymous,
+    }
+}
+
+/// An auction with a floor of 100 that any advertiser may win.
+pub fn open_auction() -> AuctionTrigger {
+    AuctionTrigger {
+        id: Uuid::new_v4(),
+        bid_floor: 100,
+        attributes: vec![],
+        presentation: None,
+        min_advertiser_tier: IdentityTier::Anonymous,
+    }
+}
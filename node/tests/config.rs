@@ -0,0 +1,33 @@
+mod common;
+
+use anyhow::Result;
+use common::temp_dir;
+use unwalled_node::config::Config;
+
+#[test]
+fn config_file_overrides_only_the_fields_it_sets() -> Result<()> {
+    let dir = temp_dir()?;
+    let path = dir.path().join("config.json");
+    std::fs::write(
+        &path,
+        r#"{"validators": ["did:key:z6MkValidator"], "governors": ["did:key:z6MkGovernor"], "snapshot_interval": 5}"#,
+    )?;
+
+    let config = Config::from_file(&path)?;
+    assert_eq!(config.validators, vec!["did:key:z6MkValidator"]);
+    assert_eq!(config.governors, vec!["did:key:z6MkGovernor"]);
+    assert_eq!(config.snapshot_interval, 5);
+    assert_eq!(config.db_path, Config::default().db_path);
+    assert!(config.fast_sync_checkpoint.is_none());
+    Ok(())
+}
+
+#[test]
+fn malformed_config_file_is_an_error() -> Result<()> {
+    let dir = temp_dir()?;
+    let path = dir.path().join("config.json");
+    std::fs::write(&path, r#"{"snapshot_interval": "often"}"#)?;
+    assert!(Config::from_file(&path).is_err());
+    assert!(Config::from_file(dir.path().join("missing.json")).is_err());
+    Ok(())
+}
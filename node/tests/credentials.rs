@@ -0,0 +1,108 @@
+mod common;
+
+use anyhow::Result;
+use common::temp_state;
+use std::collections::BTreeMap;
+use unwalled_node::credentials::{VerifiableCredential, VerifiablePresentation};
+use unwalled_node::identity::{EvmWallet, LocalWallet, Signer};
+use unwalled_node::primitives::{AuctionTrigger, Bid, IdentityTier};
+use uuid::Uuid;
+
+fn claims(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+    pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+}
+
+#[test]
+fn presentation_backs_attributes_from_trusted_issuers_only() -> Result<()> {
+    let (_dir, state_manager) = temp_state()?;
+    let trusted = LocalWallet::new();
+    let untrusted = LocalWallet::new();
+    let user = LocalWallet::new();
+    let namespaces = vec!["geo".to_string(), "age".to_string(), "interest".to_string()];
+    state_manager.add_trusted_issuer(&trusted.did(), &namespaces, 0)?;
+
+    let auction_id = Uuid::new_v4().to_string();
+    let presentation = VerifiablePresentation::present(
+        &user,
+        vec![
+            VerifiableCredential::issue(&trusted, &user.did(), claims(&[("geo", "de"), ("age", "over21")]))?,
+            VerifiableCredential::issue(&untrusted, &user.did(), claims(&[("interest", "luxury")]))?,
+            // Issued to someone else, so it does not describe the holder.
+            VerifiableCredential::issue(&trusted, &LocalWallet::new().did(), claims(&[("geo", "fr")]))?,
+        ],
+        &auction_id,
+    )?;
+
+    let attributes = presentation
+        .verified_attributes(&auction_id, |credential, attribute| state_manager.trusts_credential(credential, attribute))?;
+    assert_eq!(attributes, vec!["age=over21".to_string(), "geo=de".to_string()]);
+
+    // The holder's proof is bound to one auction.
+    let replayed = presentation.verified_attributes(&Uuid::new_v4().to_string(), |_, _| Ok(true));
+    assert!(replayed.is_err());
+    Ok(())
+}
+
+#[test]
+fn tampered_credential_is_not_counted() -> Result<()> {
+    let issuer = LocalWallet::new();
+    let user = LocalWallet::new();
+    let mut credential = VerifiableCredential::issue(&issuer, &user.did(), claims(&[("geo", "de")]))?;
+    assert!(credential.verify()?);
+    credential.credential_subject.claims.insert("geo".to_string(), "us".to_string());
+    assert!(!credential.verify()?);
+
+    let presentation = VerifiablePresentation::present(&user, vec![credential], "auction")?;
+    assert!(presentation.verified_attributes("auction", |_, _| Ok(true))?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn secp256k1_dids_issue_and_present_under_their_own_proof_type() -> Result<()> {
+    let issuer = EvmWallet::new();
+    let user = EvmWallet::new();
+    let credential = VerifiableCredential::issue(&issuer, &user.did(), claims(&[("geo", "de")]))?;
+    assert_eq!(credential.proof.as_ref().expect("proof").proof_type, "UnwalledSecp256k1Signature2024");
+    assert!(credential.verify()?);
+
+    let presentation = VerifiablePresentation::present(&user, vec![credential.clone()], "auction")?;
+    assert_eq!(presentation.verified_attributes("auction", |_, _| Ok(true))?, vec!["geo=de".to_string()]);
+
+    // A secp256k1 signature claimed as an ed25519 proof is not accepted.
+    let mut relabelled = credential;
+    relabelled.proof.as_mut().expect("proof").proof_type = "UnwalledEd25519Signature2024".to_string();
+    assert!(!relabelled.verify()?);
+    Ok(())
+}
+
+#[test]
+fn verified_targeting_ignores_self_asserted_attributes() -> Result<()> {
+    let (_dir, state_manager) = temp_state()?;
+    let advertiser = LocalWallet::new().address();
+    state_manager.set_balance(&advertiser, 1_000)?;
+    let bid = Bid {
+        id: Uuid::new_v4(),
+        price: 200,
+        targeting: vec!["geo=de".to_string()],
+        adm: "<div>ad</div>".to_string(),
+        expires_at_height: None,
+        requires_verified_targeting: true,
+        min_user_tier: IdentityTier::Anonymous,
+    };
+    let bid_id = state_manager.place_bid(&bid, &advertiser, 0)?;
+
+    let auction = AuctionTrigger {
+        id: Uuid::new_v4(),
+        bid_floor: 100,
+        attributes: vec!["geo=de".to_string()],
+        presentation: None,
+        min_advertiser_tier: IdentityTier::Anonymous,
+    };
+    let publisher = LocalWallet::new().address();
+    assert!(state_manager.find_match(&auction, &[], IdentityTier::Anonymous, &publisher, 1)?.is_none());
+
+    let verified = vec!["geo=de".to_string()];
+    let auction_match = state_manager.find_match(&auction, &verified, IdentityTier::Verified, &publisher, 1)?.expect("match");
+    assert_eq!(auction_match.bid_id, bid_id);
+    Ok(())
+}
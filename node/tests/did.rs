@@ -0,0 +1,38 @@
+use anyhow::Result;
+use unwalled_node::identity::{self, LocalWallet, PublicKey, Signer};
+
+#[test]
+fn public_key_round_trips_through_did_key() -> Result<()> {
+    let wallet = LocalWallet::new();
+    let did = wallet.did();
+    // Every ed25519 did:key starts with the base58btc encoding of 0xed01.
+    assert!(did.starts_with("did:key:z6Mk"), "{}", did);
+    assert_eq!(PublicKey::from_did(&did)?, wallet.public_key());
+    assert_eq!(wallet.address(), did);
+    assert_eq!(identity::address_of(&wallet.public_key()), did);
+    Ok(())
+}
+
+#[test]
+fn did_resolves_to_a_document_for_its_key() -> Result<()> {
+    let did = LocalWallet::new().did();
+    let document = identity::resolve_did(&did)?;
+    assert_eq!(document.id, did);
+    let method = &document.verification_method[0];
+    assert_eq!(method.controller, did);
+    assert_eq!(method.id, format!("{}#{}", did, &did["did:key:".len()..]));
+    assert_eq!(document.authentication, vec![method.id.clone()]);
+    assert_eq!(document.assertion_method, vec![method.id.clone()]);
+
+    // A verification method id names the same key.
+    assert_eq!(PublicKey::from_did(&method.id)?, PublicKey::from_did(&did)?);
+    Ok(())
+}
+
+#[test]
+fn malformed_dids_are_rejected() {
+    assert!(PublicKey::from_did("did:web:example.com").is_err());
+    // base58btc of an x25519-pub (0xec) multicodec key.
+    assert!(PublicKey::from_did("did:key:z6LSbk7MN8NDFRJBo2wkq5sYG4XonrAvuJVkS4NaaDcbD6Th").is_err());
+    assert!(PublicKey::from_did("did:key:f0123").is_err());
+}
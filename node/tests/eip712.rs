@@ -0,0 +1,116 @@
+mod common;
+
+use anyhow::Result;
+use common::temp_state;
+use unwalled_node::consensus::{ConsensusApp, Transaction};
+use unwalled_node::{eip712, encoding};
+use unwalled_node::identity::{EvmWallet, LocalWallet, PublicKey, SignatureScheme, Signer};
+use unwalled_node::primitives::{bid_id, Bid, IdentityTier, ReceiptStatus, Signed, DEFAULT_CHAIN_ID};
+use uuid::Uuid;
+
+fn sample_bid() -> Bid {
+    Bid {
+        id: Uuid::new_v4(),
+        price: 150,
+        targeting: vec!["sports".to_string()],
+        adm: "<div>ad</div>".to_string(),
+        expires_at_height: None,
+        requires_verified_targeting: false,
+        min_user_tier: IdentityTier::Anonymous,
+    }
+}
+
+#[test]
+fn evm_wallet_matches_ethereum_addresses() -> Result<()> {
+    // The example account from the web3.js documentation.
+    let secret_key = hex::decode("4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318")?;
+    let wallet = EvmWallet::from_secret_key(&secret_key)?;
+    assert_eq!(wallet.eth_address(), "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23");
+
+    // Accounts are still named by DID, using the secp256k1 multicodec.
+    let did = wallet.address();
+    assert!(did.starts_with("did:key:zQ3s"));
+    assert_eq!(PublicKey::from_did(&did)?, wallet.public_key());
+    Ok(())
+}
+
+#[test]
+fn eip712_signatures_verify_only_under_their_scheme() -> Result<()> {
+    let wallet = EvmWallet::new();
+    let signed = Signed::sign(sample_bid(), 0, 10, &wallet)?;
+    assert_eq!(signed.scheme, SignatureScheme::Eip712Secp256k1);
+    assert_eq!(signed.signature.0.len(), 65);
+    assert!(signed.verify()?);
+
+    let mut tampered = signed.clone();
+    tampered.fee = 1;
+    assert!(!tampered.verify()?);
+
+    let mut replayed = signed.clone();
+    replayed.chain_id = "unwalled-mainnet".to_string();
+    assert!(!replayed.verify()?);
+
+    let mut mislabelled = signed.clone();
+    mislabelled.scheme = SignatureScheme::Ed25519;
+    assert!(!mislabelled.verify().unwrap_or(false));
+
+    // An ed25519 signature does not pass as EIP-712 either.
+    let mut ed25519 = Signed::sign(sample_bid(), 0, 10, &LocalWallet::new())?;
+    assert_eq!(ed25519.scheme, SignatureScheme::Ed25519);
+    ed25519.scheme = SignatureScheme::Eip712Secp256k1;
+    assert!(!ed25519.verify().unwrap_or(false));
+    Ok(())
+}
+
+#[test]
+fn typed_data_names_the_action() -> Result<()> {
+    let bid = sample_bid();
+    let typed_data = Signed::typed_data(DEFAULT_CHAIN_ID, &bid, 3, 10)?;
+    assert_eq!(typed_data["primaryType"], "Action");
+    assert_eq!(typed_data["domain"]["name"], "Unwalled");
+    assert_eq!(typed_data["message"]["chain"], DEFAULT_CHAIN_ID);
+    assert_eq!(typed_data["message"]["action"], "PlaceBid");
+    assert_eq!(typed_data["message"]["nonce"], 3);
+    assert_eq!(typed_data["message"]["data"], format!("0x{}", hex::encode(eip712::data_hash(&bid)?)));
+    Ok(())
+}
+
+#[test]
+fn eip712_digest_matches_a_fixed_vector() -> Result<()> {
+    let bid = Bid {
+        id: "67e55044-10b1-426f-9247-bb680e5fe0c8".parse()?,
+        ..sample_bid()
+    };
+    // Computed independently from the EIP-712 spec over these canonical bytes.
+    assert_eq!(
+        hex::encode(encoding::canonical(&bid)?),
+        "84a361646dad3c6469763e61643c2f6469763ea26964d92436376535353034342d313062312d343236662d393234372d626236383065356665306338\
+         a57072696365cc96a9746172676574696e6791a673706f727473"
+    );
+    assert_eq!(
+        hex::encode(eip712::data_hash(&bid)?),
+        "da2b665f04adf1dace09bf7f13d7d6021cbc2fa16ad1b06f6270ec58ce4ae2dd"
+    );
+    assert_eq!(
+        hex::encode(eip712::keccak256(&eip712::encode(DEFAULT_CHAIN_ID, "PlaceBid", &bid, 3, 10)?)),
+        "6acc202356aef6c701e4da919f0684d66e4ff67f510339f82a18c3dc4d2ca8ca"
+    );
+    Ok(())
+}
+
+#[test]
+fn node_applies_eip712_signed_transactions() -> Result<()> {
+    let (_dir, state_manager) = temp_state()?;
+    let mut app = ConsensusApp::new(state_manager.clone());
+    let wallet = EvmWallet::new();
+    state_manager.set_balance(&wallet.address(), 100)?;
+
+    let bid = sample_bid();
+    let transaction = Transaction::PlaceBid(Signed::sign(bid.clone(), 0, 10, &wallet)?);
+    let tx_hash = transaction.hash()?;
+    app.deliver(transaction);
+
+    assert_eq!(state_manager.get_receipt(&tx_hash)?.expect("receipt").status, ReceiptStatus::Applied);
+    assert_eq!(state_manager.get_bid(&bid_id(&wallet.address(), &bid.id))?.expect("bid").advertiser, wallet.address());
+    Ok(())
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use anyhow::Result;
+use common::temp_state;
+use serde_json::json;
+use unwalled_node::consensus::{ConsensusApp, Transaction};
+use unwalled_node::encoding;
+use unwalled_node::error::ErrorCode;
+use unwalled_node::identity::{LocalWallet, Signer};
+use unwalled_node::primitives::{Bid, CancelBid, IdentityTier, ReceiptStatus, Signed, DEFAULT_CHAIN_ID};
+use uuid::Uuid;
+
+fn sample_bid() -> Bid {
+    Bid {
+        id: Uuid::new_v4(),
+        price: 150,
+        targeting: vec!["sports".to_string()],
+        adm: "<div>ad</div>".to_string(),
+        expires_at_height: None,
+        requires_verified_targeting: false,
+        min_user_tier: IdentityTier::Anonymous,
+    }
+}
+
+#[test]
+fn canonical_encoding_is_sorted_compact_msgpack() -> Result<()> {
+    let value = json!({ "n": -1, "big": 300, "c": null, "b": 1, "a": [true, null] });
+    // {"a": [true, nil], "b": 1, "big": 300, "n": -1}, with "c" left out.
+    assert_eq!(hex::encode(encoding::canonical(&value)?), "84a16192c3c0a16201a3626967cd012ca16eff");
+
+    assert!(encoding::canonical(&json!({ "price": 1.5 })).is_err());
+    Ok(())
+}
+
+#[test]
+fn absent_and_null_fields_encode_the_same() -> Result<()> {
+    let bid = sample_bid();
+    let mut explicit = serde_json::to_value(&bid)?;
+    explicit["expires_at_height"] = serde_json::Value::Null;
+    assert_eq!(encoding::canonical(&bid)?, encoding::canonical(&explicit)?);
+
+    // Reordering the JSON an SDK sends does not change the signed bytes.
+    let reordered: Bid = serde_json::from_str(&format!(
+        r#"{{"adm":"<div>ad</div>","targeting":["sports"],"price":150,"id":"{}"}}"#,
+        bid.id
+    ))?;
+    assert_eq!(encoding::canonical(&bid)?, encoding::canonical(&reordered)?);
+    Ok(())
+}
+
+#[test]
+fn signing_bytes_are_versioned_and_domain_separated() -> Result<()> {
+    let cancel = CancelBid { bid_id: Uuid::nil() };
+    let bytes = encoding::signing_bytes(DEFAULT_CHAIN_ID, "CancelBid", &cancel, 7, 10)?;
+    assert_eq!(&bytes[..13], b"unwalled-sig\x01");
+    assert_eq!(&bytes[13..45], &encoding::domain_separator(DEFAULT_CHAIN_ID, "CancelBid"));
+    assert_eq!(&bytes[bytes.len() - 16..], &[0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 0, 0, 0, 0, 10]);
+
+    let other_chain = encoding::signing_bytes("unwalled-mainnet", "CancelBid", &cancel, 7, 10)?;
+    let other_action = encoding::signing_bytes(DEFAULT_CHAIN_ID, "PlaceBid", &cancel, 7, 10)?;
+    assert_ne!(bytes, other_chain);
+    assert_ne!(bytes, other_action);
+    Ok(())
+}
+
+#[test]
+fn signatures_do_not_carry_across_chains() -> Result<()> {
+    let wallet = LocalWallet::new();
+    let signed = Signed::sign_for_chain("unwalled-testnet", sample_bid(), 0, 10, &wallet)?;
+    assert!(signed.verify()?);
+
+    let mut replayed = signed.clone();
+    replayed.chain_id = DEFAULT_CHAIN_ID.to_string();
+    assert!(!replayed.verify()?);
+    Ok(())
+}
+
+#[test]
+fn node_rejects_transactions_for_other_chains() -> Result<()> {
+    let (_dir, state_manager) = temp_state()?;
+    let mut app = ConsensusApp::new(state_manager.clone()).with_chain_id("unwalled-testnet".to_string());
+    let wallet = LocalWallet::new();
+    state_manager.set_balance(&wallet.address(), 100)?;
+
+    let transaction = Transaction::PlaceBid(Signed::sign(sample_bid(), 0, 10, &wallet)?);
+    let tx_hash = transaction.hash()?;
+    app.deliver(transaction);
+    let status = state_manager.get_receipt(&tx_hash)?.expect("receipt").status;
+    assert!(matches!(status, ReceiptStatus::Rejected { code: ErrorCode::WrongChain, .. }));
+    assert_eq!(state_manager.get_balance(&wallet.address())?, 100);
+
+    let transaction = Transaction::PlaceBid(Signed::sign_for_chain("unwalled-testnet", sample_bid(), 0, 10, &wallet)?);
+    let tx_hash = transaction.hash()?;
+    app.deliver(transaction);
+    assert_eq!(state_manager.get_receipt(&tx_hash)?.expect("receipt").status, ReceiptStatus::Applied);
+    Ok(())
+}
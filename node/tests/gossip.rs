@@ -0,0 +1,106 @@
+mod common;
+
+use anyhow::Result;
+use common::temp_state;
+use futures_util::StreamExt;
+use libp2p::swarm::SwarmEvent;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::sync::mpsc;
+use unwalled_node::consensus::{Transaction, ValidatorSet};
+use unwalled_node::identity::{LocalWallet, Signer};
+use unwalled_node::mempool::Mempool;
+use unwalled_node::network::{self, NetworkManager, Outbound};
+use unwalled_node::primitives::{Bid, IdentityTier, Signed};
+use unwalled_node::snapshot::SnapshotStore;
+use unwalled_node::state::StateManager;
+use unwalled_node::sync::BlockSyncer;
+use uuid::Uuid;
+
+struct Node {
+    _dir: TempDir,
+    manager: NetworkManager,
+    state_manager: StateManager,
+    outbound: mpsc::UnboundedSender<Outbound>,
+    from_network: mpsc::Receiver<Transaction>,
+}
+
+async fn start_node(validators: &ValidatorSet) -> Result<Node> {
+    let (dir, state_manager) = temp_state()?;
+    let (tx_blocks, _rx_blocks) = mpsc::channel(16);
+    let syncer = BlockSyncer::new(
+        state_manager.clone(),
+        validators.clone(),
+        SnapshotStore::new(dir.path().join("snapshots")),
+        None,
+        tx_blocks,
+    )?;
+    let (tx_outbound, rx_outbound) = mpsc::unbounded_channel();
+    let (_network, inbox) = network::consensus_network(LocalWallet::new().signing_key().clone(), tx_outbound.clone());
+    let (tx_to_consensus, from_network) = mpsc::channel(16);
+    let manager = NetworkManager::new(
+        libp2p::identity::Keypair::generate_ed25519(),
+        tx_to_consensus,
+        syncer,
+        inbox,
+        rx_outbound,
+    )
+    .await?;
+    Ok(Node {
+        _dir: dir,
+        manager,
+        state_manager,
+        outbound: tx_outbound,
+        from_network,
+    })
+}
+
+#[tokio::test]
+async fn gossiped_transaction_reaches_a_peers_mempool() -> Result<()> {
+    let validator = LocalWallet::new();
+    let validators = ValidatorSet::new("unwalled-test", vec![validator.public_key()]);
+    let mut a = start_node(&validators).await?;
+    let mut b = start_node(&validators).await?;
+
+    let address = loop {
+        if let SwarmEvent::NewListenAddr { address, .. } = a.manager.swarm.select_next_some().await {
+            if address.to_string().starts_with("/ip4/127.0.0.1/") {
+                break address;
+            }
+        }
+    };
+    b.manager.swarm.dial(address)?;
+    tokio::spawn(network::event_loop(a.manager));
+    tokio::spawn(network::event_loop(b.manager));
+
+    let advertiser = LocalWallet::new();
+    let bid = Bid {
+        id: Uuid::new_v4(),
+        price: 150,
+        targeting: vec!["geo=de".to_string()],
+        adm: "<div>ad</div>".to_string(),
+        expires_at_height: None,
+        requires_verified_targeting: false,
+        min_user_tier: IdentityTier::Anonymous,
+    };
+    let transaction = Transaction::PlaceBid(Signed::sign(bid, 0, 10, &advertiser)?);
+
+    // Publishing fails until the peers have exchanged subscriptions, so keep
+    // offering the transaction until it arrives.
+    let received = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            a.outbound.send(Outbound::Transaction(Box::new(transaction.clone())))?;
+            if let Ok(Some(received)) = tokio::time::timeout(Duration::from_millis(200), b.from_network.recv()).await {
+                return anyhow::Ok(received);
+            }
+        }
+    })
+    .await??;
+    assert_eq!(received.hash()?, transaction.hash()?);
+
+    b.state_manager.set_balance(&advertiser.address(), 100)?;
+    let mut mempool = Mempool::new();
+    let hash = mempool.admit(received, &b.state_manager)?.expect("new transaction");
+    assert!(mempool.contains(&hash));
+    Ok(())
+}
@@ -0,0 +1,201 @@
+mod common;
+
+use anyhow::Result;
+use common::{rejected_with, temp_state, Chain, FEE};
+use std::collections::BTreeMap;
+use unwalled_node::consensus::Transaction;
+use unwalled_node::credentials::{presentation_challenge, VerifiableCredential, VerifiablePresentation};
+use unwalled_node::error::ErrorCode;
+use unwalled_node::identity::{LocalWallet, Signer};
+use unwalled_node::primitives::{
+    AuctionTrigger, Bid, IdentityTier, IdentityTierUpdate, IssuerRegistryUpdate, Receipt, ReceiptStatus, Signed,
+    DEFAULT_CHAIN_ID,
+};
+use uuid::Uuid;
+
+/// A chain governed by the returned wallet.
+fn governed_chain() -> Result<(Chain, LocalWallet)> {
+    let governor = LocalWallet::new();
+    let chain = Chain::with_app(|app| app.with_governors(vec![governor.address()]))?;
+    Ok((chain, governor))
+}
+
+impl Chain {
+    fn update_tier(&mut self, signer: &LocalWallet, update: IdentityTierUpdate) -> Result<ReceiptStatus> {
+        let receipt = self.deliver_funded(signer, |nonce| {
+            Ok(Transaction::UpdateIdentityTier(Signed::sign(update, nonce, FEE, signer)?))
+        })?;
+        Ok(receipt.status)
+    }
+
+    /// Has `governor` register a new issuer trusted for `namespaces`.
+    fn trusted_issuer(&mut self, governor: &LocalWallet, namespaces: &[&str]) -> Result<LocalWallet> {
+        let issuer = LocalWallet::new();
+        let update = IssuerRegistryUpdate::AddIssuer {
+            issuer: issuer.did(),
+            namespaces: namespaces.iter().map(|namespace| namespace.to_string()).collect(),
+        };
+        let receipt = self.deliver_funded(governor, |nonce| {
+            Ok(Transaction::UpdateIssuerRegistry(Signed::sign(update, nonce, FEE, governor)?))
+        })?;
+        assert_eq!(receipt.status, ReceiptStatus::Applied);
+        Ok(issuer)
+    }
+}
+
+fn credential(issuer: &LocalWallet, subject: &LocalWallet, key: &str, value: &str) -> Result<VerifiableCredential> {
+    VerifiableCredential::issue(issuer, &subject.did(), BTreeMap::from([(key.to_string(), value.to_string())]))
+}
+
+fn bid(price: u64, min_user_tier: IdentityTier) -> Bid {
+    Bid {
+        id: Uuid::new_v4(),
+        price,
+        targeting: vec![],
+        adm: "<div>ad</div>".to_string(),
+        expires_at_height: None,
+        requires_verified_targeting: false,
+        min_user_tier,
+    }
+}
+
+fn auction(min_advertiser_tier: IdentityTier) -> AuctionTrigger {
+    AuctionTrigger {
+        id: Uuid::new_v4(),
+        bid_floor: 100,
+        attributes: vec![],
+        presentation: None,
+        min_advertiser_tier,
+    }
+}
+
+#[test]
+fn only_scoped_issuers_attest_tiers() -> Result<()> {
+    let (mut chain, governor) = governed_chain()?;
+    let attestor = chain.trusted_issuer(&governor, &["tier"])?;
+    let geo_issuer = chain.trusted_issuer(&governor, &["geo"])?;
+    let advertiser = LocalWallet::new();
+    let attest = IdentityTierUpdate::Attest { subject: advertiser.did(), tier: IdentityTier::Kyc };
+
+    assert_eq!(rejected_with(chain.update_tier(&geo_issuer, attest.clone())?), Some(ErrorCode::Unauthorized));
+    assert_eq!(rejected_with(chain.update_tier(&advertiser, attest.clone())?), Some(ErrorCode::Unauthorized));
+    assert_eq!(chain.state_manager.get_identity_tier(&advertiser.did())?, IdentityTier::Anonymous);
+
+    assert_eq!(chain.update_tier(&attestor, attest)?, ReceiptStatus::Applied);
+    let identity = chain.state_manager.get_identity(&advertiser.did())?.expect("identity");
+    assert_eq!(identity.tier, IdentityTier::Kyc);
+    assert_eq!(identity.attested_by, attestor.did());
+
+    // Attesting anonymous clears the record.
+    let clear = IdentityTierUpdate::Attest { subject: advertiser.did(), tier: IdentityTier::Anonymous };
+    assert_eq!(chain.update_tier(&attestor, clear)?, ReceiptStatus::Applied);
+    assert!(chain.state_manager.get_identity(&advertiser.did())?.is_none());
+    Ok(())
+}
+
+#[test]
+fn tiers_are_claimed_with_trusted_credentials() -> Result<()> {
+    let (mut chain, governor) = governed_chain()?;
+    let attestor = chain.trusted_issuer(&governor, &["tier"])?;
+    let geo_issuer = chain.trusted_issuer(&governor, &["geo"])?;
+    let holder = LocalWallet::new();
+
+    let out_of_scope = credential(&geo_issuer, &holder, "tier", "kyc")?;
+    let claim = IdentityTierUpdate::Claim { credential: Box::new(out_of_scope) };
+    assert_eq!(rejected_with(chain.update_tier(&holder, claim)?), Some(ErrorCode::UntrustedCredential));
+
+    let someone_elses = credential(&attestor, &LocalWallet::new(), "tier", "kyc")?;
+    let claim = IdentityTierUpdate::Claim { credential: Box::new(someone_elses) };
+    assert_eq!(rejected_with(chain.update_tier(&holder, claim)?), Some(ErrorCode::UntrustedCredential));
+
+    let claim = IdentityTierUpdate::Claim { credential: Box::new(credential(&attestor, &holder, "tier", "verified")?) };
+    assert_eq!(chain.update_tier(&holder, claim)?, ReceiptStatus::Applied);
+    assert_eq!(chain.state_manager.get_identity_tier(&holder.did())?, IdentityTier::Verified);
+    Ok(())
+}
+
+#[test]
+fn matching_enforces_user_and_advertiser_tiers() -> Result<()> {
+    let (_dir, state_manager) = temp_state()?;
+    let anonymous = LocalWallet::new().address();
+    let kyc = LocalWallet::new().address();
+    state_manager.set_identity_tier(&kyc, IdentityTier::Kyc, "did:key:attestor", 0)?;
+    for advertiser in [&anonymous, &kyc] {
+        state_manager.set_balance(advertiser, 1_000)?;
+    }
+
+    let kyc_users_only = bid(500, IdentityTier::Kyc);
+    let anonymous_advertiser = bid(300, IdentityTier::Anonymous);
+    let kyc_advertiser = bid(200, IdentityTier::Anonymous);
+    let kyc_users_only = state_manager.place_bid(&kyc_users_only, &anonymous, 0)?;
+    let anonymous_advertiser = state_manager.place_bid(&anonymous_advertiser, &anonymous, 0)?;
+    let kyc_advertiser = state_manager.place_bid(&kyc_advertiser, &kyc, 0)?;
+    let publisher = LocalWallet::new().address();
+
+    let open = auction(IdentityTier::Anonymous);
+    let winner = |user_tier, auction: &AuctionTrigger| -> Result<Option<Uuid>> {
+        Ok(state_manager.find_match(auction, &[], user_tier, &publisher, 1)?.map(|m| m.bid_id))
+    };
+    assert_eq!(winner(IdentityTier::Verified, &open)?, Some(anonymous_advertiser));
+    assert_eq!(winner(IdentityTier::Kyc, &open)?, Some(kyc_users_only));
+
+    let verified_advertisers = auction(IdentityTier::Verified);
+    assert_eq!(winner(IdentityTier::Kyc, &verified_advertisers)?, Some(kyc_advertiser));
+    Ok(())
+}
+
+#[test]
+fn presented_tier_credentials_raise_the_user_tier() -> Result<()> {
+    let (mut chain, governor) = governed_chain()?;
+    let attestor = chain.trusted_issuer(&governor, &["tier"])?;
+    let advertiser = LocalWallet::new().address();
+    chain.state_manager.set_balance(&advertiser, 1_000)?;
+    let kyc_users_only = bid(500, IdentityTier::Kyc);
+    let kyc_users_only = chain.state_manager.place_bid(&kyc_users_only, &advertiser, 0)?;
+
+    let user = LocalWallet::new();
+    let publisher = LocalWallet::new();
+    let trigger = |credentials: Vec<VerifiableCredential>, chain: &mut Chain| -> Result<Receipt> {
+        let mut auction = auction(IdentityTier::Anonymous);
+        let challenge = presentation_challenge(DEFAULT_CHAIN_ID, &publisher.address(), &auction.id);
+        auction.presentation = Some(VerifiablePresentation::present(&user, credentials, &challenge)?);
+        chain.deliver_funded(&publisher, |nonce| {
+            Ok(Transaction::TriggerAuction(Signed::sign(auction, nonce, FEE, &publisher)?))
+        })
+    };
+
+    let receipt = trigger(vec![credential(&attestor, &user, "tier", "verified")?], &mut chain)?;
+    assert_eq!(receipt.status, ReceiptStatus::Applied);
+    assert!(receipt.auction_match.is_none());
+
+    let receipt = trigger(vec![credential(&attestor, &user, "tier", "kyc")?], &mut chain)?;
+    assert_eq!(receipt.auction_match.expect("match").bid_id, kyc_users_only);
+    Ok(())
+}
+
+#[test]
+fn presentations_and_trigger_ids_are_single_use() -> Result<()> {
+    let (mut chain, governor) = governed_chain()?;
+    let attestor = chain.trusted_issuer(&governor, &["tier"])?;
+    let user = LocalWallet::new();
+    let (publisher, other_publisher) = (LocalWallet::new(), LocalWallet::new());
+    let mut trigger = |auction: AuctionTrigger, publisher: &LocalWallet| -> Result<ReceiptStatus> {
+        let receipt = chain.deliver_funded(publisher, |nonce| {
+            Ok(Transaction::TriggerAuction(Signed::sign(auction, nonce, FEE, publisher)?))
+        })?;
+        Ok(receipt.status)
+    };
+
+    // A presentation made for one publisher's auction cannot be attached to
+    // another publisher's trigger with the same id.
+    let mut auction = auction(IdentityTier::Anonymous);
+    let challenge = presentation_challenge(DEFAULT_CHAIN_ID, &publisher.address(), &auction.id);
+    let credentials = vec![credential(&attestor, &user, "tier", "verified")?];
+    auction.presentation = Some(VerifiablePresentation::present(&user, credentials, &challenge)?);
+    assert_eq!(rejected_with(trigger(auction.clone(), &other_publisher)?), Some(ErrorCode::InvalidPresentation));
+    assert_eq!(trigger(auction.clone(), &publisher)?, ReceiptStatus::Applied);
+
+    // Each publisher runs an auction id once.
+    assert_eq!(rejected_with(trigger(auction, &publisher)?), Some(ErrorCode::AuctionAlreadyExists));
+    Ok(())
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use anyhow::Result;
+use common::{rejected_with, Chain, FEE};
+use std::collections::BTreeMap;
+use unwalled_node::consensus::Transaction;
+use unwalled_node::credentials::VerifiableCredential;
+use unwalled_node::error::ErrorCode;
+use unwalled_node::identity::{LocalWallet, Signer};
+use unwalled_node::primitives::{IssuerRegistryUpdate, ReceiptStatus, Signed};
+
+fn governed_by(governor: &LocalWallet) -> Result<Chain> {
+    Chain::with_app(|app| app.with_governors(vec![governor.address()]))
+}
+
+impl Chain {
+    /// Delivers `update` signed by `signer` and returns its receipt status.
+    fn submit(&mut self, signer: &LocalWallet, update: IssuerRegistryUpdate) -> Result<ReceiptStatus> {
+        let receipt = self.deliver_funded(signer, |nonce| {
+            Ok(Transaction::UpdateIssuerRegistry(Signed::sign(update, nonce, FEE, signer)?))
+        })?;
+        Ok(receipt.status)
+    }
+}
+
+fn geo_credential(issuer: &LocalWallet) -> Result<VerifiableCredential> {
+    let claims = BTreeMap::from([("geo".to_string(), "de".to_string())]);
+    VerifiableCredential::issue(issuer, &LocalWallet::new().did(), claims)
+}
+
+#[test]
+fn only_governors_manage_issuers() -> Result<()> {
+    let governor = LocalWallet::new();
+    let issuer = LocalWallet::new();
+    let mut chain = governed_by(&governor)?;
+    let add = IssuerRegistryUpdate::AddIssuer { issuer: issuer.did(), namespaces: vec!["geo".to_string()] };
+
+    assert_eq!(rejected_with(chain.submit(&issuer, add.clone())?), Some(ErrorCode::Unauthorized));
+    assert_eq!(chain.submit(&governor, add.clone())?, ReceiptStatus::Applied);
+    assert_eq!(rejected_with(chain.submit(&governor, add)?), Some(ErrorCode::IssuerAlreadyExists));
+
+    let credential = geo_credential(&issuer)?;
+    assert!(chain.state_manager.trusts_credential(&credential, "geo=de")?);
+
+    // Scope changes take effect for existing credentials.
+    let rescope = IssuerRegistryUpdate::SetIssuerScope { issuer: issuer.did(), namespaces: vec!["age".to_string()] };
+    assert_eq!(rejected_with(chain.submit(&issuer, rescope.clone())?), Some(ErrorCode::Unauthorized));
+    assert_eq!(chain.submit(&governor, rescope)?, ReceiptStatus::Applied);
+    assert!(!chain.state_manager.trusts_credential(&credential, "geo=de")?);
+
+    let remove = IssuerRegistryUpdate::RemoveIssuer { issuer: issuer.did() };
+    assert_eq!(chain.submit(&governor, remove.clone())?, ReceiptStatus::Applied);
+    assert_eq!(rejected_with(chain.submit(&governor, remove)?), Some(ErrorCode::IssuerNotFound));
+    Ok(())
+}
+
+#[test]
+fn issuers_rotate_keys_and_revoke_credentials() -> Result<()> {
+    let governor = LocalWallet::new();
+    let issuer = LocalWallet::new();
+    let mut chain = governed_by(&governor)?;
+    let add = IssuerRegistryUpdate::AddIssuer { issuer: issuer.did(), namespaces: vec!["geo".to_string()] };
+    assert_eq!(chain.submit(&governor, add)?, ReceiptStatus::Applied);
+
+    let revoked = geo_credential(&issuer)?;
+    let kept = geo_credential(&issuer)?;
+    let revoke = IssuerRegistryUpdate::RevokeCredentials {
+        issuer: issuer.did(),
+        credential_ids: vec![revoked.id.clone().expect("credential id")],
+    };
+    assert_eq!(rejected_with(chain.submit(&LocalWallet::new(), revoke.clone())?), Some(ErrorCode::Unauthorized));
+    assert_eq!(chain.submit(&issuer, revoke)?, ReceiptStatus::Applied);
+    assert!(!chain.state_manager.trusts_credential(&revoked, "geo=de")?);
+    assert!(chain.state_manager.trusts_credential(&kept, "geo=de")?);
+
+    // After rotating, only credentials signed with the new key are trusted,
+    // and only the new key may act for the issuer.
+    let new_key = LocalWallet::new();
+    let rotate = IssuerRegistryUpdate::RotateIssuerKey { issuer: issuer.did(), new_key: new_key.did() };
+    assert_eq!(chain.submit(&issuer, rotate)?, ReceiptStatus::Applied);
+    assert!(!chain.state_manager.trusts_credential(&kept, "geo=de")?);
+    assert!(chain.state_manager.trusts_credential(&geo_credential(&new_key)?, "geo=de")?);
+
+    let rotate_back = IssuerRegistryUpdate::RotateIssuerKey { issuer: issuer.did(), new_key: issuer.did() };
+    assert_eq!(rejected_with(chain.submit(&issuer, rotate_back.clone())?), Some(ErrorCode::Unauthorized));
+    assert_eq!(chain.submit(&new_key, rotate_back)?, ReceiptStatus::Applied);
+    Ok(())
+}
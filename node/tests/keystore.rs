@@ -0,0 +1,40 @@
+use anyhow::Result;
+use unwalled_node::identity::{LocalWallet, Signer};
+use unwalled_node::keystore::Keystore;
+use uuid::Uuid;
+
+fn temp_path() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("unwalled-keystore-test-{}.json", Uuid::new_v4()))
+}
+
+#[test]
+fn wallet_round_trips_through_keystore() -> Result<()> {
+    let wallet = LocalWallet::new();
+    let path = temp_path();
+    wallet.save(&path, "hunter2")?;
+
+    let keystore = Keystore::load(&path)?;
+    assert_eq!(keystore.address, wallet.address());
+    assert_eq!(keystore.crypto.cipher, "aes-256-gcm");
+    assert!(!keystore.crypto.ciphertext.contains(&hex::encode(wallet.secret_key())));
+
+    let loaded = LocalWallet::load(&path, "hunter2")?;
+    assert_eq!(loaded.address(), wallet.address());
+    assert!(LocalWallet::load(&path, "hunter3").is_err());
+
+    // Saving never replaces an existing keystore.
+    assert!(LocalWallet::new().save(&path, "hunter2").is_err());
+    assert_eq!(LocalWallet::load(&path, "hunter2")?.address(), wallet.address());
+    Ok(())
+}
+
+#[test]
+fn tampered_address_fails_to_decrypt() -> Result<()> {
+    let secret = [7u8; 32];
+    let mut keystore = Keystore::encrypt_with_iterations(&secret, "owner", "hunter2", 1_000)?;
+    assert_eq!(keystore.decrypt("hunter2")?, secret.to_vec());
+
+    keystore.address = "someone-else".to_string();
+    assert!(keystore.decrypt("hunter2").is_err());
+    Ok(())
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use anyhow::Result;
+use common::{open_auction, sample_bid, temp_state};
+use unwalled_node::error::{self, ErrorCode};
+use unwalled_node::identity::{LocalWallet, Signer};
+use unwalled_node::primitives::{Bid, BidStatus, IdentityTier};
+
+/// A bid that expires at height 100.
+fn expiring_bid(price: u64) -> Bid {
+    Bid { expires_at_height: Some(100), ..sample_bid(price) }
+}
+
+#[test]
+fn won_bids_are_filled_and_leave_the_book() -> Result<()> {
+    let (_dir, state_manager) = temp_state()?;
+    let (advertiser, publisher) = (LocalWallet::new(), LocalWallet::new());
+    state_manager.set_balance(&advertiser.address(), 1_000)?;
+    let bid_id = state_manager.place_bid_as(&expiring_bid(150), &advertiser.address(), None, 1)?;
+
+    let auction_match = state_manager
+        .find_match(&open_auction(), &[], IdentityTier::Anonymous, &publisher.address(), 1)?
+        .expect("match");
+    state_manager.settle_match(&auction_match, 1)?;
+    assert_eq!(state_manager.get_bid(&bid_id)?.expect("bid").status, BidStatus::Filled);
+    assert_eq!(state_manager.get_balance(&advertiser.address())?, 850);
+    assert_eq!(state_manager.get_balance(&publisher.address())?, 150);
+
+    // The advertiser could pay again, but the bid only wins once.
+    assert!(state_manager.find_match(&open_auction(), &[], IdentityTier::Anonymous, &publisher.address(), 1)?.is_none());
+    let error = state_manager.settle_match(&auction_match, 1).unwrap_err();
+    assert_eq!(error::classify(&error).code, ErrorCode::BidNotOpen);
+    assert_eq!(state_manager.get_balance(&advertiser.address())?, 850);
+
+    // A filled bid no longer expires.
+    assert!(state_manager.expire_bids(100)?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn failed_settlement_changes_nothing() -> Result<()> {
+    let (_dir, state_manager) = temp_state()?;
+    let (advertiser, publisher) = (LocalWallet::new(), LocalWallet::new());
+    state_manager.set_balance(&advertiser.address(), 1_000)?;
+    state_manager.set_balance(&publisher.address(), u64::MAX - 100)?;
+    let bid_id = state_manager.place_bid_as(&expiring_bid(150), &advertiser.address(), None, 1)?;
+
+    let auction_match = state_manager
+        .find_match(&open_auction(), &[], IdentityTier::Anonymous, &publisher.address(), 1)?
+        .expect("match");
+    let error = state_manager.settle_match(&auction_match, 1).unwrap_err();
+    assert_eq!(error::classify(&error).code, ErrorCode::BalanceOverflow);
+    assert_eq!(state_manager.get_balance(&advertiser.address())?, 1_000);
+    assert_eq!(state_manager.get_balance(&publisher.address())?, u64::MAX - 100);
+    assert_eq!(state_manager.get_bid(&bid_id)?.expect("bid").status, BidStatus::Open);
+    Ok(())
+}
+
+#[test]
+fn advertisers_can_win_their_own_auctions() -> Result<()> {
+    let (_dir, state_manager) = temp_state()?;
+    let advertiser = LocalWallet::new();
+    state_manager.set_balance(&advertiser.address(), 1_000)?;
+    state_manager.place_bid_as(&expiring_bid(150), &advertiser.address(), None, 1)?;
+
+    let auction_match = state_manager
+        .find_match(&open_auction(), &[], IdentityTier::Anonymous, &advertiser.address(), 1)?
+        .expect("match");
+    state_manager.settle_match(&auction_match, 1)?;
+    assert_eq!(state_manager.get_balance(&advertiser.address())?, 1_000);
+    Ok(())
+}
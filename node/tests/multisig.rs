@@ -0,0 +1,151 @@
+mod common;
+
+use anyhow::Result;
+use common::{rejected_with, sample_bid, Chain, FEE};
+use unwalled_node::consensus::Transaction;
+use unwalled_node::error::ErrorCode;
+use unwalled_node::identity::{multisig_address, Address, LocalWallet, Signer};
+use unwalled_node::primitives::{
+    bid_id, BidStatus, MultisigAction, MultisigUpdate, ProposalStatus, ReceiptStatus, Signed, Withdraw,
+    DEFAULT_CHAIN_ID,
+};
+use unwalled_node::settlement::SettlementAttesters;
+
+/// A chain with settlement attesters, so multisig accounts can be funded
+/// and withdraw.
+fn multisig_chain() -> Result<Chain> {
+    let attesters = SettlementAttesters::new(DEFAULT_CHAIN_ID, vec![LocalWallet::new().public_key()], 1)?;
+    Chain::with_app(|app| app.with_settlement(attesters))
+}
+
+impl Chain {
+    /// Delivers `update` signed by `signer` and returns the receipt status.
+    fn update(&mut self, signer: &LocalWallet, update: MultisigUpdate) -> Result<ReceiptStatus> {
+        let receipt = self.deliver_funded(signer, |nonce| {
+            Ok(Transaction::UpdateMultisig(Signed::sign(update, nonce, FEE, signer)?))
+        })?;
+        Ok(receipt.status)
+    }
+
+    /// Creates a multisig account from `members` and returns its address.
+    fn create(&mut self, creator: &LocalWallet, members: &[&LocalWallet], threshold: u32) -> Result<Address> {
+        let address = multisig_address(&creator.address(), self.state_manager.get_nonce(&creator.address())?);
+        let members = members.iter().map(|member| member.did()).collect();
+        assert_eq!(self.update(creator, MultisigUpdate::Create { members, threshold })?, ReceiptStatus::Applied);
+        Ok(address)
+    }
+}
+
+#[test]
+fn proposals_run_once_the_threshold_approves() -> Result<()> {
+    let mut chain = multisig_chain()?;
+    let (alice, bob, carol) = (LocalWallet::new(), LocalWallet::new(), LocalWallet::new());
+    let account = chain.create(&alice, &[&alice, &bob, &carol], 2)?;
+    chain.state_manager.set_balance(&account, 1_000)?;
+
+    let bid = sample_bid(400);
+    let propose = MultisigUpdate::Propose { account: account.clone(), proposal: MultisigAction::PlaceBid(bid.clone()) };
+    assert_eq!(chain.update(&alice, propose)?, ReceiptStatus::Applied);
+    let proposal = chain.state_manager.get_multisig_proposal(&account, 0)?.expect("proposal");
+    assert_eq!(proposal.status, ProposalStatus::Open);
+    assert!(chain.state_manager.get_bid(&bid_id(&account, &bid.id))?.is_none());
+
+    // Approving twice does not count twice.
+    let approve = MultisigUpdate::Approve { account: account.clone(), proposal_id: 0 };
+    assert_eq!(rejected_with(chain.update(&alice, approve.clone())?), Some(ErrorCode::InvalidRequest));
+
+    assert_eq!(chain.update(&bob, approve.clone())?, ReceiptStatus::Applied);
+    let proposal = chain.state_manager.get_multisig_proposal(&account, 0)?.expect("proposal");
+    assert!(matches!(proposal.status, ProposalStatus::Executed { .. }));
+    let info = chain.state_manager.get_bid(&bid_id(&account, &bid.id))?.expect("bid");
+    assert_eq!(info.advertiser, account);
+    assert_eq!(info.status, BidStatus::Open);
+
+    assert_eq!(rejected_with(chain.update(&carol, approve)?), Some(ErrorCode::ProposalNotOpen));
+    let missing = MultisigUpdate::Approve { account: account.clone(), proposal_id: 7 };
+    assert_eq!(rejected_with(chain.update(&carol, missing)?), Some(ErrorCode::ProposalNotFound));
+    Ok(())
+}
+
+#[test]
+fn only_members_propose_and_approve() -> Result<()> {
+    let mut chain = multisig_chain()?;
+    let (alice, bob, mallory) = (LocalWallet::new(), LocalWallet::new(), LocalWallet::new());
+    let account = chain.create(&alice, &[&alice, &bob], 2)?;
+
+    let takeover = MultisigAction::SetMembers { members: vec![mallory.did()], threshold: 1 };
+    let propose = MultisigUpdate::Propose { account: account.clone(), proposal: takeover.clone() };
+    assert_eq!(rejected_with(chain.update(&mallory, propose)?), Some(ErrorCode::Unauthorized));
+
+    let propose = MultisigUpdate::Propose { account: account.clone(), proposal: takeover };
+    assert_eq!(chain.update(&alice, propose)?, ReceiptStatus::Applied);
+    let approve = MultisigUpdate::Approve { account: account.clone(), proposal_id: 0 };
+    assert_eq!(rejected_with(chain.update(&mallory, approve)?), Some(ErrorCode::Unauthorized));
+    assert_eq!(chain.state_manager.get_multisig_account(&account)?.expect("account").members.len(), 2);
+    Ok(())
+}
+
+#[test]
+fn member_changes_take_a_threshold_approval() -> Result<()> {
+    let mut chain = multisig_chain()?;
+    let (alice, bob, carol) = (LocalWallet::new(), LocalWallet::new(), LocalWallet::new());
+    let account = chain.create(&alice, &[&alice, &bob], 2)?;
+
+    let invalid = MultisigAction::SetMembers { members: vec![alice.did(), alice.did()], threshold: 1 };
+    let propose = MultisigUpdate::Propose { account: account.clone(), proposal: invalid };
+    assert_eq!(rejected_with(chain.update(&alice, propose)?), Some(ErrorCode::InvalidRequest));
+
+    let rotate = MultisigAction::SetMembers { members: vec![alice.did(), carol.did()], threshold: 1 };
+    let propose = MultisigUpdate::Propose { account: account.clone(), proposal: rotate };
+    assert_eq!(chain.update(&alice, propose)?, ReceiptStatus::Applied);
+    let approve = MultisigUpdate::Approve { account: account.clone(), proposal_id: 0 };
+    assert_eq!(chain.update(&bob, approve)?, ReceiptStatus::Applied);
+
+    let multisig = chain.state_manager.get_multisig_account(&account)?.expect("account");
+    assert_eq!(multisig.members, vec![alice.did(), carol.did()]);
+    assert_eq!(multisig.threshold, 1);
+    assert_eq!(multisig.proposal_count, 1);
+
+    // Bob was rotated out.
+    let bob_out = MultisigUpdate::Propose { account: account.clone(), proposal: MultisigAction::PlaceBid(sample_bid(100)) };
+    assert_eq!(rejected_with(chain.update(&bob, bob_out)?), Some(ErrorCode::Unauthorized));
+    Ok(())
+}
+
+#[test]
+fn failed_actions_close_the_proposal() -> Result<()> {
+    let mut chain = multisig_chain()?;
+    let (alice, bob) = (LocalWallet::new(), LocalWallet::new());
+    let account = chain.create(&alice, &[&alice, &bob], 1)?;
+
+    let withdraw = MultisigAction::Withdraw(Withdraw { amount: 400, recipient: "keeta:recipient".to_string() });
+    let propose = MultisigUpdate::Propose { account: account.clone(), proposal: withdraw.clone() };
+    assert_eq!(rejected_with(chain.update(&bob, propose)?), Some(ErrorCode::InsufficientFunds));
+    let proposal = chain.state_manager.get_multisig_proposal(&account, 0)?.expect("proposal");
+    assert!(matches!(proposal.status, ProposalStatus::Failed { code: ErrorCode::InsufficientFunds, .. }));
+
+    // A failed proposal stays closed; a new one runs once the account is funded.
+    chain.state_manager.set_balance(&account, 1_000)?;
+    let approve = MultisigUpdate::Approve { account: account.clone(), proposal_id: 0 };
+    assert_eq!(rejected_with(chain.update(&alice, approve)?), Some(ErrorCode::ProposalNotOpen));
+    let propose = MultisigUpdate::Propose { account: account.clone(), proposal: withdraw };
+    assert_eq!(chain.update(&bob, propose)?, ReceiptStatus::Applied);
+    assert_eq!(chain.state_manager.get_balance(&account)?, 600);
+    Ok(())
+}
+
+#[test]
+fn creation_checks_members_and_threshold() -> Result<()> {
+    let mut chain = multisig_chain()?;
+    let alice = LocalWallet::new();
+    for (members, threshold) in [
+        (vec![alice.did()], 0),
+        (vec![alice.did()], 2),
+        (vec![], 1),
+        (vec![alice.did(), "not-a-did".to_string()], 1),
+    ] {
+        let create = MultisigUpdate::Create { members, threshold };
+        assert_eq!(rejected_with(chain.update(&alice, create)?), Some(ErrorCode::InvalidRequest));
+    }
+    Ok(())
+}
@@ -0,0 +1,189 @@
+mod common;
+
+use anyhow::Result;
+use common::{sample_bid, temp_state};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::sync::{broadcast, mpsc};
+use unwalled_node::consensus::{self, BlockProducer, ConsensusApp, ReceiptWaiters, Transaction, ValidatorSet};
+use unwalled_node::http;
+use unwalled_node::identity::{LocalWallet, Signer};
+use unwalled_node::mempool::Mempool;
+use unwalled_node::network;
+use unwalled_node::openrtb::{AttributeRegistry, BidRequest, BidResponse};
+use unwalled_node::primitives::{bid_id, AuctionOutcome, AuctionResult, AuctionTrigger, Bid, IdentityTier, Signed, DEFAULT_CHAIN_ID};
+use unwalled_node::rpc::RpcHandler;
+use unwalled_node::state::StateManager;
+use uuid::Uuid;
+
+/// Runs a single-validator node the way `main` wires it: RPC submissions go
+/// through the mempool into blocks the consensus engine proposes and
+/// commits, and committed blocks are executed and answer waiting triggers.
+async fn start_node() -> Result<(TempDir, SocketAddr, StateManager, ValidatorSet)> {
+    let (dir, state_manager) = temp_state()?;
+    let validator = LocalWallet::new();
+    let validator_set = ValidatorSet::new(DEFAULT_CHAIN_ID, vec![validator.public_key()]);
+    let mempool = Arc::new(Mutex::new(Mempool::new()));
+    let (events, _) = broadcast::channel(16);
+    let receipt_waiters = ReceiptWaiters::default();
+    let mut app = ConsensusApp::new(state_manager.clone()).with_receipt_waiters(receipt_waiters.clone());
+
+    let (tx_outbound, _rx_outbound) = mpsc::unbounded_channel();
+    let (consensus_network, _inbox) = network::consensus_network(validator.signing_key().clone(), tx_outbound);
+    let (tx_commits, mut rx_commits) = mpsc::unbounded_channel();
+    let replica = consensus::start_replica(
+        DEFAULT_CHAIN_ID,
+        &state_manager,
+        &validator_set,
+        &validator,
+        BlockProducer::new(mempool.clone(), state_manager.clone()),
+        consensus_network,
+        tx_commits,
+    )?;
+
+    let (tx_to_consensus, mut rx_from_components) = mpsc::channel::<Transaction>(16);
+    let node_state = state_manager.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                Some(transaction) = rx_from_components.recv() => {
+                    let mut mempool = mempool.lock().unwrap();
+                    mempool.admit(transaction, &node_state).expect("admitted");
+                },
+                Some(()) = rx_commits.recv() => {
+                    consensus::apply_committed_blocks(&replica, &mut app, &node_state, &mempool).expect("applied");
+                },
+            }
+        }
+    });
+
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let handler = RpcHandler::new(tx_to_consensus, state_manager.clone(), events, receipt_waiters);
+    tokio::spawn(http::run_server(addr, handler));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    Ok((dir, addr, state_manager, validator_set))
+}
+
+/// Places a bid over RPC and waits for its block to commit, returning its
+/// on-chain id.
+async fn place_bid(addr: SocketAddr, state_manager: &StateManager, advertiser: &LocalWallet, bid: Bid) -> Result<Uuid> {
+    let response = reqwest::Client::new()
+        .post(format!("http://{}/rpc/place_bid", addr))
+        .json(&Signed::sign(bid.clone(), 0, 10, advertiser)?)
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+    tokio::time::timeout(Duration::from_secs(10), async {
+        let id = bid_id(&advertiser.address(), &bid.id);
+        while state_manager.get_bid(&id)?.is_none() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        anyhow::Ok(id)
+    })
+    .await?
+}
+
+#[tokio::test]
+async fn trigger_waits_for_its_block_to_commit() -> Result<()> {
+    let (_dir, addr, state_manager, validator_set) = start_node().await?;
+    let (advertiser, publisher) = (LocalWallet::new(), LocalWallet::new());
+    state_manager.set_balance(&advertiser.address(), 1_000)?;
+    state_manager.set_balance(&publisher.address(), 100)?;
+    let bid = Bid {
+        id: Uuid::new_v4(),
+        price: 150,
+        targeting: vec!["sports".to_string()],
+        adm: "<div>ad</div>".to_string(),
+        expires_at_height: None,
+        requires_verified_targeting: false,
+        min_user_tier: IdentityTier::Anonymous,
+    };
+    let id = place_bid(addr, &state_manager, &advertiser, bid).await?;
+
+    let trigger = AuctionTrigger {
+        id: Uuid::new_v4(),
+        bid_floor: 100,
+        attributes: vec!["sports".to_string()],
+        presentation: None,
+        min_advertiser_tier: IdentityTier::Anonymous,
+    };
+    let result: AuctionResult = reqwest::Client::new()
+        .post(format!("http://{}/rpc/trigger_auction?wait_ms=2000", addr))
+        .json(&Signed::sign(trigger, 0, 10, &publisher)?)
+        .send()
+        .await?
+        .json()
+        .await?;
+    match result.outcome {
+        AuctionOutcome::Matched { auction_match } => assert_eq!(auction_match.bid_id, id),
+        other => panic!("Unexpected outcome {:?}", other),
+    }
+    assert_eq!(state_manager.get_balance(&publisher.address())?, 100 - 10 + 150);
+
+    // Every executed block carries a certificate that proves it committed.
+    for height in 1..=state_manager.latest_height()? {
+        validator_set.verify(&state_manager.get_block(height)?.expect("committed block"))?;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn openrtb_request_is_answered_from_a_committed_block() -> Result<()> {
+    let (_dir, addr, state_manager, _) = start_node().await?;
+    let (advertiser, publisher) = (LocalWallet::new(), LocalWallet::new());
+    state_manager.set_balance(&advertiser.address(), 10_000)?;
+    state_manager.set_balance(&publisher.address(), 100)?;
+    let bid = Bid {
+        id: Uuid::new_v4(),
+        price: 2_500,
+        targeting: vec!["geo=usa".to_string()],
+        adm: "<div>ad</div>".to_string(),
+        expires_at_height: None,
+        requires_verified_targeting: false,
+        min_user_tier: IdentityTier::Anonymous,
+    };
+    place_bid(addr, &state_manager, &advertiser, bid).await?;
+
+    let mut request: BidRequest = serde_json::from_value(serde_json::json!({
+        "id": "req-1",
+        "imp": [{ "id": "1", "banner": { "w": 300, "h": 250 }, "bidfloor": 1.5, "bidfloorcur": "USD" }],
+        "device": { "geo": { "country": "USA" } },
+        "tmax": 2000
+    }))?;
+    AttributeRegistry::default().sign_request(&mut request, 0, 10, &publisher)?;
+    let response = reqwest::Client::new().post(format!("http://{}/rpc/openrtb/bid", addr)).json(&request).send().await?;
+    assert_eq!(response.status(), 200);
+    let response: BidResponse = response.json().await?;
+    assert_eq!(response.seatbid[0].bid[0].adm, "<div>ad</div>");
+    Ok(())
+}
+
+#[tokio::test]
+async fn transactions_ahead_of_their_nonce_wait_for_the_gap() -> Result<()> {
+    let (_dir, addr, state_manager, _) = start_node().await?;
+    let advertiser = LocalWallet::new();
+    state_manager.set_balance(&advertiser.address(), 1_000)?;
+    let (first, second) = (sample_bid(150), sample_bid(150));
+
+    // Nonce 1 arrives first and is held while blocks commit without it.
+    let client = reqwest::Client::new();
+    let response = client.post(format!("http://{}/rpc/place_bid", addr)).json(&Signed::sign(second.clone(), 1, 10, &advertiser)?).send().await?;
+    assert_eq!(response.status(), 200);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(state_manager.get_nonce(&advertiser.address())?, 0);
+
+    let response = client.post(format!("http://{}/rpc/place_bid", addr)).json(&Signed::sign(first.clone(), 0, 10, &advertiser)?).send().await?;
+    assert_eq!(response.status(), 200);
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while state_manager.get_nonce(&advertiser.address())? < 2 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        anyhow::Ok(())
+    })
+    .await??;
+    assert!(state_manager.get_bid(&bid_id(&advertiser.address(), &first.id))?.is_some());
+    assert!(state_manager.get_bid(&bid_id(&advertiser.address(), &second.id))?.is_some());
+    Ok(())
+}
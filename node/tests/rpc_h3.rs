@@ -0,0 +1,194 @@
+mod common;
+
+use anyhow::Result;
+use common::temp_state;
+use quiche::h3::NameValue;
+use ring::rand::SecureRandom;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, mpsc};
+use unwalled_node::consensus::{ReceiptWaiters, Transaction};
+use unwalled_node::error::ErrorCode;
+use unwalled_node::identity::{LocalWallet, Signer};
+use unwalled_node::primitives::{Bid, ErrorResponse, IdentityTier, Signed, SubmitResponse};
+use unwalled_node::rpc::{self, RpcHandler, TlsConfig};
+use unwalled_node::state::StateManager;
+use uuid::Uuid;
+
+const MAX_DATAGRAM_SIZE: usize = 1350;
+
+/// Starts an RPC server on a free localhost port.
+async fn start_server() -> Result<(TempDir, SocketAddr, mpsc::Receiver<Transaction>, StateManager)> {
+    let addr = std::net::UdpSocket::bind("127.0.0.1:0")?.local_addr()?;
+    let (dir, state_manager) = temp_state()?;
+    let tls = TlsConfig::self_signed(dir.path().join("tls"))?;
+    let (tx, rx) = mpsc::channel(16);
+    let handler = RpcHandler::new(tx, state_manager.clone(), broadcast::channel(16).0, ReceiptWaiters::default());
+    tokio::spawn(rpc::run_server(addr, tls, handler));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    Ok((dir, addr, rx, state_manager))
+}
+
+/// Sends one HTTP/3 POST request and returns the response status and body.
+async fn h3_post(server: SocketAddr, path: &str, body: &[u8]) -> Result<(u16, Vec<u8>)> {
+    let socket = UdpSocket::bind("127.0.0.1:0").await?;
+    let local = socket.local_addr()?;
+
+    let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    config.verify_peer(false);
+    config.set_application_protos(quiche::h3::APPLICATION_PROTOCOL)?;
+    config.set_max_idle_timeout(5000);
+    config.set_max_recv_udp_payload_size(MAX_DATAGRAM_SIZE);
+    config.set_max_send_udp_payload_size(MAX_DATAGRAM_SIZE);
+    config.set_initial_max_data(10_000_000);
+    config.set_initial_max_stream_data_bidi_local(1_000_000);
+    config.set_initial_max_stream_data_bidi_remote(1_000_000);
+    config.set_initial_max_stream_data_uni(1_000_000);
+    config.set_initial_max_streams_bidi(100);
+    config.set_initial_max_streams_uni(100);
+
+    let mut scid = [0; quiche::MAX_CONN_ID_LEN];
+    ring::rand::SystemRandom::new()
+        .fill(&mut scid)
+        .map_err(|_| anyhow::anyhow!("Failed to generate connection id"))?;
+    let scid = quiche::ConnectionId::from_ref(&scid);
+    let mut conn = quiche::connect(Some("localhost"), &scid, local, server, &mut config)?;
+
+    let h3_config = quiche::h3::Config::new()?;
+    let mut h3_conn: Option<quiche::h3::Connection> = None;
+    let mut request_sent = false;
+    let mut status = None;
+    let mut response_body = Vec::new();
+    let mut buf = [0; 65535];
+    let mut out = [0; MAX_DATAGRAM_SIZE];
+    let deadline = Instant::now() + Duration::from_secs(5);
+
+    loop {
+        loop {
+            match conn.send(&mut out) {
+                Ok((write, send_info)) => {
+                    socket.send_to(&out[..write], send_info.to).await?;
+                }
+                Err(quiche::Error::Done) => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        if Instant::now() > deadline || conn.is_closed() {
+            return Err(anyhow::anyhow!("No response from RPC server"));
+        }
+
+        let timeout = conn.timeout().unwrap_or(Duration::from_millis(100));
+        match tokio::time::timeout(timeout, socket.recv_from(&mut buf)).await {
+            Ok(recv) => {
+                let (len, from) = recv?;
+                conn.recv(&mut buf[..len], quiche::RecvInfo { from, to: local })?;
+            }
+            Err(_) => conn.on_timeout(),
+        }
+
+        if conn.is_established() && h3_conn.is_none() {
+            h3_conn = Some(quiche::h3::Connection::with_transport(&mut conn, &h3_config)?);
+        }
+        let Some(h3) = h3_conn.as_mut() else {
+            continue;
+        };
+
+        if !request_sent {
+            let headers = [
+                quiche::h3::Header::new(b":method", b"POST"),
+                quiche::h3::Header::new(b":scheme", b"https"),
+                quiche::h3::Header::new(b":authority", b"localhost"),
+                quiche::h3::Header::new(b":path", path.as_bytes()),
+                quiche::h3::Header::new(b"content-type", b"application/json"),
+            ];
+            let stream_id = h3.send_request(&mut conn, &headers, false)?;
+            h3.send_body(&mut conn, stream_id, body, true)?;
+            request_sent = true;
+        }
+
+        loop {
+            match h3.poll(&mut conn) {
+                Ok((_, quiche::h3::Event::Headers { list, .. })) => {
+                    status = list
+                        .iter()
+                        .find(|header| header.name() == b":status")
+                        .and_then(|header| std::str::from_utf8(header.value()).ok()?.parse().ok());
+                }
+                Ok((stream_id, quiche::h3::Event::Data)) => {
+                    while let Ok(read) = h3.recv_body(&mut conn, stream_id, &mut buf) {
+                        response_body.extend_from_slice(&buf[..read]);
+                    }
+                }
+                Ok((_, quiche::h3::Event::Finished)) => {
+                    conn.close(true, 0x100, b"").ok();
+                    let status = status.ok_or_else(|| anyhow::anyhow!("Response had no status"))?;
+                    return Ok((status, response_body));
+                }
+                Ok(_) => {}
+                Err(quiche::h3::Error::Done) => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+fn sample_bid() -> Bid {
+    Bid {
+        id: Uuid::new_v4(),
+        price: 150,
+        targeting: vec!["sports".to_string()],
+        adm: "<VAST version='4.2'>...</VAST>".to_string(),
+        expires_at_height: None,
+        requires_verified_targeting: false,
+        min_user_tier: IdentityTier::Anonymous,
+    }
+}
+
+#[tokio::test]
+async fn place_bid_is_forwarded_to_consensus() -> Result<()> {
+    let (_dir, addr, mut rx, state_manager) = start_server().await?;
+    let wallet = LocalWallet::new();
+    state_manager.set_balance(&wallet.address(), 100)?;
+    let signed_bid = Signed::sign(sample_bid(), 1, 10, &wallet)?;
+
+    let (status, body) = h3_post(addr, "/rpc/place_bid", &serde_json::to_vec(&signed_bid)?).await?;
+    assert_eq!(status, 200);
+    let response: SubmitResponse = serde_json::from_slice(&body)?;
+
+    let transaction = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+        .await?
+        .expect("transaction forwarded");
+    assert_eq!(response.tx_hash, hex::encode(transaction.hash()?));
+    match transaction {
+        Transaction::PlaceBid(forwarded) => assert_eq!(forwarded.data.id, signed_bid.data.id),
+        other => panic!("Unexpected transaction {:?}", other),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn invalid_signature_is_rejected() -> Result<()> {
+    let (_dir, addr, mut rx, _) = start_server().await?;
+    let wallet = LocalWallet::new();
+    let mut signed_bid = Signed::sign(sample_bid(), 1, 10, &wallet)?;
+    signed_bid.data.price += 1;
+
+    let (status, body) = h3_post(addr, "/rpc/place_bid", &serde_json::to_vec(&signed_bid)?).await?;
+    assert_eq!(status, 400);
+    let response: ErrorResponse = serde_json::from_slice(&body)?;
+    assert_eq!(response.error, "Invalid signature");
+    assert_eq!(response.code, ErrorCode::InvalidSignature);
+    assert!(rx.try_recv().is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn unknown_path_is_not_found() -> Result<()> {
+    let (_dir, addr, _rx, _) = start_server().await?;
+    let (status, _) = h3_post(addr, "/rpc/unknown", b"{}").await?;
+    assert_eq!(status, 404);
+    Ok(())
+}
@@ -0,0 +1,459 @@
+mod common;
+
+use anyhow::Result;
+use common::temp_state;
+use futures_util::{SinkExt, StreamExt};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::sync::{broadcast, mpsc};
+use unwalled_node::consensus::{ConsensusApp, ReceiptWaiters, Transaction};
+use unwalled_node::error::ErrorCode;
+use unwalled_node::http;
+use unwalled_node::identity::LocalWallet;
+use unwalled_node::identity::Signer;
+use unwalled_node::primitives::{
+    AccountInfo, AuctionOutcome, AuctionResult, AuctionTrigger, Bid, BidInfo, BidStatus, ChainEvent, ChainHead, Deposit, ErrorResponse, EventType,
+    IdentityTier, Match, Receipt, ReceiptStatus, Signed, SubmitResponse, SubscriptionFilter, Withdraw, DEFAULT_CHAIN_ID,
+};
+use tokio_tungstenite::tungstenite::Message;
+use unwalled_node::openrtb::{AttributeRegistry, BidRequest, BidResponse};
+use unwalled_node::prebid::{PrebidRequest, PrebidResponse};
+use unwalled_node::rpc::RpcHandler;
+use unwalled_node::settlement::{DepositProof, SettlementAttesters};
+use unwalled_node::state::StateManager;
+use uuid::Uuid;
+
+/// Starts the TCP fallback server on a free localhost port.
+async fn start_server(
+) -> Result<(TempDir, SocketAddr, mpsc::Receiver<Transaction>, StateManager, broadcast::Sender<ChainEvent>)> {
+    let (dir, addr, rx, state_manager, events, _) = start_server_with_waiters().await?;
+    Ok((dir, addr, rx, state_manager, events))
+}
+
+async fn start_server_with_waiters() -> Result<(
+    TempDir,
+    SocketAddr,
+    mpsc::Receiver<Transaction>,
+    StateManager,
+    broadcast::Sender<ChainEvent>,
+    ReceiptWaiters,
+)> {
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let (dir, state_manager) = temp_state()?;
+    let (tx, rx) = mpsc::channel(16);
+    let (events, _) = broadcast::channel(16);
+    let receipt_waiters = ReceiptWaiters::default();
+    let handler = RpcHandler::new(tx, state_manager.clone(), events.clone(), receipt_waiters.clone());
+    tokio::spawn(http::run_server(addr, handler));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    Ok((dir, addr, rx, state_manager, events, receipt_waiters))
+}
+
+/// Stands in for consensus by delivering each forwarded transaction immediately.
+fn spawn_app(mut rx: mpsc::Receiver<Transaction>, state_manager: StateManager, receipt_waiters: ReceiptWaiters) {
+    let mut app = ConsensusApp::new(state_manager)
+        .with_receipt_waiters(receipt_waiters)
+        .with_settlement(
+            SettlementAttesters::new(DEFAULT_CHAIN_ID, vec![LocalWallet::new().public_key()], 1).expect("attesters"),
+        );
+    tokio::spawn(async move {
+        while let Some(transaction) = rx.recv().await {
+            app.deliver(transaction);
+        }
+    });
+}
+
+fn sample_trigger() -> AuctionTrigger {
+    AuctionTrigger {
+        id: Uuid::new_v4(),
+        bid_floor: 100,
+        attributes: vec!["sports".to_string()],
+        presentation: None,
+        min_advertiser_tier: IdentityTier::Anonymous,
+    }
+}
+
+#[tokio::test]
+async fn http1_and_http2_share_routes() -> Result<()> {
+    let (_dir, addr, mut rx, state_manager, _) = start_server().await?;
+    let wallet = LocalWallet::new();
+    state_manager.set_balance(&wallet.address(), 100)?;
+
+    let clients = [
+        reqwest::Client::builder().http1_only().build()?,
+        reqwest::Client::builder().http2_prior_knowledge().build()?,
+    ];
+    for client in clients {
+        let signed_auction = Signed::sign(sample_trigger(), 1, 10, &wallet)?;
+        let response = client
+            .post(format!("http://{}/rpc/trigger_auction", addr))
+            .json(&signed_auction)
+            .send()
+            .await?;
+        assert_eq!(response.status(), 200);
+        let body: SubmitResponse = response.json().await?;
+
+        let transaction = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+            .await?
+            .expect("transaction forwarded");
+        assert_eq!(body.tx_hash, hex::encode(transaction.hash()?));
+
+        let response = client.get(format!("http://{}/rpc/place_bid", addr)).send().await?;
+        assert_eq!(response.status(), 405);
+        let body: ErrorResponse = response.json().await?;
+        assert_eq!(body.error, "Method not allowed");
+        assert_eq!(u16::from(body.code), 1003);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn queries_read_committed_state() -> Result<()> {
+    let (_dir, addr, _rx, state_manager, _) = start_server().await?;
+    let advertiser = LocalWallet::new().address();
+    let bid = Bid {
+        id: Uuid::new_v4(),
+        price: 150,
+        targeting: vec!["sports".to_string()],
+        adm: "<VAST version='4.2'>...</VAST>".to_string(),
+        expires_at_height: None,
+        requires_verified_targeting: false,
+        min_user_tier: IdentityTier::Anonymous,
+    };
+    state_manager.set_balance(&advertiser, 1_000)?;
+    let bid_id = state_manager.place_bid(&bid, &advertiser, 0)?;
+    state_manager.increment_nonce(&advertiser)?;
+
+    let client = reqwest::Client::new();
+    let account: AccountInfo = client
+        .get(format!("http://{}/rpc/account/{}", addr, advertiser))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!((account.balance, account.nonce), (1_000, 1));
+
+    let info: BidInfo = client.get(format!("http://{}/rpc/bid/{}", addr, bid_id)).send().await?.json().await?;
+    assert_eq!(info.advertiser, advertiser);
+    assert_eq!(info.status, BidStatus::Open);
+
+    let bids: Vec<BidInfo> = client
+        .get(format!("http://{}/rpc/advertiser/{}/bids", addr, advertiser))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(bids.len(), 1);
+
+    let head: ChainHead = client.get(format!("http://{}/rpc/head", addr)).send().await?.json().await?;
+    assert_eq!(head.height, 0);
+
+    let response = client.get(format!("http://{}/rpc/bid/{}", addr, Uuid::new_v4())).send().await?;
+    assert_eq!(response.status(), 404);
+    Ok(())
+}
+
+#[tokio::test]
+async fn subscription_pushes_filtered_events() -> Result<()> {
+    let (_dir, addr, _rx, _, events) = start_server().await?;
+    let advertiser = LocalWallet::new().address();
+
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{}/rpc/subscribe", addr)).await?;
+    let filter = SubscriptionFilter {
+        event_types: vec![EventType::Matched],
+        addresses: vec![advertiser.clone()],
+        ..Default::default()
+    };
+    ws.send(Message::Text(serde_json::to_string(&filter)?)).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let auction_match = |advertiser_addr: String| Match {
+        bid_id: Uuid::new_v4(),
+        auction_id: Uuid::new_v4(),
+        winning_price: 150,
+        advertiser_addr,
+        publisher_addr: "publisher".to_string(),
+    };
+    events.send(ChainEvent::BlockCommitted { height: 1, hash: String::new() })?;
+    events.send(ChainEvent::Matched { tx_hash: "other".to_string(), auction_match: auction_match("someone-else".to_string()) })?;
+    events.send(ChainEvent::Matched { tx_hash: "ours".to_string(), auction_match: auction_match(advertiser) })?;
+
+    let message = tokio::time::timeout(Duration::from_secs(1), ws.next())
+        .await?
+        .expect("event pushed")?;
+    match serde_json::from_str::<ChainEvent>(message.to_text()?)? {
+        ChainEvent::Matched { tx_hash, .. } => assert_eq!(tx_hash, "ours"),
+        other => panic!("Unexpected event {:?}", other),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn trigger_waits_for_match() -> Result<()> {
+    let (_dir, addr, rx, state_manager, _, receipt_waiters) = start_server_with_waiters().await?;
+    let advertiser = LocalWallet::new().address();
+    let bid = Bid {
+        id: Uuid::new_v4(),
+        price: 150,
+        targeting: vec!["sports".to_string()],
+        adm: "<VAST version='4.2'>...</VAST>".to_string(),
+        expires_at_height: None,
+        requires_verified_targeting: false,
+        min_user_tier: IdentityTier::Anonymous,
+    };
+    state_manager.set_balance(&advertiser, 1_000)?;
+    let bid_id = state_manager.place_bid(&bid, &advertiser, 0)?;
+
+    spawn_app(rx, state_manager.clone(), receipt_waiters);
+
+    let client = reqwest::Client::new();
+    let wallet = LocalWallet::new();
+    state_manager.set_balance(&wallet.address(), 100)?;
+    let trigger = |attribute: &str| AuctionTrigger {
+        id: Uuid::new_v4(),
+        bid_floor: 100,
+        attributes: vec![attribute.to_string()],
+        presentation: None,
+        min_advertiser_tier: IdentityTier::Anonymous,
+    };
+
+    let result: AuctionResult = client
+        .post(format!("http://{}/rpc/trigger_auction?wait_ms=500", addr))
+        .json(&Signed::sign(trigger("sports"), 0, 10, &wallet)?)
+        .send()
+        .await?
+        .json()
+        .await?;
+    match result.outcome {
+        AuctionOutcome::Matched { auction_match } => assert_eq!(auction_match.bid_id, bid_id),
+        other => panic!("Unexpected outcome {:?}", other),
+    }
+
+    let result: AuctionResult = client
+        .post(format!("http://{}/rpc/trigger_auction?wait_ms=500", addr))
+        .json(&Signed::sign(trigger("news"), 1, 10, &wallet)?)
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert!(matches!(result.outcome, AuctionOutcome::NoFill));
+    Ok(())
+}
+
+#[tokio::test]
+async fn submissions_that_cannot_succeed_are_rejected() -> Result<()> {
+    let (_dir, addr, mut rx, state_manager, _) = start_server().await?;
+    let wallet = LocalWallet::new();
+    state_manager.set_balance(&wallet.address(), 5)?;
+    state_manager.increment_nonce(&wallet.address())?;
+    let client = reqwest::Client::new();
+
+    for (nonce, fee, code) in [(0, 1, ErrorCode::BadNonce), (1, 10, ErrorCode::InsufficientFunds)] {
+        let response = client
+            .post(format!("http://{}/rpc/trigger_auction", addr))
+            .json(&Signed::sign(sample_trigger(), nonce, fee, &wallet)?)
+            .send()
+            .await?;
+        let body: ErrorResponse = response.json().await?;
+        assert_eq!(body.code, code);
+    }
+    assert!(rx.try_recv().is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn trigger_times_out_when_not_committed() -> Result<()> {
+    let (_dir, addr, _rx, state_manager, _) = start_server().await?;
+    let wallet = LocalWallet::new();
+    state_manager.set_balance(&wallet.address(), 100)?;
+    let signed_auction = Signed::sign(sample_trigger(), 0, 10, &wallet)?;
+
+    let started = std::time::Instant::now();
+    let result: AuctionResult = reqwest::Client::new()
+        .post(format!("http://{}/rpc/trigger_auction?wait_ms=50", addr))
+        .json(&signed_auction)
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert!(matches!(result.outcome, AuctionOutcome::Timeout));
+    assert!(started.elapsed() < Duration::from_secs(1));
+    Ok(())
+}
+
+#[tokio::test]
+async fn openrtb_request_fills_from_the_book() -> Result<()> {
+    let (_dir, addr, rx, state_manager, _, receipt_waiters) = start_server_with_waiters().await?;
+    let advertiser = LocalWallet::new().address();
+    let bid = Bid {
+        id: Uuid::new_v4(),
+        price: 2_500,
+        targeting: vec!["geo=usa".to_string(), "interest=sports".to_string()],
+        adm: "<div>ad</div>".to_string(),
+        expires_at_height: None,
+        requires_verified_targeting: false,
+        min_user_tier: IdentityTier::Anonymous,
+    };
+    state_manager.set_balance(&advertiser, 10_000)?;
+    state_manager.place_bid(&bid, &advertiser, 0)?;
+    let publisher = LocalWallet::new();
+    state_manager.set_balance(&publisher.address(), 100)?;
+    spawn_app(rx, state_manager, receipt_waiters);
+
+    let mut request: BidRequest = serde_json::from_value(serde_json::json!({
+        "id": "req-1",
+        "imp": [{ "id": "1", "banner": { "w": 300, "h": 250 }, "bidfloor": 1.5, "bidfloorcur": "USD" }],
+        "site": { "domain": "example.com", "cat": ["IAB17-12"] },
+        "device": { "devicetype": 4, "geo": { "country": "USA" } },
+        "tmax": 500
+    }))?;
+    let registry = AttributeRegistry::default();
+    let trigger = registry.to_trigger(&request)?;
+    assert_eq!(trigger.bid_floor, 1_500);
+    assert!(trigger.attributes.contains(&"interest=sports".to_string()));
+    registry.sign_request(&mut request, 0, 10, &publisher)?;
+
+    let client = reqwest::Client::new();
+    let response = client.post(format!("http://{}/rpc/openrtb/bid", addr)).json(&request).send().await?;
+    assert_eq!(response.status(), 200);
+    let response: BidResponse = response.json().await?;
+    assert_eq!(response.id, "req-1");
+    let winning = &response.seatbid[0].bid[0];
+    assert_eq!((winning.impid.as_str(), winning.adm.as_str(), winning.price), ("1", "<div>ad</div>", 2.5));
+
+    // Without the publisher's signature the request cannot be submitted.
+    request.ext = None;
+    let response = client.post(format!("http://{}/rpc/openrtb/bid", addr)).json(&request).send().await?;
+    assert_eq!(response.status(), 400);
+    Ok(())
+}
+
+#[tokio::test]
+async fn openrtb_request_bids_per_filled_imp() -> Result<()> {
+    let (_dir, addr, rx, state_manager, _, receipt_waiters) = start_server_with_waiters().await?;
+    let advertiser = LocalWallet::new().address();
+    let bid = Bid {
+        id: Uuid::new_v4(),
+        price: 2_000,
+        targeting: vec!["size=728x90".to_string()],
+        adm: "<div>leaderboard</div>".to_string(),
+        expires_at_height: None,
+        requires_verified_targeting: false,
+        min_user_tier: IdentityTier::Anonymous,
+    };
+    state_manager.set_balance(&advertiser, 10_000)?;
+    state_manager.place_bid(&bid, &advertiser, 0)?;
+    let publisher = LocalWallet::new();
+    state_manager.set_balance(&publisher.address(), 100)?;
+    spawn_app(rx, state_manager, receipt_waiters);
+
+    let mut request: BidRequest = serde_json::from_value(serde_json::json!({
+        "id": "req-2",
+        "imp": [
+            { "id": "top", "banner": { "w": 728, "h": 90 }, "bidfloor": 1.0 },
+            { "id": "side", "banner": { "w": 300, "h": 600 }, "bidfloor": 1.0 }
+        ],
+        "tmax": 500
+    }))?;
+    let registry = AttributeRegistry::default();
+    registry.sign_request(&mut request, 0, 10, &publisher)?;
+    assert!(request.ext.is_none());
+    let triggers = registry.to_signed_triggers(&request)?;
+    assert_eq!(triggers.iter().map(|trigger| trigger.nonce).collect::<Vec<_>>(), vec![0, 1]);
+    assert_ne!(triggers[0].data.id, triggers[1].data.id);
+
+    let response = reqwest::Client::new().post(format!("http://{}/rpc/openrtb/bid", addr)).json(&request).send().await?;
+    assert_eq!(response.status(), 200);
+    let response: BidResponse = response.json().await?;
+    assert_eq!(response.seatbid.len(), 1);
+    assert_eq!(response.seatbid[0].seat, advertiser);
+    let bids = &response.seatbid[0].bid;
+    assert_eq!(bids.len(), 1);
+    assert_eq!((bids[0].impid.as_str(), bids[0].adm.as_str(), bids[0].price), ("top", "<div>leaderboard</div>", 2.0));
+    Ok(())
+}
+
+#[tokio::test]
+async fn prebid_request_returns_bid_per_filled_unit() -> Result<()> {
+    let (_dir, addr, rx, state_manager, _, receipt_waiters) = start_server_with_waiters().await?;
+    let advertiser = LocalWallet::new().address();
+    let bid = Bid {
+        id: Uuid::new_v4(),
+        price: 3_000,
+        targeting: vec!["format=banner".to_string(), "size=728x90".to_string()],
+        adm: "<div>leaderboard</div>".to_string(),
+        expires_at_height: None,
+        requires_verified_targeting: false,
+        min_user_tier: IdentityTier::Anonymous,
+    };
+    state_manager.set_balance(&advertiser, 10_000)?;
+    state_manager.place_bid(&bid, &advertiser, 0)?;
+    let publisher = LocalWallet::new();
+    state_manager.set_balance(&publisher.address(), 100)?;
+    spawn_app(rx, state_manager, receipt_waiters);
+
+    let mut request: PrebidRequest = serde_json::from_value(serde_json::json!({
+        "bids": [
+            { "bidId": "b1", "adUnitCode": "top", "mediaTypes": { "banner": { "sizes": [[300, 250], [728, 90]] } }, "params": { "floor": 1.0 } },
+            { "bidId": "b2", "adUnitCode": "pre-roll", "mediaTypes": { "video": { "playerSize": [[640, 480]] } }, "params": { "floor": 1.0 } }
+        ],
+        "ortb2": { "site": { "domain": "example.com" } },
+        "timeout": 500
+    }))?;
+    request.sign(&AttributeRegistry::default(), 0, 10, &publisher)?;
+
+    let response: PrebidResponse = reqwest::Client::new()
+        .post(format!("http://{}/rpc/prebid/bid", addr))
+        .json(&request)
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(response.bids.len(), 1);
+    let winning = &response.bids[0];
+    assert_eq!((winning.request_id.as_str(), winning.cpm), ("b1", 3.0));
+    assert_eq!((winning.width, winning.height), (728, 90));
+    assert_eq!(winning.ad, "<div>leaderboard</div>");
+    Ok(())
+}
+
+#[tokio::test]
+async fn withdrawal_debits_and_unconfirmed_deposit_is_rejected() -> Result<()> {
+    let (_dir, addr, rx, state_manager, _, receipt_waiters) = start_server_with_waiters().await?;
+    let wallet = LocalWallet::new();
+    state_manager.set_balance(&wallet.address(), 1_000)?;
+    spawn_app(rx, state_manager.clone(), receipt_waiters);
+
+    let client = reqwest::Client::new();
+    let withdraw = Withdraw { amount: 400, recipient: "keeta:recipient".to_string() };
+    let submitted: SubmitResponse = client
+        .post(format!("http://{}/rpc/withdraw", addr))
+        .json(&Signed::sign(withdraw, 0, 10, &wallet)?)
+        .send()
+        .await?
+        .json()
+        .await?;
+    let proof = DepositProof { recipient: wallet.address(), attestations: Vec::new() };
+    let deposit = Deposit { amount: 400, settlement_ref: "keeta:tx".to_string(), account: None, proof };
+    let rejected: SubmitResponse = client
+        .post(format!("http://{}/rpc/deposit", addr))
+        .json(&Signed::sign(deposit, 1, 10, &wallet)?)
+        .send()
+        .await?
+        .json()
+        .await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let receipt = |tx_hash: &str| -> Result<Receipt> {
+        Ok(state_manager.get_receipt(&hex::decode(tx_hash)?.try_into().expect("32-byte hash"))?.expect("receipt"))
+    };
+    assert_eq!(receipt(&submitted.tx_hash)?.status, ReceiptStatus::Applied);
+    match receipt(&rejected.tx_hash)?.status {
+        ReceiptStatus::Rejected { code, .. } => assert_eq!(code, ErrorCode::DepositNotConfirmed),
+        other => panic!("Unexpected status {:?}", other),
+    }
+    // Both fees were paid, but only the withdrawal moved funds.
+    assert_eq!(state_manager.get_balance(&wallet.address())?, 580);
+    Ok(())
+}
@@ -0,0 +1,202 @@
+mod common;
+
+use anyhow::Result;
+use common::{open_auction, rejected_with, sample_bid, Chain, FEE};
+use unwalled_node::consensus::{CommitCertificate, Transaction};
+use unwalled_node::error::{classify, ErrorCode};
+use unwalled_node::identity::{LocalWallet, Signer};
+use unwalled_node::primitives::{bid_id, Bid, BidStatus, CancelBid, IdentityTier, ReceiptStatus, SessionKeyUpdate, Signed};
+
+impl Chain {
+    fn update_session(&mut self, signer: &LocalWallet, update: SessionKeyUpdate) -> Result<ReceiptStatus> {
+        let receipt = self.deliver(signer, |nonce| Ok(Transaction::UpdateSessionKey(Signed::sign(update, nonce, FEE, signer)?)))?;
+        Ok(receipt.status)
+    }
+
+    fn place_bid(&mut self, signer: &LocalWallet, bid: &Bid) -> Result<ReceiptStatus> {
+        let receipt = self.deliver(signer, |nonce| Ok(Transaction::PlaceBid(Signed::sign(bid.clone(), nonce, FEE, signer)?)))?;
+        Ok(receipt.status)
+    }
+}
+
+fn authorize(session: &LocalWallet, actions: &[&str], spend_limit: Option<u64>) -> SessionKeyUpdate {
+    SessionKeyUpdate::Authorize {
+        session_key: session.address(),
+        actions: actions.iter().map(|action| action.to_string()).collect(),
+        spend_limit,
+        expires_at_height: None,
+    }
+}
+
+#[test]
+fn session_keys_act_for_their_principal() -> Result<()> {
+    let mut chain = Chain::new()?;
+    let principal = LocalWallet::new();
+    let session = LocalWallet::new();
+    chain.state_manager.set_balance(&principal.address(), 1_000)?;
+    let status = chain.update_session(&principal, authorize(&session, &["PlaceBid", "CancelBid"], None))?;
+    assert_eq!(status, ReceiptStatus::Applied);
+
+    let bid = sample_bid(200);
+    assert_eq!(chain.place_bid(&session, &bid)?, ReceiptStatus::Applied);
+    // Stored under the principal's address, not the session's.
+    let bid_id = bid_id(&principal.address(), &bid.id);
+    let info = chain.state_manager.get_bid(&bid_id)?.expect("bid");
+    assert_eq!(info.advertiser, principal.address());
+    assert_eq!(info.session_key, Some(session.address()));
+
+    // The principal pays the fees; the session has its own nonce.
+    assert_eq!(chain.state_manager.get_balance(&principal.address())?, 980);
+    assert_eq!(chain.state_manager.get_nonce(&principal.address())?, 1);
+    assert_eq!(chain.state_manager.get_nonce(&session.address())?, 1);
+
+    let cancel = CancelBid { bid_id };
+    let status =
+        chain.deliver(&session, |nonce| Ok(Transaction::CancelBid(Signed::sign(cancel, nonce, FEE, &session)?)))?.status;
+    assert_eq!(status, ReceiptStatus::Applied);
+    Ok(())
+}
+
+#[test]
+fn session_keys_are_limited_to_their_scope() -> Result<()> {
+    let mut chain = Chain::new()?;
+    let principal = LocalWallet::new();
+    let session = LocalWallet::new();
+    chain.state_manager.set_balance(&principal.address(), 1_000)?;
+    chain.update_session(&principal, authorize(&session, &["PlaceBid"], None))?;
+
+    let trigger = open_auction();
+    let status = chain
+        .deliver(&session, |nonce| Ok(Transaction::TriggerAuction(Signed::sign(trigger, nonce, FEE, &session)?)))?
+        .status;
+    assert_eq!(rejected_with(status), Some(ErrorCode::Unauthorized));
+
+    // Sessions cannot hand out further sessions.
+    let status = chain.update_session(&session, authorize(&LocalWallet::new(), &["PlaceBid"], None))?;
+    assert_eq!(rejected_with(status), Some(ErrorCode::Unauthorized));
+
+    let status = chain.update_session(&principal, authorize(&LocalWallet::new(), &["UpdateIssuerRegistry"], None))?;
+    assert_eq!(rejected_with(status), Some(ErrorCode::InvalidRequest));
+
+    let expired = LocalWallet::new();
+    let update = SessionKeyUpdate::Authorize {
+        session_key: expired.address(),
+        actions: vec!["PlaceBid".to_string()],
+        spend_limit: None,
+        expires_at_height: Some(0),
+    };
+    chain.update_session(&principal, update)?;
+    assert_eq!(rejected_with(chain.place_bid(&expired, &sample_bid(200))?), Some(ErrorCode::Unauthorized));
+    Ok(())
+}
+
+#[test]
+fn spend_limits_cover_fees_and_winning_prices() -> Result<()> {
+    let mut chain = Chain::new()?;
+    let principal = LocalWallet::new();
+    let session = LocalWallet::new();
+    chain.state_manager.set_balance(&principal.address(), 1_000)?;
+    chain.update_session(&principal, authorize(&session, &["PlaceBid"], Some(220)))?;
+
+    let expensive = sample_bid(250);
+    let affordable = sample_bid(200);
+    assert_eq!(chain.place_bid(&session, &expensive)?, ReceiptStatus::Applied);
+    assert_eq!(chain.place_bid(&session, &affordable)?, ReceiptStatus::Applied);
+
+    // 20 spent on fees leaves 200: the 250 bid is out of reach.
+    let trigger = open_auction();
+    let publisher = LocalWallet::new().address();
+    let auction_match = chain
+        .state_manager
+        .find_match(&trigger, &[], IdentityTier::Anonymous, &publisher, 1)?
+        .expect("match");
+    assert_eq!(auction_match.bid_id, bid_id(&principal.address(), &affordable.id));
+    chain.state_manager.settle_match(&auction_match, 1)?;
+
+    let spent = chain.state_manager.get_session_key(&session.address())?.expect("session").spent;
+    assert_eq!(spent, 220);
+    assert_eq!(rejected_with(chain.place_bid(&session, &sample_bid(150))?), Some(ErrorCode::SpendLimitExceeded));
+    // A fee the session cannot cover does not use up its nonce.
+    assert_eq!(chain.state_manager.get_nonce(&session.address())?, 2);
+    Ok(())
+}
+
+#[test]
+fn revoked_sessions_lose_their_bids() -> Result<()> {
+    let mut chain = Chain::new()?;
+    let principal = LocalWallet::new();
+    let session = LocalWallet::new();
+    chain.state_manager.set_balance(&principal.address(), 1_000)?;
+    chain.update_session(&principal, authorize(&session, &["PlaceBid"], None))?;
+    let bid = sample_bid(200);
+    chain.place_bid(&session, &bid)?;
+    let own_bid = sample_bid(150);
+    chain.place_bid(&principal, &own_bid)?;
+
+    let revoke = SessionKeyUpdate::Revoke { session_key: session.address() };
+    let stranger = LocalWallet::new();
+    chain.state_manager.set_balance(&stranger.address(), 100)?;
+    assert_eq!(rejected_with(chain.update_session(&stranger, revoke.clone())?), Some(ErrorCode::Unauthorized));
+    assert_eq!(chain.update_session(&principal, revoke)?, ReceiptStatus::Applied);
+    assert!(chain.state_manager.get_session_key(&session.address())?.is_none());
+
+    let session_bid = bid_id(&principal.address(), &bid.id);
+    assert_eq!(chain.state_manager.get_bid(&session_bid)?.expect("bid").status, BidStatus::Cancelled);
+
+    // Without its grant the key signs only for its own, empty account.
+    assert_eq!(rejected_with(chain.place_bid(&session, &sample_bid(200))?), Some(ErrorCode::InsufficientFunds));
+
+    // Authorising the key again does not revive the bids it placed before,
+    // and the principal's own bids are untouched.
+    assert_eq!(chain.update_session(&principal, authorize(&session, &["PlaceBid"], None))?, ReceiptStatus::Applied);
+    let publisher = LocalWallet::new().address();
+    let auction_match = chain
+        .state_manager
+        .find_match(&open_auction(), &[], IdentityTier::Anonymous, &publisher, 1)?
+        .expect("match");
+    assert_eq!(auction_match.bid_id, bid_id(&principal.address(), &own_bid.id));
+    Ok(())
+}
+
+#[test]
+fn expired_sessions_bids_do_not_win() -> Result<()> {
+    let mut chain = Chain::new()?;
+    let principal = LocalWallet::new();
+    let session = LocalWallet::new();
+    let publisher = LocalWallet::new();
+    chain.state_manager.set_balance(&principal.address(), 1_000)?;
+    chain.state_manager.set_balance(&publisher.address(), 100)?;
+    let update = SessionKeyUpdate::Authorize {
+        session_key: session.address(),
+        actions: vec!["PlaceBid".to_string()],
+        spend_limit: None,
+        expires_at_height: Some(2),
+    };
+    chain.update_session(&principal, update)?;
+    let bid = sample_bid(200);
+    assert_eq!(chain.place_bid(&session, &bid)?, ReceiptStatus::Applied);
+    let session_bid = bid_id(&principal.address(), &bid.id);
+
+    // The bid still wins while the session is live...
+    let auction_match = chain
+        .state_manager
+        .find_match(&open_auction(), &[], IdentityTier::Anonymous, &publisher.address(), 1)?
+        .expect("match");
+    assert_eq!(auction_match.bid_id, session_bid);
+
+    // ...but not in a block at or past the session's expiry.
+    chain.app.commit_block(vec![], CommitCertificate::default())?;
+    let trigger = Transaction::TriggerAuction(Signed::sign(open_auction(), 0, FEE, &publisher)?);
+    let tx_hash = trigger.hash()?;
+    let committed = chain.app.commit_block(vec![trigger], CommitCertificate::default())?;
+    assert_eq!(committed.block.height, 2);
+    let receipt = chain.state_manager.get_receipt(&tx_hash)?.expect("receipt");
+    assert_eq!(receipt.status, ReceiptStatus::Applied);
+    assert!(receipt.auction_match.is_none());
+    assert_eq!(chain.state_manager.get_bid(&session_bid)?.expect("bid").status, BidStatus::Open);
+    assert_eq!(chain.state_manager.get_balance(&principal.address())?, 980);
+
+    let error = chain.state_manager.settle_match(&auction_match, 2).unwrap_err();
+    assert_eq!(classify(&error).code, ErrorCode::Unauthorized);
+    Ok(())
+}
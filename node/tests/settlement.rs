@@ -0,0 +1,223 @@
+mod common;
+
+use anyhow::Result;
+use common::{rejected_with, Chain, FEE};
+use unwalled_node::consensus::{Block, CommitCertificate, CommittedBlock, Transaction};
+use unwalled_node::error::{classify, ErrorCode};
+use unwalled_node::identity::{LocalWallet, Signer};
+use unwalled_node::primitives::{Deposit, ReceiptStatus, Signed, Withdraw, DEFAULT_CHAIN_ID};
+use unwalled_node::relayer::Relayer;
+use unwalled_node::settlement::{
+    attest_deposit, Attestation, DepositProof, Disconnected, Finality, MockSettlement, SettlementAttesters, SettlementLayer,
+    StateCommitment,
+};
+
+/// Three attesters, any two of which confirm a deposit.
+fn attesters() -> Result<(Vec<LocalWallet>, SettlementAttesters)> {
+    let wallets = vec![LocalWallet::new(), LocalWallet::new(), LocalWallet::new()];
+    let attesters = SettlementAttesters::new(DEFAULT_CHAIN_ID, wallets.iter().map(|wallet| wallet.public_key()).collect(), 2)?;
+    Ok((wallets, attesters))
+}
+
+fn claim(settlement_ref: &str, amount: u64, attestations: &[Attestation], nonce: u64, wallet: &LocalWallet) -> Result<Transaction> {
+    let proof = DepositProof { recipient: wallet.address(), attestations: attestations.to_vec() };
+    let deposit = Deposit { amount, settlement_ref: settlement_ref.to_string(), account: None, proof };
+    Ok(Transaction::Deposit(Signed::sign(deposit, nonce, FEE, wallet)?))
+}
+
+/// Applies `count` empty blocks on top of the current tip.
+fn apply_empty_blocks(chain: &mut Chain, count: u64) -> Result<()> {
+    let state_manager = chain.state_manager.clone();
+    for _ in 0..count {
+        let height = state_manager.latest_height()? + 1;
+        let parent_hash = match state_manager.get_block(height - 1)? {
+            Some(parent) => parent.block.hash()?,
+            None => [0u8; 32],
+        };
+        let block = Block { height, parent_hash, transactions: vec![], state_root: state_manager.state_root()? };
+        chain.app.apply_block(CommittedBlock { block, certificate: CommitCertificate::default() })?;
+    }
+    Ok(())
+}
+
+#[test]
+fn deposits_are_credited_with_enough_attestations() -> Result<()> {
+    let settlement = MockSettlement::new();
+    let (attester_wallets, attesters) = attesters()?;
+    let mut chain = Chain::with_app(|app| app.with_settlement(attesters))?;
+    let state_manager = chain.state_manager.clone();
+    let wallet = LocalWallet::new();
+
+    // Attesters only sign final deposits made out to the claimant.
+    let settlement_ref = settlement.lock_deposit(&wallet.address(), 500);
+    let attest = |attester: &LocalWallet, amount: u64| {
+        attest_deposit(&settlement, attester, DEFAULT_CHAIN_ID, &settlement_ref, &wallet.address(), amount)
+    };
+    assert_eq!(classify(&attest(&attester_wallets[0], 500).unwrap_err()).code, ErrorCode::DepositNotConfirmed);
+    settlement.finalize(&settlement_ref);
+    assert_eq!(classify(&attest(&attester_wallets[0], 600).unwrap_err()).code, ErrorCode::DepositNotConfirmed);
+    let first = attest(&attester_wallets[0], 500)?;
+    let second = attest(&attester_wallets[1], 500)?;
+
+    // One attestation, or the same one twice, is not enough.
+    for attestations in [vec![first.clone()], vec![first.clone(), first.clone()]] {
+        let status = chain.deliver_funded(&wallet, |nonce| claim(&settlement_ref, 500, &attestations, nonce, &wallet))?.status;
+        assert_eq!(rejected_with(status), Some(ErrorCode::DepositNotConfirmed));
+    }
+    // Nor are attestations to a different amount, or from outside the set.
+    let attestations = [first.clone(), second.clone()];
+    let status = chain.deliver_funded(&wallet, |nonce| claim(&settlement_ref, 600, &attestations, nonce, &wallet))?.status;
+    assert_eq!(rejected_with(status), Some(ErrorCode::DepositNotConfirmed));
+    let outsider = attest_deposit(&settlement, &LocalWallet::new(), DEFAULT_CHAIN_ID, &settlement_ref, &wallet.address(), 500)?;
+    let attestations = [first.clone(), outsider];
+    let status = chain.deliver_funded(&wallet, |nonce| claim(&settlement_ref, 500, &attestations, nonce, &wallet))?.status;
+    assert_eq!(rejected_with(status), Some(ErrorCode::DepositNotConfirmed));
+
+    let attestations = [first, second];
+    let status = chain.deliver_funded(&wallet, |nonce| claim(&settlement_ref, 500, &attestations, nonce, &wallet))?.status;
+    assert_eq!(status, ReceiptStatus::Applied);
+    assert_eq!(state_manager.get_balance(&wallet.address())?, 500);
+    let status = chain.deliver_funded(&wallet, |nonce| claim(&settlement_ref, 500, &attestations, nonce, &wallet))?.status;
+    assert_eq!(rejected_with(status), Some(ErrorCode::DepositAlreadyClaimed));
+
+    // Someone else's attested deposit cannot be claimed.
+    let other = LocalWallet::new();
+    let other_ref = settlement.deposit_final(&other.address(), 500);
+    let attestations = attester_wallets[..2]
+        .iter()
+        .map(|attester| attest_deposit(&settlement, attester, DEFAULT_CHAIN_ID, &other_ref, &other.address(), 500))
+        .collect::<Result<Vec<_>>>()?;
+    let status = chain.deliver_funded(&wallet, |nonce| {
+        let proof = DepositProof { recipient: other.address(), attestations };
+        let deposit = Deposit { amount: 500, settlement_ref: other_ref.clone(), account: None, proof };
+        Ok(Transaction::Deposit(Signed::sign(deposit, nonce, FEE, &wallet)?))
+    })?
+    .status;
+    assert_eq!(rejected_with(status), Some(ErrorCode::DepositNotConfirmed));
+    Ok(())
+}
+
+#[test]
+fn withdrawals_are_debited_then_paid_once_by_the_relayer() -> Result<()> {
+    let settlement = MockSettlement::new();
+    let attesters = attesters()?.1;
+    let mut chain = Chain::with_app(|app| app.with_settlement(attesters))?;
+    let state_manager = chain.state_manager.clone();
+    let relayer = Relayer::new(state_manager.clone(), settlement.clone(), DEFAULT_CHAIN_ID);
+    let wallet = LocalWallet::new();
+    state_manager.set_balance(&wallet.address(), 1_000)?;
+
+    apply_empty_blocks(&mut chain, 1)?;
+    let withdraw = Withdraw { amount: 400, recipient: "keeta:recipient".to_string() };
+    let transaction = Transaction::Withdraw(Signed::sign(withdraw, 0, FEE, &wallet)?);
+    let tx_hash = transaction.hash()?;
+    let height = chain.app.commit_block(vec![transaction], CommitCertificate::default())?.block.height;
+
+    // Executing the block debits the account and queues the payout, but
+    // pays nothing.
+    assert_eq!(state_manager.get_receipt(&tx_hash)?.expect("receipt").status, ReceiptStatus::Applied);
+    assert_eq!(state_manager.get_balance(&wallet.address())?, 590);
+    let pending = state_manager.withdrawals_at(height)?;
+    assert_eq!(pending.len(), 1);
+    assert_eq!((pending[0].id, pending[0].amount), (tx_hash, 400));
+    assert!(settlement.withdrawals().is_empty());
+
+    relayer.run()?;
+    let withdrawals = settlement.withdrawals();
+    assert_eq!(withdrawals.len(), 1);
+    assert_eq!(withdrawals[0].withdrawal_id, tx_hash);
+    assert_eq!(withdrawals[0].address, wallet.address());
+    assert_eq!(withdrawals[0].amount, 400);
+    assert_eq!(withdrawals[0].recipient, "keeta:recipient");
+
+    // Running again pays nothing more, and neither does another validator's
+    // relayer submitting the same withdrawal.
+    relayer.run()?;
+    state_manager.set_relayed_height(0)?;
+    relayer.run()?;
+    let repeat = settlement.withdraw(&tx_hash, &wallet.address(), 400, "keeta:recipient")?;
+    assert_eq!(repeat, withdrawals[0].settlement_ref);
+    assert_eq!(settlement.withdrawals().len(), 1);
+    Ok(())
+}
+
+#[test]
+fn state_commitments_are_anchored_by_the_relayer() -> Result<()> {
+    let settlement = MockSettlement::new();
+    let attesters = attesters()?.1;
+    let mut chain = Chain::with_app(|app| app.with_settlement(attesters))?;
+    let state_manager = chain.state_manager.clone();
+    let relayer = Relayer::new(state_manager.clone(), settlement.clone(), DEFAULT_CHAIN_ID).with_commitment_interval(2);
+
+    apply_empty_blocks(&mut chain, 4)?;
+    assert!(settlement.commitments().is_empty());
+    relayer.run()?;
+    relayer.run()?;
+
+    let commitments = settlement.commitments();
+    let heights: Vec<u64> = commitments.iter().map(|(_, commitment)| commitment.height).collect();
+    assert_eq!(heights, vec![2, 4]);
+    let (settlement_ref, last) = commitments.last().expect("commitment");
+    let block = state_manager.get_block(4)?.expect("block").block;
+    assert_eq!(
+        last,
+        &StateCommitment {
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
+            height: 4,
+            block_hash: block.hash()?,
+            state_root: block.state_root,
+        }
+    );
+    assert_eq!(settlement.finality(settlement_ref)?, Finality::Pending);
+    settlement.finalize(settlement_ref);
+    assert_eq!(settlement.finality(settlement_ref)?, Finality::Final);
+    Ok(())
+}
+
+#[test]
+fn relayer_retries_until_the_settlement_layer_accepts() -> Result<()> {
+    let attesters = attesters()?.1;
+    let mut chain = Chain::with_app(|app| app.with_settlement(attesters))?;
+    let state_manager = chain.state_manager.clone();
+    let wallet = LocalWallet::new();
+    state_manager.set_balance(&wallet.address(), 1_000)?;
+    // Delivered as part of block 1.
+    apply_empty_blocks(&mut chain, 1)?;
+    let withdraw = Withdraw { amount: 400, recipient: "keeta:recipient".to_string() };
+    let status = chain.deliver_funded(&wallet, |nonce| {
+        Ok(Transaction::Withdraw(Signed::sign(withdraw, nonce, FEE, &wallet)?))
+    })?
+    .status;
+    assert_eq!(status, ReceiptStatus::Applied);
+
+    // Nothing is marked relayed while the payout cannot be made.
+    let disconnected = Relayer::new(state_manager.clone(), Disconnected, DEFAULT_CHAIN_ID);
+    assert!(disconnected.run().is_err());
+    assert_eq!(state_manager.relayed_height()?, 0);
+
+    let settlement = MockSettlement::new();
+    Relayer::new(state_manager.clone(), settlement.clone(), DEFAULT_CHAIN_ID).run()?;
+    assert_eq!(settlement.withdrawals().len(), 1);
+    assert_eq!(state_manager.relayed_height()?, 1);
+    Ok(())
+}
+
+#[test]
+fn chains_without_settlement_refuse_deposits_and_withdrawals() -> Result<()> {
+    let mut chain = Chain::new()?;
+    let state_manager = chain.state_manager.clone();
+    let wallet = LocalWallet::new();
+    state_manager.set_balance(&wallet.address(), 1_000)?;
+
+    let status = chain.deliver_funded(&wallet, |nonce| claim("keeta:tx", 500, &[], nonce, &wallet))?.status;
+    assert_eq!(rejected_with(status), Some(ErrorCode::Unavailable));
+    let withdraw = Withdraw { amount: 400, recipient: "keeta:recipient".to_string() };
+    let status = chain.deliver_funded(&wallet, |nonce| {
+        Ok(Transaction::Withdraw(Signed::sign(withdraw, nonce, FEE, &wallet)?))
+    })?
+    .status;
+    assert_eq!(rejected_with(status), Some(ErrorCode::Unavailable));
+    // Nothing was charged, as the transactions were refused up front.
+    assert_eq!(state_manager.get_balance(&wallet.address())?, 1_020);
+    Ok(())
+}
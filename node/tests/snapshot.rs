@@ -0,0 +1,94 @@
+mod common;
+
+use anyhow::Result;
+use common::{temp_dir, temp_state};
+use tempfile::TempDir;
+use unwalled_node::consensus::{CommitCertificate, ConsensusApp};
+use unwalled_node::identity::{LocalWallet, Signer};
+use unwalled_node::snapshot::{self, SnapshotStore};
+use unwalled_node::state::StateManager;
+
+/// A chain with `accounts` funded accounts, committed in one block.
+fn funded_chain(accounts: usize) -> Result<(TempDir, StateManager)> {
+    let (dir, state_manager) = temp_state()?;
+    for index in 0..accounts {
+        state_manager.set_balance(&LocalWallet::new().address(), index as u64 + 1)?;
+    }
+    ConsensusApp::new(state_manager.clone()).commit_block(vec![], CommitCertificate::default())?;
+    Ok((dir, state_manager))
+}
+
+/// The manifest and raw chunks of the snapshot at `height`.
+fn read_snapshot(store: &SnapshotStore, height: u64) -> Result<(snapshot::SnapshotManifest, Vec<Vec<u8>>)> {
+    let manifest = store.manifest(height)?.expect("snapshot exists");
+    let chunks = (0..manifest.chunk_hashes.len())
+        .map(|index| Ok(store.chunk(height, index)?.expect("chunk exists")))
+        .collect::<Result<Vec<_>>>()?;
+    Ok((manifest, chunks))
+}
+
+#[test]
+fn snapshot_round_trips_through_restore() -> Result<()> {
+    let (_source_dir, source) = funded_chain(5_000)?;
+    let store_dir = temp_dir()?;
+    let store = SnapshotStore::new(store_dir.path());
+    let manifest = store.create(&source)?;
+    assert_eq!(manifest.height(), 1);
+    assert!(manifest.chunk_hashes.len() > 1);
+    for index in 0..manifest.chunk_hashes.len() {
+        assert!(store.chunk(1, index)?.expect("chunk exists").len() <= snapshot::SNAPSHOT_CHUNK_SIZE + 1024);
+    }
+
+    let (_target_dir, target) = temp_state()?;
+    store.restore(&target, 1)?;
+    assert_eq!(target.latest_height()?, 1);
+    assert_eq!(target.state_root()?, source.state_root()?);
+    assert_eq!(target.get_block(1)?.expect("anchor block").block.hash()?, manifest.anchor.block.hash()?);
+    Ok(())
+}
+
+#[test]
+fn mismatched_state_root_leaves_the_database_empty() -> Result<()> {
+    let (_source_dir, source) = funded_chain(10)?;
+    let store_dir = temp_dir()?;
+    let store = SnapshotStore::new(store_dir.path());
+    store.create(&source)?;
+    let (mut manifest, chunks) = read_snapshot(&store, 1)?;
+    manifest.anchor.block.state_root = [7u8; 32];
+
+    let (_target_dir, target) = temp_state()?;
+    assert!(snapshot::restore(&target, &manifest, &chunks).is_err());
+    assert!(target.is_empty()?);
+    assert_eq!(target.latest_height()?, 0);
+    Ok(())
+}
+
+#[test]
+fn corrupted_chunk_is_rejected_before_anything_is_written() -> Result<()> {
+    let (_source_dir, source) = funded_chain(10)?;
+    let store_dir = temp_dir()?;
+    let store = SnapshotStore::new(store_dir.path());
+    store.create(&source)?;
+    let (manifest, mut chunks) = read_snapshot(&store, 1)?;
+    chunks[0].push(b' ');
+
+    let (_target_dir, target) = temp_state()?;
+    assert!(snapshot::restore(&target, &manifest, &chunks).is_err());
+    assert!(target.is_empty()?);
+    Ok(())
+}
+
+#[test]
+fn restore_requires_an_empty_database() -> Result<()> {
+    let (_source_dir, source) = funded_chain(10)?;
+    let store_dir = temp_dir()?;
+    let store = SnapshotStore::new(store_dir.path());
+    store.create(&source)?;
+
+    // State without any blocks still counts as data.
+    let (_target_dir, target) = temp_state()?;
+    target.set_balance(&LocalWallet::new().address(), 1)?;
+    assert!(!target.is_empty()?);
+    assert!(store.restore(&target, 1).is_err());
+    Ok(())
+}
@@ -0,0 +1,375 @@
+mod common;
+
+use anyhow::Result;
+use borsh::{BorshDeserialize, BorshSerialize};
+use common::{temp_dir, temp_state};
+use ed25519_dalek::Signer as _;
+use hotstuff_rs::hotstuff::types::{Phase, PhaseCertificate};
+use hotstuff_rs::types::block::Block as ConsensusBlock;
+use hotstuff_rs::types::data_types::{BlockHeight, CryptoHash, ViewNumber};
+use libp2p::PeerId;
+use tempfile::TempDir;
+use tokio::sync::mpsc;
+use unwalled_node::consensus::{
+    consensus_chain_id, transactions_hash, Block, CertifiedDescendant, CommitCertificate, CommittedBlock, ConsensusApp, Transaction, ValidatorSet,
+};
+use unwalled_node::identity::{LocalWallet, Signer};
+use unwalled_node::primitives::{Bid, IdentityTier, Signed, DEFAULT_CHAIN_ID};
+use unwalled_node::snapshot::SnapshotStore;
+use unwalled_node::state::StateManager;
+use unwalled_node::sync::{BlockSyncer, Checkpoint, SyncRequest, SyncResponse, SyncedBlock, MAX_BLOCK_BYTES_PER_RESPONSE};
+use uuid::Uuid;
+
+/// Validators sorted by public key, the order their signatures appear in.
+fn validators(count: usize) -> (Vec<LocalWallet>, ValidatorSet) {
+    let mut wallets: Vec<LocalWallet> = (0..count).map(|_| LocalWallet::new()).collect();
+    wallets.sort_by_key(|wallet| wallet.public_key().0);
+    let set = ValidatorSet::new(DEFAULT_CHAIN_ID, wallets.iter().map(|wallet| wallet.public_key()).collect());
+    (wallets, set)
+}
+
+/// A certificate for the block at `height` carrying `transactions`, signed
+/// by the validators at `signers` (positions in `validators`).
+fn certify(height: u64, transactions: &[Transaction], validators: &[LocalWallet], signers: &[usize]) -> Result<CommitCertificate> {
+    certify_in_views(height, transactions, validators, signers, &[(height, Phase::Commit)])
+}
+
+/// Like `certify`, with one phase certificate per `(view, phase)`: the first
+/// over the block, and each further one over an empty descendant.
+fn certify_in_views(
+    height: u64,
+    transactions: &[Transaction],
+    validators: &[LocalWallet],
+    signers: &[usize],
+    views: &[(u64, Phase)],
+) -> Result<CommitCertificate> {
+    let justify = PhaseCertificate::genesis_pc();
+    let empty = transactions_hash(&[])?;
+    let mut block = ConsensusBlock::hash(BlockHeight::new(height - 1), &justify, &CryptoHash::new(transactions_hash(transactions)?));
+    let mut certificates: Vec<PhaseCertificate> = Vec::new();
+    for (offset, &(view, phase)) in (0..).zip(views) {
+        if let Some(parent) = certificates.last() {
+            block = ConsensusBlock::hash(BlockHeight::new(height - 1 + offset), parent, &CryptoHash::new(empty));
+        }
+        let (chain_id, view) = (consensus_chain_id(DEFAULT_CHAIN_ID), ViewNumber::new(view));
+        let message = (chain_id, view, block, phase).try_to_vec()?;
+        let signatures: Vec<Option<[u8; 64]>> = validators
+            .iter()
+            .enumerate()
+            .map(|(index, wallet)| signers.contains(&index).then(|| wallet.signing_key().sign(&message).to_bytes()))
+            .collect();
+        // A phase certificate encodes as its fields in order.
+        certificates.push(PhaseCertificate::try_from_slice(&(chain_id, view, block, phase, signatures).try_to_vec()?)?);
+    }
+    let descendants = certificates[1..]
+        .iter()
+        .map(|certificate| Ok(CertifiedDescendant { data_hash: empty, phase_certificate: certificate.try_to_vec()? }))
+        .collect::<Result<_>>()?;
+    Ok(CommitCertificate { justify: justify.try_to_vec()?, phase_certificate: certificates[0].try_to_vec()?, descendants })
+}
+
+fn syncer(state_manager: &StateManager, validators: &ValidatorSet) -> Result<(BlockSyncer, mpsc::Receiver<SyncedBlock>)> {
+    // Fast sync is off, so the snapshot store is never written.
+    fast_syncer(state_manager, validators, SnapshotStore::new(temp_dir()?.path()), None)
+}
+
+fn fast_syncer(
+    state_manager: &StateManager,
+    validators: &ValidatorSet,
+    snapshots: SnapshotStore,
+    checkpoint: Option<Checkpoint>,
+) -> Result<(BlockSyncer, mpsc::Receiver<SyncedBlock>)> {
+    let (tx, rx) = mpsc::channel(16);
+    let syncer = BlockSyncer::new(state_manager.clone(), validators.clone(), snapshots, checkpoint, tx)?;
+    Ok((syncer, rx))
+}
+
+/// Applies synced blocks like the node's main loop, failing any block at
+/// `fail_at`.
+fn spawn_app(state_manager: &StateManager, mut rx: mpsc::Receiver<SyncedBlock>, fail_at: Option<u64>) {
+    let mut app = ConsensusApp::new(state_manager.clone());
+    tokio::spawn(async move {
+        while let Some(synced) = rx.recv().await {
+            let result = if Some(synced.block.block.height) == fail_at {
+                Err(anyhow::anyhow!("Refusing block {}", synced.block.block.height))
+            } else {
+                app.apply_block(synced.block)
+            };
+            let _ = synced.applied.send(result);
+        }
+    });
+}
+
+/// Verifies and replays a batch of blocks the way the network task does.
+async fn sync_blocks(client: &mut BlockSyncer, peer: PeerId, blocks: Vec<CommittedBlock>) -> Result<Option<(PeerId, SyncRequest)>> {
+    let blocks = client.on_blocks(peer, blocks)?;
+    let result = client.replay(blocks).await;
+    client.on_replayed(result)
+}
+
+/// A chain of `length` certified empty blocks.
+fn certified_chain(length: u64, validators: &[LocalWallet]) -> Result<(TempDir, StateManager)> {
+    let (dir, state_manager) = temp_state()?;
+    let mut app = ConsensusApp::new(state_manager.clone());
+    let signers: Vec<usize> = (0..validators.len()).collect();
+    for height in 1..=length {
+        app.commit_block(vec![], certify(height, &[], validators, &signers)?)?;
+    }
+    Ok((dir, state_manager))
+}
+
+#[test]
+fn blocks_need_a_two_thirds_quorum() -> Result<()> {
+    // Three of three, and three of four, validators are needed.
+    for (count, quorum) in [(1, 1), (3, 3), (4, 3)] {
+        let (wallets, set) = validators(count);
+        assert_eq!(set.quorum(), quorum);
+        for signed in 0..=count {
+            let signers: Vec<usize> = (0..signed).collect();
+            let block = Block { height: 1, parent_hash: [0u8; 32], transactions: vec![], state_root: [0u8; 32] };
+            let committed = CommittedBlock { block, certificate: certify(1, &[], &wallets, &signers)? };
+            assert_eq!(set.verify(&committed).is_ok(), signed >= quorum, "{} of {} signatures", signed, count);
+        }
+    }
+
+    // Signatures from outside the set do not count.
+    let (wallets, set) = validators(4);
+    let (outsiders, _) = validators(4);
+    let mut mixed: Vec<LocalWallet> = wallets.into_iter().take(2).collect();
+    mixed.extend(outsiders.into_iter().take(2));
+    let block = Block { height: 1, parent_hash: [0u8; 32], transactions: vec![], state_root: [0u8; 32] };
+    let committed = CommittedBlock { block, certificate: certify(1, &[], &mixed, &[0, 1, 2, 3])? };
+    assert!(set.verify(&committed).is_err());
+    Ok(())
+}
+
+#[test]
+fn certificates_must_prove_the_block_committed() -> Result<()> {
+    use Phase::Generic;
+    let (wallets, set) = validators(4);
+    let block = Block { height: 1, parent_hash: [0u8; 32], transactions: vec![], state_root: [0u8; 32] };
+    let verifies = |views: &[(u64, Phase)]| -> Result<bool> {
+        let certificate = certify_in_views(1, &[], &wallets, &[0, 1, 2], views)?;
+        Ok(set.verify(&CommittedBlock { block: block.clone(), certificate }).is_ok())
+    };
+
+    assert!(verifies(&[(1, Phase::Commit)])?);
+    assert!(verifies(&[(1, Phase::Decide)])?);
+    // A quorum that only prepared or voted for the block commits nothing.
+    assert!(!verifies(&[(1, Phase::Prepare)])?);
+    assert!(!verifies(&[(1, Phase::Precommit)])?);
+    assert!(!verifies(&[(1, Generic)])?);
+    assert!(!verifies(&[(1, Generic), (2, Generic)])?);
+
+    // Generic certificates commit from three consecutive views, which may
+    // certify descendants after a view change.
+    assert!(verifies(&[(1, Generic), (2, Generic), (3, Generic)])?);
+    assert!(!verifies(&[(1, Generic), (2, Generic), (4, Generic)])?);
+    assert!(verifies(&[(1, Generic), (2, Generic), (4, Generic), (5, Generic), (6, Generic)])?);
+
+    // Descendants must extend the block.
+    let mut certificate = certify_in_views(1, &[], &wallets, &[0, 1, 2], &[(1, Generic), (2, Generic), (3, Generic)])?;
+    certificate.descendants[0].data_hash = [7u8; 32];
+    assert!(set.verify(&CommittedBlock { block, certificate }).is_err());
+    Ok(())
+}
+
+#[test]
+fn syncer_refuses_an_empty_validator_set() -> Result<()> {
+    let (_dir, state_manager) = temp_state()?;
+    assert!(syncer(&state_manager, &ValidatorSet::new(DEFAULT_CHAIN_ID, Vec::new())).is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn syncer_catches_up_with_a_peer() -> Result<()> {
+    let (wallets, set) = validators(4);
+    let peer = PeerId::random();
+    let (_server_dir, server_state) = certified_chain(5, &wallets)?;
+    let (server, _) = syncer(&server_state, &set)?;
+
+    let (_dir, state_manager) = temp_state()?;
+    let (mut client, rx) = syncer(&state_manager, &set)?;
+    spawn_app(&state_manager, rx, None);
+
+    let mut next = client.on_status(peer, 5);
+    while let Some((_, request)) = next {
+        let SyncResponse::Blocks(blocks) = server.handle_request(request)? else {
+            panic!("expected blocks");
+        };
+        next = sync_blocks(&mut client, peer, blocks).await?;
+    }
+    assert_eq!(state_manager.latest_height()?, 5);
+    Ok(())
+}
+
+#[tokio::test]
+async fn no_request_is_sent_while_blocks_replay() -> Result<()> {
+    let (wallets, set) = validators(1);
+    let peer = PeerId::random();
+    let (_server_dir, server_state) = certified_chain(5, &wallets)?;
+    let (server, _) = syncer(&server_state, &set)?;
+
+    let (_dir, state_manager) = temp_state()?;
+    let (mut client, rx) = syncer(&state_manager, &set)?;
+    spawn_app(&state_manager, rx, None);
+    let Some((_, request)) = client.on_status(peer, 3) else {
+        panic!("expected a request");
+    };
+    let SyncResponse::Blocks(blocks) = server.handle_request(request)? else {
+        panic!("expected blocks");
+    };
+    let blocks = client.on_blocks(peer, blocks)?;
+    assert!(client.on_status(peer, 5).is_none());
+
+    let result = client.replay(blocks).await;
+    match client.on_replayed(result)? {
+        Some((_, SyncRequest::Blocks { from_height, to_height })) => assert_eq!((from_height, to_height), (4, 5)),
+        other => panic!("unexpected request {:?}", other),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn failed_apply_resumes_from_the_state_height() -> Result<()> {
+    let (wallets, set) = validators(1);
+    let peer = PeerId::random();
+    let (_server_dir, server_state) = certified_chain(5, &wallets)?;
+    let (server, _) = syncer(&server_state, &set)?;
+
+    let (_dir, state_manager) = temp_state()?;
+    let (mut client, rx) = syncer(&state_manager, &set)?;
+    spawn_app(&state_manager, rx, Some(3));
+
+    let Some((_, request)) = client.on_status(peer, 5) else {
+        panic!("expected a request");
+    };
+    let SyncResponse::Blocks(blocks) = server.handle_request(request)? else {
+        panic!("expected blocks");
+    };
+    assert!(sync_blocks(&mut client, peer, blocks).await.is_err());
+    assert_eq!(state_manager.latest_height()?, 2);
+
+    // The next attempt asks for the first block that was not applied.
+    match client.on_status(peer, 5) {
+        Some((_, SyncRequest::Blocks { from_height, .. })) => assert_eq!(from_height, 3),
+        other => panic!("unexpected request {:?}", other),
+    }
+    Ok(())
+}
+
+/// Answers `client`'s fast sync requests from `server` until it stops asking.
+fn fast_sync(client: &mut BlockSyncer, server: &BlockSyncer, peer_height: u64) -> Result<()> {
+    let peer = PeerId::random();
+    let mut next = client.on_status(peer, peer_height);
+    while let Some((_, request)) = next {
+        next = match server.handle_request(request)? {
+            SyncResponse::SnapshotManifest(manifest) => client.on_snapshot_manifest(peer, manifest)?,
+            SyncResponse::SnapshotChunk { height, index, data } => client.on_snapshot_chunk(peer, height, index, data)?,
+            // Block sync takes over once the snapshot is restored.
+            SyncResponse::Blocks(_) | SyncResponse::Status { .. } => None,
+        };
+    }
+    Ok(())
+}
+
+#[test]
+fn fast_sync_restores_only_the_checkpoint_snapshot() -> Result<()> {
+    let (wallets, set) = validators(1);
+    let (server_dir, server_state) = certified_chain(3, &wallets)?;
+    let store = SnapshotStore::new(server_dir.path().join("snapshots"));
+    store.create(&server_state)?;
+    let (server, _) = fast_syncer(&server_state, &set, store, None)?;
+    let block_hash = server_state.get_block(3)?.expect("block 3").block.hash()?;
+
+    let (_dir, state_manager) = temp_state()?;
+    let checkpoint = Checkpoint { height: 3, block_hash };
+    let (mut client, _rx) = fast_syncer(&state_manager, &set, SnapshotStore::new(temp_dir()?.path()), Some(checkpoint))?;
+    fast_sync(&mut client, &server, 3)?;
+    assert_eq!(state_manager.latest_height()?, 3);
+    assert_eq!(state_manager.state_root()?, server_state.state_root()?);
+
+    // A certified anchor that is not the checkpoint block is refused.
+    let (_dir, state_manager) = temp_state()?;
+    let checkpoint = Checkpoint { height: 3, block_hash: [7u8; 32] };
+    let (mut client, _rx) = fast_syncer(&state_manager, &set, SnapshotStore::new(temp_dir()?.path()), Some(checkpoint))?;
+    assert!(fast_sync(&mut client, &server, 3).is_err());
+    assert_eq!(state_manager.latest_height()?, 0);
+    Ok(())
+}
+
+#[test]
+fn checkpoints_parse_from_height_and_hash() -> Result<()> {
+    let checkpoint: Checkpoint = format!("42:{}", hex::encode([9u8; 32])).parse()?;
+    assert_eq!(checkpoint, Checkpoint { height: 42, block_hash: [9u8; 32] });
+    assert!("42".parse::<Checkpoint>().is_err());
+    assert!("42:abcd".parse::<Checkpoint>().is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn replayed_blocks_keep_their_own_state_root() -> Result<()> {
+    let (wallets, set) = validators(1);
+    let peer = PeerId::random();
+    let (_server_dir, server_state) = certified_chain(3, &wallets)?;
+    let mut forged = server_state.get_block(2)?.expect("block 2");
+    let state_root = forged.block.state_root;
+    forged.block.state_root = [7u8; 32];
+    server_state.put_block(&forged)?;
+    let (server, _) = syncer(&server_state, &set)?;
+
+    let (_dir, state_manager) = temp_state()?;
+    let (mut client, rx) = syncer(&state_manager, &set)?;
+    spawn_app(&state_manager, rx, None);
+    let Some((_, request)) = client.on_status(peer, 2) else {
+        panic!("expected a request");
+    };
+    let SyncResponse::Blocks(blocks) = server.handle_request(request)? else {
+        panic!("expected blocks");
+    };
+    assert!(sync_blocks(&mut client, peer, blocks).await.is_err());
+
+    // The forged root is never stored, and the genuine block 3 extends the
+    // replayed block 2.
+    assert_eq!(state_manager.latest_height()?, 2);
+    assert_eq!(state_manager.get_block(2)?.expect("block 2").block.state_root, state_root);
+    let Some((_, request)) = client.on_status(peer, 3) else {
+        panic!("expected a request");
+    };
+    let SyncResponse::Blocks(blocks) = server.handle_request(request)? else {
+        panic!("expected blocks");
+    };
+    sync_blocks(&mut client, peer, blocks).await?;
+    assert_eq!(state_manager.latest_height()?, 3);
+    Ok(())
+}
+
+#[test]
+fn block_responses_are_capped_in_bytes() -> Result<()> {
+    let (_dir, state_manager) = temp_state()?;
+    let advertiser = LocalWallet::new();
+    let mut parent_hash = [0u8; 32];
+    for height in 1..=12 {
+        let bid = Bid {
+            id: Uuid::new_v4(),
+            price: 150,
+            targeting: vec![],
+            adm: "x".repeat(1024 * 1024),
+            expires_at_height: None,
+            requires_verified_targeting: false,
+            min_user_tier: IdentityTier::Anonymous,
+        };
+        let transactions = vec![Transaction::PlaceBid(Signed::sign(bid, height, 10, &advertiser)?)];
+        let block = Block { height, parent_hash, transactions, state_root: [0u8; 32] };
+        parent_hash = block.hash()?;
+        state_manager.put_block(&CommittedBlock { block, certificate: CommitCertificate::default() })?;
+    }
+
+    let (server, _) = syncer(&state_manager, &validators(1).1)?;
+    let SyncResponse::Blocks(blocks) = server.handle_request(SyncRequest::Blocks { from_height: 1, to_height: 12 })? else {
+        panic!("expected blocks");
+    };
+    assert!(!blocks.is_empty() && blocks.len() < 12);
+    assert!(serde_json::to_vec(&SyncResponse::Blocks(blocks))?.len() <= MAX_BLOCK_BYTES_PER_RESPONSE + 64);
+    Ok(())
+}
@@ -0,0 +1,163 @@
+mod common;
+
+use anyhow::Result;
+use common::temp_state;
+use unwalled_node::consensus::{ConsensusApp, Transaction};
+use unwalled_node::error::{classify, ErrorCode};
+use unwalled_node::identity::{LocalWallet, Signer};
+use unwalled_node::mempool::Mempool;
+use unwalled_node::primitives::{bid_id, Bid, CancelBid, Hash, IdentityTier, ReceiptStatus, Signed};
+use unwalled_node::wire::WireFormat;
+use uuid::Uuid;
+
+fn sample_bid(id: Uuid) -> Bid {
+    Bid {
+        id,
+        price: 150,
+        targeting: vec!["geo=de".to_string()],
+        adm: "<div>ad</div>".to_string(),
+        expires_at_height: None,
+        requires_verified_targeting: false,
+        min_user_tier: IdentityTier::Anonymous,
+    }
+}
+
+#[test]
+fn hash_does_not_depend_on_the_wire_format() -> Result<()> {
+    let wallet = LocalWallet::new();
+    let signed = Signed::sign(sample_bid(Uuid::new_v4()), 0, 10, &wallet)?;
+    let transaction = Transaction::PlaceBid(signed.clone());
+    assert_eq!(transaction.hash()?, signed.hash()?);
+
+    for format in [WireFormat::Json, WireFormat::MessagePack] {
+        let decoded: Transaction = format.decode(&format.encode(&transaction)?)?;
+        assert_eq!(decoded.hash()?, transaction.hash()?);
+    }
+
+    // Field order and explicit nulls do not matter either.
+    let mut json: serde_json::Value = serde_json::to_value(&transaction)?;
+    json["PlaceBid"]["data"]["expires_at_height"] = serde_json::Value::Null;
+    let reordered: Transaction = serde_json::from_str(&json.to_string())?;
+    assert_eq!(reordered.hash()?, transaction.hash()?);
+    Ok(())
+}
+
+#[test]
+fn hash_covers_every_part_of_the_signed_action() -> Result<()> {
+    let wallet = LocalWallet::new();
+    let bid = sample_bid(Uuid::new_v4());
+    let hash = Signed::sign(bid.clone(), 0, 10, &wallet)?.hash()?;
+
+    assert_ne!(Signed::sign(bid.clone(), 1, 10, &wallet)?.hash()?, hash);
+    assert_ne!(Signed::sign(bid.clone(), 0, 11, &wallet)?.hash()?, hash);
+    assert_ne!(Signed::sign(bid.clone(), 0, 10, &LocalWallet::new())?.hash()?, hash);
+    assert_ne!(Signed::sign_for_chain("unwalled-testnet", bid.clone(), 0, 10, &wallet)?.hash()?, hash);
+    assert_ne!(Signed::sign(sample_bid(Uuid::new_v4()), 0, 10, &wallet)?.hash()?, hash);
+
+    // The same signed payload under another action type is another transaction.
+    let cancel = Signed::sign(CancelBid { bid_id: bid.id }, 0, 10, &wallet)?;
+    assert_ne!(cancel.hash()?, hash);
+    Ok(())
+}
+
+#[test]
+fn redelivering_a_transaction_keeps_its_receipt() -> Result<()> {
+    let (_dir, state_manager) = temp_state()?;
+    let mut app = ConsensusApp::new(state_manager.clone());
+    let wallet = LocalWallet::new();
+    state_manager.set_balance(&wallet.address(), 100)?;
+
+    let transaction = Transaction::PlaceBid(Signed::sign(sample_bid(Uuid::new_v4()), 0, 10, &wallet)?);
+    let tx_hash = transaction.hash()?;
+    app.deliver(transaction.clone());
+    app.deliver(transaction);
+
+    assert_eq!(state_manager.get_receipt(&tx_hash)?.expect("receipt").status, ReceiptStatus::Applied);
+    assert_eq!(state_manager.get_balance(&wallet.address())?, 90);
+    assert_eq!(state_manager.get_nonce(&wallet.address())?, 1);
+    Ok(())
+}
+
+#[test]
+fn mempool_holds_each_transaction_once() -> Result<()> {
+    let wallet = LocalWallet::new();
+    let first = Transaction::PlaceBid(Signed::sign(sample_bid(Uuid::new_v4()), 0, 10, &wallet)?);
+    let second = Transaction::PlaceBid(Signed::sign(sample_bid(Uuid::new_v4()), 1, 10, &wallet)?);
+
+    let mut mempool = Mempool::new();
+    let first_hash = mempool.insert(first.clone())?.expect("new transaction");
+    assert_eq!(first_hash, first.hash()?);
+    // The same transaction re-encoded, as if gossiped by another peer.
+    let regossiped: Transaction = WireFormat::MessagePack.decode(&WireFormat::MessagePack.encode(&first)?)?;
+    assert!(mempool.insert(regossiped)?.is_none());
+    mempool.insert(second.clone())?.expect("new transaction");
+    assert_eq!(mempool.len(), 2);
+
+    assert_eq!(mempool.iter().next().map(|(hash, _)| *hash), Some(first_hash));
+    assert!(mempool.remove(&first_hash).is_some());
+    assert!(!mempool.contains(&first_hash));
+    assert!(mempool.remove(&second.hash()?).is_some());
+    assert!(mempool.is_empty());
+    Ok(())
+}
+
+#[test]
+fn mempool_refuses_transactions_beyond_capacity() -> Result<()> {
+    let wallet = LocalWallet::new();
+    let mut mempool = Mempool::with_capacity(2);
+    let mut hashes = Vec::new();
+    for nonce in 0..2 {
+        let transaction = Transaction::PlaceBid(Signed::sign(sample_bid(Uuid::new_v4()), nonce, 10, &wallet)?);
+        hashes.push(mempool.insert(transaction)?.expect("new transaction"));
+    }
+    let third = Transaction::PlaceBid(Signed::sign(sample_bid(Uuid::new_v4()), 2, 10, &wallet)?);
+    let error = mempool.insert(third.clone()).expect_err("mempool is full");
+    assert_eq!(classify(&error).code, ErrorCode::Unavailable);
+
+    // Removing from the middle frees a slot and keeps arrival order.
+    mempool.remove(&hashes[0]);
+    mempool.insert(third.clone())?.expect("new transaction");
+    let order: Vec<Hash> = mempool.iter().map(|(hash, _)| *hash).collect();
+    assert_eq!(order, vec![hashes[1], third.hash()?]);
+    Ok(())
+}
+
+#[test]
+fn precheck_rejects_used_nonces_and_unaffordable_fees() -> Result<()> {
+    let (_dir, state_manager) = temp_state()?;
+    let wallet = LocalWallet::new();
+    state_manager.set_balance(&wallet.address(), 10)?;
+    state_manager.increment_nonce(&wallet.address())?;
+
+    let transaction = |nonce, fee| -> Result<Transaction> {
+        Ok(Transaction::PlaceBid(Signed::sign(sample_bid(Uuid::new_v4()), nonce, fee, &wallet)?))
+    };
+    transaction(1, 10)?.precheck(&state_manager)?;
+    // A later nonce may wait behind pending transactions from the same signer.
+    transaction(3, 10)?.precheck(&state_manager)?;
+    let stale = transaction(0, 10)?.precheck(&state_manager).expect_err("nonce is used");
+    assert_eq!(classify(&stale).code, ErrorCode::BadNonce);
+    let unaffordable = transaction(1, 11)?.precheck(&state_manager).expect_err("fee is unaffordable");
+    assert_eq!(classify(&unaffordable).code, ErrorCode::InsufficientFunds);
+    Ok(())
+}
+
+#[test]
+fn bid_ids_are_namespaced_by_advertiser() -> Result<()> {
+    let (_dir, state_manager) = temp_state()?;
+    let (alice, mallory) = (LocalWallet::new().address(), LocalWallet::new().address());
+    let id = Uuid::new_v4();
+
+    // Mallory front-running Alice's bid id does not block or overwrite hers.
+    let mallorys = state_manager.place_bid(&sample_bid(id), &mallory, 0)?;
+    let alices = state_manager.place_bid(&sample_bid(id), &alice, 0)?;
+    assert_ne!(alices, mallorys);
+    assert_eq!(alices, bid_id(&alice, &id));
+    let info = state_manager.get_bid(&alices)?.expect("bid");
+    assert_eq!(info.advertiser, alice);
+    assert_eq!(info.bid.id, alices);
+
+    let error = state_manager.place_bid(&sample_bid(id), &alice, 0).unwrap_err();
+    assert_eq!(classify(&error).code, ErrorCode::BidAlreadyExists);
+    Ok(())
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use anyhow::Result;
+use common::temp_state;
+use tokio::sync::{broadcast, mpsc};
+use unwalled_node::consensus::{ReceiptWaiters, Transaction};
+use unwalled_node::identity::{EvmWallet, LocalWallet, Signer};
+use unwalled_node::primitives::{
+    AuctionTrigger, Bid, CancelBid, IdentityTier, MultisigAction, MultisigUpdate, SessionKeyUpdate, Signed, SubmitResponse,
+    Withdraw,
+};
+use unwalled_node::rpc::RpcHandler;
+use unwalled_node::wire::{Negotiated, WireFormat};
+use uuid::Uuid;
+
+fn sample_bid() -> Bid {
+    Bid {
+        id: Uuid::new_v4(),
+        price: 150,
+        targeting: vec!["geo=de".to_string(), "interest=sports".to_string()],
+        adm: "<div>ad</div>".to_string(),
+        expires_at_height: Some(1_000),
+        requires_verified_targeting: true,
+        min_user_tier: IdentityTier::Verified,
+    }
+}
+
+fn sample_transactions() -> Result<Vec<Transaction>> {
+    let wallet = LocalWallet::new();
+    let evm = EvmWallet::new();
+    let auction = AuctionTrigger {
+        id: Uuid::new_v4(),
+        bid_floor: 100,
+        attributes: vec!["geo=de".to_string()],
+        presentation: None,
+        min_advertiser_tier: IdentityTier::Anonymous,
+    };
+    let withdraw = Withdraw { amount: 10, recipient: "keeta:recipient".to_string() };
+    Ok(vec![
+        Transaction::PlaceBid(Signed::sign(sample_bid(), 0, 10, &wallet)?),
+        Transaction::PlaceBid(Signed::sign(sample_bid(), 0, 10, &evm)?),
+        Transaction::TriggerAuction(Signed::sign(auction, 1, 10, &wallet)?),
+        Transaction::CancelBid(Signed::sign(CancelBid { bid_id: Uuid::new_v4() }, 2, 10, &wallet)?),
+        Transaction::Withdraw(Signed::sign(withdraw.clone(), 3, 10, &wallet)?),
+        Transaction::UpdateSessionKey(Signed::sign(SessionKeyUpdate::Revoke { session_key: evm.did() }, 4, 10, &wallet)?),
+        Transaction::UpdateMultisig(Signed::sign(
+            MultisigUpdate::Propose { account: "multisig:00".to_string(), proposal: MultisigAction::Withdraw(withdraw) },
+            5,
+            10,
+            &wallet,
+        )?),
+    ])
+}
+
+#[test]
+fn msgpack_round_trips_every_transaction_and_is_smaller() -> Result<()> {
+    for transaction in sample_transactions()? {
+        let json = WireFormat::Json.encode(&transaction)?;
+        let msgpack = WireFormat::MessagePack.encode(&transaction)?;
+        assert!(msgpack.len() < json.len(), "{} >= {} bytes for {:?}", msgpack.len(), json.len(), transaction);
+
+        let decoded: Transaction = WireFormat::MessagePack.decode(&msgpack)?;
+        assert_eq!(decoded.hash()?, transaction.hash()?);
+        // JSON decodes the same transaction, so either can be used to debug.
+        assert_eq!(WireFormat::Json.decode::<Transaction>(&json)?.hash()?, transaction.hash()?);
+    }
+    Ok(())
+}
+
+#[test]
+fn signatures_survive_the_binary_encoding() -> Result<()> {
+    let signed = Signed::sign(sample_bid(), 0, 10, &LocalWallet::new())?;
+    let decoded: Signed<Bid> = WireFormat::MessagePack.decode(&WireFormat::MessagePack.encode(&signed)?)?;
+    assert_eq!(decoded.signature, signed.signature);
+    assert!(decoded.verify()?);
+    Ok(())
+}
+
+#[test]
+fn formats_are_negotiated_from_headers() {
+    let json = WireFormat::Json;
+    let msgpack = WireFormat::MessagePack;
+    let negotiate = |content_type, accept| Negotiated::from_headers(content_type, accept);
+
+    assert_eq!(negotiate(None, None), Negotiated { request: json, response: json });
+    assert_eq!(negotiate(Some("application/msgpack"), None), Negotiated { request: msgpack, response: msgpack });
+    assert_eq!(
+        negotiate(Some("application/json; charset=utf-8"), Some("text/html, application/x-msgpack")),
+        Negotiated { request: json, response: msgpack }
+    );
+    assert_eq!(negotiate(Some("application/msgpack"), Some("application/json")), Negotiated { request: msgpack, response: json });
+    // Unknown types fall back to JSON, as before formats were negotiated.
+    assert_eq!(negotiate(Some("text/plain"), Some("*/*")), Negotiated { request: json, response: json });
+}
+
+#[tokio::test]
+async fn rpc_answers_in_the_negotiated_format() -> Result<()> {
+    let (_dir, state_manager) = temp_state()?;
+    let (tx, mut rx) = mpsc::channel(16);
+    let wallet = LocalWallet::new();
+    state_manager.set_balance(&wallet.address(), 100)?;
+    let handler = RpcHandler::new(tx, state_manager, broadcast::channel(16).0, ReceiptWaiters::default());
+
+    let signed = Signed::sign(sample_bid(), 0, 10, &wallet)?;
+    let msgpack = Negotiated { request: WireFormat::MessagePack, response: WireFormat::MessagePack };
+    let response = handler.handle_as("POST", "/rpc/place_bid", &WireFormat::MessagePack.encode(&signed)?, msgpack).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.content_type, "application/msgpack");
+    let submitted: SubmitResponse = WireFormat::MessagePack.decode(&response.body)?;
+    let forwarded = rx.recv().await.expect("transaction forwarded");
+    assert_eq!(submitted.tx_hash, hex::encode(forwarded.hash()?));
+
+    // A JSON body sent as MessagePack is rejected, and errors are JSON.
+    let response = handler.handle_as("POST", "/rpc/place_bid", &serde_json::to_vec(&signed)?, msgpack).await;
+    assert_eq!(response.status, 400);
+    assert_eq!(response.content_type, "application/json");
+    Ok(())
+}